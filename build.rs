@@ -0,0 +1,54 @@
+use std::process::Command;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc comes with the build so no system install is needed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .bytes(["."])
+        .compile_protos(&["proto/iroh_api.proto"], &["proto"])?;
+
+    // What went into the build, for GET /version
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(git_commit);
+    println!(
+        "cargo:rustc-env=IROH_API_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (name, var) in [
+        ("iroh", "IROH_API_IROH_VERSION"),
+        ("iroh-blobs", "IROH_API_IROH_BLOBS_VERSION"),
+    ] {
+        println!(
+            "cargo:rustc-env={}={}",
+            var,
+            locked_version(&lock, name).unwrap_or("unknown")
+        );
+    }
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !commit.is_empty()).then_some(commit)
+}
+
+/// The version of `name` in Cargo.lock.
+fn locked_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    let mut lines = lock.lines();
+    let wanted = format!("name = \"{}\"", name);
+    lines.find(|line| *line == wanted)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,245 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use http_body::{Frame, SizeHint};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+use crate::proxy::ClientInfo;
+use crate::reload::Live;
+use crate::AppState;
+
+enum Message {
+    Line(String),
+    /// Write to this file from now on, or to stderr.
+    Open(Option<PathBuf>),
+}
+
+/// Writes a line per HTTP request, once its response has been sent.
+///
+/// Lines are handed to a task owning the file, so requests never wait for the disk.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: Live<AccessLogFormat>,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_lines(receiver));
+        let access_log = Self {
+            format: Live::new(config.format),
+            sender,
+        };
+        access_log.reload(config);
+        access_log
+    }
+
+    /// Switches to the format of `config` and reopens its file, which may have been
+    /// rotated away.
+    pub fn reload(&self, config: &AccessLogConfig) {
+        self.format.set(config.format);
+        let _ = self.sender.send(Message::Open(config.file.clone()));
+    }
+}
+
+async fn open(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+async fn write_lines(mut receiver: mpsc::UnboundedReceiver<Message>) {
+    let mut file: Option<File> = None;
+    while let Some(message) = receiver.recv().await {
+        match message {
+            Message::Open(None) => file = None,
+            Message::Open(Some(path)) => match open(&path).await {
+                Ok(opened) => file = Some(opened),
+                // Keep writing where lines went so far
+                Err(err) => println!("Failed to open access log {}: {}", path.display(), err),
+            },
+            Message::Line(line) => {
+                let written = match &mut file {
+                    Some(file) => match file.write_all(line.as_bytes()).await {
+                        Ok(()) => file.flush().await,
+                        Err(err) => Err(err),
+                    },
+                    None => tokio::io::stderr().write_all(line.as_bytes()).await,
+                };
+                if let Err(err) = written {
+                    println!("Failed to write access log: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// What is known about a request once its response starts.
+struct Entry {
+    time: DateTime<Utc>,
+    ip: Option<IpAddr>,
+    method: String,
+    uri: String,
+    protocol: String,
+    status: u16,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// A quoted field of the combined format, `-` when missing.
+fn quoted(value: &Option<String>) -> String {
+    match value {
+        Some(value) => value.replace('\\', "\\\\").replace('"', "\\\""),
+        None => "-".to_string(),
+    }
+}
+
+impl Entry {
+    fn line(&self, format: AccessLogFormat, bytes: u64, duration_ms: u64) -> String {
+        match format {
+            AccessLogFormat::Json => {
+                let line = serde_json::json!({
+                    "time": self.time.to_rfc3339(),
+                    "remote_ip": self.ip,
+                    "method": self.method,
+                    "uri": self.uri,
+                    "protocol": self.protocol,
+                    "status": self.status,
+                    "bytes": bytes,
+                    "duration_ms": duration_ms,
+                    "referer": self.referer,
+                    "user_agent": self.user_agent,
+                });
+                format!("{}\n", line)
+            }
+            AccessLogFormat::Combined | AccessLogFormat::Off => format!(
+                "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"\n",
+                self.ip
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                self.method,
+                self.uri,
+                self.protocol,
+                self.status,
+                if bytes == 0 {
+                    "-".to_string()
+                } else {
+                    bytes.to_string()
+                },
+                quoted(&self.referer),
+                quoted(&self.user_agent),
+            ),
+        }
+    }
+}
+
+/// A response body that writes the access log line when it is done with, sent in full
+/// or dropped by a client going away.
+struct Logged {
+    inner: Body,
+    bytes: u64,
+    started: Instant,
+    format: AccessLogFormat,
+    entry: Entry,
+    access_log: AccessLog,
+}
+
+impl http_body::Body for Logged {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for Logged {
+    fn drop(&mut self) {
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        let line = self.entry.line(self.format, self.bytes, duration_ms);
+        let _ = self.access_log.sender.send(Message::Line(line));
+    }
+}
+
+/// Middleware writing the access log. Runs outside everything else, so it sees the
+/// paths and the compressed bytes clients actually got.
+pub async fn apply(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Response {
+    let access_log = app_state.access_log;
+    let format = *access_log.format.get();
+    if format == AccessLogFormat::Off {
+        return next.run(request).await;
+    }
+    let started = Instant::now();
+    let headers = request.headers();
+    let mut entry = Entry {
+        time: Utc::now(),
+        ip: client.ip,
+        method: request.method().to_string(),
+        uri: request.uri().to_string(),
+        protocol: format!("{:?}", request.version()),
+        status: 0,
+        referer: header_value(headers, header::REFERER),
+        user_agent: header_value(headers, header::USER_AGENT),
+    };
+
+    let response = next.run(request).await;
+    entry.status = response.status().as_u16();
+    response.map(|inner| {
+        Body::new(Logged {
+            inner,
+            bytes: 0,
+            started,
+            format,
+            entry,
+            access_log,
+        })
+    })
+}
@@ -0,0 +1,233 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::TryStreamExt;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::util::Tag;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use crate::blob::{download_blob, parse_hash};
+use crate::AppState;
+
+/// Tags aliased content is kept under, as `alias/<name>`.
+const TAG_PREFIX: &str = "alias/";
+
+const MAX_NAME_LEN: usize = 128;
+
+fn alias_tag(name: &str) -> Tag {
+    Tag::from(format!("{}{}", TAG_PREFIX, name))
+}
+
+/// Names that fit in one path segment of `/a/<name>` without escaping.
+fn is_valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LEN).contains(&name.len())
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+#[derive(Serialize)]
+pub struct Alias {
+    pub name: String,
+    pub hash: Hash,
+    pub format: BlobFormat,
+}
+
+/// Stable names for content that changes, like `latest-build`.
+///
+/// Each alias is a tag on the content it points to, which keeps that content and is
+/// the record of the alias, so exports and snapshots carry aliases along. Pointing an
+/// alias elsewhere moves the tag; the blobs themselves never change.
+#[derive(Clone)]
+pub struct Aliases {
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    aliases: Arc<RwLock<BTreeMap<String, HashAndFormat>>>,
+}
+
+impl Aliases {
+    pub async fn load(blobs: &Blobs<iroh_blobs::store::fs::Store>) -> Result<Self> {
+        let aliases = Self {
+            blobs: blobs.clone(),
+            aliases: Arc::default(),
+        };
+        aliases.rescan().await?;
+        Ok(aliases)
+    }
+
+    /// Points `name` at `content`, wherever it pointed before.
+    pub async fn set(&self, name: &str, content: HashAndFormat) -> Result<()> {
+        let batch = self.blobs.client().batch().await?;
+        let temp_tag = batch.temp_tag(content).await?;
+        batch.persist_to(temp_tag, alias_tag(name)).await?;
+        self.aliases
+            .write()
+            .unwrap()
+            .insert(name.to_string(), content);
+        Ok(())
+    }
+
+    /// Drops an alias. Its content goes with the next garbage collection unless
+    /// something else keeps it.
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        if !self.aliases.read().unwrap().contains_key(name) {
+            return Ok(false);
+        }
+        self.blobs.client().tags().delete(alias_tag(name)).await?;
+        self.aliases.write().unwrap().remove(name);
+        Ok(true)
+    }
+
+    pub fn get(&self, name: &str) -> Option<HashAndFormat> {
+        self.aliases.read().unwrap().get(name).copied()
+    }
+
+    pub fn list(&self) -> Vec<Alias> {
+        self.aliases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, content)| Alias {
+                name: name.clone(),
+                hash: content.hash,
+                format: content.format,
+            })
+            .collect()
+    }
+
+    /// Records the alias of a tag set elsewhere, like by importing an archive.
+    pub fn adopt(&self, tag: &str, content: HashAndFormat) {
+        if let Some(name) = tag.strip_prefix(TAG_PREFIX) {
+            self.aliases
+                .write()
+                .unwrap()
+                .insert(name.to_string(), content);
+        }
+    }
+
+    /// Rebuilds the aliases from the tags after they changed underneath, like with a
+    /// rollback.
+    pub async fn rescan(&self) -> Result<()> {
+        let tags: Vec<_> = self
+            .blobs
+            .client()
+            .tags()
+            .list()
+            .await?
+            .try_collect()
+            .await?;
+        let aliases = tags
+            .into_iter()
+            .filter_map(|tag| {
+                let name = std::str::from_utf8(&tag.name.0)
+                    .ok()?
+                    .strip_prefix(TAG_PREFIX)?
+                    .to_string();
+                let content = HashAndFormat {
+                    hash: tag.hash,
+                    format: tag.format,
+                };
+                Some((name, content))
+            })
+            .collect();
+        *self.aliases.write().unwrap() = aliases;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AliasRequest {
+    hash: String,
+    #[serde(default)]
+    hash_seq: bool,
+}
+
+/// `PUT /alias/{name}`: points an alias at a stored blob, given as `{"hash": ...}`.
+pub async fn set_alias(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<AliasRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !is_valid_name(&name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let hash = parse_hash(&request.hash)?;
+    let has_blob = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !has_blob {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let format = if request.hash_seq {
+        BlobFormat::HashSeq
+    } else {
+        BlobFormat::Raw
+    };
+    let content = HashAndFormat { hash, format };
+    app_state.aliases.set(&name, content).await.map_err(|err| {
+        println!("Failed to set alias {}: {}", name, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(Alias { name, hash, format }))
+}
+
+/// `GET /alias/{name}`: what an alias points at.
+pub async fn get_alias(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let content = app_state.aliases.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(Alias {
+        name,
+        hash: content.hash,
+        format: content.format,
+    }))
+}
+
+/// `DELETE /alias/{name}`.
+pub async fn delete_alias(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if app_state
+        .aliases
+        .get(&name)
+        .is_some_and(|content| app_state.holds.is_held(&content.hash))
+    {
+        return Err(StatusCode::LOCKED);
+    }
+    let removed = app_state.aliases.remove(&name).await.map_err(|err| {
+        println!("Failed to remove alias {}: {}", name, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !removed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /aliases`: every alias, by name.
+pub async fn list_aliases(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.aliases.list())
+}
+
+/// `GET /a/{name}`: downloads what an alias points at right now.
+pub async fn download_alias(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let content = app_state.aliases.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    download_blob(State(app_state), Path(content.hash.to_string()), headers).await
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use axum::{body::Bytes, extract::State, response::IntoResponse, Json};
+use futures::StreamExt;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_gossip::net::{Event, Gossip, GossipEvent, GossipReceiver, GossipSender};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AnnounceConfig;
+use crate::gossip::parse_topic;
+use crate::providers::{Providers, Source};
+use crate::AppState;
+
+/// Announcements kept at most, as any peer on the topic can send them. The one heard
+/// longest ago goes first.
+const MAX_HEARD: usize = 10_000;
+
+/// Message broadcast on the announce topic whenever a blob is added.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Announcement {
+    pub ticket: String,
+    pub node_id: String,
+    pub blob_hash: String,
+    pub blob_format: String,
+    pub size: u64,
+    pub file_name: Option<String>,
+}
+
+/// An announcement received from a peer, as kept in the local index.
+#[derive(Serialize, Clone)]
+pub struct HeardAnnouncement {
+    #[serde(flatten)]
+    announcement: Announcement,
+    delivered_from: String,
+    received_at: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Announcer {
+    sender: Option<Arc<GossipSender>>,
+    heard: Arc<RwLock<HashMap<String, HeardAnnouncement>>>,
+}
+
+impl Announcer {
+    /// Joins the configured announce topic, if any, and starts collecting peer
+    /// announcements. Announced blobs are remembered with their `providers`.
+    pub fn spawn(gossip: &Gossip, config: &AnnounceConfig, providers: Providers) -> Result<Self> {
+        let Some(topic) = &config.topic else {
+            return Ok(Self::default());
+        };
+        let topic_id = parse_topic(topic);
+        let (sender, receiver) = gossip.subscribe(topic_id, config.bootstrap.clone())?.split();
+
+        let announcer = Self {
+            sender: Some(Arc::new(sender)),
+            heard: Default::default(),
+        };
+        tokio::spawn(announcer.clone().listen(receiver, providers));
+
+        println!("Announcing uploads on gossip topic {}", topic_id);
+        Ok(announcer)
+    }
+
+    /// Broadcasts a new blob on the announce topic. Does nothing when announcing is disabled.
+    pub async fn announce(&self, announcement: &Announcement) -> Result<()> {
+        let Some(sender) = &self.sender else {
+            return Ok(());
+        };
+        let message = serde_json::to_vec(announcement)?;
+        sender.broadcast(Bytes::from(message)).await
+    }
+
+    async fn listen(self, mut receiver: GossipReceiver, providers: Providers) {
+        while let Some(event) = receiver.next().await {
+            let Ok(Event::Gossip(GossipEvent::Received(message))) = event else {
+                continue;
+            };
+            // Ignore anything on the topic that isn't an announcement
+            let Ok(announcement) = serde_json::from_slice::<Announcement>(&message.content) else {
+                continue;
+            };
+            if let Ok(ticket) = BlobTicket::from_str(&announcement.ticket) {
+                providers.record(
+                    ticket.hash(),
+                    ticket.format(),
+                    ticket.node_addr().clone(),
+                    Source::Announcement,
+                );
+            }
+            let received_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let heard = HeardAnnouncement {
+                announcement,
+                delivered_from: message.delivered_from.to_string(),
+                received_at,
+            };
+            let mut all_heard = self.heard.write().unwrap();
+            all_heard.insert(heard.announcement.blob_hash.clone(), heard);
+            if all_heard.len() > MAX_HEARD {
+                let oldest = all_heard
+                    .iter()
+                    .min_by_key(|(_, heard)| heard.received_at)
+                    .map(|(hash, _)| hash.clone());
+                if let Some(oldest) = oldest {
+                    all_heard.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Announcements heard from peers, most recent first.
+    pub fn heard(&self) -> Vec<HeardAnnouncement> {
+        let mut heard: Vec<_> = self.heard.read().unwrap().values().cloned().collect();
+        heard.sort_by_key(|heard| std::cmp::Reverse(heard.received_at));
+        heard
+    }
+}
+
+pub async fn list_announcements(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.announcer.heard())
+}
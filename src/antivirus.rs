@@ -0,0 +1,188 @@
+use anyhow::{bail, Result};
+use axum::http::StatusCode;
+use chrono::Utc;
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::{AntivirusConfig, InfectedAction};
+use crate::persist::StateFile;
+
+/// Size of the chunks uploads are streamed to clamd in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanResult {
+    Clean,
+    Infected,
+}
+
+/// What a scan found, as kept for `GET /blob/{hash}/info`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Verdict {
+    pub result: ScanResult,
+    /// The signature clamd matched, for infected content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Whether infected content was kept in `data/quarantine`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quarantined: bool,
+    pub scanned_at: i64,
+}
+
+/// Sends `data` to clamd with `INSTREAM` and returns its reply.
+async fn instream(mut stream: impl AsyncRead + AsyncWrite + Unpin, data: &[u8]) -> Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string())
+}
+
+/// Scans uploads with ClamAV before they are admitted to the store.
+///
+/// Verdicts are kept by hash and written to disk, so they are still known after a
+/// restart.
+#[derive(Clone)]
+pub struct Antivirus {
+    config: AntivirusConfig,
+    quarantine: PathBuf,
+    verdicts: Arc<RwLock<BTreeMap<String, Verdict>>>,
+    file: StateFile,
+}
+
+impl Antivirus {
+    pub fn load(config: &AntivirusConfig, data_dir: impl Into<PathBuf>) -> Result<Self> {
+        let data_dir = data_dir.into();
+        let path = data_dir.join("antivirus.json");
+        let verdicts = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        let verdicts = Arc::new(RwLock::new(verdicts));
+        let file = StateFile::spawn(path, {
+            let verdicts = verdicts.clone();
+            move || Ok(serde_json::to_vec_pretty(&*verdicts.read().unwrap())?)
+        });
+        Ok(Self {
+            config: config.clone(),
+            quarantine: data_dir.join("quarantine"),
+            verdicts,
+            file,
+        })
+    }
+
+    pub fn verdict(&self, hash: &Hash) -> Option<Verdict> {
+        self.verdicts
+            .read()
+            .unwrap()
+            .get(&hash.to_string())
+            .cloned()
+    }
+
+    /// The signature clamd finds in `data`, or `None` when it is clean.
+    async fn scan(&self, clamd: &str, data: &[u8]) -> Result<Option<String>> {
+        #[cfg(unix)]
+        let reply = if clamd.contains('/') {
+            instream(tokio::net::UnixStream::connect(clamd).await?, data).await?
+        } else {
+            instream(tokio::net::TcpStream::connect(clamd).await?, data).await?
+        };
+        #[cfg(not(unix))]
+        let reply = instream(tokio::net::TcpStream::connect(clamd).await?, data).await?;
+
+        // Replies look like "stream: OK" or "stream: Eicar-Signature FOUND"
+        let reply = reply.strip_prefix("stream:").unwrap_or(&reply).trim();
+        if reply == "OK" {
+            return Ok(None);
+        }
+        match reply.strip_suffix(" FOUND") {
+            Some(signature) => Ok(Some(signature.to_string())),
+            None => bail!("clamd: {}", reply),
+        }
+    }
+
+    /// Lets `data` into the store only when clamd finds it clean, answering infected
+    /// uploads with 422.
+    pub async fn check(&self, data: &[u8]) -> Result<(), StatusCode> {
+        let Some(clamd) = &self.config.clamd else {
+            return Ok(());
+        };
+        let hash = Hash::new(data);
+        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let signature = match tokio::time::timeout(timeout, self.scan(clamd, data)).await {
+            Ok(Ok(signature)) => signature,
+            Ok(Err(err)) if self.config.fail_open => {
+                println!("Storing {} unscanned: {}", hash, err);
+                return Ok(());
+            }
+            Err(_) if self.config.fail_open => {
+                println!("Storing {} unscanned: clamd timed out", hash);
+                return Ok(());
+            }
+            Ok(Err(err)) => {
+                println!("Failed to scan {}: {}", hash, err);
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            Err(_) => {
+                println!("Failed to scan {}: clamd timed out", hash);
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        };
+
+        let quarantined = signature.is_some() && self.config.action == InfectedAction::Quarantine;
+        if quarantined {
+            if let Err(err) = self.keep_in_quarantine(&hash, data).await {
+                println!("Failed to quarantine {}: {}", hash, err);
+            }
+        }
+        let verdict = Verdict {
+            result: if signature.is_some() {
+                ScanResult::Infected
+            } else {
+                ScanResult::Clean
+            },
+            signature: signature.clone(),
+            quarantined,
+            scanned_at: Utc::now().timestamp(),
+        };
+        self.record(hash, verdict);
+
+        match signature {
+            Some(signature) => {
+                println!("Rejected upload {}: {}", hash, signature);
+                Err(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn keep_in_quarantine(&self, hash: &Hash, data: &[u8]) -> Result<()> {
+        tokio::fs::create_dir_all(&self.quarantine).await?;
+        tokio::fs::write(self.quarantine.join(hash.to_string()), data).await?;
+        Ok(())
+    }
+
+    fn record(&self, hash: Hash, verdict: Verdict) {
+        self.verdicts
+            .write()
+            .unwrap()
+            .insert(hash.to_string(), verdict);
+        self.file.changed();
+    }
+}
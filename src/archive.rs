@@ -0,0 +1,922 @@
+use anyhow::{bail, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::BytesMut;
+use chrono::Utc;
+use futures::stream::BoxStream;
+use futures::{stream, StreamExt, TryStreamExt};
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::rpc::client::blobs::Batch;
+use iroh_blobs::rpc::proto::{Request, Response as RpcResponse};
+use iroh_blobs::store::fs::Store;
+use iroh_blobs::store::{EntryStatus, MapMut};
+use iroh_blobs::util::Tag;
+use iroh_blobs::TempTag;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use quic_rpc::transport::flume::FlumeConnector;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::audit::AuditEntry;
+use crate::holds::Hold;
+use crate::pins::Pin;
+use crate::providers::KnownBlob;
+use crate::snapshot;
+use crate::AppState;
+
+pub type MemBatch = Batch<FlumeConnector<RpcResponse, Request>>;
+
+/// Version of the archive layout, in the manifest.
+const ARCHIVE_VERSION: u32 = 1;
+
+/// The gateway's records carried along, by the file they go in.
+const CATALOG_FILES: &[&str] = &[
+    "pins.json",
+    "holds.json",
+    "s3.json",
+    "providers.json",
+    "audit.jsonl",
+];
+
+const BLOCK_SIZE: usize = 512;
+
+/// Bytes gathered before they are passed on, so tar headers don't go out one by one.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest entry besides blobs read from an archive, they are all held in memory.
+const MAX_RECORD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The first entry of an archive, `manifest.json`.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub node_id: String,
+    pub created_at: i64,
+    pub blobs: u64,
+    pub bytes: u64,
+}
+
+/// A tag of the store, in `tags.json`.
+#[derive(Serialize, Deserialize)]
+pub struct ArchivedTag {
+    pub name: String,
+    pub hash: Hash,
+    pub format: BlobFormat,
+}
+
+/// The tags of the store named by strings, which every tag the gateway makes is.
+pub async fn string_tags(blobs: &Blobs<Store>) -> Result<Vec<ArchivedTag>> {
+    let mut tags = Vec::new();
+    let mut list = blobs.client().tags().list().await?;
+    while let Some(tag) = list.next().await {
+        let tag = tag?;
+        let Ok(name) = String::from_utf8(tag.name.0.to_vec()) else {
+            continue;
+        };
+        tags.push(ArchivedTag {
+            name,
+            hash: tag.hash,
+            format: tag.format,
+        });
+    }
+    Ok(tags)
+}
+
+/// Sets tags through a batch, which keeps content added to it until the tags are in
+/// place.
+///
+/// Every tag is set through a temp tag of its own. One more is held for the content
+/// until the end, so releasing those can't let the content go before its tag is set.
+pub struct Tagger {
+    batch: MemBatch,
+    held: HashMap<HashAndFormat, TempTag>,
+}
+
+impl Tagger {
+    pub async fn new(blobs: &Blobs<Store>) -> Result<Self> {
+        Ok(Self {
+            batch: blobs.client().batch().await?,
+            held: HashMap::new(),
+        })
+    }
+
+    pub fn batch(&self) -> &MemBatch {
+        &self.batch
+    }
+
+    /// Keeps content added through the batch.
+    pub fn keep(&mut self, temp_tag: TempTag) {
+        self.held.insert(temp_tag.hash_and_format(), temp_tag);
+    }
+
+    pub async fn set(&mut self, name: &str, content: HashAndFormat) -> Result<()> {
+        if let Entry::Vacant(entry) = self.held.entry(content) {
+            entry.insert(self.batch.temp_tag(content).await?);
+        }
+        let temp_tag = self.batch.temp_tag(content).await?;
+        self.batch.persist_to(temp_tag, Tag::from(name)).await?;
+        Ok(())
+    }
+}
+
+/// A tar header for a regular file. Sizes too large for the octal field are written in
+/// the base-256 form GNU tar introduced, which other tars read as well.
+fn tar_header(path: &str, size: u64, mtime: u64) -> Result<[u8; BLOCK_SIZE]> {
+    if path.len() > 100 {
+        bail!("{} is too long for a tar entry", path);
+    }
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..path.len()].copy_from_slice(path.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    if size < 1 << 33 {
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    } else {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is taken with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+/// Where an archive goes: the response body, or a file on the server.
+enum Sink {
+    Body(mpsc::Sender<io::Result<Bytes>>),
+    File(tokio::io::BufWriter<tokio::fs::File>),
+}
+
+struct TarWriter {
+    sink: Sink,
+    buffer: Vec<u8>,
+    mtime: u64,
+}
+
+impl TarWriter {
+    fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            buffer: Vec::new(),
+            mtime: Utc::now().timestamp().max(0) as u64,
+        }
+    }
+
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        match &mut self.sink {
+            Sink::Body(sender) => sender
+                .send(Ok(chunk))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away")),
+            Sink::File(file) => file.write_all(&chunk).await,
+        }
+    }
+
+    async fn pad(&mut self, size: u64) -> io::Result<()> {
+        let padding = (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE;
+        self.write(&[0; BLOCK_SIZE][..padding]).await
+    }
+
+    async fn append(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.write(&tar_header(path, data.len() as u64, self.mtime)?)
+            .await?;
+        self.write(data).await?;
+        self.pad(data.len() as u64).await?;
+        Ok(())
+    }
+
+    /// Appends a stored blob, which has to be complete. Blobs deleted since they were
+    /// listed are left out.
+    async fn append_blob(&mut self, app_state: &AppState, hash: Hash, size: u64) -> Result<()> {
+        let mut reader = match app_state.blobs.client().read(hash).await {
+            Ok(reader) => reader,
+            Err(err) => {
+                println!("Leaving {} out of the export: {}", hash, err);
+                return Ok(());
+            }
+        };
+        let path = format!("blobs/{}", hash.to_hex());
+        self.write(&tar_header(&path, size, self.mtime)?).await?;
+        let mut written = 0;
+        while let Some(chunk) = reader.next().await {
+            let chunk = chunk?;
+            written += chunk.len() as u64;
+            if written > size {
+                bail!("{} is larger than listed", hash);
+            }
+            self.write(&chunk).await?;
+        }
+        if written != size {
+            bail!("{} is smaller than listed", hash);
+        }
+        self.pad(size).await?;
+        Ok(())
+    }
+
+    /// Ends the archive with the two empty blocks tar expects.
+    async fn finish(mut self) -> Result<()> {
+        self.write(&[0; 2 * BLOCK_SIZE]).await?;
+        self.flush().await?;
+        if let Sink::File(file) = &mut self.sink {
+            file.flush().await?;
+            file.get_ref().sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes every complete blob of the store into a tar archive, after a manifest, the
+/// tags and the gateway's records of pins, holds, S3 objects, providers and the audit
+/// log. Tenant uploads and trash are kept in tags and come along with them.
+async fn export(app_state: &AppState, mut tar: TarWriter) -> Result<Manifest> {
+    let mut blobs = Vec::new();
+    let mut list = app_state.blobs.client().list().await?;
+    while let Some(blob) = list.next().await {
+        let blob = blob?;
+        blobs.push((blob.hash, blob.size));
+    }
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        node_id: app_state.node_id.to_string(),
+        created_at: Utc::now().timestamp(),
+        blobs: blobs.len() as u64,
+        bytes: blobs.iter().map(|(_, size)| size).sum(),
+    };
+    tar.append("manifest.json", &serde_json::to_vec_pretty(&manifest)?)
+        .await?;
+
+    let tags = string_tags(&app_state.blobs).await?;
+    tar.append("tags.json", &serde_json::to_vec_pretty(&tags)?)
+        .await?;
+
+    // Records come from memory, their files may not have caught up yet
+    let mut catalog = snapshot::records(app_state)?.to_vec();
+    catalog.push(("providers.json", app_state.providers.to_json()?));
+    catalog.push(("audit.jsonl", app_state.audit.to_jsonl().await?));
+    for (file, data) in catalog {
+        tar.append(&format!("catalog/{}", file), &data).await?;
+    }
+
+    for (hash, size) in blobs {
+        tar.append_blob(app_state, hash, size).await?;
+    }
+    tar.finish().await?;
+    Ok(manifest)
+}
+
+/// The file `name` in the archive directory. Only plain file names are taken, and
+/// `existing` files are resolved through symlinks and must stay inside the directory,
+/// so clients reach nothing else on the server. 403 without an archive directory.
+fn archive_path(dir: Option<&Path>, name: &str, existing: bool) -> Result<PathBuf, StatusCode> {
+    let dir = dir.ok_or(StatusCode::FORBIDDEN)?;
+    let mut components = Path::new(name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let path = dir.join(name);
+    if !existing {
+        return Ok(path);
+    }
+    let (Ok(dir), Ok(path)) = (dir.canonicalize(), path.canonicalize()) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if !path.starts_with(&dir) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(path)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ExportRequest {
+    /// Writes the archive to this file in the archive directory instead of sending it.
+    name: Option<String>,
+}
+
+/// `POST /admin/export-all`: the whole store as one tar archive, for moving a node to
+/// new hardware. With a `name` in the JSON body the archive is written to the archive
+/// directory by a job instead, and the answer is the job's id.
+pub async fn export_all(
+    State(app_state): State<AppState>,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    let request: ExportRequest = if body.is_empty() {
+        ExportRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?
+    };
+
+    if let Some(name) = request.name {
+        let path = archive_path(app_state.archives.dir.as_deref(), &name, false)?;
+        let state = app_state.clone();
+        let job_id = app_state.jobs.spawn("export", async move {
+            // Written next to the target first, so a failed export leaves no archive
+            // that looks whole
+            let mut partial = path.clone().into_os_string();
+            partial.push(".partial");
+            let file = tokio::fs::File::create(&partial).await?;
+            let tar = TarWriter::new(Sink::File(tokio::io::BufWriter::new(file)));
+            let manifest = match export(&state, tar).await {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    let _ = tokio::fs::remove_file(&partial).await;
+                    return Err(err);
+                }
+            };
+            tokio::fs::rename(&partial, &path).await?;
+            Ok(serde_json::json!({
+                "path": path,
+                "blobs": manifest.blobs,
+                "bytes": manifest.bytes,
+            }))
+        });
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({ "job_id": job_id })),
+        )
+            .into_response());
+    }
+
+    let (sender, receiver) = mpsc::channel(2);
+    let state = app_state.clone();
+    tokio::spawn(async move {
+        let tar = TarWriter::new(Sink::Body(sender.clone()));
+        // Failing the body cuts the response short, so the client can't take it as whole
+        if let Err(err) = export(&state, tar).await {
+            println!("Failed to export the store: {}", err);
+            let _ = sender.send(Err(io::Error::other(err))).await;
+        }
+    });
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let file_name = format!("iroh-api-{}.tar", app_state.node_id.fmt_short());
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        Body::from_stream(app_state.throttle.upload().stream(chunks)),
+    )
+        .into_response())
+}
+
+/// Returned for archives that can't be read, as opposed to failures of the store.
+#[derive(Debug)]
+pub struct InvalidArchive(String);
+
+impl fmt::Display for InvalidArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid archive: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidArchive {}
+
+fn invalid(reason: impl Into<String>) -> anyhow::Error {
+    InvalidArchive(reason.into()).into()
+}
+
+/// Parses a numeric header field, in octal or the base-256 form of [`tar_header`].
+fn tar_number(field: &[u8]) -> Result<u64> {
+    if field.first().is_some_and(|byte| byte & 0x80 != 0) {
+        let mut value = (field[0] & 0x7f) as u64;
+        for byte in &field[1..] {
+            value = value
+                .checked_mul(256)
+                .and_then(|value| value.checked_add(*byte as u64))
+                .ok_or_else(|| invalid("size out of range"))?;
+        }
+        return Ok(value);
+    }
+    let text = std::str::from_utf8(field).map_err(|_| invalid("malformed header"))?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("malformed header"))
+}
+
+/// An entry of an archive, whose data is read next.
+struct TarEntry {
+    path: String,
+    size: u64,
+    is_file: bool,
+}
+
+/// Reads a tar archive off a stream, one entry after another.
+struct TarReader {
+    stream: BoxStream<'static, io::Result<Bytes>>,
+    buffer: Bytes,
+    /// Data of the current entry not read yet, and the padding after it.
+    remaining: u64,
+    padding: u64,
+}
+
+impl TarReader {
+    fn new(stream: BoxStream<'static, io::Result<Bytes>>) -> Self {
+        Self {
+            stream,
+            buffer: Bytes::new(),
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// Up to `max` bytes, `None` once the stream ends.
+    async fn read(&mut self, max: u64) -> Result<Option<Bytes>> {
+        while self.buffer.is_empty() {
+            match self.stream.next().await {
+                Some(chunk) => self.buffer = chunk?,
+                None => return Ok(None),
+            }
+        }
+        let len = self.buffer.len().min(max as usize);
+        Ok(Some(self.buffer.split_to(len)))
+    }
+
+    async fn read_exact(&mut self, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(len as usize);
+        while (data.len() as u64) < len {
+            let chunk = self
+                .read(len - data.len() as u64)
+                .await?
+                .ok_or_else(|| invalid("ends in the middle of an entry"))?;
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    async fn skip(&mut self, mut len: u64) -> Result<()> {
+        while len > 0 {
+            let chunk = self
+                .read(len)
+                .await?
+                .ok_or_else(|| invalid("ends in the middle of an entry"))?;
+            len -= chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// The next entry, skipping what is left of the current one. `None` at the end of
+    /// the archive.
+    async fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        self.skip(self.remaining + self.padding).await?;
+        self.remaining = 0;
+        self.padding = 0;
+        let header = match self.read(BLOCK_SIZE as u64).await? {
+            Some(first) => {
+                let mut header = first.to_vec();
+                header.extend(self.read_exact((BLOCK_SIZE - header.len()) as u64).await?);
+                header
+            }
+            None => return Err(invalid("ends without the closing blocks")),
+        };
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        let checksum = tar_number(&header[148..156])?;
+        let sum: u64 = header[..148]
+            .iter()
+            .chain(&[b' '; 8])
+            .chain(&header[156..])
+            .map(|byte| *byte as u64)
+            .sum();
+        if checksum != sum {
+            return Err(invalid("header checksum mismatch"));
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let end = field
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+        let mut path = field(0..100);
+        // ustar keeps the start of long paths in a prefix field
+        if &header[257..262] == b"ustar" {
+            let prefix = field(345..500);
+            if !prefix.is_empty() {
+                path = format!("{}/{}", prefix, path);
+            }
+        }
+        let size = tar_number(&header[124..136])?;
+        self.remaining = size;
+        self.padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+        Ok(Some(TarEntry {
+            path,
+            size,
+            is_file: matches!(header[156], b'0' | 0),
+        }))
+    }
+
+    /// The next chunk of the current entry's data, `None` once it is all read.
+    async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let chunk = self
+            .read(self.remaining)
+            .await?
+            .ok_or_else(|| invalid("ends in the middle of an entry"))?;
+        self.remaining -= chunk.len() as u64;
+        Ok(Some(chunk))
+    }
+
+    /// The whole data of the current entry, for the small ones besides blobs.
+    async fn data(&mut self) -> Result<Vec<u8>> {
+        if self.remaining > MAX_RECORD_SIZE {
+            return Err(invalid("record too large"));
+        }
+        let data = self.read_exact(self.remaining).await?;
+        self.remaining = 0;
+        Ok(data)
+    }
+}
+
+/// A name that points to other content here than in the archive. The one here is kept.
+#[derive(Serialize)]
+pub struct Conflict {
+    /// `tag`.
+    kind: &'static str,
+    name: String,
+    local: Hash,
+    archived: Hash,
+}
+
+/// Something of the archive left out because its blob is in neither the archive nor
+/// the store.
+#[derive(Serialize)]
+pub struct MissingContent {
+    /// `tag`, `pin`, `hold` or `s3`.
+    kind: &'static str,
+    /// The tag, or the bucket and key of an S3 object.
+    name: Option<String>,
+    hash: Hash,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    /// The node the archive was exported from.
+    node_id: String,
+    blobs_added: u64,
+    bytes_added: u64,
+    /// Blobs of the archive the store had already.
+    blobs_present: u64,
+    /// Blobs whose data doesn't match their hash, which were left out.
+    corrupt: Vec<Hash>,
+    tags_added: u64,
+    /// Pins, holds, S3 objects, providers and audit log entries added.
+    records_added: u64,
+    conflicts: Vec<Conflict>,
+    missing: Vec<MissingContent>,
+}
+
+/// Merges an archive made by [`export`] into the store. Blobs the store has already
+/// are skipped, and every other one is checked against its hash. Tags and records
+/// only fill in what isn't here yet: a name taken here keeps pointing where it does,
+/// and is reported as a conflict.
+async fn import(app_state: &AppState, mut tar: TarReader) -> Result<ImportReport> {
+    let manifest: Manifest = match tar.next_entry().await? {
+        Some(entry) if entry.path == "manifest.json" => serde_json::from_slice(&tar.data().await?)
+            .map_err(|err| invalid(format!("manifest: {}", err)))?,
+        _ => return Err(invalid("no manifest, not made by export-all?")),
+    };
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(invalid(format!(
+            "made by a newer gateway, version {}",
+            manifest.version
+        )));
+    }
+    let mut report = ImportReport {
+        node_id: manifest.node_id,
+        ..Default::default()
+    };
+
+    let mut tagger = Tagger::new(&app_state.blobs).await?;
+    let mut tags: Vec<ArchivedTag> = Vec::new();
+    let mut catalog: HashMap<String, Vec<u8>> = HashMap::new();
+    while let Some(entry) = tar.next_entry().await? {
+        if !entry.is_file {
+            continue;
+        }
+        if entry.path == "tags.json" {
+            tags = serde_json::from_slice(&tar.data().await?)
+                .map_err(|err| invalid(format!("tags: {}", err)))?;
+        } else if let Some(file) = entry.path.strip_prefix("catalog/") {
+            if CATALOG_FILES.contains(&file) {
+                catalog.insert(file.to_string(), tar.data().await?);
+            }
+        } else if let Some(hex) = entry.path.strip_prefix("blobs/") {
+            let hash = Hash::from_str(hex).map_err(|_| invalid(format!("blob {}", hex)))?;
+            if app_state.blobs.store().entry_status(&hash).await? == EntryStatus::Complete {
+                report.blobs_present += 1;
+                continue;
+            }
+            let temp_tag = import_blob(tagger.batch(), &mut tar).await?;
+            if *temp_tag.hash() == hash {
+                report.blobs_added += 1;
+                report.bytes_added += entry.size;
+                tagger.keep(temp_tag);
+            } else {
+                println!("Left {} out of the import, its data doesn't match", hash);
+                report.corrupt.push(hash);
+            }
+        }
+    }
+
+    let stored: HashMap<Hash, u64> = app_state
+        .blobs
+        .client()
+        .list()
+        .await?
+        .map_ok(|blob| (blob.hash, blob.size))
+        .try_collect()
+        .await?;
+    let local: HashMap<String, Hash> = string_tags(&app_state.blobs)
+        .await?
+        .into_iter()
+        .map(|tag| (tag.name, tag.hash))
+        .collect();
+    for tag in tags {
+        match local.get(&tag.name) {
+            Some(hash) if *hash == tag.hash => {}
+            Some(hash) => report.conflicts.push(Conflict {
+                kind: "tag",
+                name: tag.name,
+                local: *hash,
+                archived: tag.hash,
+            }),
+            None => match stored.get(&tag.hash) {
+                Some(size) => {
+                    let content = HashAndFormat {
+                        hash: tag.hash,
+                        format: tag.format,
+                    };
+                    tagger.set(&tag.name, content).await?;
+                    app_state.tenancy.adopt(&tag.name, tag.hash, *size);
+                    app_state.aliases.adopt(&tag.name, content);
+                    report.tags_added += 1;
+                }
+                None => report.missing.push(MissingContent {
+                    kind: "tag",
+                    name: Some(tag.name),
+                    hash: tag.hash,
+                }),
+            },
+        }
+    }
+    drop(tagger);
+
+    merge_catalog(app_state, catalog, &stored, &mut report).await?;
+    println!(
+        "Imported {} blobs, {} tags and {} records from an archive of {}, {} conflicts",
+        report.blobs_added,
+        report.tags_added,
+        report.records_added,
+        report.node_id,
+        report.conflicts.len()
+    );
+    Ok(report)
+}
+
+/// Adds the data of the current entry to the store, kept by a temp tag of the batch.
+async fn import_blob(batch: &MemBatch, tar: &mut TarReader) -> Result<TempTag> {
+    let (sender, receiver) = mpsc::channel(2);
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let feed = async move {
+        loop {
+            match tar.chunk().await {
+                Ok(Some(chunk)) => {
+                    if sender.send(Ok(chunk)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    // Failing the stream keeps the store from taking the blob as whole
+                    let _ = sender.send(Err(io::Error::other(err.to_string()))).await;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    };
+    let (added, fed) = tokio::join!(batch.add_stream(Box::pin(chunks)), feed);
+    fed?;
+    added
+}
+
+async fn merge_catalog(
+    app_state: &AppState,
+    catalog: HashMap<String, Vec<u8>>,
+    stored: &HashMap<Hash, u64>,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let parse_error = |file: &str, err: serde_json::Error| invalid(format!("{}: {}", file, err));
+    if let Some(data) = catalog.get("pins.json") {
+        let pins: Vec<Pin> =
+            serde_json::from_slice(data).map_err(|err| parse_error("pins", err))?;
+        for pin in pins {
+            if !stored.contains_key(&pin.hash) {
+                report.missing.push(MissingContent {
+                    kind: "pin",
+                    name: None,
+                    hash: pin.hash,
+                });
+            } else if app_state.pins.adopt(pin) {
+                report.records_added += 1;
+            }
+        }
+    }
+    if let Some(data) = catalog.get("holds.json") {
+        let holds: Vec<Hold> =
+            serde_json::from_slice(data).map_err(|err| parse_error("holds", err))?;
+        for hold in holds {
+            if !stored.contains_key(&hold.hash) {
+                report.missing.push(MissingContent {
+                    kind: "hold",
+                    name: None,
+                    hash: hold.hash,
+                });
+            } else if app_state.holds.adopt(hold) {
+                report.records_added += 1;
+            }
+        }
+    }
+    if let Some(data) = catalog.get("s3.json") {
+        let merged = app_state
+            .buckets
+            .merge(data, |hash| stored.contains_key(hash))?;
+        report.records_added += merged.added as u64;
+        for (name, hash) in merged.missing {
+            report.missing.push(MissingContent {
+                kind: "s3",
+                name: Some(name),
+                hash,
+            });
+        }
+    }
+    if let Some(data) = catalog.get("providers.json") {
+        let known: Vec<KnownBlob> =
+            serde_json::from_slice(data).map_err(|err| parse_error("providers", err))?;
+        for blob in known {
+            report.records_added += app_state.providers.merge(blob) as u64;
+        }
+    }
+    if let Some(data) = catalog.get("audit.jsonl") {
+        let entries = String::from_utf8_lossy(data)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<AuditEntry>, _>>()
+            .map_err(|err| parse_error("audit log", err))?;
+        report.records_added += app_state.audit.merge(entries).await? as u64;
+    }
+    Ok(())
+}
+
+/// A file on the server as a stream of chunks.
+fn read_file(file: tokio::fs::File) -> BoxStream<'static, io::Result<Bytes>> {
+    stream::try_unfold(file, |mut file| async move {
+        let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+        match file.read_buf(&mut chunk).await? {
+            0 => Ok(None),
+            _ => Ok(Some((chunk.freeze(), file))),
+        }
+    })
+    .boxed()
+}
+
+fn import_error(err: anyhow::Error) -> StatusCode {
+    println!("Failed to import an archive: {}", err);
+    if err.downcast_ref::<InvalidArchive>().is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// `POST /admin/import-all`: merges an archive of `POST /admin/export-all` into the
+/// store, sent as the body, and answers with what was imported and what conflicted.
+/// With a JSON body giving the `name` of an archive in the archive directory instead,
+/// a job reads it from there and the report is its result.
+pub async fn import_all(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if is_json {
+        let body = axum::body::to_bytes(body, MAX_RECORD_SIZE as usize)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let request: ImportRequest =
+            serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let path = archive_path(app_state.archives.dir.as_deref(), &request.name, true)?;
+        let state = app_state.clone();
+        let job_id = app_state.jobs.spawn("import", async move {
+            let file = tokio::fs::File::open(&path).await?;
+            let report = import(&state, TarReader::new(read_file(file))).await?;
+            Ok(serde_json::to_value(report)?)
+        });
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({ "job_id": job_id })),
+        )
+            .into_response());
+    }
+
+    let chunks = app_state
+        .throttle
+        .download()
+        .stream(body.into_data_stream())
+        .map_err(io::Error::other)
+        .boxed();
+    let report = import(&app_state, TarReader::new(chunks))
+        .await
+        .map_err(import_error)?;
+    Ok(Json(report).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    /// An archive in the archive directory.
+    name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_are_named_inside_their_directory() {
+        let dir = std::env::temp_dir().join(format!("iroh-api-test-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("node.tar"), b"").unwrap();
+        let outside = dir.with_extension("outside");
+        std::fs::write(&outside, b"").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, dir.join("link.tar")).unwrap();
+
+        let path = |name: &str, existing: bool| archive_path(Some(&dir), name, existing);
+        assert_eq!(path("node.tar", false), Ok(dir.join("node.tar")));
+        assert_eq!(
+            path("node.tar", true),
+            Ok(dir.canonicalize().unwrap().join("node.tar"))
+        );
+        for name in ["", ".", "..", "../node.tar", "sub/node.tar", "/etc/passwd"] {
+            assert_eq!(path(name, false), Err(StatusCode::BAD_REQUEST), "{}", name);
+            assert!(path(name, true).is_err(), "{}", name);
+        }
+        assert_eq!(path("missing.tar", true), Err(StatusCode::NOT_FOUND));
+        #[cfg(unix)]
+        assert_eq!(path("link.tar", true), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            archive_path(None, "node.tar", false),
+            Err(StatusCode::FORBIDDEN)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&outside).unwrap();
+    }
+}
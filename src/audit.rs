@@ -0,0 +1,145 @@
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::blob::parse_hash;
+use crate::AppState;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub action: String,
+    pub hash: Hash,
+    /// Id of the API key used, see [`crate::metering::key_id`].
+    pub key: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Append-only record of actions on blobs that have to be accounted for later, one JSON
+/// object per line.
+#[derive(Clone)]
+pub struct Audit {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Audit {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        action: &str,
+        hash: Hash,
+        key: Option<String>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            action: action.to_string(),
+            hash,
+            key,
+            reason,
+        };
+        let _lock = self.lock.lock().await;
+        self.append(&[entry]).await
+    }
+
+    /// Appends the entries of another gateway's log that this one doesn't have yet,
+    /// returning how many there were.
+    pub async fn merge(&self, entries: Vec<AuditEntry>) -> Result<usize> {
+        let _lock = self.lock.lock().await;
+        let known = self.read().await?;
+        let new: Vec<AuditEntry> = entries
+            .into_iter()
+            .filter(|entry| !known.contains(entry))
+            .collect();
+        if !new.is_empty() {
+            self.append(&new).await?;
+        }
+        Ok(new.len())
+    }
+
+    async fn append(&self, entries: &[AuditEntry]) -> Result<()> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    /// The log as it is on disk, without an append in progress.
+    pub async fn to_jsonl(&self) -> Result<Vec<u8>> {
+        let _lock = self.lock.lock().await;
+        match tokio::fs::read(&self.path).await {
+            Ok(log) => Ok(log),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn entries(&self) -> Result<Vec<AuditEntry>> {
+        let _lock = self.lock.lock().await;
+        self.read().await
+    }
+
+    async fn read(&self) -> Result<Vec<AuditEntry>> {
+        let text = match tokio::fs::read_to_string(&self.path).await {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditParams {
+    hash: Option<String>,
+}
+
+/// `GET /audit`: the audit log, oldest first, or just the entries of `?hash=`.
+pub async fn list_audit(
+    State(app_state): State<AppState>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    let hash = params.hash.as_deref().map(parse_hash).transpose()?;
+    let entries = app_state.audit.entries().await.map_err(|err| {
+        println!("Failed to read the audit log: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(
+        entries
+            .into_iter()
+            .filter(|entry| hash.is_none_or(|hash| entry.hash == hash))
+            .collect(),
+    ))
+}
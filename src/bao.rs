@@ -0,0 +1,202 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bao_tree::io::fsm::{encode_ranges_validated, Outboard};
+use bao_tree::io::EncodeError;
+use bao_tree::{BaoTree, ChunkNum, ChunkRanges};
+use futures::stream;
+use iroh_blobs::store::{Map, MapEntry};
+use iroh_blobs::{Hash, IROH_BLOCK_SIZE};
+use iroh_io::AsyncStreamWriter;
+use serde::Deserialize;
+use std::io;
+use tokio::sync::mpsc;
+
+use crate::blob::{etag, etag_matches, make_local, not_modified, parse_hash};
+use crate::AppState;
+
+/// Content type of bao-encoded responses.
+const CONTENT_TYPE: &str = "application/x-bao";
+
+/// Bytes of the encoding gathered before they are passed on to the client, so parent
+/// nodes don't go out 64 bytes at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hands the encoding over to the response body.
+struct BodyWriter {
+    buffer: Vec<u8>,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl BodyWriter {
+    async fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.sender
+            .send(Ok(chunk))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl AsyncStreamWriter for BodyWriter {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_bytes(&mut self, data: Bytes) -> io::Result<()> {
+        self.write(&data).await
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        self.flush().await
+    }
+}
+
+type Entry = <iroh_blobs::store::fs::Store as Map>::Entry;
+
+/// A blob stored here in full, the only kind that can be encoded.
+async fn complete_entry(app_state: &AppState, hash: Hash) -> Result<Entry, StatusCode> {
+    app_state
+        .blobs
+        .store()
+        .get(&hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|entry| entry.is_complete())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Streams the bao encoding of `ranges` of a blob, the way iroh sends it to peers: the
+/// blob size as 8 bytes little endian, then the parent hashes and 16 KiB chunk groups
+/// covering the ranges in pre-order.
+fn encode(
+    app_state: &AppState,
+    hash: Hash,
+    entry: Entry,
+    ranges: ChunkRanges,
+) -> Result<Body, StatusCode> {
+    let outboard = entry
+        .outboard()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let data = entry.data_reader();
+    let size = outboard.tree().size();
+
+    let (sender, receiver) = mpsc::channel(2);
+    tokio::spawn(async move {
+        let mut writer = BodyWriter {
+            buffer: size.to_le_bytes().to_vec(),
+            sender: sender.clone(),
+        };
+        let result = match encode_ranges_validated(data, outboard, &ranges, &mut writer).await {
+            Ok(()) => writer.flush().await,
+            Err(err) => {
+                if matches!(
+                    err,
+                    EncodeError::ParentHashMismatch(_) | EncodeError::LeafHashMismatch(_)
+                ) {
+                    println!("Stored data of {} is corrupt: {}", hash, err);
+                }
+                Err(io::Error::other(err))
+            }
+        };
+        // Failing the body cuts the response short, so the client can't take it as whole
+        if let Err(err) = result {
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Ok(Body::from_stream(
+        app_state.throttle.upload().stream(chunks),
+    ))
+}
+
+/// The whole blob bao-encoded, so browser and WASM clients can check every chunk group
+/// against the hash as it arrives instead of trusting the gateway.
+pub async fn download_verified(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&hash)) == Some(true) {
+        return Ok(not_modified(&hash));
+    }
+    if let Some(redirect) = make_local(&app_state, hash, "/bao").await? {
+        return Ok(redirect.into_response());
+    }
+
+    let entry = complete_entry(&app_state, hash).await?;
+    let size = entry.size().value();
+    let body = encode(&app_state, hash, entry, ChunkRanges::all())?;
+    let encoded_len = 8 + size + BaoTree::new(size, IROH_BLOCK_SIZE).outboard_size();
+    Ok((
+        [
+            (header::CONTENT_TYPE, CONTENT_TYPE.to_string()),
+            (header::CONTENT_LENGTH, encoded_len.to_string()),
+            (header::ETAG, etag(&hash)),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SliceQuery {
+    offset: u64,
+    len: u64,
+}
+
+/// The bao encoding of just the 1 KiB chunks overlapping `len` bytes at `offset`, with
+/// the hashes proving they belong to the hash. For verified seeking and sparse reads.
+pub async fn download_slice(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    Query(query): Query<SliceQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if query.len == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&hash)) == Some(true) {
+        return Ok(not_modified(&hash));
+    }
+    let route = format!("/slice?offset={}&len={}", query.offset, query.len);
+    if let Some(redirect) = make_local(&app_state, hash, &route).await? {
+        return Ok(redirect.into_response());
+    }
+
+    let entry = complete_entry(&app_state, hash).await?;
+    let size = entry.size().value();
+    if query.offset >= size {
+        return Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+        )
+            .into_response());
+    }
+    let end = query.offset.saturating_add(query.len).min(size);
+    let ranges = ChunkRanges::from(ChunkNum::full_chunks(query.offset)..ChunkNum::chunks(end));
+    let body = encode(&app_state, hash, entry, ranges)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, CONTENT_TYPE.to_string()),
+            (header::ETAG, etag(&hash)),
+        ],
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,215 @@
+use anyhow::{bail, Context, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use iroh::Endpoint;
+use iroh_blobs::{net_protocol::Blobs, store::fs::Store, util::local_pool::LocalPool, Hash};
+use rand::RngCore;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::blob::parse_hash;
+
+const DEFAULT_SIZES: &str = "4K,1M,64M";
+const DEFAULT_COUNT: usize = 8;
+const DEFAULT_DIR: &str = "bench-data";
+
+const USAGE: &str = "usage: iroh-api bench [--sizes 4K,1M,64M] [--count 8] [--dir bench-data]";
+
+struct Options {
+    sizes: Vec<usize>,
+    count: usize,
+    /// Where the store is created, and removed again. On the disk the gateway's data
+    /// is on, to measure that one.
+    dir: PathBuf,
+}
+
+/// A size like `4096`, `4K`, `1M` or `1G`, in powers of 1024.
+fn parse_size(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((at, _)) => size.split_at(at),
+        None => (size, ""),
+    };
+    let unit: usize = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => bail!("unknown size {}", size),
+    };
+    let size = digits
+        .parse::<usize>()
+        .with_context(|| format!("unknown size {}", size))?
+        * unit;
+    if size == 0 {
+        bail!("sizes must be more than 0 bytes");
+    }
+    Ok(size)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut sizes = DEFAULT_SIZES.to_string();
+    let mut count = DEFAULT_COUNT;
+    let mut dir = PathBuf::from(DEFAULT_DIR);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--sizes" => sizes = value()?,
+            "--count" => count = value()?.parse().context("--count takes a number")?,
+            "--dir" => dir = value()?.into(),
+            _ => bail!("{}\nunknown argument {}", USAGE, arg),
+        }
+    }
+    Ok(Options {
+        sizes: sizes.split(',').map(parse_size).collect::<Result<_>>()?,
+        count: count.max(1),
+        dir,
+    })
+}
+
+fn format_size(size: usize) -> String {
+    match size {
+        size if size >= 1 << 30 && size % (1 << 30) == 0 => format!("{} GiB", size >> 30),
+        size if size >= 1 << 20 && size % (1 << 20) == 0 => format!("{} MiB", size >> 20),
+        size if size >= 1 << 10 && size % (1 << 10) == 0 => format!("{} KiB", size >> 10),
+        size => format!("{} B", size),
+    }
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> String {
+    format!(
+        "{:.1}",
+        bytes as f64 / (1 << 20) as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    )
+}
+
+/// Serves blobs from the store the way `GET /blob/<hash>` streams them.
+async fn serve_blob(
+    State(blobs): State<Blobs<Store>>,
+    Path(hash): Path<String>,
+) -> Result<Body, StatusCode> {
+    let reader = blobs
+        .client()
+        .read(parse_hash(&hash)?)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Body::from_stream(reader))
+}
+
+/// Times each step for `count` blobs of `size` random bytes, so none of them dedupe.
+async fn bench_size(
+    blobs: &Blobs<Store>,
+    http: &reqwest::Client,
+    base_url: &str,
+    size: usize,
+    count: usize,
+) -> Result<[Duration; 4]> {
+    let mut elapsed = [Duration::ZERO; 4];
+    let mut data = vec![0u8; size];
+    for _ in 0..count {
+        rand::thread_rng().fill_bytes(&mut data);
+        let bytes = Bytes::copy_from_slice(&data);
+
+        let started = Instant::now();
+        let hash = Hash::new(&bytes);
+        elapsed[0] += started.elapsed();
+
+        let started = Instant::now();
+        blobs.client().add_bytes(bytes).await?;
+        elapsed[1] += started.elapsed();
+
+        let started = Instant::now();
+        let read = blobs.client().read_to_bytes(hash).await?;
+        elapsed[2] += started.elapsed();
+
+        let started = Instant::now();
+        let served = http
+            .get(format!("{}/{}", base_url, hash))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        elapsed[3] += started.elapsed();
+
+        if read.len() != size || served.len() != size {
+            bail!("{} came back with the wrong size", hash);
+        }
+    }
+    Ok(elapsed)
+}
+
+/// `iroh-api bench`: ingests and reads back random blobs in a store of its own and
+/// prints the throughput of hashing, store writes and reads, and HTTP downloads, to size
+/// hardware before deploying on it.
+pub async fn run(args: impl Iterator<Item = String>) -> Result<()> {
+    let options = parse_options(args)?;
+    if options.dir.exists() {
+        bail!(
+            "{} already exists, pick another --dir",
+            options.dir.display()
+        );
+    }
+
+    let endpoint = Endpoint::builder()
+        .relay_mode(iroh::RelayMode::Disabled)
+        .clear_discovery()
+        .bind()
+        .await?;
+    let local_pool = LocalPool::default();
+    let blobs = Blobs::persistent(&options.dir)
+        .await?
+        .build(&local_pool, &endpoint);
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let app = Router::new()
+        .route("/{hash}", get(serve_blob))
+        .with_state(blobs.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let http = reqwest::Client::new();
+
+    println!(
+        "Benchmarking {} blobs of each size in {}",
+        options.count,
+        options.dir.display()
+    );
+    println!(
+        "{:>10} {:>12} {:>12} {:>12} {:>12}",
+        "size", "hash MiB/s", "write MiB/s", "read MiB/s", "http MiB/s"
+    );
+    let mut result = Ok(());
+    for &size in &options.sizes {
+        match bench_size(&blobs, &http, &base_url, size, options.count).await {
+            Ok(elapsed) => {
+                let total = size * options.count;
+                println!(
+                    "{:>10} {:>12} {:>12} {:>12} {:>12}",
+                    format_size(size),
+                    throughput(total, elapsed[0]),
+                    throughput(total, elapsed[1]),
+                    throughput(total, elapsed[2]),
+                    throughput(total, elapsed[3]),
+                );
+            }
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    endpoint.close().await;
+    local_pool.shutdown().await;
+    std::fs::remove_dir_all(&options.dir)
+        .with_context(|| format!("failed to remove {}", options.dir.display()))?;
+    result
+}
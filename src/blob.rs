@@ -0,0 +1,261 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_blobs::{BlobFormat, Hash};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::tenancy::Tenant;
+use crate::AppState;
+
+/// Size of the chunks compressed downloads are streamed in, so they pace smoothly
+/// through the bandwidth cap.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The most blobs one `GET /blobs` page holds.
+const MAX_LIST_LIMIT: usize = 1000;
+
+pub fn parse_hash(hash: &str) -> Result<Hash, StatusCode> {
+    // Hash::from_str panics on base32 input of the wrong length
+    match hash.len() {
+        52 | 64 => Hash::from_str(hash).map_err(|_| StatusCode::BAD_REQUEST),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Entity tag of content, which is simply its hash.
+pub fn etag(hash: &Hash) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether the `If-Match` or `If-None-Match` header `name` lists `hash`, or `None` when
+/// the request has no such header. Tags are compared weakly, so compressed variants
+/// match too.
+pub fn etag_matches(headers: &HeaderMap, name: HeaderName, hash: Option<&Hash>) -> Option<bool> {
+    let value = headers.get(name)?.to_str().unwrap_or_default();
+    let hash = hash.map(Hash::to_string);
+    Some(value.split(',').any(|tag| {
+        let tag = tag.trim();
+        if tag == "*" {
+            return hash.is_some();
+        }
+        let tag = tag.strip_prefix("W/").unwrap_or(tag).trim_matches('"');
+        hash.as_deref() == Some(tag)
+    }))
+}
+
+/// A `304 Not Modified` for content the client already holds.
+pub fn not_modified(hash: &Hash) -> Response {
+    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag(hash))]).into_response()
+}
+
+/// Makes sure a blob is stored here and passes screening before `route` of it (like
+/// `/bao`) is served.
+///
+/// Blobs owned by another cluster member are served by redirecting there, or by
+/// fetching them from the owner when it doesn't advertise an HTTP address.
+pub async fn make_local(
+    app_state: &AppState,
+    hash: Hash,
+    route: &str,
+) -> Result<Option<Redirect>, StatusCode> {
+    let has_blob = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if has_blob {
+        app_state.screening.check_serve(app_state, hash).await?;
+        app_state.plugins.check_download(hash).await?;
+        return Ok(None);
+    }
+
+    let owner = app_state
+        .cluster
+        .remote_owner(&hash)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if let Some(url) = app_state.cluster.url_of(&owner) {
+        let location = format!("{}/blob/{}{}", url.trim_end_matches('/'), hash, route);
+        return Ok(Some(Redirect::temporary(&location)));
+    }
+    app_state
+        .fetcher
+        .fetch(hash, BlobFormat::Raw, vec![owner.into()])
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    app_state.screening.check_serve(app_state, hash).await?;
+    app_state.plugins.check_download(hash).await?;
+    Ok(None)
+}
+
+pub async fn download_blob(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    // Content never changes under a hash, so a matching tag is always current
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&hash)) == Some(true) {
+        let mut response = not_modified(&hash);
+        if app_state.compressor.is_enabled() {
+            response
+                .headers_mut()
+                .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        return Ok(response);
+    }
+    if let Some(redirect) = make_local(&app_state, hash, "").await? {
+        return Ok(redirect.into_response());
+    }
+
+    let blobs_client = app_state.blobs.client();
+    let reader = blobs_client
+        .read(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let size = reader.size();
+    let mut response = if let Some((encoding, data)) = app_state
+        .compressor
+        .encode(&app_state, hash, size, accept_encoding(&headers))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = (0..data.len())
+            .step_by(CHUNK_SIZE)
+            .map(|start| Ok(data.slice(start..data.len().min(start + CHUNK_SIZE))))
+            .collect();
+        (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::CONTENT_ENCODING, encoding.name().to_string()),
+                (header::ETAG, format!("W/{}", etag(&hash))),
+                (header::CONTENT_LENGTH, data.len().to_string()),
+            ],
+            Body::from_stream(app_state.throttle.upload().stream(stream::iter(chunks))),
+        )
+            .into_response()
+    } else {
+        (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::CONTENT_LENGTH, size.to_string()),
+                (header::ETAG, etag(&hash)),
+            ],
+            Body::from_stream(app_state.throttle.upload().stream(reader)),
+        )
+            .into_response()
+    };
+    if app_state.compressor.is_enabled() {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    Ok(response)
+}
+
+fn accept_encoding(headers: &HeaderMap) -> &str {
+    headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+pub async fn blob_info(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let status = app_state
+        .blobs
+        .client()
+        .status(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let (complete, size) = match status {
+        BlobStatus::NotFound => return Err(StatusCode::NOT_FOUND),
+        BlobStatus::Partial { size } => (false, size.value()),
+        BlobStatus::Complete { size } => (true, size),
+    };
+
+    Ok(Json(serde_json::json!({
+        "hash": hash.to_string(),
+        "cid": crate::cid::cid(&hash),
+        "multihash": crate::cid::multihash(&hash),
+        "complete": complete,
+        "size": size,
+        "replication": app_state.replicator.status(&hash),
+        "owner": app_state.cluster.owner(&hash).map(|owner| owner.to_string()),
+        "scan": app_state.antivirus.verdict(&hash),
+        "pin": app_state.pins.get(&hash).map(|pin| pin.seen_by(&tenant)),
+        "hold": app_state.holds.get(&hash),
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct BlobEntry {
+    hash: String,
+    size: u64,
+    ticket: String,
+}
+
+/// `GET /blobs`: complete blobs in the store, 100 at a time unless `limit` says
+/// otherwise.
+pub async fn list_blobs(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Query(params): Query<ListParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let node_id = app_state.node_id;
+    let entry = |hash: Hash, size| BlobEntry {
+        hash: hash.to_string(),
+        size,
+        ticket: BlobTicket::new(node_id.into(), hash, BlobFormat::Raw)
+            .map(|ticket| ticket.to_string())
+            .unwrap_or_default(),
+    };
+    let limit = params.limit.unwrap_or(100).min(MAX_LIST_LIMIT);
+
+    // Tenants only see their own uploads
+    if let Some(name) = &tenant.0 {
+        let blobs: Vec<BlobEntry> = app_state
+            .tenancy
+            .blobs(name)
+            .into_iter()
+            .skip(params.offset)
+            .take(limit)
+            .map(|(hash, size)| entry(hash, size))
+            .collect();
+        return Ok(Json(blobs));
+    }
+
+    let blobs: Vec<BlobEntry> = app_state
+        .blobs
+        .client()
+        .list()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .skip(params.offset)
+        .take(limit)
+        .map_ok(|blob| entry(blob.hash, blob.size))
+        .try_collect()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(blobs))
+}
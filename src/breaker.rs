@@ -0,0 +1,148 @@
+use axum::{extract::State, Json};
+use iroh::NodeId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::CircuitBreakerConfig;
+use crate::AppState;
+
+/// Returned instead of trying a node whose breaker is open.
+#[derive(Debug, Serialize, Clone)]
+pub struct CircuitOpen {
+    pub node_id: String,
+    pub failures: u32,
+    /// When the next attempt is let through.
+    pub retry_after_secs: u64,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "circuit open for {} after {} failures, retrying in {}s",
+            self.node_id, self.failures, self.retry_after_secs
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    /// Set while open, and again for every trial let through after `open_for`.
+    opened_at: Option<Instant>,
+}
+
+#[derive(Serialize)]
+pub struct BreakerState {
+    node_id: String,
+    failures: u32,
+    open: bool,
+    retry_after_secs: Option<u64>,
+}
+
+/// Failures of fetches from and pushes to each remote node.
+///
+/// Once a node failed `failure_threshold` times in a row it isn't tried for `open_secs`,
+/// so requests fail fast instead of waiting out timeouts. After that one attempt is let
+/// through at a time: success closes the breaker, failure keeps it open for another
+/// period.
+#[derive(Clone)]
+pub struct Breakers {
+    threshold: u32,
+    open_for: Duration,
+    nodes: Arc<Mutex<HashMap<NodeId, Breaker>>>,
+}
+
+impl fmt::Debug for Breakers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Breakers").finish_non_exhaustive()
+    }
+}
+
+impl Breakers {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            threshold: config.failure_threshold,
+            open_for: Duration::from_secs(config.open_secs),
+            nodes: Default::default(),
+        }
+    }
+
+    /// Whether `node` may be tried now.
+    pub fn check(&self, node: NodeId) -> Result<(), CircuitOpen> {
+        if self.threshold == 0 {
+            return Ok(());
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        let Some(breaker) = nodes.get_mut(&node) else {
+            return Ok(());
+        };
+        let Some(opened_at) = breaker.opened_at else {
+            return Ok(());
+        };
+        let elapsed = opened_at.elapsed();
+        if elapsed < self.open_for {
+            return Err(CircuitOpen {
+                node_id: node.to_string(),
+                failures: breaker.failures,
+                retry_after_secs: (self.open_for - elapsed).as_secs().max(1),
+            });
+        }
+        // The trial, everyone else waits for its outcome
+        breaker.opened_at = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn record(&self, node: NodeId, ok: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        if ok {
+            nodes.remove(&node);
+            return;
+        }
+        let breaker = nodes.entry(node).or_default();
+        breaker.failures += 1;
+        if breaker.failures >= self.threshold {
+            if breaker.opened_at.is_none() {
+                println!(
+                    "Opened circuit for {} after {} failures",
+                    node, breaker.failures
+                );
+            }
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn states(&self) -> Vec<BreakerState> {
+        let nodes = self.nodes.lock().unwrap();
+        let mut states: Vec<BreakerState> = nodes
+            .iter()
+            .map(|(node, breaker)| {
+                let retry_after = breaker
+                    .opened_at
+                    .and_then(|opened_at| self.open_for.checked_sub(opened_at.elapsed()));
+                BreakerState {
+                    node_id: node.to_string(),
+                    failures: breaker.failures,
+                    open: retry_after.is_some(),
+                    retry_after_secs: retry_after.map(|left| left.as_secs().max(1)),
+                }
+            })
+            .collect();
+        states.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        states
+    }
+}
+
+/// `GET /network/breakers`: remote nodes that failed recently, and whether they are
+/// being skipped.
+pub async fn list_breakers(State(app_state): State<AppState>) -> Json<Vec<BreakerState>> {
+    Json(app_state.breakers.states())
+}
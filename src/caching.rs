@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+
+use crate::config::CacheControlConfig;
+use crate::reload::Live;
+
+/// Blob content never changes under its hash, so it can be cached forever.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// `Cache-Control` values routes get unless the config says otherwise.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("/blob/{hash}", IMMUTABLE),
+    ("/blob/{hash}/bao", IMMUTABLE),
+    ("/blob/{hash}/slice", IMMUTABLE),
+    ("/blob/{hash}/thumb", IMMUTABLE),
+    ("/blob/{hash}/preview", IMMUTABLE),
+    ("/raw/{hash}", IMMUTABLE),
+    ("/ipfs/{cid}", IMMUTABLE),
+    // Aliases move, so caches have to check the ETag every time
+    ("/a/{name}", "no-cache"),
+];
+
+/// `Cache-Control` headers added to successful responses, by route.
+#[derive(Clone)]
+pub struct CacheControl {
+    routes: Live<HashMap<String, HeaderValue>>,
+    base_path: String,
+}
+
+impl CacheControl {
+    /// Routes are matched under `base_path`, the prefix the API is served under.
+    pub fn new(config: &CacheControlConfig, base_path: &str) -> Result<Self> {
+        Ok(Self {
+            routes: Live::new(routes(config, base_path)?),
+            base_path: base_path.to_string(),
+        })
+    }
+
+    pub fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    pub fn set_routes(&self, routes: HashMap<String, HeaderValue>) {
+        self.routes.set(routes);
+    }
+}
+
+/// The header value of every route, the config's on top of the defaults.
+pub fn routes(
+    config: &CacheControlConfig,
+    base_path: &str,
+) -> Result<HashMap<String, HeaderValue>> {
+    let mut routes: HashMap<String, String> = DEFAULTS
+        .iter()
+        .map(|(route, value)| (route.to_string(), value.to_string()))
+        .collect();
+    routes.extend(config.routes.clone());
+
+    routes
+        .into_iter()
+        // An empty value turns a default off
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(route, value)| {
+            let value = HeaderValue::from_str(&value)
+                .with_context(|| format!("Invalid Cache-Control value for {}", route))?;
+            Ok((format!("{}{}", base_path, route), value))
+        })
+        .collect()
+}
+
+/// Middleware setting the route's `Cache-Control` on success and `304` responses.
+/// Errors are left alone, a missing blob may well show up later.
+pub async fn apply(State(cache): State<CacheControl>, request: Request, next: Next) -> Response {
+    let value = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| cache.routes.get().get(path.as_str()).cloned());
+    let mut response = next.run(request).await;
+    let status = response.status();
+    if let Some(value) = value {
+        if (status.is_success() || status.as_u16() == 304)
+            && !response.headers().contains_key(header::CACHE_CONTROL)
+        {
+            response.headers_mut().insert(header::CACHE_CONTROL, value);
+        }
+    }
+    response
+}
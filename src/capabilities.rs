@@ -0,0 +1,60 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::AppState;
+
+/// APIs every instance serves, next to the REST API.
+const APIS: &[&str] = &["rest", "s3", "webdav", "graphql", "grpc"];
+
+#[derive(Serialize)]
+pub struct UploadCapabilities {
+    /// `None` when there is no limit.
+    max_file_size: Option<u64>,
+    /// Empty when any are accepted, unless denied.
+    allowed_types: Vec<String>,
+    allowed_extensions: Vec<String>,
+    idempotency_keys: bool,
+    resumable: bool,
+}
+
+/// What this instance offers, so clients can adapt to it without being configured
+/// for it.
+#[derive(Serialize)]
+pub struct Capabilities {
+    /// `none` or `api_key`, see [`crate::tenancy::Tenancy::auth_mode`].
+    auth: &'static str,
+    /// Keys go in `Authorization: Bearer <key>` or `X-Api-Key`.
+    auth_headers: &'static [&'static str],
+    /// Whether tenants are configured, and reachable under `/t/<name>`.
+    tenants: bool,
+    upload: UploadCapabilities,
+    docs: bool,
+    gossip: bool,
+    http3: bool,
+    /// Whether uploads and other changes are refused for now, see [`crate::readonly`].
+    read_only: bool,
+    apis: &'static [&'static str],
+}
+
+/// `GET /capabilities`: what is switched on, with the upload limits of the current config.
+pub async fn get_capabilities(State(app_state): State<AppState>) -> Json<Capabilities> {
+    let upload = app_state.upload.get();
+    Json(Capabilities {
+        auth: app_state.tenancy.auth_mode(),
+        auth_headers: &["authorization", "x-api-key"],
+        tenants: !app_state.tenancy.names().is_empty(),
+        upload: UploadCapabilities {
+            max_file_size: (upload.max_file_size > 0).then_some(upload.max_file_size),
+            allowed_types: upload.allowed_types.clone(),
+            allowed_extensions: upload.allowed_extensions.clone(),
+            idempotency_keys: upload.idempotency_ttl_secs > 0,
+            resumable: false,
+        },
+        // Both run on every node
+        docs: true,
+        gossip: true,
+        http3: app_state.features.contains(&"http3"),
+        read_only: app_state.read_only.is_enabled(),
+        apis: APIS,
+    })
+}
@@ -0,0 +1,77 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use data_encoding::BASE32_NOPAD;
+use iroh_blobs::Hash;
+
+use crate::blob::{download_blob, parse_hash};
+use crate::AppState;
+
+/// Multicodec code of BLAKE3 in multihashes.
+const BLAKE3: u64 = 0x1e;
+/// Multicodec code of raw bytes as the content of a CID.
+const RAW: u64 = 0x55;
+
+/// The hash as a multihash, in hex.
+pub fn multihash(hash: &Hash) -> String {
+    format!("{:02x}{:02x}{}", BLAKE3, 32, hash.to_hex())
+}
+
+/// The hash as a CIDv1 of raw content in base32, the form IPFS tooling expects.
+pub fn cid(hash: &Hash) -> String {
+    let mut bytes = vec![1, RAW as u8, BLAKE3 as u8, 32];
+    bytes.extend_from_slice(hash.as_bytes());
+    format!("b{}", BASE32_NOPAD.encode(&bytes).to_ascii_lowercase())
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The hash in a base32 CIDv1 of raw content hashed with BLAKE3. Plain hashes are
+/// taken too. CIDs of other content or hash functions can't name a blob here.
+pub fn parse_cid(cid: &str) -> Result<Hash, StatusCode> {
+    let Some(encoded) = cid.strip_prefix('b') else {
+        return parse_hash(cid);
+    };
+    if let Ok(hash) = parse_hash(cid) {
+        return Ok(hash);
+    }
+    let bytes = BASE32_NOPAD
+        .decode(encoded.to_ascii_uppercase().as_bytes())
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut rest = bytes.as_slice();
+    let version = read_varint(&mut rest).ok_or(StatusCode::BAD_REQUEST)?;
+    let codec = read_varint(&mut rest).ok_or(StatusCode::BAD_REQUEST)?;
+    let hash_function = read_varint(&mut rest).ok_or(StatusCode::BAD_REQUEST)?;
+    let len = read_varint(&mut rest).ok_or(StatusCode::BAD_REQUEST)?;
+    if version != 1 || len != rest.len() as u64 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if codec != RAW || hash_function != BLAKE3 || len != 32 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let digest: [u8; 32] = rest.try_into().map_err(|_| StatusCode::BAD_REQUEST)?;
+    Ok(Hash::from_bytes(digest))
+}
+
+/// `GET /ipfs/{cid}`, the path IPFS gateways serve content under.
+pub async fn download_cid(
+    State(app_state): State<AppState>,
+    Path(cid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_cid(&cid)?;
+    download_blob(State(app_state), Path(hash.to_string()), headers).await
+}
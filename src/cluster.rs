@@ -0,0 +1,267 @@
+use anyhow::{bail, Result};
+use axum::{body::Bytes, extract::State, response::IntoResponse, Json};
+use futures::StreamExt;
+use iroh::{NodeId, SecretKey};
+use iroh_base::Signature;
+use iroh_blobs::Hash;
+use iroh_gossip::net::{Event, Gossip, GossipEvent, GossipReceiver, GossipSender};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::ClusterConfig;
+use crate::gossip::parse_topic;
+use crate::jobs::now_secs;
+use crate::AppState;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Members that haven't sent a heartbeat for this long no longer own any blobs.
+const MEMBER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Put in front of heartbeats before signing, so no other statement signed with the
+/// node key can pass for one.
+const HEARTBEAT_DOMAIN: &[u8] = b"iroh-api/cluster-heartbeat/0";
+
+#[derive(Serialize, Deserialize)]
+struct Heartbeat {
+    node_id: NodeId,
+    url: Option<String>,
+    /// Seconds since the Unix epoch, so old heartbeats can't be replayed for long.
+    timestamp: u64,
+}
+
+impl Heartbeat {
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        Ok([HEARTBEAT_DOMAIN, &postcard::to_allocvec(self)?].concat())
+    }
+}
+
+/// A heartbeat signed with the key of the node it names. Gossip is relayed by any
+/// peer on the topic, so only the signature says who a heartbeat comes from.
+#[derive(Serialize, Deserialize)]
+struct SignedHeartbeat {
+    heartbeat: Heartbeat,
+    signature: Signature,
+}
+
+impl SignedHeartbeat {
+    fn sign(secret_key: &SecretKey, url: Option<String>) -> Result<Self> {
+        let heartbeat = Heartbeat {
+            node_id: secret_key.public(),
+            url,
+            timestamp: now_secs(),
+        };
+        let signature = secret_key.sign(&heartbeat.signed_bytes()?);
+        Ok(Self {
+            heartbeat,
+            signature,
+        })
+    }
+
+    /// The heartbeat, if its node signed it and it is recent.
+    fn verify(self) -> Option<Heartbeat> {
+        let bytes = self.heartbeat.signed_bytes().ok()?;
+        self.heartbeat
+            .node_id
+            .verify(&bytes, &self.signature)
+            .ok()?;
+        let age = now_secs().abs_diff(self.heartbeat.timestamp);
+        (age < MEMBER_TIMEOUT.as_secs()).then_some(self.heartbeat)
+    }
+}
+
+struct Member {
+    url: Option<String>,
+    last_seen: Instant,
+}
+
+#[derive(Serialize)]
+pub struct MemberInfo {
+    node_id: String,
+    url: Option<String>,
+    local: bool,
+    last_seen_secs: u64,
+}
+
+struct Inner {
+    node_id: NodeId,
+    url: Option<String>,
+    /// Nodes allowed to join. The topic is public to anyone knowing the cluster name,
+    /// and a signed heartbeat only says who sent it.
+    allowed: HashSet<NodeId>,
+    members: RwLock<HashMap<NodeId, Member>>,
+}
+
+/// Cluster membership shared over a gossip topic, used to shard blobs across gateways.
+///
+/// Each blob is owned by the live member with the highest rendezvous score for its hash,
+/// so adding or removing a member only moves the blobs that member owns.
+#[derive(Clone, Default)]
+pub struct Cluster {
+    inner: Option<Arc<Inner>>,
+}
+
+impl Cluster {
+    pub fn spawn(gossip: &Gossip, secret_key: SecretKey, config: &ClusterConfig) -> Result<Self> {
+        let Some(name) = &config.name else {
+            return Ok(Self::default());
+        };
+        if config.members.is_empty() {
+            bail!("cluster.members must list the nodes allowed to join cluster {}", name);
+        }
+        let topic_id = parse_topic(&format!("iroh-api-cluster/{}", name));
+        let (sender, receiver) = gossip
+            .subscribe(topic_id, config.bootstrap.clone())?
+            .split();
+
+        let inner = Arc::new(Inner {
+            node_id: secret_key.public(),
+            url: config.advertise_url.clone(),
+            allowed: config.members.iter().copied().collect(),
+            members: Default::default(),
+        });
+        tokio::spawn(heartbeat(inner.clone(), secret_key, sender, receiver));
+
+        println!("Joined cluster {}", name);
+        Ok(Self { inner: Some(inner) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Node responsible for a blob, or `None` when cluster mode is off.
+    pub fn owner(&self, hash: &Hash) -> Option<NodeId> {
+        let inner = self.inner.as_ref()?;
+        let members = inner.members.read().unwrap();
+        let live = members
+            .iter()
+            .filter(|(_, member)| member.last_seen.elapsed() < MEMBER_TIMEOUT)
+            .map(|(node_id, _)| *node_id);
+        std::iter::once(inner.node_id)
+            .chain(live)
+            .max_by_key(|node_id| score(node_id, hash))
+    }
+
+    /// Whether `hash` belongs to another member, returning that member.
+    pub fn remote_owner(&self, hash: &Hash) -> Option<NodeId> {
+        let inner = self.inner.as_ref()?;
+        self.owner(hash).filter(|owner| *owner != inner.node_id)
+    }
+
+    pub fn url_of(&self, node_id: &NodeId) -> Option<String> {
+        let inner = self.inner.as_ref()?;
+        let members = inner.members.read().unwrap();
+        members.get(node_id).and_then(|member| member.url.clone())
+    }
+
+    /// Whether `node_id` is configured as a member, whether or not it is live.
+    pub fn allows(&self, node_id: &NodeId) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|inner| inner.allowed.contains(node_id))
+    }
+
+    pub fn is_member(&self, node_id: &NodeId) -> bool {
+        self.inner.as_ref().is_some_and(|inner| {
+            inner
+                .members
+                .read()
+                .unwrap()
+                .get(node_id)
+                .is_some_and(|member| member.last_seen.elapsed() < MEMBER_TIMEOUT)
+        })
+    }
+
+    pub fn members(&self) -> Vec<MemberInfo> {
+        let Some(inner) = &self.inner else {
+            return Vec::new();
+        };
+        let mut members = vec![MemberInfo {
+            node_id: inner.node_id.to_string(),
+            url: inner.url.clone(),
+            local: true,
+            last_seen_secs: 0,
+        }];
+        members.extend(
+            inner
+                .members
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, member)| member.last_seen.elapsed() < MEMBER_TIMEOUT)
+                .map(|(node_id, member)| MemberInfo {
+                    node_id: node_id.to_string(),
+                    url: member.url.clone(),
+                    local: false,
+                    last_seen_secs: member.last_seen.elapsed().as_secs(),
+                }),
+        );
+        members
+    }
+}
+
+fn score(node_id: &NodeId, hash: &Hash) -> [u8; 32] {
+    *Hash::new([node_id.as_bytes().as_slice(), hash.as_bytes()].concat()).as_bytes()
+}
+
+async fn heartbeat(
+    inner: Arc<Inner>,
+    secret_key: SecretKey,
+    sender: GossipSender,
+    mut receiver: GossipReceiver,
+) {
+    let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let message = SignedHeartbeat::sign(&secret_key, inner.url.clone())
+                    .and_then(|message| Ok(Bytes::from(serde_json::to_vec(&message)?)));
+                let Ok(message) = message else {
+                    continue;
+                };
+                if let Err(err) = sender.broadcast(message).await {
+                    println!("Failed to send cluster heartbeat: {}", err);
+                }
+            }
+            event = receiver.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let Ok(Event::Gossip(GossipEvent::Received(message))) = event else {
+                    continue;
+                };
+                let heartbeat = serde_json::from_slice::<SignedHeartbeat>(&message.content)
+                    .ok()
+                    .and_then(SignedHeartbeat::verify);
+                let Some(heartbeat) = heartbeat else {
+                    continue;
+                };
+                if heartbeat.node_id == inner.node_id {
+                    continue;
+                }
+                if !inner.allowed.contains(&heartbeat.node_id) {
+                    continue;
+                }
+                let member = Member {
+                    url: heartbeat.url,
+                    last_seen: Instant::now(),
+                };
+                let joined = inner
+                    .members
+                    .write()
+                    .unwrap()
+                    .insert(heartbeat.node_id, member)
+                    .is_none();
+                if joined {
+                    println!("Cluster member {} joined", heartbeat.node_id);
+                }
+            }
+        }
+    }
+}
+
+pub async fn list_members(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.cluster.members())
+}
@@ -0,0 +1,139 @@
+use anyhow::{bail, Context, Result};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use iroh_blobs::store::fs::Store;
+use iroh_blobs::store::{EntryStatus, MapMut};
+use iroh_blobs::Hash;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::AppState;
+
+const DEFAULT_DIR: &str = "data";
+
+const USAGE: &str = "usage: iroh-api compact [--dir data]";
+
+/// Files touched more recently than this may belong to an import still under way, and
+/// are left alone.
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// What a compaction freed.
+#[derive(Serialize, Default)]
+pub struct Compacted {
+    pub files_removed: u64,
+    pub bytes_reclaimed: u64,
+}
+
+/// Removes what the store in `root` no longer uses: data and outboard files of blobs
+/// it has no entry for, left behind by crashes and interrupted deletes, and temp files
+/// of imports that never finished.
+pub async fn sweep(store: &Store, root: &Path) -> Result<Compacted> {
+    let mut compacted = Compacted::default();
+    for (path, len) in old_files(&root.join("data")).await? {
+        // Files are named after their blob, like <hash>.data and <hash>.obao4
+        let Some(hash) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| Hash::from_str(stem).ok())
+        else {
+            continue;
+        };
+        if store.entry_status(&hash).await? == EntryStatus::NotFound {
+            remove(&path, len, &mut compacted).await;
+        }
+    }
+    for (path, len) in old_files(&root.join("temp")).await? {
+        remove(&path, len, &mut compacted).await;
+    }
+    Ok(compacted)
+}
+
+async fn remove(path: &Path, len: u64, compacted: &mut Compacted) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {
+            compacted.files_removed += 1;
+            compacted.bytes_reclaimed += len;
+        }
+        Err(err) => println!("Failed to remove {}: {}", path.display(), err),
+    }
+}
+
+/// The files in `dir` older than [`MIN_AGE`], with their sizes.
+async fn old_files(dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let mut files = Vec::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let meta = entry.metadata().await?;
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if meta.is_file() && age.is_some_and(|age| age >= MIN_AGE) {
+            files.push((entry.path(), meta.len()));
+        }
+    }
+    Ok(files)
+}
+
+/// `iroh-api compact`: sweeps the store of a stopped gateway and compacts its database,
+/// which only works while nothing else has it open.
+pub async fn run(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut dir = PathBuf::from(DEFAULT_DIR);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = args.next().context("--dir needs a value")?.into(),
+            _ => bail!("{}\nunknown argument {}", USAGE, arg),
+        }
+    }
+    let db_path = dir.join("blobs.db");
+    if !db_path.exists() {
+        bail!("No store in {}", dir.display());
+    }
+
+    let store = Store::load(&dir).await?;
+    let swept = sweep(&store, &dir).await?;
+    // Dropping the last handle shuts the store down and releases the database
+    drop(store);
+    println!(
+        "Removed {} unused files, {} bytes",
+        swept.files_removed, swept.bytes_reclaimed
+    );
+
+    let before = tokio::fs::metadata(&db_path).await?.len();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut db = redb::Database::open(&db_path)
+            .context("Failed to open the store database, is the gateway still running?")?;
+        db.compact()?;
+        Ok(())
+    })
+    .await??;
+    let after = tokio::fs::metadata(dir.join("blobs.db")).await?.len();
+    println!(
+        "Compacted the store database from {} to {} bytes",
+        before, after
+    );
+    Ok(())
+}
+
+/// `POST /store/compact`: removes unused files from the running store. The database
+/// itself is only compacted by `iroh-api compact`, with the gateway stopped.
+pub async fn compact_store(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let compacted = sweep(app_state.blobs.store(), Path::new(DEFAULT_DIR))
+        .await
+        .map_err(|err| {
+            println!("Failed to compact the store: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    println!(
+        "Compaction removed {} unused files, {} bytes",
+        compacted.files_removed, compacted.bytes_reclaimed
+    );
+    Ok(Json(compacted))
+}
@@ -0,0 +1,922 @@
+use anyhow::{Context, Result};
+use iroh::{NodeAddr, NodeId, RelayUrl};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Default location of the config file, overridable with `IROH_API_CONFIG`.
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub proxy: ProxyConfig,
+    pub cors: CorsConfig,
+    pub announce: AnnounceConfig,
+    pub sync: Vec<DirSyncConfig>,
+    pub push: PushConfig,
+    pub tracker: TrackerConfig,
+    pub replication: ReplicationConfig,
+    pub jobs: JobsConfig,
+    pub mirror: MirrorConfig,
+    pub forward: ForwardConfig,
+    pub cluster: ClusterConfig,
+    pub network: NetworkConfig,
+    pub bandwidth: BandwidthConfig,
+    pub fetch: FetchConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub p2p_timeouts: P2pTimeoutsConfig,
+    pub upload: UploadConfig,
+    pub load_shedding: LoadSheddingConfig,
+    pub read_only: ReadOnlyConfig,
+    pub maintenance: MaintenanceConfig,
+    pub listing: ListingConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub sessions: SessionsConfig,
+    pub antivirus: AntivirusConfig,
+    pub screening: ScreeningConfig,
+    pub tenancy: TenancyConfig,
+    pub roles: RolesConfig,
+    pub gc: GcConfig,
+    pub metering: MeteringConfig,
+    pub compression: CompressionConfig,
+    pub cache_control: CacheControlConfig,
+    pub access_log: AccessLogConfig,
+    pub diagnostics: DiagnosticsConfig,
+    pub daemon: DaemonConfig,
+    pub plugins: PluginsConfig,
+    pub hooks: Vec<HookConfig>,
+    pub schedule: Vec<ScheduleConfig>,
+    pub partial: PartialConfig,
+    pub archives: ArchivesConfig,
+}
+
+/// Addresses the HTTP API listens on, and the certificate to serve it over TLS with.
+///
+/// `listen` takes one address or a list, e.g. `["[::]:3000", "0.0.0.0:3000"]` for
+/// dual-stack hosts. `http3` additionally serves the API over QUIC on the same port
+/// numbers, which needs the certificate. It is experimental.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<SocketAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub http3: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: vec![([0, 0, 0, 0], 3000).into()],
+            tls_cert: None,
+            tls_key: None,
+            http3: false,
+        }
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<SocketAddr>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(SocketAddr),
+        Many(Vec<SocketAddr>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    })
+}
+
+/// Running behind a reverse proxy such as nginx or Traefik.
+///
+/// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` are only believed on
+/// requests from the `trusted` proxy addresses. `base_path` is the prefix the API is
+/// served under, e.g. `/files`.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub trusted: Vec<IpAddr>,
+    pub base_path: String,
+}
+
+/// Which browser origins may call the API, with which methods and headers.
+///
+/// Everything is allowed by default; list the origins of your web apps to lock it
+/// down. `allow_credentials` lets browsers send cookies and auth headers, and needs
+/// explicit lists rather than `"*"`.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".into()],
+            allowed_methods: vec!["*".into()],
+            allowed_headers: vec!["*".into()],
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayMode {
+    /// The public n0 relays.
+    #[default]
+    Default,
+    /// The n0 staging relays.
+    Staging,
+    /// The self-hosted relays listed in `relays`.
+    Custom,
+    /// No relays at all, for private networks where nodes reach each other directly.
+    Disabled,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// Direct connections where hole punching works, relays otherwise.
+    #[default]
+    Any,
+    /// Send everything through relays so peers never learn this node's IP.
+    RelayOnly,
+    /// Never use relays, for air-gapped LANs. Overrides `relay_mode`.
+    DirectOnly,
+}
+
+/// How the endpoint reaches other nodes.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub relay_mode: RelayMode,
+    pub relays: Vec<RelayUrl>,
+    pub transport: Transport,
+    /// Find nodes on the same LAN over mDNS, without needing internet access.
+    pub local_discovery: bool,
+    pub discovery: DiscoveryConfig,
+    /// Addresses of fixed infrastructure nodes, reachable even when discovery is down.
+    pub peers: Vec<NodeAddr>,
+}
+
+/// Where the node publishes its address and how it resolves other nodes' addresses.
+///
+/// Self-hosters can turn off `n0` and point `pkarr_relays` and `dns_origins` at their own
+/// iroh-dns-server to run without n0's infrastructure.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Publish to and resolve from n0's DNS discovery service.
+    pub n0: bool,
+    /// Publish to and resolve from the mainline DHT.
+    pub dht: bool,
+    /// Pkarr relays to publish to and resolve from.
+    pub pkarr_relays: Vec<Url>,
+    /// DNS origin domains to resolve node addresses from.
+    pub dns_origins: Vec<String>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            n0: true,
+            dht: false,
+            pkarr_relays: Vec::new(),
+            dns_origins: Vec::new(),
+        }
+    }
+}
+
+/// Bandwidth caps in bytes per second, 0 meaning unlimited.
+///
+/// `upload` covers everything this node sends, blobs served to peers as well as HTTP
+/// downloads; `download` covers HTTP uploads. `per_connection` caps each single peer
+/// connection or HTTP request on top of that.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    pub upload: u64,
+    pub download: u64,
+    pub per_connection: u64,
+}
+
+/// Gossip topic on which uploads are announced and peer announcements are collected.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct AnnounceConfig {
+    pub topic: Option<String>,
+    pub bootstrap: Vec<NodeId>,
+}
+
+/// Announce topic of other gateways whose announced blobs are fetched automatically.
+///
+/// With no `sources`, every announcement on the topic is mirrored.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct MirrorConfig {
+    pub topic: Option<String>,
+    pub bootstrap: Vec<NodeId>,
+    pub sources: Vec<NodeId>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardMode {
+    #[default]
+    Off,
+    /// Forward every upload.
+    Edge,
+    /// Forward uploads once free space in the data directory drops below `min_free_bytes`.
+    WhenFull,
+}
+
+/// Upstream gateways that uploads are forwarded to, and the edge nodes an upstream
+/// accepts forwarded uploads from.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ForwardConfig {
+    pub mode: ForwardMode,
+    pub upstream: Vec<NodeId>,
+    pub min_free_bytes: u64,
+    pub accept_from: Vec<NodeId>,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            mode: ForwardMode::Off,
+            upstream: Vec::new(),
+            min_free_bytes: 1 << 30,
+            accept_from: Vec::new(),
+        }
+    }
+}
+
+/// Cluster of gateways sharding blobs between them by hash.
+///
+/// Members find each other on a gossip topic derived from `name`; `advertise_url` is
+/// where other members redirect downloads of blobs this node owns. Only the nodes in
+/// `members` can join, anyone else's heartbeats are ignored.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub name: Option<String>,
+    pub members: Vec<NodeId>,
+    pub bootstrap: Vec<NodeId>,
+    pub advertise_url: Option<String>,
+}
+
+/// Nodes that may push blobs into this node's store.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PushConfig {
+    pub accept_from: Vec<NodeId>,
+}
+
+/// Content trackers this node announces its blobs to every `announce_interval_secs`,
+/// and asks for providers of blobs fetched by hash alone. None by default.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct TrackerConfig {
+    pub trackers: Vec<NodeId>,
+    pub announce_interval_secs: u64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            trackers: Vec::new(),
+            announce_interval_secs: 600,
+        }
+    }
+}
+
+/// Peers every upload is pushed to, and how many of them must hold a copy.
+///
+/// `factor` defaults to all peers.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    pub peers: Vec<String>,
+    pub factor: Option<usize>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct JobsConfig {
+    pub concurrency: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self { concurrency: 4 }
+    }
+}
+
+/// How many remote blob downloads run at once, and how many `/fetch` requests may wait
+/// for a slot before further ones are turned away.
+///
+/// A failed download is tried up to `attempts` times in all, waiting `backoff_ms`
+/// before the second attempt and twice as long before each one after, up to
+/// `max_backoff_ms`, with jitter.
+///
+/// Raw blobs of at least `parallel_min_size` bytes are downloaded in slices from all
+/// nodes known to have them at once.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub concurrency: usize,
+    pub queue: usize,
+    pub attempts: u32,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub parallel_min_size: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            queue: 32,
+            attempts: 3,
+            backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            parallel_min_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Skipping remote nodes that keep failing. After `failure_threshold` failed fetches or
+/// pushes in a row a node isn't tried for `open_secs`, and requests for it fail fast
+/// with 502. 0 turns the breaker off.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub open_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_secs: 30,
+        }
+    }
+}
+
+/// Time limits on blob fetches from and pushes to remote nodes, in place of the
+/// library defaults. `connect_secs` bounds dialing a node, `first_byte_secs` the wait
+/// for data once connected (fetches only, the node on the other end of a push fetches
+/// from us), and `transfer_secs` the whole transfer. 0 turns a limit off.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct P2pTimeoutsConfig {
+    pub connect_secs: u64,
+    pub first_byte_secs: u64,
+    pub transfer_secs: u64,
+}
+
+impl Default for P2pTimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            connect_secs: 15,
+            first_byte_secs: 30,
+            transfer_secs: 3600,
+        }
+    }
+}
+
+/// How many uploads, over HTTP or forwarded by other nodes, are received and stored at
+/// once. Further uploads are turned away until a slot frees up.
+///
+/// HTTP upload bodies are dropped when the client sends nothing for `read_timeout_secs`,
+/// or sends slower than `min_bytes_per_sec` (0 disables the check) once that long has
+/// passed, so slow clients can't hold on to a slot.
+///
+/// Stored files can be limited to `max_file_size` bytes (0 for 1 GiB, as uploads are
+/// held in memory) and to content types and file extensions. Types are matched against
+/// what the content turns out to be, with patterns like `image/*`. Denials win over
+/// allowances, and empty allow lists allow everything.
+///
+/// Responses to uploads sent with an `Idempotency-Key` are replayed for retries with
+/// the same key for `idempotency_ttl_secs` (0 ignores the header).
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub concurrency: usize,
+    pub read_timeout_secs: u64,
+    pub min_bytes_per_sec: u64,
+    pub max_file_size: u64,
+    pub allowed_types: Vec<String>,
+    pub denied_types: Vec<String>,
+    pub allowed_extensions: Vec<String>,
+    pub denied_extensions: Vec<String>,
+    pub idempotency_ttl_secs: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            read_timeout_secs: 30,
+            min_bytes_per_sec: 1024,
+            max_file_size: 0,
+            allowed_types: Vec::new(),
+            denied_types: Vec::new(),
+            allowed_extensions: Vec::new(),
+            denied_extensions: Vec::new(),
+            idempotency_ttl_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Starts the node refusing every change over HTTP, see [`crate::readonly`]. It can be
+/// switched at runtime with `PUT /admin/read-only` too.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ReadOnlyConfig {
+    pub enabled: bool,
+}
+
+/// Taking the node down for maintenance, see [`crate::maintenance`]. Browsers get the
+/// HTML file `page`, or `message` in a plain page of its own; other clients get
+/// `message` in JSON. `pause_p2p` turns away new connections of other nodes too.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    pub message: String,
+    pub page: Option<PathBuf>,
+    pub pause_p2p: bool,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            message: "Down for maintenance, back soon.".to_string(),
+            page: None,
+            pause_p2p: false,
+        }
+    }
+}
+
+/// Directory listings for browsers, see [`crate::listing`]. With `html` on, folders
+/// under `/files` and collections under `/collection/<hash>/` are shown as index pages
+/// to clients accepting HTML, and stay JSON for the rest.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ListingConfig {
+    pub html: bool,
+}
+
+/// Headers telling browsers how to treat the gateway's responses, see
+/// [`crate::security_headers`]. An empty value leaves a header out, and
+/// `strict_transport_security` is only sent over HTTPS.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub enabled: bool,
+    pub content_security_policy: String,
+    pub strict_transport_security: String,
+    pub content_type_options: String,
+    pub referrer_policy: String,
+    pub frame_options: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            content_security_policy: "default-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'self'; frame-ancestors 'none'".to_string(),
+            strict_transport_security: "max-age=31536000; includeSubDomains".to_string(),
+            content_type_options: "nosniff".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+            frame_options: "DENY".to_string(),
+        }
+    }
+}
+
+/// Sessions of users signed in to the web UI, see [`crate::users`]. They last
+/// `ttl_secs`, a week by default. With `registration` on, anyone can create an account,
+/// otherwise only admins can.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SessionsConfig {
+    pub ttl_secs: u64,
+    pub registration: bool,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 7 * 24 * 60 * 60,
+            registration: false,
+        }
+    }
+}
+
+/// Refusing work before the node runs out of room. New uploads are answered with 503
+/// once free disk space or memory drops below `min_free_disk_bytes` or
+/// `min_free_memory_bytes`, or more than `max_in_flight` requests are being handled.
+/// Past half the free space or twice the requests, fetches from other nodes are
+/// refused too. 0 turns a threshold off.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    pub min_free_disk_bytes: u64,
+    pub min_free_memory_bytes: u64,
+    pub max_in_flight: usize,
+    pub retry_after_secs: u64,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            min_free_disk_bytes: 0,
+            min_free_memory_bytes: 0,
+            max_in_flight: 0,
+            retry_after_secs: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InfectedAction {
+    /// Turn infected uploads away.
+    #[default]
+    Reject,
+    /// Turn them away too, but keep a copy in `data/quarantine` for review.
+    Quarantine,
+}
+
+/// Scanning of uploads with ClamAV before they are stored. Off unless `clamd` is set,
+/// to the path of clamd's unix socket or its `host:port`.
+///
+/// Uploads are refused while clamd can't be reached or takes longer than
+/// `timeout_secs`, unless `fail_open` lets them through unscanned.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AntivirusConfig {
+    pub clamd: Option<String>,
+    pub action: InfectedAction,
+    pub fail_open: bool,
+    pub timeout_secs: u64,
+}
+
+impl Default for AntivirusConfig {
+    fn default() -> Self {
+        Self {
+            clamd: None,
+            action: InfectedAction::Reject,
+            fail_open: false,
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// Content checks by an outside service, asked about every upload and before blobs
+/// are served. Off unless `webhook` is set.
+///
+/// Verdicts on served blobs are reused for `cache_secs`. When the webhook fails or takes
+/// longer than `timeout_secs`, content is refused unless `fail_open` lets it through.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ScreeningConfig {
+    pub webhook: Option<Url>,
+    pub fail_open: bool,
+    pub timeout_secs: u64,
+    pub cache_secs: u64,
+}
+
+impl Default for ScreeningConfig {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            fail_open: false,
+            timeout_secs: 10,
+            cache_secs: 300,
+        }
+    }
+}
+
+/// Compression of responses for clients sending `Accept-Encoding`.
+///
+/// Only blobs that look like text and are between `min_size` and `max_size` bytes are
+/// compressed. Compressed variants are kept in memory up to `cache_bytes`. `json`
+/// covers the API's JSON responses.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub downloads: bool,
+    pub json: bool,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub cache_bytes: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            downloads: true,
+            json: true,
+            min_size: 1024,
+            max_size: 16 << 20,
+            cache_bytes: 64 << 20,
+        }
+    }
+}
+
+/// Applications sharing the gateway, each with its own uploads, listings and quota.
+///
+/// Requests made with one of `admin_keys` see the whole store. When admin keys are set,
+/// requests without a key are refused unless they name a tenant that has no keys.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct TenancyConfig {
+    pub admin_keys: Vec<String>,
+    pub tenants: Vec<TenantConfig>,
+}
+
+/// A tenant, used with one of `api_keys` or under the path prefix `/t/<name>`.
+/// `quota_bytes` caps what it stores, 0 for no limit.
+#[derive(Deserialize, Clone)]
+pub struct TenantConfig {
+    pub name: String,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    #[serde(default)]
+    pub quota_bytes: u64,
+}
+
+/// What API keys and users may do, see [`crate::roles`]. `groups` gives the route
+/// groups each role may use, on top of the built-in `admin`, `uploader` and `reader`,
+/// and `routes` moves routes to another group than their own. `keys` gives API keys
+/// other roles than `admin` for admin keys and `uploader` for tenant keys,
+/// `default_user_role` is the role of users created without one.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RolesConfig {
+    pub groups: HashMap<String, Vec<RouteGroup>>,
+    pub routes: HashMap<String, RouteGroup>,
+    pub keys: HashMap<String, String>,
+    pub default_user_role: String,
+}
+
+impl Default for RolesConfig {
+    fn default() -> Self {
+        Self {
+            groups: HashMap::new(),
+            routes: HashMap::new(),
+            keys: HashMap::new(),
+            default_user_role: "uploader".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    /// Routes working on the whole store, outside those tenants may use.
+    Admin,
+    /// Routes tenants may use, with methods that change something.
+    Mutating,
+    /// Routes tenants may use, with methods that only read.
+    Read,
+}
+
+/// Garbage collection of blobs no tag or document keeps, every `interval_secs`. Off
+/// when 0, so nothing is ever removed from the store. Uploads tenants delete can be
+/// restored for `grace_secs` before they are left to it, 0 lets them go right away.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct GcConfig {
+    pub interval_secs: u64,
+    pub grace_secs: u64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            grace_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Partial blobs nothing was written to for `max_idle_secs` are removed, unless a failed
+/// fetch is waiting to resume them or a tag keeps them. 0 keeps them until garbage
+/// collection takes them.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PartialConfig {
+    pub max_idle_secs: u64,
+}
+
+/// The directory `POST /admin/export-all` and `/admin/import-all` write and read
+/// archives on the server in, named by clients. Without it they only stream archives.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ArchivesConfig {
+    pub dir: Option<PathBuf>,
+}
+
+/// Usage records for billing, by tenant and API key, every `interval_secs`. They are
+/// appended to `file`, as CSV when it ends in `.csv` and JSON lines otherwise, and
+/// POSTed to `webhook` as a JSON array. Off unless one of them is set.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MeteringConfig {
+    pub interval_secs: u64,
+    pub file: Option<PathBuf>,
+    pub webhook: Option<Url>,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 3600,
+            file: None,
+            webhook: None,
+        }
+    }
+}
+
+/// `Cache-Control` values by route pattern, e.g. `"/blob/{hash}/info" = "no-cache"`.
+///
+/// Blob downloads are cached as immutable unless overridden here; an empty value
+/// removes the header from a route.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct CacheControlConfig {
+    pub routes: HashMap<String, String>,
+}
+
+/// The access log of HTTP requests, kept apart from the gateway's own output: lines go
+/// to `file`, or to stderr when none is set. `combined` is the Apache and nginx format,
+/// `json` writes one JSON object per request. The file is reopened on reload, so it can
+/// be rotated by renaming it.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    #[default]
+    Off,
+    Combined,
+    Json,
+}
+
+/// Runtime diagnostics. `console` serves task data to `tokio-console` on
+/// `console_addr`, which needs a build with the `console` feature and
+/// `RUSTFLAGS="--cfg tokio_unstable"`.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    pub console: bool,
+    pub console_addr: SocketAddr,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            console: false,
+            console_addr: ([127, 0, 0, 1], 6669).into(),
+        }
+    }
+}
+
+/// Where `--daemon` writes its PID and output. Relative paths are taken from the
+/// directory the gateway is started in.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DaemonConfig {
+    pub pid_file: PathBuf,
+    pub log_file: PathBuf,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            pid_file: "iroh-api.pid".into(),
+            log_file: "iroh-api.log".into(),
+        }
+    }
+}
+
+/// Plugins built into the gateway to start, by name, and the settings handed to each,
+/// as in `[plugins.settings.<name>]`.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PluginsConfig {
+    pub enabled: Vec<String>,
+    pub settings: HashMap<String, toml::Value>,
+}
+
+/// An external command run on every node event named `event`, as listed by
+/// `GET /events`. The event's fields are passed in `IROH_API_*` environment variables and
+/// as JSON on stdin. Commands still running after `timeout_secs` are killed.
+#[derive(Deserialize, Clone)]
+pub struct HookConfig {
+    pub event: String,
+    pub command: Vec<String>,
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_hook_timeout() -> u64 {
+    60
+}
+
+/// A maintenance task run whenever `cron`, a five field cron expression in UTC, matches.
+/// Entries can be turned off with `enabled = false` and still show up in `GET /schedule`.
+#[derive(Deserialize, Clone)]
+pub struct ScheduleConfig {
+    pub task: ScheduledTask,
+    pub cron: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledTask {
+    /// Garbage collection. Once scheduled it only runs on the schedule, and needs
+    /// `[gc] interval_secs` set.
+    Gc,
+    /// Checks the stored blobs against their hashes.
+    Validate,
+    /// Writes out usage records, see `[metering]`.
+    UsageReport,
+    /// Replicates tagged blobs that are on fewer replica peers than they should be.
+    Replication,
+    /// Reports blobs nothing refers to, and records of blobs the store lost.
+    Orphans,
+}
+
+impl ScheduledTask {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gc => "gc",
+            Self::Validate => "validate",
+            Self::UsageReport => "usage_report",
+            Self::Replication => "replication",
+            Self::Orphans => "orphans",
+        }
+    }
+}
+
+/// A local directory kept in two-way sync with a docs namespace.
+#[derive(Deserialize, Clone)]
+pub struct DirSyncConfig {
+    pub namespace: String,
+    pub path: PathBuf,
+    #[serde(default = "default_sync_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_sync_interval() -> u64 {
+    10
+}
+
+impl Config {
+    /// Loads the config file, falling back to defaults when it does not exist.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("IROH_API_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.into());
+        let path = Path::new(&path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+}
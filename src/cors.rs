@@ -0,0 +1,104 @@
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::time::Duration;
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+use crate::reload::Live;
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|value| value == "*")
+}
+
+/// Builds the CORS layer from the config. `"*"` allows anything, which browsers don't
+/// accept together with credentials, so that combination is rejected up front.
+pub fn layer(config: &CorsConfig) -> Result<CorsLayer> {
+    let wildcard = is_wildcard(&config.allowed_origins)
+        || is_wildcard(&config.allowed_methods)
+        || is_wildcard(&config.allowed_headers);
+    if config.allow_credentials && wildcard {
+        bail!(
+            "cors.allow_credentials needs explicit origins, methods and headers instead of \"*\""
+        );
+    }
+
+    let origins = if is_wildcard(&config.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin))
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid CORS origin")?,
+        )
+    };
+    let methods = if is_wildcard(&config.allowed_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(
+            config
+                .allowed_methods
+                .iter()
+                .map(|method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid CORS method")?,
+        )
+    };
+    let headers = if is_wildcard(&config.allowed_headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(
+            config
+                .allowed_headers
+                .iter()
+                .map(|header| HeaderName::from_bytes(header.as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid CORS header")?,
+        )
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = config.max_age_secs {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+    Ok(layer)
+}
+
+/// Middleware answering with the CORS policy in effect, which changes on reload.
+///
+/// `OPTIONS` requests that aren't preflights, like those of WebDAV clients, are let
+/// through instead of being answered as one.
+pub async fn apply(
+    State(policy): State<Live<CorsLayer>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::OPTIONS
+        && !request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return next.run(request).await;
+    }
+    let service = policy.get().layer(tower::service_fn(move |request| {
+        let next = next.clone();
+        async move { Ok::<_, Infallible>(next.run(request).await) }
+    }));
+    match service.oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
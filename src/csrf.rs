@@ -0,0 +1,199 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use data_encoding::HEXLOWER;
+use rand::RngCore;
+
+use crate::proxy::ClientInfo;
+use crate::AppState;
+
+/// The cookie browser sessions are kept in. Browsers send it along on their own, also
+/// with requests other sites make them send, which is what the token guards against.
+pub const SESSION_COOKIE: &str = "iroh_api_session";
+/// The cookie holding the token, which other sites can't read.
+const TOKEN_COOKIE: &str = "iroh_api_csrf";
+/// The header the token has to come back in.
+const TOKEN_HEADER: &str = "x-csrf-token";
+
+/// The value of a cookie the request came with.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// The attributes of the gateway's cookies: sent back for the whole API, never on
+/// requests from other sites, and only over HTTPS when that's how clients connect.
+pub fn cookie_attributes(app_state: &AppState, client: &ClientInfo) -> String {
+    let secure = if client.proto == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "Path={}/; HttpOnly; SameSite=Strict{}",
+        app_state.proxy.base_path(),
+        secure
+    )
+}
+
+/// 32 random bytes as hex, for tokens nobody can guess.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    HEXLOWER.encode(&bytes)
+}
+
+fn is_token(value: &str) -> bool {
+    value.len() == 64 && HEXLOWER.decode(value.as_bytes()).is_ok()
+}
+
+/// Compares in constant time, so the token can't be guessed byte by byte.
+fn matches(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// `GET /csrf-token`: the token browser clients send as `X-CSRF-Token` with changes.
+/// It is kept in a cookie as well, and the same token is returned while that lasts.
+pub async fn issue_token(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    let token = cookie(&headers, TOKEN_COOKIE)
+        .filter(|token| is_token(token))
+        .map(str::to_string)
+        .unwrap_or_else(new_token);
+    let set_cookie = format!(
+        "{}={}; {}",
+        TOKEN_COOKIE,
+        token,
+        cookie_attributes(&app_state, &client)
+    );
+    (
+        [
+            (header::SET_COOKIE, set_cookie),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Json(serde_json::json!({ "token": token })),
+    )
+        .into_response()
+}
+
+/// Whether requests with `method` only read, and change nothing.
+pub fn is_safe(method: &Method) -> bool {
+    method == Method::GET
+        || method == Method::HEAD
+        || method == Method::OPTIONS
+        || method.as_str() == "PROPFIND"
+}
+
+/// Middleware refusing changes made with a session cookie unless they carry the token
+/// of the cookie in `X-CSRF-Token`, which only pages of the gateway itself can read.
+/// Requests authenticated with API keys don't carry the session cookie, and pass.
+pub async fn apply(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    if is_safe(request.method()) || cookie(headers, SESSION_COOKIE).is_none() {
+        return next.run(request).await;
+    }
+    let expected = cookie(headers, TOKEN_COOKIE);
+    let sent = headers
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match (expected, sent) {
+        (Some(expected), Some(sent)) if is_token(expected) && matches(expected, sent) => {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": "csrf",
+                "message": "missing or wrong X-CSRF-Token, get one from GET /csrf-token",
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::post, Router};
+    use tower::Service;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    async fn status(method: Method, cookies: Option<&str>, token: Option<&str>) -> StatusCode {
+        let mut app = Router::new()
+            .route("/change", post(|| async {}).get(|| async {}))
+            .layer(middleware::from_fn(apply));
+        let mut request = Request::builder().method(method).uri("/change");
+        if let Some(cookies) = cookies {
+            request = request.header(header::COOKIE, cookies);
+        }
+        if let Some(token) = token {
+            request = request.header(TOKEN_HEADER, token);
+        }
+        let response = app.call(request.body(Body::empty()).unwrap()).await.unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn changes_with_a_session_need_the_token() {
+        let session = format!("{}=session", SESSION_COOKIE);
+        let both = format!("{}; {}={}", session, TOKEN_COOKIE, TOKEN);
+
+        assert_eq!(status(Method::POST, Some(&session), None).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(Method::POST, Some(&both), None).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(Method::POST, Some(&both), Some(TOKEN)).await, StatusCode::OK);
+
+        let other = TOKEN.replace('0', "1");
+        assert_eq!(status(Method::POST, Some(&both), Some(&other)).await, StatusCode::FORBIDDEN);
+        // Without the cookie the header alone proves nothing
+        assert_eq!(status(Method::POST, Some(&session), Some(TOKEN)).await, StatusCode::FORBIDDEN);
+        // Cookies that aren't tokens don't count, even when sent back as they are
+        let short = format!("{}; {}=abc", session, TOKEN_COOKIE);
+        assert_eq!(status(Method::POST, Some(&short), Some("abc")).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn reads_and_requests_without_a_session_pass() {
+        let session = format!("{}=session", SESSION_COOKIE);
+        assert_eq!(status(Method::GET, Some(&session), None).await, StatusCode::OK);
+        assert_eq!(status(Method::POST, None, None).await, StatusCode::OK);
+        assert_eq!(status(Method::POST, Some("other=1"), None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn cookies_are_found_by_name() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, "a=1; iroh_api_csrf=x".parse().unwrap());
+        headers.append(header::COOKIE, "b=2".parse().unwrap());
+        assert_eq!(cookie(&headers, TOKEN_COOKIE), Some("x"));
+        assert_eq!(cookie(&headers, "b"), Some("2"));
+        assert_eq!(cookie(&headers, "iroh_api"), None);
+    }
+
+    #[test]
+    fn tokens_are_random_hex() {
+        let token = new_token();
+        assert!(is_token(&token));
+        assert_ne!(token, new_token());
+        assert!(!is_token("abc"));
+        assert!(matches(&token, &token));
+        assert!(!matches(&token, &token[1..]));
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::{bail, Context, Result};
+use daemonize::Daemonize;
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+
+use crate::config::DaemonConfig;
+
+/// Detaches from the terminal, for running without a process supervisor.
+///
+/// Forks twice so the gateway is re-parented to init and can't get a controlling
+/// terminal back, keeps the PID file locked while running and sends stdout and stderr
+/// to the log file. Must be called before any threads are started.
+pub fn daemonize(config: &DaemonConfig) -> Result<()> {
+    // Fail while still in the foreground, where the operator sees why
+    if let Ok(file) = File::open(&config.pid_file) {
+        if file.try_lock_exclusive().is_err() {
+            let pid = fs::read_to_string(&config.pid_file).unwrap_or_default();
+            bail!(
+                "Already running with PID {} ({})",
+                pid.trim(),
+                config.pid_file.display()
+            );
+        }
+    }
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.log_file)
+        .with_context(|| format!("Failed to open log file {}", config.log_file.display()))?;
+    Daemonize::new()
+        .pid_file(&config.pid_file)
+        // The data directory and config are relative to where the gateway was started
+        .working_directory(std::env::current_dir()?)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .start()
+        .context("Failed to daemonize")?;
+    Ok(())
+}
+
+/// Removes the PID file on the way out, so a stale one doesn't point at another process.
+pub fn remove_pid_file(config: &DaemonConfig) {
+    let _ = fs::remove_file(&config.pid_file);
+}
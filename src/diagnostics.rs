@@ -0,0 +1,134 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use iroh_blobs::util::local_pool::LocalPoolHandle;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::config::DiagnosticsConfig;
+use crate::shedding::LoadStatus;
+use crate::AppState;
+
+/// Starts serving task data to `tokio-console` when `config` asks for it.
+pub fn init(config: &DiagnosticsConfig) {
+    if !config.console {
+        return;
+    }
+    #[cfg(feature = "console")]
+    {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(config.console_addr)
+            .init();
+        println!("Serving tokio-console on {}", config.console_addr);
+    }
+    #[cfg(not(feature = "console"))]
+    println!("diagnostics.console is set, but this build has no console feature");
+}
+
+/// What `GET /admin/runtime` looks at besides load shedding: the pool the store runs
+/// its file work on and the slots uploads are ingested in.
+#[derive(Clone)]
+pub struct Diagnostics {
+    pub local_pool: LocalPoolHandle,
+    pub local_pool_threads: usize,
+    pub ingest_slots: Arc<Semaphore>,
+    pub ingest_slot_count: usize,
+}
+
+#[derive(Serialize)]
+pub struct WorkerStats {
+    busy_ms: u64,
+    polls: u64,
+    parks: u64,
+    local_queue_depth: usize,
+}
+
+#[derive(Serialize)]
+pub struct RuntimeStats {
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+    /// Only in builds with `--cfg tokio_unstable`, like those for `tokio-console`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocking_queue_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_worker: Option<Vec<WorkerStats>>,
+}
+
+#[derive(Serialize)]
+pub struct PoolStats {
+    threads: usize,
+    /// Tasks waiting for a thread, high when store threads are blocked.
+    waiting_tasks: usize,
+}
+
+#[derive(Serialize)]
+pub struct SlotStats {
+    slots: usize,
+    in_use: usize,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    runtime: RuntimeStats,
+    local_pool: PoolStats,
+    ingest: SlotStats,
+    load: LoadStatus,
+}
+
+fn runtime_stats() -> RuntimeStats {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    #[allow(unused_mut)]
+    let mut stats = RuntimeStats {
+        workers: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+        blocking_threads: None,
+        idle_blocking_threads: None,
+        blocking_queue_depth: None,
+        per_worker: None,
+    };
+    #[cfg(tokio_unstable)]
+    {
+        stats.blocking_threads = Some(metrics.num_blocking_threads());
+        stats.idle_blocking_threads = Some(metrics.num_idle_blocking_threads());
+        stats.blocking_queue_depth = Some(metrics.blocking_queue_depth());
+        stats.per_worker = Some(
+            (0..metrics.num_workers())
+                .map(|worker| WorkerStats {
+                    busy_ms: metrics.worker_total_busy_duration(worker).as_millis() as u64,
+                    polls: metrics.worker_poll_count(worker),
+                    parks: metrics.worker_park_count(worker),
+                    local_queue_depth: metrics.worker_local_queue_depth(worker),
+                })
+                .collect(),
+        );
+    }
+    stats
+}
+
+/// `GET /admin/runtime`, an admin route like the rest of `/admin`.
+pub async fn runtime_report(State(app_state): State<AppState>) -> Result<Json<Report>, StatusCode> {
+    let diagnostics = &app_state.diagnostics;
+    Ok(Json(Report {
+        runtime: runtime_stats(),
+        local_pool: PoolStats {
+            threads: diagnostics.local_pool_threads,
+            waiting_tasks: diagnostics.local_pool.waiting_tasks(),
+        },
+        ingest: SlotStats {
+            slots: diagnostics.ingest_slot_count,
+            in_use: diagnostics
+                .ingest_slot_count
+                .saturating_sub(diagnostics.ingest_slots.available_permits()),
+        },
+        load: app_state.shedder.status(),
+    }))
+}
@@ -0,0 +1,299 @@
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use iroh_blobs::{store::ExportMode, Hash};
+use iroh_docs::rpc::client::docs::{Entry, LiveEvent, MemClient};
+use iroh_docs::store::Query as DocQuery;
+use iroh_docs::{AuthorId, ContentStatus, NamespaceId};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::DirSyncConfig;
+use crate::docs::MemDoc;
+
+/// Size and modification time of a file as last seen or written by the sync task.
+#[derive(Clone, Copy, PartialEq)]
+struct FileState {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl FileState {
+    fn of(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+        }
+    }
+}
+
+/// Keeps a local directory and a docs namespace in sync in both directions.
+///
+/// Local changes are picked up by rescanning the directory on an interval; remote
+/// changes are applied as they arrive on the namespace's live event stream.
+struct DirSync {
+    doc: MemDoc,
+    author: AuthorId,
+    root: PathBuf,
+    /// Files whose current contents are known to be reflected in the namespace.
+    known: HashMap<String, FileState>,
+    /// Remote entries whose content is still downloading, by content hash.
+    pending: HashMap<Hash, Vec<String>>,
+}
+
+/// Starts a sync task for every configured directory.
+pub async fn spawn(client: &MemClient, configs: &[DirSyncConfig]) -> Result<()> {
+    for config in configs {
+        let namespace = NamespaceId::from_str(&config.namespace)
+            .with_context(|| format!("Invalid sync namespace {}", config.namespace))?;
+        let doc = client
+            .open(namespace)
+            .await?
+            .with_context(|| format!("Unknown sync namespace {}", namespace))?;
+        let author = client.authors().default().await?;
+
+        fs::create_dir_all(&config.path)?;
+        let root = fs::canonicalize(&config.path)?;
+        let interval = Duration::from_secs(config.interval_secs);
+
+        doc.start_sync(vec![]).await?;
+        println!("Syncing {} with namespace {}", root.display(), namespace);
+
+        let sync = DirSync {
+            doc,
+            author,
+            root,
+            known: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = sync.run(interval).await {
+                println!("Directory sync for {} stopped: {}", namespace, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+impl DirSync {
+    async fn run(mut self, interval: Duration) -> Result<()> {
+        let mut events = self.doc.subscribe().await?;
+        self.reconcile().await?;
+
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(err) = self.scan().await {
+                        println!("Failed to scan {}: {}", self.root.display(), err);
+                    }
+                }
+                event = events.next() => {
+                    let Some(event) = event else {
+                        bail!("namespace event stream closed");
+                    };
+                    if let Err(err) = self.apply_remote(event?).await {
+                        println!("Failed to apply remote change: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Brings the directory up to date with the namespace on startup.
+    ///
+    /// Entries missing on disk are written out. When both sides have a file, the one
+    /// changed last wins; local files that win are imported by the first scan.
+    async fn reconcile(&mut self) -> Result<()> {
+        let mut entries = self
+            .doc
+            .get_many(DocQuery::single_latest_per_key().build())
+            .await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let Some((key, path)) = self.entry_path(&entry) else {
+                continue;
+            };
+            let Ok(metadata) = fs::metadata(&path) else {
+                self.write_or_defer(key, entry).await?;
+                continue;
+            };
+            if Hash::new(fs::read(&path)?) == entry.content_hash() {
+                self.known.insert(key, FileState::of(&metadata));
+                continue;
+            }
+            let modified_micros = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64;
+            if modified_micros < entry.timestamp() {
+                self.write_or_defer(key, entry).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports new and modified files and deletes entries for removed files.
+    async fn scan(&mut self) -> Result<()> {
+        let root = self.root.clone();
+        let files = tokio::task::spawn_blocking(move || walk(&root)).await??;
+
+        for (key, state) in &files {
+            if self.known.get(key) == Some(state) {
+                continue;
+            }
+            self.import(key).await?;
+            self.known.insert(key.clone(), *state);
+        }
+
+        let removed: Vec<String> = self
+            .known
+            .keys()
+            .filter(|key| !files.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.known.remove(&key);
+            self.doc.del(self.author, key.clone()).await?;
+            println!("Removed {} from sync namespace", key);
+
+            // Deletion in docs is by prefix, so re-insert any files that merely share it
+            let siblings: Vec<String> = self
+                .known
+                .keys()
+                .filter(|other| other.starts_with(&key))
+                .cloned()
+                .collect();
+            for sibling in siblings {
+                self.import(&sibling).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import(&self, key: &str) -> Result<()> {
+        let outcome = self
+            .doc
+            .import_file(self.author, key.to_string().into(), self.root.join(key), false)
+            .await?
+            .finish()
+            .await?;
+        println!("Synced {} ({} bytes) to namespace", key, outcome.size);
+        Ok(())
+    }
+
+    async fn apply_remote(&mut self, event: LiveEvent) -> Result<()> {
+        match event {
+            LiveEvent::InsertRemote {
+                entry,
+                content_status,
+                ..
+            } => {
+                let Some((key, path)) = self.entry_path(&entry) else {
+                    return Ok(());
+                };
+                if entry.record().is_empty() {
+                    self.known.remove(&key);
+                    if path.is_file() {
+                        fs::remove_file(&path)?;
+                        println!("Removed {} after remote delete", key);
+                    }
+                } else if matches!(content_status, ContentStatus::Complete) {
+                    self.write(key, entry).await?;
+                } else {
+                    self.pending
+                        .entry(entry.content_hash())
+                        .or_default()
+                        .push(key);
+                }
+            }
+            LiveEvent::ContentReady { hash } => {
+                for key in self.pending.remove(&hash).unwrap_or_default() {
+                    // Only write the content if it is still the latest version of the key
+                    let latest = self
+                        .doc
+                        .get_one(DocQuery::single_latest_per_key().key_exact(&key).build())
+                        .await?;
+                    if let Some(entry) = latest.filter(|entry| entry.content_hash() == hash) {
+                        self.write(key, entry).await?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn write_or_defer(&mut self, key: String, entry: Entry) -> Result<()> {
+        if self.write(key.clone(), entry.clone()).await.is_err() {
+            self.pending
+                .entry(entry.content_hash())
+                .or_default()
+                .push(key);
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, key: String, entry: Entry) -> Result<()> {
+        let path = self.root.join(&key);
+        self.doc
+            .export_file(entry, &path, ExportMode::Copy)
+            .await?
+            .finish()
+            .await?;
+        self.known
+            .insert(key.clone(), FileState::of(&fs::metadata(&path)?));
+        println!("Wrote {} from sync namespace", key);
+        Ok(())
+    }
+
+    /// Maps an entry key to a path inside the synced directory, rejecting keys that
+    /// are not plain relative paths so peers can't write outside of it.
+    fn entry_path(&self, entry: &Entry) -> Option<(String, PathBuf)> {
+        let key = std::str::from_utf8(entry.key()).ok()?;
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        Some((key.to_string(), self.root.join(relative)))
+    }
+}
+
+/// Lists every regular file below `root`, keyed by its `/` separated relative path.
+fn walk(root: &Path) -> Result<HashMap<String, FileState>> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for dir_entry in fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            let path = dir_entry.path();
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(root) else {
+                continue;
+            };
+            let Some(key) = relative
+                .components()
+                .map(|component| component.as_os_str().to_str())
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            files.insert(key.join("/"), FileState::of(&metadata));
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,451 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Json,
+};
+use futures::{StreamExt, TryStreamExt};
+use iroh_docs::rpc::client::docs::{Doc, Entry, LiveEvent, ShareMode};
+use iroh_docs::rpc::proto::{Request, Response};
+use iroh_docs::rpc::AddrInfoOptions;
+use iroh_docs::store::Query as DocQuery;
+use iroh_docs::{Author, AuthorId, DocTicket, NamespaceId};
+use quic_rpc::transport::flume::FlumeConnector;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::blob::{etag, etag_matches, not_modified};
+use crate::AppState;
+
+pub type MemDoc = Doc<FlumeConnector<Response, Request>>;
+
+#[derive(Serialize)]
+pub struct NamespaceResponse {
+    namespace: String,
+    capability: String,
+}
+
+#[derive(Serialize)]
+pub struct EntryResponse {
+    key: String,
+    author: String,
+    hash: String,
+    size: u64,
+    timestamp: u64,
+}
+
+impl From<&Entry> for EntryResponse {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            key: String::from_utf8_lossy(entry.key()).into_owned(),
+            author: entry.author().to_string(),
+            hash: entry.content_hash().to_string(),
+            size: entry.content_len(),
+            timestamp: entry.timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    prefix: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WriteParams {
+    /// Author to sign the write with, defaults to the node's default author.
+    author: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuthorResponse {
+    author: String,
+    default: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ImportAuthorRequest {
+    secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct ShareParams {
+    /// `read` (default) or `write`.
+    mode: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct JoinRequest {
+    ticket: String,
+}
+
+/// Opens a namespace known to this node, or 404 if it has never been created or joined.
+pub async fn open_doc(app_state: &AppState, namespace: &str) -> Result<MemDoc, StatusCode> {
+    let namespace = NamespaceId::from_str(namespace).map_err(|_| StatusCode::BAD_REQUEST)?;
+    app_state
+        .docs
+        .client()
+        .open(namespace)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Picks the author named in the request, or the default author when none is given.
+async fn resolve_author(
+    app_state: &AppState,
+    author: Option<&str>,
+) -> Result<AuthorId, StatusCode> {
+    match author {
+        Some(author) => AuthorId::from_str(author).map_err(|_| StatusCode::BAD_REQUEST),
+        None => app_state
+            .docs
+            .client()
+            .authors()
+            .default()
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn create_namespace(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let doc = app_state
+        .docs
+        .client()
+        .create()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    println!("Created namespace {}", doc.id());
+    Ok(Json(NamespaceResponse {
+        namespace: doc.id().to_string(),
+        capability: "write".to_string(),
+    }))
+}
+
+pub async fn list_namespaces(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let namespaces: Vec<_> = app_state
+        .docs
+        .client()
+        .list()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_ok(|(namespace, capability)| NamespaceResponse {
+            namespace: namespace.to_string(),
+            capability: capability.to_string(),
+        })
+        .try_collect()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(namespaces))
+}
+
+pub async fn list_entries(
+    State(app_state): State<AppState>,
+    Path(namespace): Path<String>,
+    Query(params): Query<ListParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let doc = open_doc(&app_state, &namespace).await?;
+    let query = DocQuery::single_latest_per_key()
+        .key_prefix(params.prefix.unwrap_or_default())
+        .build();
+
+    let entries: Vec<EntryResponse> = doc
+        .get_many(query)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_ok(|entry| EntryResponse::from(&entry))
+        .try_collect()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(entries))
+}
+
+pub async fn get_entry(
+    State(app_state): State<AppState>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, StatusCode> {
+    let doc = open_doc(&app_state, &namespace).await?;
+    let entry = latest_entry(&doc, &key)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let hash = entry.content_hash();
+    app_state.screening.check_serve(&app_state, hash).await?;
+    app_state.plugins.check_download(hash).await?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&hash)) == Some(true) {
+        return Ok(not_modified(&hash));
+    }
+
+    // The value may not have been downloaded yet if the entry came from a peer
+    let value = app_state
+        .blobs
+        .client()
+        .read_to_bytes(hash)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok(([(header::ETAG, etag(&hash))], value).into_response())
+}
+
+async fn latest_entry(doc: &MemDoc, key: &str) -> Result<Option<Entry>, StatusCode> {
+    doc.get_one(DocQuery::single_latest_per_key().key_exact(key).build())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Rejects a write with 412 when `If-Match` doesn't name the key's current value, so
+/// clients can update an entry without clobbering someone else's change.
+async fn check_if_match(doc: &MemDoc, key: &str, headers: &HeaderMap) -> Result<(), StatusCode> {
+    if !headers.contains_key(header::IF_MATCH) {
+        return Ok(());
+    }
+    let current = latest_entry(doc, key)
+        .await?
+        .map(|entry| entry.content_hash());
+    match etag_matches(headers, header::IF_MATCH, current.as_ref()) {
+        Some(false) => Err(StatusCode::PRECONDITION_FAILED),
+        _ => Ok(()),
+    }
+}
+
+pub async fn set_entry(
+    State(app_state): State<AppState>,
+    Path((namespace, key)): Path<(String, String)>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    value: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let doc = open_doc(&app_state, &namespace).await?;
+    let author = resolve_author(&app_state, params.author.as_deref()).await?;
+    check_if_match(&doc, &key, &headers).await?;
+
+    // Fails for read-only replicas and for authors this node doesn't hold the secret of
+    doc.set_bytes(author, key.clone(), value)
+        .await
+        .map_err(|_| StatusCode::FORBIDDEN)?;
+    let entry = doc
+        .get_exact(author, &key, false)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(EntryResponse::from(&entry)))
+}
+
+pub async fn delete_entry(
+    State(app_state): State<AppState>,
+    Path((namespace, key)): Path<(String, String)>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let doc = open_doc(&app_state, &namespace).await?;
+    let author = resolve_author(&app_state, params.author.as_deref()).await?;
+    check_if_match(&doc, &key, &headers).await?;
+
+    let removed = doc
+        .del(author, key)
+        .await
+        .map_err(|_| StatusCode::FORBIDDEN)?;
+
+    Ok(Json(serde_json::json!({ "removed": removed })))
+}
+
+pub async fn share_namespace(
+    State(app_state): State<AppState>,
+    Path(namespace): Path<String>,
+    Query(params): Query<ShareParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mode = match params.mode.as_deref().unwrap_or("read") {
+        "read" => ShareMode::Read,
+        "write" => ShareMode::Write,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let doc = open_doc(&app_state, &namespace).await?;
+
+    // Sharing write access from a read-only replica fails
+    let ticket = doc
+        .share(mode, AddrInfoOptions::RelayAndAddresses)
+        .await
+        .map_err(|_| StatusCode::FORBIDDEN)?;
+
+    Ok(Json(serde_json::json!({ "ticket": ticket.to_string() })))
+}
+
+pub async fn join_namespace(
+    State(app_state): State<AppState>,
+    Json(request): Json<JoinRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let ticket = DocTicket::from_str(&request.ticket).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let capability = ticket.capability.kind();
+
+    let doc = app_state
+        .docs
+        .client()
+        .import(ticket)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    println!("Joined namespace {}", doc.id());
+    Ok(Json(NamespaceResponse {
+        namespace: doc.id().to_string(),
+        capability: capability.to_string(),
+    }))
+}
+
+pub async fn namespace_events(
+    State(app_state): State<AppState>,
+    Path(namespace): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let doc = open_doc(&app_state, &namespace).await?;
+    let events = doc
+        .subscribe()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // The stream holds on to the doc so it stays open for as long as the client listens
+    let stream = events.map(move |event| {
+        let _ = &doc;
+        let (name, data) = match event {
+            Ok(event) => live_event_json(event),
+            Err(err) => ("error", serde_json::json!({ "message": err.to_string() })),
+        };
+        Event::default().event(name).json_data(data)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+fn live_event_json(event: LiveEvent) -> (&'static str, serde_json::Value) {
+    match event {
+        LiveEvent::InsertLocal { entry } => entry_event(&entry, None),
+        LiveEvent::InsertRemote { from, entry, .. } => entry_event(&entry, Some(from.to_string())),
+        LiveEvent::ContentReady { hash } => (
+            "content_ready",
+            serde_json::json!({ "hash": hash.to_string() }),
+        ),
+        LiveEvent::PendingContentReady => ("pending_content_ready", serde_json::json!({})),
+        LiveEvent::NeighborUp(peer) => (
+            "neighbor_up",
+            serde_json::json!({ "node_id": peer.to_string() }),
+        ),
+        LiveEvent::NeighborDown(peer) => (
+            "neighbor_down",
+            serde_json::json!({ "node_id": peer.to_string() }),
+        ),
+        LiveEvent::SyncFinished(sync) => (
+            "sync",
+            serde_json::json!({
+                "node_id": sync.peer.to_string(),
+                "entries_received": sync.result.as_ref().map(|details| details.entries_received).ok(),
+                "entries_sent": sync.result.as_ref().map(|details| details.entries_sent).ok(),
+                "error": sync.result.err(),
+            }),
+        ),
+    }
+}
+
+/// Empty entries are deletion markers, so they are reported as removals.
+fn entry_event(entry: &Entry, from: Option<String>) -> (&'static str, serde_json::Value) {
+    let name = if entry.record().is_empty() {
+        "remove"
+    } else {
+        "insert"
+    };
+    let mut data = serde_json::to_value(EntryResponse::from(entry)).unwrap_or_default();
+    data["from"] = serde_json::json!(from);
+    (name, data)
+}
+
+pub async fn create_author(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let author = app_state
+        .docs
+        .client()
+        .authors()
+        .create()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    println!("Created author {}", author);
+    Ok(Json(AuthorResponse {
+        author: author.to_string(),
+        default: false,
+    }))
+}
+
+pub async fn list_authors(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let authors_client = app_state.docs.client().authors();
+    let default = authors_client
+        .default()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let authors: Vec<_> = authors_client
+        .list()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_ok(|author| AuthorResponse {
+            author: author.to_string(),
+            default: author == default,
+        })
+        .try_collect()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(authors))
+}
+
+pub async fn import_author(
+    State(app_state): State<AppState>,
+    Json(request): Json<ImportAuthorRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let author = Author::from_str(&request.secret).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let author_id = author.id();
+
+    app_state
+        .docs
+        .client()
+        .authors()
+        .import(author)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(AuthorResponse {
+        author: author_id.to_string(),
+        default: false,
+    }))
+}
+
+pub async fn export_author(
+    State(app_state): State<AppState>,
+    Path(author): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let author_id = AuthorId::from_str(&author).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let author = app_state
+        .docs
+        .client()
+        .authors()
+        .export(author_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(serde_json::json!({
+        "author": author_id.to_string(),
+        "secret": author.to_string(),
+    })))
+}
@@ -0,0 +1,202 @@
+use anyhow::Result;
+use axum::body::Bytes;
+use iroh_blobs::rpc::client::blobs::ReadAtLen;
+use iroh_blobs::Hash;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::config::CompressionConfig;
+use crate::reload::Live;
+use crate::AppState;
+
+/// Bytes at the start of a blob looked at to guess whether it is text.
+const SNIFF_LEN: u64 = 512;
+/// What a cached "not worth compressing" decision counts against the cache budget.
+const NEGATIVE_ENTRY_COST: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// In order of preference when the client likes several equally.
+    const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::encode_all(data, 3),
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the supported encoding the client gives the highest quality in its
+/// `Accept-Encoding` header.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut wildcard = None;
+    let mut qualities = HashMap::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == "*" {
+            wildcard = Some(quality);
+        } else if let Some(encoding) = Encoding::ALL.into_iter().find(|e| e.name() == name) {
+            qualities.insert(encoding, quality);
+        }
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL {
+        let Some(quality) = qualities.get(&encoding).copied().or(wildcard) else {
+            continue;
+        };
+        if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether the start of a blob looks like text, which is what compresses well. Binary
+/// formats worth serving are usually compressed already.
+pub fn looks_like_text(prefix: &[u8]) -> bool {
+    if prefix.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        // The prefix may cut a multi-byte character in half
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+#[derive(Default)]
+struct Cache {
+    /// `None` records blobs that turned out not to be worth compressing.
+    entries: HashMap<(Hash, Encoding), Option<Bytes>>,
+    order: VecDeque<(Hash, Encoding)>,
+    size: usize,
+}
+
+impl Cache {
+    fn cost(entry: &Option<Bytes>) -> usize {
+        entry.as_ref().map_or(NEGATIVE_ENTRY_COST, Bytes::len)
+    }
+
+    fn insert(&mut self, key: (Hash, Encoding), entry: Option<Bytes>, budget: usize) {
+        let cost = Self::cost(&entry);
+        if cost > budget {
+            return;
+        }
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.size -= Self::cost(&previous);
+        } else {
+            self.order.push_back(key);
+        }
+        self.size += cost;
+        while self.size > budget {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= Self::cost(&evicted);
+            }
+        }
+    }
+}
+
+/// Compresses downloads of text-like blobs, keeping the compressed variants in memory.
+///
+/// Blobs never change under their hash, so a cached variant stays valid until evicted.
+#[derive(Clone)]
+pub struct Compressor {
+    config: Live<CompressionConfig>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl Compressor {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Self {
+            config: Live::new(config.clone()),
+            cache: Default::default(),
+        }
+    }
+
+    /// Cached variants stay, they are still valid. A smaller cache budget takes effect
+    /// with the next insert.
+    pub fn reload(&self, config: &CompressionConfig) {
+        self.config.set(config.clone());
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.get().downloads
+    }
+
+    /// The blob compressed with the best encoding the client accepts, or `None` when it
+    /// should be sent as is.
+    pub async fn encode(
+        &self,
+        app_state: &AppState,
+        hash: Hash,
+        size: u64,
+        accept_encoding: &str,
+    ) -> Result<Option<(Encoding, Bytes)>> {
+        let config = self.config.get();
+        if !config.downloads || size < config.min_size || size > config.max_size {
+            return Ok(None);
+        }
+        let Some(encoding) = negotiate(accept_encoding) else {
+            return Ok(None);
+        };
+        let key = (hash, encoding);
+        if let Some(entry) = self.cache.lock().unwrap().entries.get(&key) {
+            return Ok(entry.clone().map(|data| (encoding, data)));
+        }
+
+        let blobs_client = app_state.blobs.client();
+        let prefix = blobs_client
+            .read_at_to_bytes(hash, 0, ReadAtLen::AtMost(SNIFF_LEN))
+            .await?;
+        let entry = if looks_like_text(&prefix) {
+            let data = blobs_client.read_to_bytes(hash).await?;
+            let compressed =
+                tokio::task::spawn_blocking(move || encoding.compress(&data)).await??;
+            // Not worth it when compression barely saves anything
+            (compressed.len() < size as usize).then(|| Bytes::from(compressed))
+        } else {
+            None
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, entry.clone(), config.cache_bytes as usize);
+        Ok(entry.map(|data| (encoding, data)))
+    }
+}
@@ -0,0 +1,200 @@
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+};
+use futures::future::BoxFuture;
+use futures::{stream, StreamExt};
+use iroh::endpoint::ConnectionType;
+use iroh::{Endpoint, NodeId};
+use iroh_blobs::provider;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::AppState;
+
+/// How often the endpoint's remote info is checked for opened and closed connections.
+const CONNECTION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct NodeEvent {
+    pub name: &'static str,
+    pub data: serde_json::Value,
+}
+
+/// Fan-out of node activity to the `/events` subscribers.
+///
+/// Blob provider events arrive through the blobs event sender; connection, relay and
+/// discovery changes are picked up by watching the endpoint.
+#[derive(Clone, Debug)]
+pub struct NodeEvents {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl NodeEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        Self { sender }
+    }
+
+    pub fn send(&self, name: &'static str, data: serde_json::Value) {
+        // Nobody listening is fine, the event is simply dropped
+        let _ = self.sender.send(NodeEvent { name, data });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn watch(&self, endpoint: Endpoint) {
+        tokio::spawn(watch_connections(self.clone(), endpoint.clone()));
+        tokio::spawn(watch_home_relay(self.clone(), endpoint.clone()));
+        if let Some(mut discovered) = endpoint.discovery().and_then(|d| d.subscribe()) {
+            let events = self.clone();
+            tokio::spawn(async move {
+                while let Some(item) = discovered.next().await {
+                    events.send(
+                        "discovered",
+                        serde_json::json!({
+                            "node_id": item.node_addr.node_id.to_string(),
+                            "provenance": item.provenance,
+                            "direct_addresses": item.node_addr.direct_addresses,
+                            "relay_url": item.node_addr.relay_url,
+                        }),
+                    );
+                }
+            });
+        }
+    }
+
+    fn provider_event(&self, event: provider::Event) {
+        let (name, data) = match event {
+            provider::Event::ClientConnected { connection_id } => (
+                "client_connected",
+                serde_json::json!({ "connection_id": connection_id }),
+            ),
+            provider::Event::GetRequestReceived {
+                connection_id,
+                request_id,
+                hash,
+            } => (
+                "blob_requested",
+                serde_json::json!({
+                    "connection_id": connection_id,
+                    "request_id": request_id,
+                    "hash": hash.to_string(),
+                }),
+            ),
+            provider::Event::TransferCompleted {
+                connection_id,
+                request_id,
+                stats,
+            } => (
+                "transfer_completed",
+                serde_json::json!({
+                    "connection_id": connection_id,
+                    "request_id": request_id,
+                    "duration_ms": stats.duration.as_millis() as u64,
+                    "bytes_sent": stats.send.total().size,
+                }),
+            ),
+            provider::Event::TransferAborted {
+                connection_id,
+                request_id,
+                stats,
+            } => (
+                "transfer_aborted",
+                serde_json::json!({
+                    "connection_id": connection_id,
+                    "request_id": request_id,
+                    "bytes_sent": stats.map_or(0, |stats| stats.send.total().size),
+                }),
+            ),
+            // Per chunk progress and blob additions are too noisy or reported elsewhere
+            _ => return,
+        };
+        self.send(name, data);
+    }
+}
+
+impl provider::CustomEventSender for NodeEvents {
+    fn send(&self, event: provider::Event) -> BoxFuture<'static, ()> {
+        self.provider_event(event);
+        Box::pin(async {})
+    }
+
+    fn try_send(&self, event: provider::Event) {
+        self.provider_event(event);
+    }
+}
+
+fn conn_type_name(conn_type: &ConnectionType) -> Option<&'static str> {
+    match conn_type {
+        ConnectionType::None => None,
+        ConnectionType::Direct(_) => Some("direct"),
+        ConnectionType::Relay(_) => Some("relay"),
+        ConnectionType::Mixed(..) => Some("mixed"),
+    }
+}
+
+async fn watch_connections(events: NodeEvents, endpoint: Endpoint) {
+    let mut known: HashMap<NodeId, &'static str> = HashMap::new();
+    let mut ticker = tokio::time::interval(CONNECTION_POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let current: HashMap<NodeId, &'static str> = endpoint
+            .remote_info_iter()
+            .filter_map(|info| Some((info.node_id, conn_type_name(&info.conn_type)?)))
+            .collect();
+
+        for (node_id, conn_type) in current.iter() {
+            let name = match known.get(node_id) {
+                None => "connection_opened",
+                Some(previous) if previous != conn_type => "connection_changed",
+                Some(_) => continue,
+            };
+            events.send(
+                name,
+                serde_json::json!({ "node_id": node_id.to_string(), "conn_type": conn_type }),
+            );
+        }
+        for node_id in known
+            .keys()
+            .filter(|node_id| !current.contains_key(*node_id))
+        {
+            events.send(
+                "connection_closed",
+                serde_json::json!({ "node_id": node_id.to_string() }),
+            );
+        }
+        known = current;
+    }
+}
+
+async fn watch_home_relay(events: NodeEvents, endpoint: Endpoint) {
+    let mut relays = endpoint.home_relay().stream_updates_only();
+    while let Some(relay_url) = relays.next().await {
+        events.send(
+            "home_relay_changed",
+            serde_json::json!({ "relay_url": relay_url.map(|url| url.to_string()) }),
+        );
+    }
+}
+
+pub async fn node_events(State(app_state): State<AppState>) -> impl IntoResponse {
+    let receiver = app_state.events.subscribe();
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default().event(event.name).json_data(event.data),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => Event::default()
+                .event("lagged")
+                .json_data(serde_json::json!({ "skipped": skipped })),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((event, receiver))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,630 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use iroh::NodeAddr;
+use iroh_blobs::get::db::DownloadProgress;
+use iroh_blobs::hashseq::HashSeq;
+use iroh_blobs::net_protocol::{Blobs, DownloadMode, ProtectCb};
+use iroh_blobs::rpc::client::blobs::{BlobStatus, DownloadOptions};
+use iroh_blobs::ticket::BlobTicket;
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::{BlobFormat, Hash};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::blob::parse_hash;
+use crate::breaker::{Breakers, CircuitOpen};
+use crate::config::FetchConfig;
+use crate::events::NodeEvents;
+use crate::parallel::{self, range_bytes, Share};
+use crate::providers::{Providers, Source};
+use crate::push::parse_target;
+use crate::timeouts::{Phase, Timeouts};
+use crate::AppState;
+
+/// Seconds clients are asked to wait when the fetch queue is full.
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Returned by [`Fetcher::try_fetch`] when too many fetches are already waiting.
+#[derive(Debug)]
+pub struct QueueFull;
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fetch queue is full")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// A failed download attempt that was tried again.
+#[derive(Debug, Serialize, Clone)]
+pub struct Retry {
+    pub attempt: u32,
+    pub error: String,
+    /// How long the next attempt waited.
+    pub backoff_ms: u64,
+}
+
+/// Returned by [`Fetcher::fetch`] once the attempt budget is used up, with the attempts
+/// that came before the last one.
+#[derive(Debug, Serialize, Clone)]
+pub struct FetchFailed {
+    pub attempts: u32,
+    pub error: String,
+    pub retries: Vec<Retry>,
+}
+
+impl fmt::Display for FetchFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (after {} attempts)", self.error, self.attempts)
+    }
+}
+
+impl std::error::Error for FetchFailed {}
+
+/// A finished download, and the attempts that failed before it.
+pub struct Fetched {
+    pub local_size: u64,
+    pub downloaded_size: u64,
+    /// Bytes left by interrupted earlier attempts or fetches, which weren't downloaded
+    /// again.
+    pub resumed_size: u64,
+    /// What each node sent, when the blob came in slices from several at once.
+    pub providers: Vec<Share>,
+    pub retries: Vec<Retry>,
+}
+
+/// What one download attempt did.
+struct Downloaded {
+    local_size: u64,
+    downloaded_size: u64,
+    resumed_size: u64,
+    providers: Vec<Share>,
+}
+
+/// Downloads blobs from remote nodes, with a bound on how many run at once.
+///
+/// Every remote fetch goes through here so bursts don't open hundreds of connections
+/// and thrash the disk.
+#[derive(Clone)]
+pub struct Fetcher {
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    max_waiting: usize,
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    breakers: Breakers,
+    timeouts: Timeouts,
+    providers: Providers,
+    parallel_min_size: u64,
+    /// Hashes of fetches that failed part way, with the children of hash sequences,
+    /// kept from garbage collection so the next fetch resumes them.
+    partial: Arc<Mutex<HashMap<Hash, Vec<Hash>>>>,
+    /// Hashes being fetched right now, with how many fetches of each.
+    in_progress: Arc<Mutex<HashMap<Hash, usize>>>,
+    events: NodeEvents,
+}
+
+/// Counts a fetch as in progress until dropped.
+struct InProgress {
+    in_progress: Arc<Mutex<HashMap<Hash, usize>>>,
+    hash: Hash,
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        let mut in_progress = self.in_progress.lock().unwrap();
+        if let Some(count) = in_progress.get_mut(&self.hash) {
+            *count -= 1;
+            if *count == 0 {
+                in_progress.remove(&self.hash);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Fetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fetcher").finish_non_exhaustive()
+    }
+}
+
+impl Fetcher {
+    pub fn new(
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        config: &FetchConfig,
+        breakers: Breakers,
+        timeouts: Timeouts,
+        providers: Providers,
+        events: NodeEvents,
+    ) -> Self {
+        Self {
+            blobs,
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            waiting: Default::default(),
+            max_waiting: config.queue,
+            attempts: config.attempts.max(1),
+            backoff: Duration::from_millis(config.backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            breakers,
+            timeouts,
+            providers,
+            parallel_min_size: config.parallel_min_size,
+            partial: Default::default(),
+            in_progress: Default::default(),
+            events,
+        }
+    }
+
+    /// Keeps the partial downloads of failed fetches from garbage collection.
+    pub fn protect_cb(&self) -> ProtectCb {
+        let partial = self.partial.clone();
+        Box::new(move |live| {
+            let partial = partial.clone();
+            Box::pin(async move {
+                for (hash, children) in partial.lock().unwrap().iter() {
+                    live.insert(*hash);
+                    live.extend(children.iter().copied());
+                }
+            })
+        })
+    }
+
+    /// Whether `hash` is being fetched right now.
+    pub fn is_fetching(&self, hash: &Hash) -> bool {
+        self.in_progress.lock().unwrap().contains_key(hash)
+    }
+
+    /// Gives up on resuming the failed fetches that left `hash` behind, for partial
+    /// blobs that were removed. What they kept goes with the next garbage collection.
+    pub fn forget_partial(&self, hash: &Hash) {
+        self.partial
+            .lock()
+            .unwrap()
+            .retain(|kept, children| kept != hash && !children.contains(hash));
+    }
+
+    /// Remembers what a failed fetch left behind, so it isn't collected before the
+    /// fetch is tried again.
+    async fn keep_partial(&self, hash: Hash, format: BlobFormat) {
+        let blobs_client = self.blobs.client();
+        if matches!(
+            blobs_client.status(hash).await,
+            Ok(BlobStatus::NotFound) | Err(_)
+        ) {
+            return;
+        }
+        // Children are only known once the hash sequence itself is complete
+        let children = match format {
+            BlobFormat::HashSeq => match blobs_client.read_to_bytes(hash).await {
+                Ok(bytes) => HashSeq::try_from(bytes)
+                    .map(|hash_seq| hash_seq.iter().collect())
+                    .unwrap_or_default(),
+                Err(_) => Vec::new(),
+            },
+            BlobFormat::Raw => Vec::new(),
+        };
+        self.partial.lock().unwrap().insert(hash, children);
+    }
+
+    /// The nodes whose breakers let them be tried, failing with [`CircuitOpen`] when
+    /// that is none of them.
+    fn reachable(&self, nodes: Vec<NodeAddr>) -> Result<Vec<NodeAddr>> {
+        let mut open = None;
+        let reachable: Vec<NodeAddr> = nodes
+            .into_iter()
+            .filter(|node| match self.breakers.check(node.node_id) {
+                Ok(()) => true,
+                Err(err) => {
+                    open = Some(err);
+                    false
+                }
+            })
+            .collect();
+        match open {
+            Some(err) if reachable.is_empty() => Err(err.into()),
+            _ => Ok(reachable),
+        }
+    }
+
+    /// How long to wait before attempt `attempt + 1`: doubling from `backoff` up to
+    /// `max_backoff`, of which a random half is taken off so retries of fetches that
+    /// failed together spread out.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .backoff
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_backoff);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Downloads a blob, waiting for a free slot however long the queue is. Failed
+    /// downloads are tried again until `fetch.attempts` is used up.
+    pub async fn fetch(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        nodes: Vec<NodeAddr>,
+    ) -> Result<Fetched> {
+        self.fetch_tagged(hash, format, nodes, SetTagOption::Auto)
+            .await
+    }
+
+    /// Like [`Fetcher::fetch`], but keeps the download under `tag`.
+    pub async fn fetch_tagged(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        nodes: Vec<NodeAddr>,
+        tag: SetTagOption,
+    ) -> Result<Fetched> {
+        let mut reachable = self.reachable(nodes.clone())?;
+        *self.in_progress.lock().unwrap().entry(hash).or_default() += 1;
+        let _in_progress = InProgress {
+            in_progress: self.in_progress.clone(),
+            hash,
+        };
+        let mut retries = Vec::new();
+        let mut attempt = 1;
+        loop {
+            let tried = reachable.clone();
+            let result = self.attempt(hash, format, reachable, tag.clone()).await;
+            // Which of several nodes failed is not known, they all take the blame
+            for node in &tried {
+                self.breakers.record(node.node_id, result.is_ok());
+            }
+            let error = match result {
+                Ok(downloaded) => {
+                    self.partial.lock().unwrap().remove(&hash);
+                    self.record_providers(hash, format, tried, &downloaded.providers);
+                    self.events.send(
+                        "blob_fetched",
+                        serde_json::json!({
+                            "hash": hash.to_string(),
+                            "format": format.to_string(),
+                            "local_size": downloaded.local_size,
+                            "downloaded_size": downloaded.downloaded_size,
+                        }),
+                    );
+                    return Ok(Fetched {
+                        local_size: downloaded.local_size,
+                        downloaded_size: downloaded.downloaded_size,
+                        resumed_size: downloaded.resumed_size,
+                        providers: downloaded.providers,
+                        retries,
+                    });
+                }
+                Err(err) => format!("{:#}", err),
+            };
+            if attempt >= self.attempts {
+                self.keep_partial(hash, format).await;
+                return Err(FetchFailed {
+                    attempts: attempt,
+                    error,
+                    retries,
+                }
+                .into());
+            }
+
+            let backoff = self.backoff(attempt);
+            println!(
+                "Attempt {} to fetch {} failed, retrying in {}ms: {}",
+                attempt,
+                hash,
+                backoff.as_millis(),
+                error
+            );
+            retries.push(Retry {
+                attempt,
+                error,
+                backoff_ms: backoff.as_millis() as u64,
+            });
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+            // The failures may have opened the breakers meanwhile
+            reachable = match self.reachable(nodes.clone()) {
+                Ok(reachable) => reachable,
+                Err(open) => {
+                    self.keep_partial(hash, format).await;
+                    return Err(FetchFailed {
+                        attempts: attempt - 1,
+                        error: open.to_string(),
+                        retries,
+                    }
+                    .into());
+                }
+            };
+        }
+    }
+
+    /// Remembers the nodes a blob was fetched from: those that sent slices, or the
+    /// only one tried. Which of several the downloader picked isn't known.
+    fn record_providers(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        tried: Vec<NodeAddr>,
+        shares: &[Share],
+    ) {
+        let sent: Vec<NodeAddr> = if shares.is_empty() {
+            if tried.len() == 1 {
+                tried
+            } else {
+                Vec::new()
+            }
+        } else {
+            tried
+                .into_iter()
+                .filter(|node| {
+                    shares.iter().any(|share| {
+                        share.error.is_none() && share.node_id == node.node_id.to_string()
+                    })
+                })
+                .collect()
+        };
+        for node in sent {
+            self.providers.record(hash, format, node, Source::Fetch);
+        }
+    }
+
+    /// One download, in a free slot. Retries wait outside of slots.
+    async fn attempt(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        nodes: Vec<NodeAddr>,
+        tag: SetTagOption,
+    ) -> Result<Downloaded> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = self.permits.acquire().await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        let _permit = permit?;
+        // Large raw blobs with several holders come in slices from all of them, the
+        // download then gets what they couldn't send
+        let providers = match format {
+            BlobFormat::Raw => parallel::download(
+                &self.blobs,
+                &self.timeouts,
+                hash,
+                nodes.clone(),
+                self.parallel_min_size,
+            )
+            .await
+            .unwrap_or_else(|err| {
+                println!("Parallel fetch of {} failed: {:#}", hash, err);
+                Vec::new()
+            }),
+            BlobFormat::HashSeq => Vec::new(),
+        };
+        let mut downloaded = self.download(hash, format, nodes, tag).await?;
+        // The slices were in the store by the time the download looked
+        let sliced: u64 = providers.iter().map(|share| share.downloaded_size).sum();
+        downloaded.local_size = downloaded.local_size.saturating_sub(sliced);
+        downloaded.resumed_size = downloaded.resumed_size.saturating_sub(sliced);
+        downloaded.downloaded_size += sliced;
+        downloaded.providers = providers;
+        Ok(downloaded)
+    }
+
+    /// Like [`Fetcher::fetch`], but fails with [`QueueFull`] when the queue is full.
+    pub async fn try_fetch(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        nodes: Vec<NodeAddr>,
+    ) -> Result<Fetched> {
+        if self.permits.available_permits() == 0
+            && self.waiting.load(Ordering::Relaxed) >= self.max_waiting
+        {
+            return Err(QueueFull.into());
+        }
+        self.fetch(hash, format, nodes).await
+    }
+
+    /// Runs the download, timing each phase with the `[p2p_timeouts]`: until a node
+    /// is connected, until its first data arrives, and the download as a whole. Only
+    /// the ranges missing from partial entries in the store are requested.
+    async fn download(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        nodes: Vec<NodeAddr>,
+        tag: SetTagOption,
+    ) -> Result<Downloaded> {
+        let clock = self.timeouts.start();
+        let mut progress = self
+            .blobs
+            .client()
+            .download_with_opts(
+                hash,
+                DownloadOptions {
+                    format,
+                    nodes,
+                    tag,
+                    mode: DownloadMode::Queued,
+                },
+            )
+            .await?;
+        let mut phase = Phase::Connect;
+        let mut local_size = 0;
+        let mut downloaded_size = 0;
+        let mut resumed_size = 0;
+        loop {
+            let event = clock
+                .within(phase, progress.next())
+                .await?
+                .ok_or_else(|| anyhow!("download of {} ended prematurely", hash))??;
+            match event {
+                DownloadProgress::FoundLocal {
+                    size, valid_ranges, ..
+                } => {
+                    local_size += size.value();
+                    if !valid_ranges.is_all() {
+                        resumed_size += range_bytes(&valid_ranges.to_chunk_ranges(), size.value());
+                    }
+                }
+                DownloadProgress::Connected => phase = Phase::FirstByte,
+                DownloadProgress::Found { size, .. } => {
+                    downloaded_size += size;
+                    phase = Phase::Transfer;
+                }
+                DownloadProgress::FoundHashSeq { .. } | DownloadProgress::Progress { .. } => {
+                    phase = Phase::Transfer
+                }
+                DownloadProgress::AllDone(_) => {
+                    return Ok(Downloaded {
+                        local_size,
+                        downloaded_size,
+                        resumed_size,
+                        providers: Vec::new(),
+                    })
+                }
+                DownloadProgress::Abort(err) => return Err(err.into()),
+                DownloadProgress::InitialState(_) | DownloadProgress::Done { .. } => {}
+            }
+        }
+    }
+}
+
+/// A blob to fetch, by ticket or by hash, and further nodes holding it.
+#[derive(Deserialize)]
+pub struct FetchRequest {
+    ticket: Option<String>,
+    hash: Option<String>,
+    #[serde(default)]
+    hash_seq: bool,
+    /// Node ids, node tickets or blob tickets.
+    #[serde(default)]
+    providers: Vec<String>,
+}
+
+impl FetchRequest {
+    /// The hash and format asked for, and the nodes given to get them from.
+    fn parse(&self) -> Result<(Hash, BlobFormat, Vec<NodeAddr>), StatusCode> {
+        let ticket = match &self.ticket {
+            Some(ticket) => {
+                Some(BlobTicket::from_str(ticket).map_err(|_| StatusCode::BAD_REQUEST)?)
+            }
+            None => None,
+        };
+        let hash = match (&ticket, &self.hash) {
+            (Some(ticket), None) => ticket.hash(),
+            (ticket, Some(hash)) => {
+                let hash = parse_hash(hash)?;
+                if ticket.as_ref().is_some_and(|ticket| ticket.hash() != hash) {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                hash
+            }
+            (None, None) => return Err(StatusCode::BAD_REQUEST),
+        };
+        let format = match &ticket {
+            Some(ticket) => ticket.format(),
+            None if self.hash_seq => BlobFormat::HashSeq,
+            None => BlobFormat::Raw,
+        };
+
+        let mut nodes: Vec<NodeAddr> = ticket.iter().map(|t| t.node_addr().clone()).collect();
+        for provider in &self.providers {
+            if let Ok(ticket) = BlobTicket::from_str(provider) {
+                if ticket.hash() != hash {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+            }
+            let node = parse_target(provider).ok_or(StatusCode::BAD_REQUEST)?;
+            if !nodes.iter().any(|known| known.node_id == node.node_id) {
+                nodes.push(node);
+            }
+        }
+        Ok((hash, format, nodes))
+    }
+}
+
+pub async fn fetch_ticket(
+    State(app_state): State<AppState>,
+    Json(request): Json<FetchRequest>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let (hash, mut format, mut nodes) = request.parse().map_err(IntoResponse::into_response)?;
+    for node in &nodes {
+        app_state
+            .providers
+            .record(hash, format, node.clone(), Source::Ticket);
+    }
+    // A bare hash is fetched from the nodes it was seen on before, and those the
+    // trackers know of
+    if nodes.is_empty() {
+        if let Some((known_format, known)) = app_state.providers.get(&hash) {
+            if !request.hash_seq {
+                format = known_format;
+            }
+            nodes = known;
+        }
+        for node_id in app_state.tracker.query(hash, format).await {
+            app_state
+                .providers
+                .record(hash, format, node_id.into(), Source::Tracker);
+            if !nodes.iter().any(|node| node.node_id == node_id) {
+                nodes.push(node_id.into());
+            }
+        }
+        if nodes.is_empty() {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        }
+    }
+    let outcome = app_state
+        .fetcher
+        .try_fetch(hash, format, nodes)
+        .await
+        .map_err(|err| {
+            if err.is::<QueueFull>() {
+                let retry_after = RETRY_AFTER_SECS.to_string();
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, retry_after)],
+                )
+                    .into_response()
+            } else if let Some(open) = err.downcast_ref::<CircuitOpen>() {
+                (
+                    StatusCode::BAD_GATEWAY,
+                    [(header::RETRY_AFTER, open.retry_after_secs.to_string())],
+                    Json(serde_json::json!({ "error": "circuit_open", "breaker": open })),
+                )
+                    .into_response()
+            } else if let Some(failed) = err.downcast_ref::<FetchFailed>() {
+                println!("Failed to fetch {}: {}", hash, err);
+                (
+                    StatusCode::BAD_GATEWAY,
+                    Json(serde_json::json!({ "error": "fetch_failed", "fetch": failed })),
+                )
+                    .into_response()
+            } else {
+                println!("Failed to fetch {}: {}", hash, err);
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "hash": hash.to_string(),
+        "format": format.to_string(),
+        "local_size": outcome.local_size,
+        "downloaded_size": outcome.downloaded_size,
+        "resumed_size": outcome.resumed_size,
+        "providers": outcome.providers,
+        "retries": outcome.retries,
+    })))
+}
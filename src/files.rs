@@ -0,0 +1,351 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::listing;
+use crate::s3::{self, folder_prefix, Object};
+use crate::webdav::FOLDER_CONTENT_TYPE;
+use crate::AppState;
+
+/// A path below `/files`: the first segment is a bucket of the S3 API and the rest
+/// is a key in it, so the same files show up over S3 and WebDAV too.
+struct FilePath {
+    bucket: String,
+    /// Empty for the bucket itself.
+    key: String,
+}
+
+impl FilePath {
+    fn of(path: &str) -> Self {
+        let path = path.trim_start_matches('/');
+        match path.split_once('/') {
+            Some((bucket, key)) => Self {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            },
+            None => Self {
+                bucket: path.to_string(),
+                key: String::new(),
+            },
+        }
+    }
+
+    /// Whether the path names a folder with a trailing `/`, or is a bucket.
+    fn is_folder_path(&self) -> bool {
+        self.key.is_empty() || self.key.ends_with('/')
+    }
+
+    fn key_trimmed(&self) -> &str {
+        self.key.trim_end_matches('/')
+    }
+
+    fn display(&self) -> String {
+        if self.key.is_empty() {
+            format!("{}/", self.bucket)
+        } else {
+            format!("{}/{}", self.bucket, self.key)
+        }
+    }
+}
+
+/// Folders exist as long as their bucket does, for the top level, or as long as keys
+/// below them do, which may be just the marker named after the folder.
+fn is_folder(app_state: &AppState, bucket: &str, key: &str) -> bool {
+    if key.trim_end_matches('/').is_empty() {
+        return app_state.buckets.exists(bucket);
+    }
+    app_state
+        .buckets
+        .keys(bucket, &folder_prefix(key))
+        .is_ok_and(|keys| !keys.is_empty())
+}
+
+#[derive(Serialize)]
+pub struct FileEntry {
+    name: String,
+    /// `file` or `folder`.
+    kind: &'static str,
+    #[serde(flatten)]
+    object: Option<Object>,
+}
+
+#[derive(Serialize)]
+pub struct Listing {
+    path: String,
+    entries: Vec<FileEntry>,
+}
+
+#[derive(Serialize)]
+pub struct FileInfo {
+    path: String,
+    #[serde(flatten)]
+    object: Object,
+}
+
+/// `GET /files/`: the top level folders.
+pub async fn list_root(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let entries: Vec<FileEntry> = app_state
+        .buckets
+        .names()
+        .into_iter()
+        .map(|(name, _)| FileEntry {
+            name,
+            kind: "folder",
+            object: None,
+        })
+        .collect();
+    if listing::wants_html(&app_state, &headers) {
+        if !uri.path().ends_with('/') {
+            return listing::redirect_to_folder("files");
+        }
+        let rows: Vec<listing::Row> = entries
+            .into_iter()
+            .map(|entry| listing::Row {
+                name: entry.name,
+                is_folder: true,
+                size: None,
+                modified: None,
+            })
+            .collect();
+        return listing::render("/", false, &rows);
+    }
+    Json(Listing {
+        path: String::new(),
+        entries,
+    })
+    .into_response()
+}
+
+/// `GET /files/{*path}`: the content of a file, or what is right inside a folder.
+pub async fn get_file(
+    State(app_state): State<AppState>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Response, StatusCode> {
+    let path = FilePath::of(&path);
+    if !path.is_folder_path() && app_state.buckets.object(&path.bucket, &path.key).is_ok() {
+        let response =
+            s3::get_object(State(app_state), Path((path.bucket, path.key)), headers).await;
+        return Ok(response.unwrap_or_else(|err| err.status().into_response()));
+    }
+    if !is_folder(&app_state, &path.bucket, &path.key) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let wants_html = listing::wants_html(&app_state, &headers);
+    if wants_html && !uri.path().ends_with('/') {
+        let name = path.display();
+        let name = name
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        return Ok(listing::redirect_to_folder(name));
+    }
+    let entries: Vec<FileEntry> = app_state
+        .buckets
+        .folder(&path.bucket, &folder_prefix(&path.key))
+        .map_err(|err| err.status())?
+        .into_iter()
+        .map(|(name, object)| FileEntry {
+            name,
+            kind: if object.is_some() { "file" } else { "folder" },
+            object,
+        })
+        .collect();
+    let folder = folder_prefix(&format!("{}/{}", path.bucket, path.key_trimmed()));
+
+    if wants_html {
+        let rows: Vec<listing::Row> = entries
+            .into_iter()
+            .map(|entry| listing::Row {
+                name: entry.name,
+                is_folder: entry.object.is_none(),
+                size: entry.object.as_ref().map(|object| object.size),
+                modified: entry.object.as_ref().map(|object| object.last_modified),
+            })
+            .collect();
+        return Ok(listing::render(&format!("/{}", folder), true, &rows));
+    }
+    Ok(Json(Listing {
+        path: folder,
+        entries,
+    })
+    .into_response())
+}
+
+/// `PUT /files/{*path}`: stores the body as a file, replacing what was there, or with
+/// a trailing `/` creates an empty folder. Top level folders are created as needed.
+pub async fn put_file(
+    State(app_state): State<AppState>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    let path = FilePath::of(&path);
+    if is_folder(&app_state, &path.bucket, &path.key) {
+        if path.is_folder_path() {
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+        return Err(StatusCode::CONFLICT);
+    }
+    app_state
+        .buckets
+        .create(&path.bucket)
+        .map_err(|err| err.status())?;
+    if path.key.is_empty() {
+        return Ok(StatusCode::CREATED.into_response());
+    }
+    if path.is_folder_path() {
+        s3::store_object(
+            &app_state,
+            &path.bucket,
+            &folder_prefix(&path.key),
+            Bytes::new(),
+            FOLDER_CONTENT_TYPE.to_string(),
+        )
+        .await
+        .map_err(|err| err.status())?;
+        return Ok(StatusCode::CREATED.into_response());
+    }
+
+    let existed = app_state.buckets.object(&path.bucket, &path.key).is_ok();
+    let response = s3::put_object(
+        State(app_state.clone()),
+        Path((path.bucket.clone(), path.key.clone())),
+        headers,
+        body,
+    )
+    .await;
+    if let Err(err) = response {
+        return Err(err.status());
+    }
+    let object = app_state
+        .buckets
+        .object(&path.bucket, &path.key)
+        .map_err(|err| err.status())?;
+    let status = if existed {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((
+        status,
+        Json(FileInfo {
+            path: path.display(),
+            object,
+        }),
+    )
+        .into_response())
+}
+
+/// `DELETE /files/{*path}`: removes a file, or a folder once it is empty.
+pub async fn delete_file(
+    State(app_state): State<AppState>,
+    Path(path): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let path = FilePath::of(&path);
+    if path.key_trimmed().is_empty() {
+        app_state
+            .buckets
+            .remove(&path.bucket)
+            .map_err(|err| err.status())?;
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    if !path.is_folder_path()
+        && s3::remove_object(&app_state, &path.bucket, &path.key)
+            .await
+            .map_err(|err| err.status())?
+    {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    let marker = folder_prefix(&path.key);
+    let keys = app_state
+        .buckets
+        .keys(&path.bucket, &marker)
+        .map_err(|err| err.status())?;
+    if keys.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if keys.iter().any(|key| *key != marker) {
+        return Err(StatusCode::CONFLICT);
+    }
+    s3::remove_object(&app_state, &path.bucket, &marker)
+        .await
+        .map_err(|err| err.status())?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct MoveRequest {
+    to: String,
+}
+
+#[derive(Serialize)]
+pub struct Moved {
+    from: String,
+    to: String,
+    files: usize,
+}
+
+/// `POST /files/{*path}` with `{"to": ...}`: moves or renames a file or folder. Only
+/// the tags of the blobs move, and a path that exists already is left alone with a 409.
+pub async fn move_file(
+    State(app_state): State<AppState>,
+    Path(path): Path<String>,
+    Json(request): Json<MoveRequest>,
+) -> Result<Json<Moved>, StatusCode> {
+    let from = FilePath::of(&path);
+    let to = FilePath::of(&request.to);
+    let from_key = from.key_trimmed();
+    let to_key = to.key_trimmed();
+    // Top level folders only move to the top level
+    if to.bucket.is_empty() || from_key.is_empty() != to_key.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let is_file = app_state.buckets.object(&from.bucket, from_key).is_ok();
+    if !is_file && !is_folder(&app_state, &from.bucket, from_key) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    // A folder can't go inside itself
+    if !is_file
+        && to.bucket == from.bucket
+        && folder_prefix(to_key).starts_with(&folder_prefix(from_key))
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if app_state.buckets.object(&to.bucket, to_key).is_ok()
+        || is_folder(&app_state, &to.bucket, to_key)
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    app_state
+        .buckets
+        .create(&to.bucket)
+        .map_err(|err| err.status())?;
+    let files = s3::move_objects(&app_state, (&from.bucket, from_key), (&to.bucket, to_key))
+        .await
+        .map_err(|err| err.status())?;
+    // Moving a top level folder renames its bucket
+    if from_key.is_empty() {
+        app_state
+            .buckets
+            .remove(&from.bucket)
+            .map_err(|err| err.status())?;
+    }
+    Ok(Json(Moved {
+        from: from.display(),
+        to: to.display(),
+        files,
+    }))
+}
@@ -0,0 +1,227 @@
+use anyhow::{anyhow, bail, Result};
+use axum::body::Bytes;
+use futures::future::BoxFuture;
+use iroh::endpoint::{get_remote_node_id, Connecting};
+use iroh::protocol::ProtocolHandler;
+use iroh::{Endpoint, NodeId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use crate::config::{ForwardConfig, ForwardMode};
+use crate::reload::Live;
+use crate::upload::{ingest, UploadResponse};
+use crate::AppState;
+
+/// ALPN of the upload forwarding protocol.
+///
+/// The edge node sends a length prefixed JSON header followed by the file contents; the
+/// upstream ingests it like a regular upload and replies with its own upload response.
+pub const ALPN: &[u8] = b"iroh-api/forward/0";
+
+const MAX_HEADER_SIZE: usize = 4096;
+const MAX_FORWARD_SIZE: usize = 1 << 30;
+
+#[derive(Serialize, Deserialize)]
+struct ForwardHeader {
+    file_name: Option<String>,
+    /// Who made the upload on the edge node. The forwarding node is named instead when
+    /// left out.
+    #[serde(default)]
+    uploader: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ForwardReply {
+    response: Option<UploadResponse>,
+    error: Option<String>,
+}
+
+/// Decides whether uploads stay local and hands them to upstream gateways when not.
+#[derive(Clone)]
+pub struct Forwarder {
+    endpoint: Endpoint,
+    mode: ForwardMode,
+    upstream: Arc<Vec<NodeId>>,
+    min_free_bytes: u64,
+    data_dir: PathBuf,
+}
+
+impl Forwarder {
+    pub fn new(endpoint: Endpoint, config: &ForwardConfig, data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            endpoint,
+            mode: config.mode,
+            upstream: Arc::new(config.upstream.clone()),
+            min_free_bytes: config.min_free_bytes,
+            data_dir: data_dir.into(),
+        }
+    }
+
+    pub fn should_forward(&self) -> bool {
+        match self.mode {
+            ForwardMode::Off => false,
+            ForwardMode::Edge => true,
+            ForwardMode::WhenFull => fs2::available_space(&self.data_dir)
+                .map(|free| free < self.min_free_bytes)
+                .unwrap_or(false),
+        }
+    }
+
+    /// Forwards an upload to the first upstream that accepts it.
+    pub async fn forward(
+        &self,
+        uploader: Option<String>,
+        file_name: Option<String>,
+        data: Bytes,
+    ) -> Result<UploadResponse> {
+        let header = serde_json::to_vec(&ForwardHeader {
+            file_name,
+            uploader,
+        })?;
+        for upstream in self.upstream.iter() {
+            match forward_to(&self.endpoint, *upstream, &header, &data).await {
+                Ok(response) => {
+                    println!("Forwarded upload ({} bytes) to {}", data.len(), upstream);
+                    return Ok(response);
+                }
+                Err(err) => println!("Failed to forward upload to {}: {}", upstream, err),
+            }
+        }
+        bail!("no upstream accepted the upload")
+    }
+
+    /// Forwards an upload to one specific node, such as the cluster member owning it.
+    pub async fn forward_to_node(
+        &self,
+        node_id: NodeId,
+        uploader: Option<String>,
+        file_name: Option<String>,
+        data: Bytes,
+    ) -> Result<UploadResponse> {
+        let header = serde_json::to_vec(&ForwardHeader {
+            file_name,
+            uploader,
+        })?;
+        forward_to(&self.endpoint, node_id, &header, &data).await
+    }
+}
+
+async fn forward_to(
+    endpoint: &Endpoint,
+    upstream: NodeId,
+    header: &[u8],
+    data: &[u8],
+) -> Result<UploadResponse> {
+    let conn = endpoint.connect(upstream, ALPN).await?;
+    let (mut send, mut recv) = conn.open_bi().await?;
+    send.write_all(&(header.len() as u32).to_be_bytes()).await?;
+    send.write_all(header).await?;
+    send.write_all(data).await?;
+    send.finish()?;
+
+    let reply: ForwardReply = serde_json::from_slice(&recv.read_to_end(MAX_HEADER_SIZE).await?)?;
+    conn.close(0u32.into(), b"done");
+    match reply.response {
+        Some(response) => Ok(response),
+        None => Err(anyhow!(reply.error.unwrap_or_default())),
+    }
+}
+
+/// Accepts forwarded uploads from trusted edge nodes and configured cluster members.
+///
+/// The handler is registered before the app state exists, so the state is filled in
+/// once the node is up.
+#[derive(Clone)]
+pub struct ForwardReceiver {
+    state: Arc<OnceLock<AppState>>,
+    accept_from: Live<HashSet<NodeId>>,
+}
+
+impl fmt::Debug for ForwardReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForwardReceiver").finish_non_exhaustive()
+    }
+}
+
+impl ForwardReceiver {
+    pub fn new(accept_from: &[NodeId]) -> Self {
+        Self {
+            state: Default::default(),
+            accept_from: Live::new(accept_from.iter().copied().collect()),
+        }
+    }
+
+    pub fn set_state(&self, app_state: AppState) {
+        let _ = self.state.set(app_state);
+    }
+
+    pub fn set_accept_from(&self, accept_from: &[NodeId]) {
+        self.accept_from.set(accept_from.iter().copied().collect());
+    }
+
+    async fn handle(self, conn: Connecting) -> Result<()> {
+        let conn = conn.await?;
+        let remote = get_remote_node_id(&conn)?;
+        let (mut send, mut recv) = conn.accept_bi().await?;
+
+        let result = async {
+            let app_state = self
+                .state
+                .get()
+                .ok_or_else(|| anyhow!("node is starting"))?;
+            // Cluster members are trusted by configuration, not by having sent heartbeats
+            if !self.accept_from.get().contains(&remote) && !app_state.cluster.allows(&remote) {
+                bail!("node {} is not allowed to forward uploads", remote);
+            }
+
+            let _slot = app_state
+                .ingest_slots
+                .try_acquire()
+                .map_err(|_| anyhow!("too many uploads in progress"))?;
+
+            let mut len = [0u8; 4];
+            recv.read_exact(&mut len).await?;
+            let len = u32::from_be_bytes(len) as usize;
+            if len > MAX_HEADER_SIZE {
+                bail!("header too large");
+            }
+            let mut header = vec![0u8; len];
+            recv.read_exact(&mut header).await?;
+            let header: ForwardHeader = serde_json::from_slice(&header)?;
+            let data = recv.read_to_end(MAX_FORWARD_SIZE).await?;
+
+            let uploader = header.uploader.or_else(|| Some(remote.to_string()));
+            ingest(app_state, uploader, header.file_name, data.into())
+                .await
+                .map_err(|status| anyhow!("upload failed with {}", status))
+        }
+        .await;
+
+        let reply = match result {
+            Ok(response) => ForwardReply {
+                response: Some(response),
+                error: None,
+            },
+            Err(err) => {
+                println!("Rejected forwarded upload from {}: {}", remote, err);
+                ForwardReply {
+                    response: None,
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+        send.write_all(&serde_json::to_vec(&reply)?).await?;
+        send.finish()?;
+        conn.closed().await;
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for ForwardReceiver {
+    fn accept(&self, conn: Connecting) -> BoxFuture<'static, Result<()>> {
+        Box::pin(self.clone().handle(conn))
+    }
+}
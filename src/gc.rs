@@ -0,0 +1,101 @@
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::orphans::referenced;
+use crate::partial;
+use crate::AppState;
+
+/// A blob the next garbage collection would delete.
+#[derive(Serialize)]
+pub struct Collectable {
+    hash: Hash,
+    /// What it takes up in the store: all of it for complete blobs, what arrived of
+    /// partial ones.
+    bytes: u64,
+    complete: bool,
+}
+
+#[derive(Serialize)]
+pub struct GcPreview {
+    blobs: Vec<Collectable>,
+    count: usize,
+    bytes: u64,
+}
+
+/// What garbage collection would take right now: every blob, complete or partial, that
+/// no tag, temp tag, hash sequence, document or fetch waiting to resume keeps.
+pub async fn preview(app_state: &AppState) -> Result<GcPreview> {
+    let live = referenced(app_state).await?;
+    let mut blobs = Vec::new();
+    let mut list = app_state.blobs.client().list().await?;
+    while let Some(blob) = list.next().await {
+        let blob = blob?;
+        if !live.contains(&blob.hash) {
+            blobs.push(Collectable {
+                hash: blob.hash,
+                bytes: blob.size,
+                complete: true,
+            });
+        }
+    }
+    for blob in partial::list(&app_state.blobs).await? {
+        if !live.contains(&blob.hash) {
+            blobs.push(Collectable {
+                hash: blob.hash,
+                bytes: blob.present_bytes,
+                complete: false,
+            });
+        }
+    }
+    blobs.sort_by_key(|blob| blob.hash);
+    Ok(GcPreview {
+        count: blobs.len(),
+        bytes: blobs.iter().map(|blob| blob.bytes).sum(),
+        blobs,
+    })
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct GcParams {
+    dry_run: bool,
+}
+
+/// `POST /admin/gc`: runs a garbage collection cycle as a job. With `?dry_run=true` it
+/// only answers with what the cycle would delete.
+///
+/// Cycles can only be run on demand when they are scheduled, otherwise they run every
+/// `gc.interval_secs` on their own and this answers 409. Like scheduled ones, the cycle
+/// starts at most `gc.interval_secs` after the last.
+pub async fn run_gc(
+    State(app_state): State<AppState>,
+    Query(params): Query<GcParams>,
+) -> Result<Response, StatusCode> {
+    if params.dry_run {
+        let preview = preview(&app_state).await.map_err(|err| {
+            println!("Failed to preview garbage collection: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        return Ok(Json(preview).into_response());
+    }
+    let Some(gc_window) = app_state.scheduler.gc_window().cloned() else {
+        return Err(StatusCode::CONFLICT);
+    };
+    let job_id = app_state.jobs.spawn("gc", async move {
+        gc_window.run().await;
+        Ok(serde_json::json!({}))
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "job_id": job_id })),
+    )
+        .into_response())
+}
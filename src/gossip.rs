@@ -0,0 +1,120 @@
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+};
+use futures::StreamExt;
+use iroh::NodeId;
+use iroh_gossip::net::{Event, GossipEvent, GossipReceiver, GossipSender};
+use iroh_gossip::proto::TopicId;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct JoinParams {
+    /// Comma separated node ids used to bootstrap the topic swarm.
+    peers: Option<String>,
+}
+
+/// Accepts either a hex encoded 32 byte topic id or an arbitrary name,
+/// which is hashed into a topic id so clients can agree on readable names.
+pub fn parse_topic(topic: &str) -> TopicId {
+    TopicId::from_str(topic)
+        .unwrap_or_else(|_| TopicId::from_bytes(*iroh_blobs::Hash::new(topic).as_bytes()))
+}
+
+/// Parses a hex or base32 node id.
+///
+/// `NodeId::from_str` panics on base32 input of the wrong length, so the length is
+/// checked before handing user input to it.
+pub fn parse_node_id(node_id: &str) -> Option<NodeId> {
+    match node_id.len() {
+        52 | 64 => NodeId::from_str(node_id).ok(),
+        _ => None,
+    }
+}
+
+pub fn parse_peers(peers: Option<&str>) -> Result<Vec<NodeId>, StatusCode> {
+    peers
+        .unwrap_or_default()
+        .split(',')
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| parse_node_id(peer.trim()).ok_or(StatusCode::BAD_REQUEST))
+        .collect()
+}
+
+pub async fn topic_ws(
+    State(app_state): State<AppState>,
+    Path(topic): Path<String>,
+    Query(params): Query<JoinParams>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let topic_id = parse_topic(&topic);
+    let bootstrap = parse_peers(params.peers.as_deref())?;
+
+    let topic = app_state
+        .gossip
+        .subscribe(topic_id, bootstrap)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (sender, receiver) = topic.split();
+
+    println!("WebSocket joined gossip topic {}", topic_id);
+    Ok(ws.on_upgrade(move |socket| relay(socket, sender, receiver)))
+}
+
+/// Pumps messages between the websocket and the gossip topic until either side closes.
+async fn relay(mut socket: WebSocket, sender: GossipSender, mut receiver: GossipReceiver) {
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let content = match incoming {
+                    Some(Ok(Message::Text(text))) => Bytes::from(text.as_str().to_owned()),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => continue,
+                };
+                if sender.broadcast(content).await.is_err() {
+                    break;
+                }
+            }
+            event = receiver.next() => {
+                let payload = match event {
+                    Some(Ok(Event::Gossip(event))) => event_json(event),
+                    Some(Ok(Event::Lagged)) => serde_json::json!({ "type": "lagged" }),
+                    None | Some(Err(_)) => break,
+                };
+                if socket.send(Message::Text(payload.to_string().into())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn event_json(event: GossipEvent) -> serde_json::Value {
+    match event {
+        GossipEvent::Joined(peers) => serde_json::json!({
+            "type": "joined",
+            "peers": peers.iter().map(|peer| peer.to_string()).collect::<Vec<_>>(),
+        }),
+        GossipEvent::NeighborUp(peer) => serde_json::json!({
+            "type": "neighbor_up",
+            "node_id": peer.to_string(),
+        }),
+        GossipEvent::NeighborDown(peer) => serde_json::json!({
+            "type": "neighbor_down",
+            "node_id": peer.to_string(),
+        }),
+        GossipEvent::Received(message) => serde_json::json!({
+            "type": "message",
+            "delivered_from": message.delivered_from.to_string(),
+            "content": String::from_utf8_lossy(&message.content),
+        }),
+    }
+}
@@ -0,0 +1,435 @@
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::{GraphiQLSource, ALL_WEBSOCKET_PROTOCOLS};
+use async_graphql::{
+    Context, Data, EmptyMutation, Json, Object, Schema, SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::{State, WebSocketUpgrade},
+    http::header,
+    response::{Html, IntoResponse},
+};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_blobs::{BlobFormat, Hash};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::blob::parse_hash;
+use crate::network::{self, ConnectionInfo};
+use crate::replication::ReplicationStatus;
+use crate::AppState;
+
+/// How deeply queries may nest. Blobs and tags refer to each other, so without a limit
+/// one query could walk the store over and over.
+const MAX_DEPTH: usize = 10;
+/// How much work a query may ask for, counting each field once and the fields of
+/// listed blobs once per blob asked for.
+const MAX_COMPLEXITY: usize = 5000;
+/// The most blobs one `blobs` query returns.
+const MAX_BLOBS: usize = 1000;
+/// GraphiQL loads from unpkg and runs inline scripts and styles, which the default
+/// content security policy would block.
+const GRAPHIQL_CSP: &str = "default-src 'self'; script-src 'self' 'unsafe-inline' https://unpkg.com; \
+     style-src 'self' 'unsafe-inline' https://unpkg.com; img-src 'self' data: https://unpkg.com; \
+     font-src 'self' data: https://unpkg.com; connect-src 'self' ws: wss:; frame-ancestors 'none'";
+
+pub type ApiSchema = Schema<Query, EmptyMutation, SubscriptionRoot>;
+
+/// The schema, without data: each request brings the [`AppState`] along.
+pub fn schema() -> ApiSchema {
+    Schema::build(Query, EmptyMutation, SubscriptionRoot)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// The loader a request looks up the tags of blobs with, batching those of all blobs
+/// in a query into one listing.
+fn tag_loader(app_state: &AppState) -> DataLoader<TagLoader> {
+    DataLoader::new(
+        TagLoader {
+            app_state: app_state.clone(),
+        },
+        tokio::spawn,
+    )
+}
+
+fn parse(hash: &str) -> async_graphql::Result<Hash> {
+    parse_hash(hash).map_err(|_| "invalid hash".into())
+}
+
+pub struct Blob {
+    hash: Hash,
+    size: u64,
+    complete: bool,
+}
+
+impl Blob {
+    /// The blob with `hash`, or `None` when the store holds none of it.
+    async fn load(app_state: &AppState, hash: Hash) -> async_graphql::Result<Option<Self>> {
+        let (complete, size) = match app_state.blobs.client().status(hash).await? {
+            BlobStatus::NotFound => return Ok(None),
+            BlobStatus::Partial { size } => (false, size.value()),
+            BlobStatus::Complete { size } => (true, size),
+        };
+        Ok(Some(Self {
+            hash,
+            size,
+            complete,
+        }))
+    }
+}
+
+/// A blob in the store, with what `GET /blob/{hash}/info` reports about it.
+#[Object]
+impl Blob {
+    async fn hash(&self) -> String {
+        self.hash.to_string()
+    }
+
+    /// The full size, or the verified size so far for incomplete blobs.
+    async fn size(&self) -> u64 {
+        self.size
+    }
+
+    async fn complete(&self) -> bool {
+        self.complete
+    }
+
+    async fn cid(&self) -> String {
+        crate::cid::cid(&self.hash)
+    }
+
+    async fn multihash(&self) -> String {
+        crate::cid::multihash(&self.hash)
+    }
+
+    /// A ticket for fetching the blob from this node.
+    async fn ticket(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        let app_state = ctx.data::<AppState>()?;
+        let ticket = BlobTicket::new(app_state.node_id.into(), self.hash, BlobFormat::Raw)?;
+        Ok(ticket.to_string())
+    }
+
+    async fn replication(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Option<Json<ReplicationStatus>>> {
+        let app_state = ctx.data::<AppState>()?;
+        Ok(app_state.replicator.status(&self.hash).map(Json))
+    }
+
+    /// The cluster member owning the blob, in cluster mode.
+    async fn owner(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<String>> {
+        let app_state = ctx.data::<AppState>()?;
+        Ok(app_state
+            .cluster
+            .owner(&self.hash)
+            .map(|owner| owner.to_string()))
+    }
+
+    /// The tags keeping the blob from being garbage collected.
+    async fn tags(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Tag>> {
+        let loader = ctx.data::<DataLoader<TagLoader>>()?;
+        Ok(loader.load_one(self.hash).await?.unwrap_or_default())
+    }
+}
+
+#[derive(Clone)]
+pub struct Tag {
+    name: String,
+    hash: Hash,
+    format: BlobFormat,
+}
+
+#[Object]
+impl Tag {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn hash(&self) -> String {
+        self.hash.to_string()
+    }
+
+    /// `raw` for single blobs, `hash_seq` for collections.
+    async fn format(&self) -> String {
+        self.format.to_string()
+    }
+
+    async fn blob(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Blob>> {
+        Blob::load(ctx.data::<AppState>()?, self.hash).await
+    }
+}
+
+async fn list_tags(app_state: &AppState, prefix: Option<&str>) -> anyhow::Result<Vec<Tag>> {
+    let tags: Vec<_> = app_state
+        .blobs
+        .client()
+        .tags()
+        .list()
+        .await?
+        .try_collect()
+        .await?;
+    Ok(tags
+        .into_iter()
+        .map(|tag| Tag {
+            name: String::from_utf8_lossy(&tag.name.0).into_owned(),
+            hash: tag.hash,
+            format: tag.format,
+        })
+        .filter(|tag| prefix.is_none_or(|prefix| tag.name.starts_with(prefix)))
+        .collect())
+}
+
+/// Loads the tags of every blob a query asks about with one listing.
+pub struct TagLoader {
+    app_state: AppState,
+}
+
+impl Loader<Hash> for TagLoader {
+    type Value = Vec<Tag>;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, hashes: &[Hash]) -> Result<HashMap<Hash, Vec<Tag>>, Self::Error> {
+        let mut tags: HashMap<Hash, Vec<Tag>> =
+            hashes.iter().map(|hash| (*hash, Vec::new())).collect();
+        for tag in list_tags(&self.app_state, None).await.map_err(Arc::new)? {
+            if let Some(tags) = tags.get_mut(&tag.hash) {
+                tags.push(tag);
+            }
+        }
+        Ok(tags)
+    }
+}
+
+pub struct Collection {
+    hash: Hash,
+    tag: Option<String>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct CollectionEntry {
+    name: String,
+    hash: String,
+}
+
+#[async_graphql::ComplexObject]
+impl CollectionEntry {
+    async fn blob(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Blob>> {
+        Blob::load(ctx.data::<AppState>()?, parse(&self.hash)?).await
+    }
+}
+
+/// A named list of blobs, as sent and fetched together.
+#[Object]
+impl Collection {
+    async fn hash(&self) -> String {
+        self.hash.to_string()
+    }
+
+    /// The tag the collection is kept under, when listed through `collections`.
+    async fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    async fn entries(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CollectionEntry>> {
+        let app_state = ctx.data::<AppState>()?;
+        let collection = app_state.blobs.client().get_collection(self.hash).await?;
+        Ok(collection
+            .iter()
+            .map(|(name, hash)| CollectionEntry {
+                name: name.clone(),
+                hash: hash.to_string(),
+            })
+            .collect())
+    }
+}
+
+/// What `GET /network/status` and `GET /network/connections` report.
+pub struct Network;
+
+#[Object]
+impl Network {
+    async fn node_id(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(ctx.data::<AppState>()?.node_id.to_string())
+    }
+
+    async fn connections(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ConnectionInfo>> {
+        Ok(network::connections(&ctx.data::<AppState>()?.endpoint))
+    }
+
+    /// Relay, direct addresses, NAT and discovery details.
+    async fn status(&self, ctx: &Context<'_>) -> async_graphql::Result<Json<serde_json::Value>> {
+        Ok(Json(network::status(ctx.data::<AppState>()?)))
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn blob(&self, ctx: &Context<'_>, hash: String) -> async_graphql::Result<Option<Blob>> {
+        Blob::load(ctx.data::<AppState>()?, parse(&hash)?).await
+    }
+
+    /// Complete blobs in the store, in store order.
+    #[graphql(complexity = "limit.min(MAX_BLOBS) * child_complexity")]
+    async fn blobs(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 100)] limit: usize,
+        #[graphql(default)] offset: usize,
+    ) -> async_graphql::Result<Vec<Blob>> {
+        let app_state = ctx.data::<AppState>()?;
+        let blobs: Vec<_> = app_state
+            .blobs
+            .client()
+            .list()
+            .await?
+            .skip(offset)
+            .take(limit.min(MAX_BLOBS))
+            .try_collect()
+            .await?;
+        Ok(blobs
+            .into_iter()
+            .map(|blob| Blob {
+                hash: blob.hash,
+                size: blob.size,
+                complete: true,
+            })
+            .collect())
+    }
+
+    async fn tags(
+        &self,
+        ctx: &Context<'_>,
+        prefix: Option<String>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        Ok(list_tags(ctx.data::<AppState>()?, prefix.as_deref()).await?)
+    }
+
+    async fn collection(&self, hash: String) -> async_graphql::Result<Collection> {
+        Ok(Collection {
+            hash: parse(&hash)?,
+            tag: None,
+        })
+    }
+
+    /// Collections kept under a tag.
+    async fn collections(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Collection>> {
+        let app_state = ctx.data::<AppState>()?;
+        let collections: Vec<_> = app_state
+            .blobs
+            .client()
+            .list_collections()?
+            .try_collect()
+            .await?;
+        Ok(collections
+            .into_iter()
+            .map(|collection| Collection {
+                hash: collection.hash,
+                tag: Some(String::from_utf8_lossy(&collection.tag.0).into_owned()),
+            })
+            .collect())
+    }
+
+    async fn network(&self) -> Network {
+        Network
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Event {
+    name: String,
+    data: Json<serde_json::Value>,
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Node activity, as sent on `GET /events`, limited to the events named in `names`
+    /// when given. Subscribers too slow to keep up get a `lagged` event.
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        names: Option<Vec<String>>,
+    ) -> async_graphql::Result<impl Stream<Item = Event>> {
+        let receiver = ctx.data::<AppState>()?.events.subscribe();
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            let event = match receiver.recv().await {
+                Ok(event) => Event {
+                    name: event.name.to_string(),
+                    data: Json(event.data),
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => Event {
+                    name: "lagged".to_string(),
+                    data: Json(serde_json::json!({ "skipped": skipped })),
+                },
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((event, receiver))
+        });
+        Ok(events.filter(move |event| {
+            let wanted = names
+                .as_ref()
+                .is_none_or(|names| event.name == "lagged" || names.contains(&event.name));
+            async move { wanted }
+        }))
+    }
+}
+
+/// `GET /graphql`: the GraphiQL IDE.
+pub async fn graphiql(State(app_state): State<AppState>) -> impl IntoResponse {
+    let base_path = app_state.proxy.base_path();
+    (
+        [(header::CONTENT_SECURITY_POLICY, GRAPHIQL_CSP)],
+        Html(
+            GraphiQLSource::build()
+                .endpoint(&format!("{}/graphql", base_path))
+                .subscription_endpoint(&format!("{}/graphql/ws", base_path))
+                .finish(),
+        ),
+    )
+}
+
+/// `POST /graphql`: runs a query.
+pub async fn execute(
+    State(app_state): State<AppState>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let schema = app_state.graphql.clone();
+    schema
+        .execute(
+            request
+                .into_inner()
+                .data(tag_loader(&app_state))
+                .data(app_state),
+        )
+        .await
+        .into()
+}
+
+/// `GET /graphql/ws`: subscriptions over WebSocket, with either GraphQL WebSocket
+/// protocol.
+pub async fn subscribe(
+    State(app_state): State<AppState>,
+    protocol: GraphQLProtocol,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            let schema = app_state.graphql.clone();
+            let mut data = Data::default();
+            data.insert(tag_loader(&app_state));
+            data.insert(app_state);
+            GraphQLWebSocket::new(socket, schema, protocol)
+                .with_data(data)
+                .serve()
+        })
+}
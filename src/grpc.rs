@@ -0,0 +1,247 @@
+use axum::body::Bytes;
+use axum::extract::ConnectInfo;
+use axum::http::StatusCode;
+use futures::{Stream, StreamExt, TryStreamExt};
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::ticket::BlobTicket;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::server::NamedService;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::blob::parse_hash;
+use crate::fetch::QueueFull;
+use crate::AppState;
+
+mod proto {
+    tonic::include_proto!("iroh_api.v1");
+}
+
+use proto::iroh_api_server::{IrohApi, IrohApiServer};
+use proto::{
+    BlobInfo, DownloadRequest, DownloadResponse, FetchRequest, FetchResponse, InfoRequest,
+    ListRequest, UploadRequest, UploadResponse,
+};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// The gRPC status for what the HTTP API answers with `code`.
+fn status(code: StatusCode) -> Status {
+    let message = code.canonical_reason().unwrap_or_default();
+    match code {
+        StatusCode::BAD_REQUEST => Status::invalid_argument(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::REQUEST_TIMEOUT => Status::deadline_exceeded(message),
+        StatusCode::PAYLOAD_TOO_LARGE => Status::out_of_range(message),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => Status::failed_precondition(message),
+        StatusCode::SERVICE_UNAVAILABLE => Status::resource_exhausted(message),
+        StatusCode::BAD_GATEWAY => Status::unavailable(message),
+        StatusCode::UNPROCESSABLE_ENTITY | StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => {
+            Status::permission_denied(message)
+        }
+        _ => Status::internal(message),
+    }
+}
+
+fn internal(err: anyhow::Error) -> Status {
+    Status::internal(err.to_string())
+}
+
+/// The gRPC mirror of the HTTP API, see `proto/iroh_api.proto`.
+pub struct GrpcApi {
+    app_state: AppState,
+}
+
+/// Routes for the gRPC service, to be served next to the HTTP API. They live at
+/// `/iroh_api.v1.IrohApi/*`.
+pub fn router(app_state: AppState) -> axum::Router {
+    // Not tonic's Routes, whose fallback would answer every unknown path with gRPC
+    axum::Router::new().route_service(
+        &format!("/{}/{{*method}}", IrohApiServer::<GrpcApi>::NAME),
+        IrohApiServer::new(GrpcApi { app_state }),
+    )
+}
+
+#[tonic::async_trait]
+impl IrohApi for GrpcApi {
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResponse>, Status> {
+        let app_state = &self.app_state;
+        // As with HTTP uploads, the slot bounds the memory buffered messages take up
+        let _slot = app_state
+            .ingest_slots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| status(StatusCode::SERVICE_UNAVAILABLE))?;
+        // Seen through trusted proxies like HTTP clients, from the headers tonic keeps
+        // as metadata
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+        let ip = app_state
+            .proxy
+            .client_ip(peer, &request.metadata().clone().into_headers());
+
+        let upload = app_state.upload.get();
+        let read_timeout = Duration::from_secs(upload.read_timeout_secs.max(1));
+        let mut file_name = None;
+        let mut first = true;
+        let chunks = request.into_inner().map_ok(|message| {
+            if std::mem::take(&mut first) {
+                file_name = message.file_name;
+            }
+            message.data
+        });
+        let mut data = Vec::new();
+        crate::upload::read_body(
+            app_state,
+            chunks,
+            read_timeout,
+            upload.max_file_size,
+            |chunk| data.extend_from_slice(chunk),
+        )
+        .await
+        .map_err(status)?;
+
+        let response = crate::upload::store(
+            app_state,
+            ip,
+            ip.map(|ip| ip.to_string()),
+            file_name,
+            Bytes::from(data),
+            None,
+        )
+        .await
+        .map_err(status)?;
+        Ok(Response::new(UploadResponse {
+            ticket: response.ticket,
+            node_id: response.node_id,
+            blob_hash: response.blob_hash,
+            blob_format: response.blob_format,
+        }))
+    }
+
+    async fn fetch(
+        &self,
+        request: Request<FetchRequest>,
+    ) -> Result<Response<FetchResponse>, Status> {
+        let ticket = BlobTicket::from_str(&request.into_inner().ticket)
+            .map_err(|_| Status::invalid_argument("invalid ticket"))?;
+        let hash = ticket.hash();
+        let outcome = self
+            .app_state
+            .fetcher
+            .try_fetch(hash, ticket.format(), vec![ticket.node_addr().clone()])
+            .await
+            .map_err(|err| {
+                if err.is::<QueueFull>() {
+                    Status::resource_exhausted(err.to_string())
+                } else {
+                    println!("Failed to fetch {}: {}", hash, err);
+                    Status::unavailable(err.to_string())
+                }
+            })?;
+        Ok(Response::new(FetchResponse {
+            hash: hash.to_string(),
+            format: ticket.format().to_string(),
+            local_size: outcome.local_size,
+            downloaded_size: outcome.downloaded_size,
+        }))
+    }
+
+    type DownloadStream = ResponseStream<DownloadResponse>;
+
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let hash = parse_hash(&request.into_inner().hash).map_err(status)?;
+        let blobs_client = self.app_state.blobs.client();
+        if !blobs_client.has(hash).await.map_err(internal)? {
+            return Err(status(StatusCode::NOT_FOUND));
+        }
+        self.app_state
+            .screening
+            .check_serve(&self.app_state, hash)
+            .await
+            .map_err(status)?;
+        self.app_state
+            .plugins
+            .check_download(hash)
+            .await
+            .map_err(status)?;
+        let reader = blobs_client.read(hash).await.map_err(internal)?;
+        let chunks = self
+            .app_state
+            .throttle
+            .upload()
+            .stream(reader)
+            .map_ok(|data| DownloadResponse { data })
+            .map_err(|err| Status::internal(err.to_string()));
+        Ok(Response::new(Box::pin(chunks)))
+    }
+
+    type ListStream = ResponseStream<BlobInfo>;
+
+    async fn list(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        let request = request.into_inner();
+        let app_state = self.app_state.clone();
+        let blobs = app_state
+            .blobs
+            .client()
+            .list()
+            .await
+            .map_err(internal)?
+            .skip(request.offset as usize)
+            .take(request.limit.map_or(usize::MAX, |limit| limit as usize))
+            .map_ok(move |blob| BlobInfo {
+                hash: blob.hash.to_string(),
+                cid: crate::cid::cid(&blob.hash),
+                multihash: crate::cid::multihash(&blob.hash),
+                complete: true,
+                size: blob.size,
+                owner: app_state
+                    .cluster
+                    .owner(&blob.hash)
+                    .map(|owner| owner.to_string()),
+            })
+            .map_err(internal);
+        Ok(Response::new(Box::pin(blobs)))
+    }
+
+    async fn info(&self, request: Request<InfoRequest>) -> Result<Response<BlobInfo>, Status> {
+        let hash = parse_hash(&request.into_inner().hash).map_err(status)?;
+        let (complete, size) = match self
+            .app_state
+            .blobs
+            .client()
+            .status(hash)
+            .await
+            .map_err(internal)?
+        {
+            BlobStatus::NotFound => return Err(status(StatusCode::NOT_FOUND)),
+            BlobStatus::Partial { size } => (false, size.value()),
+            BlobStatus::Complete { size } => (true, size),
+        };
+        Ok(Response::new(BlobInfo {
+            hash: hash.to_string(),
+            cid: crate::cid::cid(&hash),
+            multihash: crate::cid::multihash(&hash),
+            complete,
+            size,
+            owner: self
+                .app_state
+                .cluster
+                .owner(&hash)
+                .map(|owner| owner.to_string()),
+        }))
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::util::Tag;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blob::parse_hash;
+use crate::metering::key_id;
+use crate::persist::StateFile;
+use crate::tenancy::api_key;
+use crate::AppState;
+
+/// Tags held blobs are kept under, which garbage collection can't take them from.
+pub const TAG_PREFIX: &str = "hold/";
+
+fn hold_tag(hash: &Hash) -> Tag {
+    Tag::from(format!("{}{}", TAG_PREFIX, hash))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Hold {
+    pub hash: Hash,
+    pub format: BlobFormat,
+    pub reason: Option<String>,
+    pub held_at: u64,
+}
+
+/// Blobs under legal hold, which nothing may delete until the hold is released.
+///
+/// Like pins, holds are tags on the blobs. Deletes through the API, S3, WebDAV or
+/// aliases are refused for held blobs too, and every hold and release goes to the
+/// audit log.
+#[derive(Clone)]
+pub struct Holds {
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    holds: Arc<RwLock<BTreeMap<Hash, Hold>>>,
+    file: StateFile,
+}
+
+impl Holds {
+    pub fn load(
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self> {
+        let path = path.into();
+        let mut holds = BTreeMap::new();
+        if path.exists() {
+            let saved: Vec<Hold> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            for hold in saved {
+                holds.insert(hold.hash, hold);
+            }
+        }
+        let holds = Arc::new(RwLock::new(holds));
+        let file = StateFile::spawn(path, {
+            let holds = holds.clone();
+            move || to_json(&holds.read().unwrap())
+        });
+        Ok(Self { blobs, holds, file })
+    }
+
+    async fn hold(&self, hash: Hash, format: BlobFormat, reason: Option<String>) -> Result<Hold> {
+        let held_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let hold = Hold {
+            hash,
+            format,
+            reason,
+            held_at,
+        };
+        self.put(hold.clone()).await?;
+        Ok(hold)
+    }
+
+    /// Places `hold`, replacing any hold on its blob.
+    async fn put(&self, hold: Hold) -> Result<()> {
+        let batch = self.blobs.client().batch().await?;
+        let content = HashAndFormat {
+            hash: hold.hash,
+            format: hold.format,
+        };
+        let temp_tag = batch.temp_tag(content).await?;
+        batch.persist_to(temp_tag, hold_tag(&hold.hash)).await?;
+        self.holds.write().unwrap().insert(hold.hash, hold);
+        self.file.changed();
+        Ok(())
+    }
+
+    async fn release(&self, hash: &Hash) -> Result<Option<Hold>> {
+        let Some(hold) = self.get(hash) else {
+            return Ok(None);
+        };
+        self.blobs.client().tags().delete(hold_tag(hash)).await?;
+        self.holds.write().unwrap().remove(hash);
+        self.file.changed();
+        Ok(Some(hold))
+    }
+
+    /// Keeps a hold placed elsewhere, whose tag came along with it. A blob held here
+    /// already keeps its own hold.
+    pub fn adopt(&self, hold: Hold) -> bool {
+        let mut holds = self.holds.write().unwrap();
+        if holds.contains_key(&hold.hash) {
+            return false;
+        }
+        holds.insert(hold.hash, hold);
+        self.file.changed();
+        true
+    }
+
+    pub fn is_held(&self, hash: &Hash) -> bool {
+        self.holds.read().unwrap().contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<Hold> {
+        self.holds.read().unwrap().get(hash).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Hold> {
+        self.holds.read().unwrap().values().cloned().collect()
+    }
+
+    /// The holds as they are written to disk, also when the file is yet to catch up.
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        to_json(&self.holds.read().unwrap())
+    }
+}
+
+fn to_json(holds: &BTreeMap<Hash, Hold>) -> Result<Vec<u8>> {
+    let holds: Vec<&Hold> = holds.values().collect();
+    Ok(serde_json::to_vec_pretty(&holds)?)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct HoldRequest {
+    reason: Option<String>,
+    hash_seq: bool,
+}
+
+fn optional_body(body: &Bytes) -> Result<HoldRequest, StatusCode> {
+    if body.is_empty() {
+        Ok(HoldRequest::default())
+    } else {
+        serde_json::from_slice(body).map_err(|_| StatusCode::BAD_REQUEST)
+    }
+}
+
+async fn audit(
+    app_state: &AppState,
+    action: &str,
+    hash: Hash,
+    headers: &HeaderMap,
+    reason: Option<String>,
+) -> Result<(), StatusCode> {
+    let key = api_key(headers).map(key_id);
+    app_state
+        .audit
+        .record(action, hash, key, reason)
+        .await
+        .map_err(|err| {
+            println!("Failed to write the audit log: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// `POST /blob/{hash}/hold`: puts a stored blob under legal hold, with an optional JSON
+/// body giving the `reason`.
+pub async fn hold_blob(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Path(hash): Path<String>,
+    body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let request = optional_body(&body)?;
+    let has_blob = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !has_blob {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let format = if request.hash_seq {
+        BlobFormat::HashSeq
+    } else {
+        BlobFormat::Raw
+    };
+    let previous = app_state.holds.get(&hash);
+    let hold = app_state
+        .holds
+        .hold(hash, format, request.reason.clone())
+        .await
+        .map_err(|err| {
+            println!("Failed to hold {}: {}", hash, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    // No hold changes without the audit log knowing
+    if let Err(status) = audit(&app_state, "hold", hash, &headers, request.reason).await {
+        let rolled_back = match previous {
+            Some(previous) => app_state.holds.put(previous).await,
+            None => app_state.holds.release(&hash).await.map(|_| ()),
+        };
+        if let Err(err) = rolled_back {
+            println!("Failed to take back the hold on {}: {}", hash, err);
+        }
+        return Err(status);
+    }
+    Ok(Json(hold))
+}
+
+/// `DELETE /blob/{hash}/hold`: releases a legal hold. The optional JSON body's `reason`
+/// goes to the audit log.
+pub async fn release_hold(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Path(hash): Path<String>,
+    body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let request = optional_body(&body)?;
+    let released = app_state.holds.release(&hash).await.map_err(|err| {
+        println!("Failed to release the hold on {}: {}", hash, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(released) = released else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if let Err(status) = audit(&app_state, "release", hash, &headers, request.reason).await {
+        if let Err(err) = app_state.holds.put(released).await {
+            println!("Failed to put back the hold on {}: {}", hash, err);
+        }
+        return Err(status);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /holds`: every blob under legal hold.
+pub async fn list_holds(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.holds.list())
+}
@@ -0,0 +1,79 @@
+use anyhow::{bail, Result};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast;
+
+use crate::config::HookConfig;
+use crate::events::{NodeEvent, NodeEvents};
+
+/// Runs the commands of `hooks` on the node events they are for, so operators can
+/// automate with shell scripts instead of writing webhook receivers.
+pub fn spawn(events: &NodeEvents, hooks: &[HookConfig]) -> Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    if let Some(hook) = hooks.iter().find(|hook| hook.command.is_empty()) {
+        bail!("Hook for {} has no command", hook.event);
+    }
+    println!("Running {} hooks on node events", hooks.len());
+    let hooks = Arc::new(hooks.to_vec());
+    let mut receiver = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    println!("Hooks missed {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            for hook in hooks.iter().filter(|hook| hook.event == event.name) {
+                let (hook, event) = (hook.clone(), event.clone());
+                tokio::spawn(async move {
+                    if let Err(err) = run(&hook, &event).await {
+                        println!("Hook {:?} for {} failed: {}", hook.command, event.name, err);
+                    }
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn run(hook: &HookConfig, event: &NodeEvent) -> Result<()> {
+    let mut command = Command::new(&hook.command[0]);
+    command
+        .args(&hook.command[1..])
+        .env("IROH_API_EVENT", event.name)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(fields) = event.data.as_object() {
+        for (key, value) in fields {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                serde_json::Value::Null => String::new(),
+                value => value.to_string(),
+            };
+            command.env(format!("IROH_API_{}", key.to_uppercase()), value);
+        }
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands that don't read their input are fine
+        let _ = stdin.write_all(event.data.to_string().as_bytes()).await;
+    }
+    let timeout = Duration::from_secs(hook.timeout_secs.max(1));
+    let Ok(status) = tokio::time::timeout(timeout, child.wait()).await else {
+        bail!("timed out after {}s", timeout.as_secs());
+    };
+    let status = status?;
+    if !status.success() {
+        bail!("exited with {}", status);
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use axum::body::{Body, Bytes};
+use axum::extract::ConnectInfo;
+use axum::http::{Request, Response};
+use axum::Router;
+use bytes::Buf;
+use futures::{stream, StreamExt};
+use h3::server::{RequestResolver, RequestStream};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// QUIC server config for HTTP/3 with the same certificate as the TCP listener.
+pub fn server_config(cert: &Path, key: &Path) -> Result<quinn::ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .context("Failed to load TLS certificate")?;
+    let key = PrivateKeyDer::from_pem_file(key).context("Failed to load TLS key")?;
+    let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Serves the API over HTTP/3 on a bound UDP socket. Experimental: request bodies and
+/// responses are streamed, but extras like WebSocket upgrades aren't available over
+/// HTTP/3.
+pub async fn serve(app: Router, socket: UdpSocket, config: quinn::ServerConfig) -> Result<()> {
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(config),
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+
+    while let Some(incoming) = endpoint.accept().await {
+        let app = app.clone();
+        tokio::spawn(async move {
+            // Connections ending, cleanly or not, show up as errors here
+            let _ = handle_connection(app, incoming).await;
+        });
+    }
+    Ok(())
+}
+
+async fn handle_connection(app: Router, incoming: quinn::Incoming) -> Result<()> {
+    let conn = incoming.await?;
+    let remote = conn.remote_address();
+    let mut conn = h3::server::Connection::new(h3_quinn::Connection::new(conn)).await?;
+    while let Some(resolver) = conn.accept().await? {
+        let app = app.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_request(app, remote, resolver).await {
+                println!("HTTP/3 request failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_request(
+    app: Router,
+    remote: SocketAddr,
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+) -> Result<()> {
+    let (request, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::from_parts(parts, Body::from_stream(request_body(recv)));
+    request.extensions_mut().insert(ConnectInfo(remote));
+    let (parts, body) = app.oneshot(request).await?.into_parts();
+
+    send.send_response(Response::from_parts(parts, ())).await?;
+    let mut body = body.into_data_stream();
+    while let Some(chunk) = body.next().await {
+        send.send_data(chunk?).await?;
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+/// The request body as a stream of chunks, ending after the first error.
+fn request_body(
+    recv: RequestStream<h3_quinn::RecvStream, Bytes>,
+) -> impl futures::Stream<Item = Result<Bytes, h3::error::StreamError>> {
+    stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut chunk)) => Some((Ok(chunk.copy_to_bytes(chunk.remaining())), Some(recv))),
+            Ok(None) => None,
+            Err(err) => Some((Err(err), None)),
+        }
+    })
+}
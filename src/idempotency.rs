@@ -0,0 +1,133 @@
+use anyhow::Result;
+use axum::http::{HeaderMap, StatusCode};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::persist::StateFile;
+use crate::upload::UploadResponse;
+
+pub const HEADER: &str = "idempotency-key";
+/// Set on responses that are replays of an earlier upload.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    response: UploadResponse,
+    stored_at: i64,
+}
+
+/// The `Idempotency-Key` of a request, if it sent one. Empty, overlong or non-ASCII keys
+/// are answered with 400.
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, StatusCode> {
+    let Some(value) = headers.get(HEADER) else {
+        return Ok(None);
+    };
+    let key = value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?.trim();
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// What to do with an upload carrying an idempotency key.
+pub enum Claim {
+    /// The key was used before, answer with its response.
+    Replay(UploadResponse),
+    /// The key is new, process the upload and hand the response to the claim.
+    New(Pending),
+}
+
+/// Responses of uploads made with an `Idempotency-Key`, so retries of an upload whose
+/// response got lost get that response again instead of storing the body a second time.
+///
+/// Responses are kept for the configured time and written to disk, so they are still
+/// known after a restart.
+#[derive(Clone)]
+pub struct Idempotency {
+    responses: Arc<Mutex<BTreeMap<String, Stored>>>,
+    in_progress: Arc<Mutex<HashSet<String>>>,
+    file: StateFile,
+}
+
+impl Idempotency {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let responses = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        let responses = Arc::new(Mutex::new(responses));
+        let file = StateFile::spawn(path, {
+            let responses = responses.clone();
+            move || Ok(serde_json::to_vec_pretty(&*responses.lock().unwrap())?)
+        });
+        Ok(Self {
+            responses,
+            in_progress: Default::default(),
+            file,
+        })
+    }
+
+    /// Claims `key` for an upload. Keys whose upload is still being processed are
+    /// answered with 409, so a retry racing the original waits for it to finish.
+    pub fn claim(&self, key: String, ttl_secs: u64) -> Result<Claim, StatusCode> {
+        let now = Utc::now().timestamp();
+        if let Some(stored) = self.responses.lock().unwrap().get(&key) {
+            if now - stored.stored_at < ttl_secs as i64 {
+                return Ok(Claim::Replay(stored.response.clone()));
+            }
+        }
+        if !self.in_progress.lock().unwrap().insert(key.clone()) {
+            return Err(StatusCode::CONFLICT);
+        }
+        Ok(Claim::New(Pending {
+            idempotency: self.clone(),
+            key,
+            ttl_secs,
+        }))
+    }
+
+    fn store(&self, key: String, response: &UploadResponse, ttl_secs: u64) {
+        let now = Utc::now().timestamp();
+        let mut responses = self.responses.lock().unwrap();
+        responses.retain(|_, stored| now - stored.stored_at < ttl_secs as i64);
+        responses.insert(
+            key,
+            Stored {
+                response: response.clone(),
+                stored_at: now,
+            },
+        );
+        self.file.changed();
+    }
+}
+
+/// A claimed key. Dropping it without completing, as failed uploads do, frees the key
+/// for a retry.
+pub struct Pending {
+    idempotency: Idempotency,
+    key: String,
+    ttl_secs: u64,
+}
+
+impl Pending {
+    pub fn complete(self, response: &UploadResponse) {
+        self.idempotency
+            .store(self.key.clone(), response, self.ttl_secs);
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.idempotency
+            .in_progress
+            .lock()
+            .unwrap()
+            .remove(&self.key);
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+use crate::fetch::FetchFailed;
+use crate::AppState;
+
+/// How many finished jobs are kept around for inspection.
+const FINISHED_JOBS_KEPT: usize = 1000;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobInfo {
+    id: u64,
+    kind: String,
+    state: JobState,
+    created_at: u64,
+    finished_at: Option<u64>,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+}
+
+/// Background job queue. Jobs run on the tokio runtime with bounded concurrency and
+/// their state is kept in memory so it can be queried over the API.
+#[derive(Clone)]
+pub struct Jobs {
+    next_id: Arc<AtomicU64>,
+    jobs: Arc<RwLock<BTreeMap<u64, JobInfo>>>,
+    permits: Arc<Semaphore>,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl Jobs {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            next_id: Arc::new(AtomicU64::new(1)),
+            jobs: Default::default(),
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// Queues a job and returns its id. The value the job resolves to is kept as its result.
+    pub fn spawn<F>(&self, kind: &str, job: F) -> u64
+    where
+        F: Future<Output = Result<serde_json::Value>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.jobs.write().unwrap().insert(
+            id,
+            JobInfo {
+                id,
+                kind: kind.to_string(),
+                state: JobState::Queued,
+                created_at: now_secs(),
+                finished_at: None,
+                result: None,
+                error: None,
+            },
+        );
+
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let _permit = jobs.permits.clone().acquire_owned().await;
+            jobs.update(id, |info| info.state = JobState::Running);
+            let outcome = job.await;
+            jobs.update(id, |info| {
+                info.finished_at = Some(now_secs());
+                match outcome {
+                    Ok(result) => {
+                        info.state = JobState::Done;
+                        info.result = Some(result);
+                    }
+                    Err(err) => {
+                        info.state = JobState::Failed;
+                        info.error = Some(err.to_string());
+                        // Failed fetches keep the attempts made, to tell flaky peers
+                        // from dead ones
+                        if let Some(failed) = err.downcast_ref::<FetchFailed>() {
+                            info.result = serde_json::to_value(failed).ok();
+                        }
+                    }
+                }
+            });
+            jobs.prune();
+        });
+        id
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut JobInfo)) {
+        if let Some(info) = self.jobs.write().unwrap().get_mut(&id) {
+            f(info);
+        }
+    }
+
+    /// Drops the oldest finished jobs once more than [`FINISHED_JOBS_KEPT`] have piled up.
+    fn prune(&self) {
+        let mut jobs = self.jobs.write().unwrap();
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|info| matches!(info.state, JobState::Done | JobState::Failed))
+            .map(|info| info.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS_KEPT))
+        {
+            jobs.remove(id);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        self.jobs.read().unwrap().get(&id).cloned()
+    }
+
+    /// Whether a job is done or failed. Jobs pruned since count as finished.
+    pub fn is_finished(&self, id: u64) -> bool {
+        self.get(id)
+            .is_none_or(|info| matches!(info.state, JobState::Done | JobState::Failed))
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.jobs.read().unwrap().values().rev().cloned().collect()
+    }
+}
+
+pub async fn list_jobs(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.jobs.list())
+}
+
+pub async fn get_job(
+    State(app_state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, StatusCode> {
+    app_state
+        .jobs
+        .get(id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
@@ -0,0 +1,177 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::DateTime;
+use iroh_blobs::store::{Map, MapEntry};
+use iroh_blobs::Hash;
+use serde::Serialize;
+use std::fmt::Write;
+
+use crate::blob::{download_blob, parse_hash};
+use crate::s3::escape;
+use crate::AppState;
+
+/// A line of a directory listing.
+pub struct Row {
+    pub name: String,
+    pub is_folder: bool,
+    pub size: Option<u64>,
+    /// Seconds since the Unix epoch.
+    pub modified: Option<i64>,
+}
+
+/// Whether to answer with an HTML index rather than JSON: only browsers get one, and
+/// only with `listing.html` on.
+pub fn wants_html(app_state: &AppState, headers: &HeaderMap) -> bool {
+    app_state.listing.html
+        && headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Sends browsers from a folder to its path with a trailing `/`, which the relative
+/// links of its index need. `name` is the last segment of the path.
+pub fn redirect_to_folder(name: &str) -> Response {
+    Redirect::permanent(&format!("{}/", encode(name))).into_response()
+}
+
+/// Percent-encodes a name for a relative link, leaving the `/` of nested names. Colons
+/// are encoded too, so no name reads as a URL scheme.
+fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+/// An index page in the manner of nginx's autoindex: a link per row, with the time it
+/// was last modified and its size where known, folders first.
+pub fn render(title: &str, parent: bool, rows: &[Row]) -> Response {
+    let title = escape(title);
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body><h1>Index of {0}</h1><hr><table>\n\
+         <tr><th align=\"left\">Name</th><th align=\"left\">Last modified</th><th align=\"right\">Size</th></tr>\n",
+        title
+    );
+    if parent {
+        body.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    let folders = rows.iter().filter(|row| row.is_folder);
+    let files = rows.iter().filter(|row| !row.is_folder);
+    for row in folders.chain(files) {
+        let suffix = if row.is_folder { "/" } else { "" };
+        let modified = row
+            .modified
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|time| time.format("%d-%b-%Y %H:%M").to_string())
+            .unwrap_or_default();
+        let size = row.size.map(|size| size.to_string()).unwrap_or("-".into());
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td align=\"right\">{}</td></tr>",
+            escape(&encode(&row.name)),
+            suffix,
+            escape(&row.name),
+            suffix,
+            modified,
+            size
+        );
+    }
+    body.push_str("</table><hr></body></html>\n");
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+#[derive(Serialize)]
+pub struct CollectionEntry {
+    name: String,
+    hash: Hash,
+    /// `None` for entries the store doesn't have.
+    size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionListing {
+    hash: Hash,
+    entries: Vec<CollectionEntry>,
+}
+
+/// `GET /collection/{hash}/`: the entries of a collection, with their sizes.
+pub async fn list_collection(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Response, StatusCode> {
+    let parsed = parse_hash(&hash)?;
+    let wants_html = wants_html(&app_state, &headers);
+    if wants_html && !uri.path().ends_with('/') {
+        return Ok(redirect_to_folder(&hash));
+    }
+    let collection = app_state
+        .blobs
+        .client()
+        .get_collection(parsed)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let mut entries = Vec::new();
+    for (name, hash) in collection.iter() {
+        let size = match app_state.blobs.store().get(hash).await {
+            Ok(Some(entry)) => Some(entry.size().value()),
+            _ => None,
+        };
+        entries.push(CollectionEntry {
+            name: name.clone(),
+            hash: *hash,
+            size,
+        });
+    }
+
+    if wants_html {
+        let rows: Vec<Row> = entries
+            .into_iter()
+            .map(|entry| Row {
+                name: entry.name,
+                is_folder: false,
+                size: entry.size,
+                modified: None,
+            })
+            .collect();
+        return Ok(render(&format!("collection {}", parsed), false, &rows));
+    }
+    Ok(Json(CollectionListing {
+        hash: parsed,
+        entries,
+    })
+    .into_response())
+}
+
+/// `GET /collection/{hash}/{*name}`: downloads an entry of a collection by name.
+pub async fn get_collection_entry(
+    State(app_state): State<AppState>,
+    Path((hash, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let collection = app_state
+        .blobs
+        .client()
+        .get_collection(hash)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let entry = collection
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .map(|(_, hash)| *hash)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    download_blob(State(app_state), Path(entry.to_string()), headers).await
+}
@@ -1,36 +1,175 @@
 use axum::{
-    extract::{Multipart, State},
-    routing::{post, get},
+    extract::{DefaultBodyLimit, State},
+    middleware,
+    routing::{any, delete, post, get, put},
     response::{IntoResponse, Json},
     Router,
 };
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::compression::{predicate::DefaultPredicate, CompressionLayer, Predicate};
 use std::fs;
+use std::future::Future;
 use std::path::Path;
-use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
 use anyhow::Result;
-use iroh::{protocol::Router as IrohRouter, Endpoint, SecretKey};
+use iroh::{Endpoint, SecretKey};
 use iroh_blobs::{
     net_protocol::Blobs,
-    ticket::BlobTicket,
-    util::local_pool::LocalPool,
+    util::local_pool::{self, LocalPool},
 };
-use tokio::net::TcpListener;
+use iroh_docs::protocol::Docs;
+use iroh_gossip::net::Gossip;
+use tokio::sync::Semaphore;
+
+mod access_log;
+mod alias;
+mod announce;
+mod antivirus;
+mod archive;
+mod audit;
+mod bao;
+mod bench;
+mod breaker;
+mod blob;
+mod caching;
+mod capabilities;
+mod cid;
+mod cluster;
+mod compact;
+mod config;
+mod cors;
+mod csrf;
+#[cfg(unix)]
+mod daemon;
+mod diagnostics;
+mod dirsync;
+mod docs;
+mod encoding;
+mod events;
+mod fetch;
+mod files;
+mod forward;
+mod gc;
+mod gossip;
+mod graphql;
+mod grpc;
+mod hooks;
+mod holds;
+mod http3;
+mod idempotency;
+mod jobs;
+mod listing;
+mod maintenance;
+mod metering;
+mod mirror;
+mod network;
+mod orphans;
+mod parallel;
+mod partial;
+mod peers;
+mod persist;
+mod pins;
+mod plugins;
+mod policy;
+mod preview;
+mod protocols;
+mod proxy;
+mod providers;
+mod push;
+mod readonly;
+mod receipt;
+mod reload;
+mod replication;
+mod roles;
+mod s3;
+mod schedule;
+mod screening;
+mod security_headers;
+mod server;
+mod shedding;
+mod snapshot;
+mod stats;
+mod systemd;
+mod tenancy;
+mod thumb;
+mod throttle;
+mod timeouts;
+mod tracker;
+mod ui;
+mod upload;
+mod users;
+mod version;
+mod webdav;
+#[cfg(windows)]
+mod winservice;
+
+use announce::Announcer;
+use caching::CacheControl;
+use cluster::Cluster;
+use config::{Config, ScheduledTask};
+use encoding::Compressor;
+use events::NodeEvents;
+use fetch::Fetcher;
+use forward::{ForwardReceiver, Forwarder};
+use jobs::Jobs;
+use peers::Peers;
+use protocols::{Pause, Protocols};
+use proxy::Proxy;
+use reload::{Live, Reloader};
+use replication::Replicator;
+use throttle::Throttle;
 
 #[derive(Clone)]
 struct AppState {
     blobs: Blobs<iroh_blobs::store::fs::Store>,
+    gossip: Gossip,
+    docs: Docs<iroh_blobs::store::fs::Store>,
+    announcer: Announcer,
+    jobs: Jobs,
+    replicator: Replicator,
+    forwarder: Forwarder,
+    cluster: Cluster,
+    peers: Peers,
+    pins: pins::Pins,
+    aliases: alias::Aliases,
+    plugins: plugins::Plugins,
+    holds: holds::Holds,
+    audit: audit::Audit,
+    scheduler: schedule::Scheduler,
+    buckets: s3::Buckets,
+    graphql: graphql::ApiSchema,
+    thumbnails: thumb::Thumbnails,
+    antivirus: antivirus::Antivirus,
+    screening: screening::Screening,
+    tenancy: tenancy::Tenancy,
+    users: users::Users,
+    metering: metering::Metering,
+    events: NodeEvents,
+    throttle: Throttle,
+    fetcher: Fetcher,
+    providers: providers::Providers,
+    tracker: tracker::Tracker,
+    breakers: breaker::Breakers,
+    timeouts: timeouts::Timeouts,
+    ingest_slots: Arc<Semaphore>,
+    shedder: shedding::LoadShedder,
+    read_only: readonly::ReadOnly,
+    maintenance: maintenance::Maintenance,
+    listing: config::ListingConfig,
+    archives: config::ArchivesConfig,
+    diagnostics: diagnostics::Diagnostics,
+    idempotency: idempotency::Idempotency,
+    stats: stats::Stats,
+    features: Arc<Vec<&'static str>>,
+    upload: Live<config::UploadConfig>,
+    compressor: Compressor,
+    access_log: access_log::AccessLog,
+    proxy: Proxy,
+    reloader: Reloader,
+    endpoint: Endpoint,
     node_id: iroh::PublicKey,
 }
 
-#[derive(Serialize)]
-struct UploadResponse {
-    ticket: String,
-    node_id: String,
-    blob_hash: String,
-    blob_format: String,
-}
-
 fn load_or_generate_secret_key(file_path: &str) -> SecretKey {
     let path = Path::new(file_path);
     if path.exists() {
@@ -48,13 +187,68 @@ fn load_or_generate_secret_key(file_path: &str) -> SecretKey {
         let secret_key = SecretKey::generate(rand::rngs::OsRng);
         let key_bytes = secret_key.to_bytes();
 
-        fs::write(path, &key_bytes).expect("Failed to write secret key file");
+        fs::write(path, key_bytes).expect("Failed to write secret key file");
         secret_key
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    // Under the Windows service control manager the service drives the runtime itself
+    #[cfg(windows)]
+    if std::env::args().any(|arg| arg == "--service") {
+        return winservice::run();
+    }
+
+    // Benchmarks run on a store of their own and don't need the config
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        return tokio::runtime::Runtime::new()?.block_on(bench::run(std::env::args().skip(2)));
+    }
+    if std::env::args().nth(1).as_deref() == Some("compact") {
+        return tokio::runtime::Runtime::new()?.block_on(compact::run(std::env::args().skip(2)));
+    }
+
+    let config = Config::load()?;
+
+    // Detach before the runtime starts its threads, only the forking thread survives a fork
+    #[cfg(unix)]
+    let daemon = std::env::args()
+        .any(|arg| arg == "--daemon")
+        .then(|| config.daemon.clone());
+    #[cfg(unix)]
+    if let Some(daemon) = &daemon {
+        daemon::daemonize(daemon)?;
+    }
+
+    let result = tokio::runtime::Runtime::new()?.block_on(run(config, Protocols::default(), shutdown_signal()));
+    #[cfg(unix)]
+    if let Some(daemon) = &daemon {
+        daemon::remove_pid_file(daemon);
+    }
+    result
+}
+
+/// Completes on Ctrl-C, or on SIGTERM on Unix, which is how daemons and services are stopped.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut terminate) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Runs the gateway until `shutdown` completes, then shuts it down gracefully.
+/// `protocols` are served on the node's endpoint next to the gateway's own.
+async fn run(
+    config: Config,
+    protocols: Protocols,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    diagnostics::init(&config.diagnostics);
+
     // Initialize secret key, endpoint, blobs, and router
 
     let secret_key_path = "secret/secret_key.bin";
@@ -63,42 +257,382 @@ async fn main() -> Result<()> {
     //     7, 248, 9, 217, 34, 111, 158, 135, 199, 100, 110, 193, 1, 232, 53, 11, 121, 235, 201, 241,
     //     64, 188, 34, 219, 189, 167, 10, 134, 165, 2, 59, 254,
     // ]);
-    let endpoint = Endpoint::builder()
-        .secret_key(secret_key)
-        .discovery_n0()
-        .bind()
-        .await?;
+    let endpoint = network::bind_endpoint(secret_key, &config.network, &config.bandwidth).await?;
 
-    let local_pool = LocalPool::default();
-    let blobs = Blobs::persistent("data").await?.build(&local_pool, &endpoint);
+    let events = NodeEvents::new();
+    let local_pool_threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let local_pool = LocalPool::new(local_pool::Config {
+        threads: local_pool_threads,
+        ..Default::default()
+    });
+    let blobs = Blobs::persistent("data")
+        .await?
+        .events(events.clone().into())
+        .build(&local_pool, &endpoint);
+    let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
+    let docs = Docs::persistent("data".into()).spawn(&blobs, &gossip).await?;
+    let breakers = breaker::Breakers::new(&config.circuit_breaker);
+    let timeouts = timeouts::Timeouts::new(&config.p2p_timeouts);
+    let providers = providers::Providers::load("data/providers.json")?;
+    let fetcher = Fetcher::new(
+        blobs.clone(),
+        &config.fetch,
+        breakers.clone(),
+        timeouts,
+        providers.clone(),
+        events.clone(),
+    );
+    let scheduled_gc = config.schedule.iter().any(|entry| entry.enabled && entry.task == ScheduledTask::Gc);
+    let gc_window = (config.gc.interval_secs > 0 && scheduled_gc).then(schedule::GcWindow::new);
+    if config.gc.interval_secs > 0 {
+        // Waits for the schedule before the others list what they protect
+        if let Some(gc_window) = &gc_window {
+            blobs.add_protected(gc_window.protect_cb())?;
+        }
+        // Document entries only keep their content through this
+        blobs.add_protected(docs.protect_cb())?;
+        // Failed fetches resume from what they left behind
+        blobs.add_protected(fetcher.protect_cb())?;
+        blobs.start_gc(iroh_blobs::store::GcConfig {
+            period: Duration::from_secs(config.gc.interval_secs),
+            done_callback: Some(Box::new({
+                let events = events.clone();
+                let gc_window = gc_window.clone();
+                move || {
+                    events.send("gc_completed", serde_json::json!({}));
+                    if let Some(gc_window) = &gc_window {
+                        gc_window.completed();
+                    }
+                }
+            })),
+        })?;
+    }
 
 
 
-    let node = IrohRouter::builder(endpoint)
+    let forward_receiver = ForwardReceiver::new(&config.forward.accept_from);
+    let push_receiver = push::PushReceiver::new(fetcher.clone(), &config.push.accept_from);
+    let pause = Pause::default();
+    let node = Protocols::default()
         .accept(iroh_blobs::ALPN, blobs.clone())
-        .spawn()
+        .accept(iroh_gossip::ALPN, gossip.clone())
+        .accept(iroh_docs::ALPN, docs.clone())
+        .accept(push::ALPN, push_receiver.clone())
+        .accept(forward::ALPN, forward_receiver.clone())
+        .merge(protocols)?
+        .spawn(endpoint, pause.clone())
         .await?;
 
     let node_id  = node.endpoint().node_id();
+    let announcer = Announcer::spawn(&gossip, &config.announce, providers.clone())?;
+    dirsync::spawn(docs.client(), &config.sync).await?;
+    let tracker = tracker::Tracker::spawn(node.endpoint().clone(), blobs.clone(), timeouts, &config.tracker);
+    let replicator = Replicator::new(node.endpoint().clone(), breakers.clone(), timeouts, &config.replication)?;
+    let jobs = Jobs::new(config.jobs.concurrency);
+    mirror::spawn(&gossip, blobs.clone(), fetcher.clone(), jobs.clone(), &config.mirror)?;
+    let cluster = Cluster::spawn(&gossip, node.endpoint().secret_key().clone(), &config.cluster)?;
+    let peers = Peers::load(node.endpoint().clone(), "data/peers.json")?;
+    let pins = pins::Pins::load(blobs.clone(), "data/pins.json")?;
+    let aliases = alias::Aliases::load(&blobs).await?;
+    let plugins = plugins::Plugins::load(&config.plugins)?;
+    let holds = holds::Holds::load(blobs.clone(), "data/holds.json")?;
+    let audit = audit::Audit::new("data/audit.jsonl");
+    let scheduler = schedule::Scheduler::new(&config.schedule, gc_window)?;
+    let buckets = s3::Buckets::load("data/s3.json")?;
+    let thumbnails = thumb::Thumbnails::load(&blobs).await?;
+    let antivirus = antivirus::Antivirus::load(&config.antivirus, "data")?;
+    let screening = screening::Screening::from_config(&config.screening)?;
+    let idempotency = idempotency::Idempotency::load("data/idempotency.json")?;
+    let stats = stats::Stats::load("data/stats.json")?;
+    events.watch(node.endpoint().clone());
+
+    let proxy = Proxy::new(&config.proxy, config.http.tls_cert.is_some());
+    let base_path = proxy.base_path().to_string();
+    let roles = roles::Roles::new(&config.roles)?;
+    let tenancy = tenancy::Tenancy::load(&config.tenancy, roles.clone(), &config.roles, &base_path, &blobs, config.gc.grace_secs).await?;
+    let users = users::Users::load(&config.sessions, roles.clone(), "data/users.json")?;
+    let metering = metering::Metering::new(&config.metering);
+    let reloader = Reloader {
+        cors: Live::new(cors::layer(&config.cors)?),
+        cache_control: CacheControl::new(&config.cache_control, &base_path)?,
+        throttle: Throttle::new(&config.bandwidth),
+        upload: Live::new(config.upload.clone()),
+        compressor: Compressor::new(&config.compression),
+        push_receiver,
+        forward_receiver: forward_receiver.clone(),
+        access_log: access_log::AccessLog::new(&config.access_log),
+        security_headers: security_headers::SecurityHeaders::new(&config.security_headers)?,
+        roles,
+        tenancy: tenancy.clone(),
+        screening: screening.clone(),
+        metering: metering.clone(),
+    };
+    #[cfg(unix)]
+    reloader.clone().reload_on_hangup()?;
 
+    let ingest_slot_count = config.upload.concurrency.max(1);
+    let ingest_slots = Arc::new(Semaphore::new(ingest_slot_count));
     let app_state = AppState{
         blobs,
+        gossip,
+        docs,
+        announcer,
+        jobs,
+        replicator,
+        forwarder: Forwarder::new(node.endpoint().clone(), &config.forward, "data"),
+        cluster,
+        peers,
+        pins,
+        aliases,
+        plugins,
+        holds,
+        audit,
+        scheduler,
+        buckets,
+        graphql: graphql::schema(),
+        thumbnails,
+        antivirus,
+        screening,
+        tenancy,
+        users,
+        metering,
+        events,
+        throttle: reloader.throttle.clone(),
+        fetcher,
+        providers,
+        tracker,
+        breakers,
+        timeouts,
+        ingest_slots: ingest_slots.clone(),
+        shedder: shedding::LoadShedder::new(&config.load_shedding, "data"),
+        read_only: readonly::ReadOnly::new(&config.read_only),
+        maintenance: maintenance::Maintenance::new(&config.maintenance, pause, &base_path)?,
+        listing: config.listing.clone(),
+        archives: config.archives.clone(),
+        diagnostics: diagnostics::Diagnostics {
+            local_pool: local_pool.handle().clone(),
+            local_pool_threads,
+            ingest_slots,
+            ingest_slot_count,
+        },
+        idempotency,
+        stats,
+        features: Arc::new(version::features(&config)),
+        upload: reloader.upload.clone(),
+        compressor: reloader.compressor.clone(),
+        access_log: reloader.access_log.clone(),
+        proxy,
+        reloader: reloader.clone(),
+        endpoint: node.endpoint().clone(),
         node_id
     };
+    forward_receiver.set_state(app_state.clone());
+    app_state.reloader.push_receiver.set_state(app_state.clone());
+    app_state.metering.spawn(app_state.clone());
+    app_state.shedder.spawn();
+    app_state.plugins.spawn(&app_state);
+    app_state.scheduler.spawn(&app_state);
+    partial::spawn(&config.partial, app_state.clone());
+    hooks::spawn(&app_state.events, &config.hooks)?;
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any) // Allow any origin (use a specific one in production)
-        .allow_methods(Any)
-        .allow_headers(Any);
     // Build Axum app
     let app = Router::new()
-    .route("/upload", post(upload_file))
+    .route("/qr", get(ui::ticket_qr))
+    // The upload policy's max_file_size limits uploads rather than axum's default 2 MiB
+    .route("/upload", post(upload::upload_file).layer(DefaultBodyLimit::disable()))
+    .route("/hash", post(upload::hash_body))
+    .route("/verify/{hash}", post(upload::verify_body))
+    .route("/fetch", post(fetch::fetch_ticket))
     .route("/node-id", get(get_node_id)) // New route for node ID
-    .with_state(app_state).layer(cors);
+    .route("/version", get(version::get_version))
+    .route("/capabilities", get(capabilities::get_capabilities))
+    .route("/events", get(events::node_events))
+    .route("/graphql", get(graphql::graphiql).post(graphql::execute))
+    .route("/graphql/ws", get(graphql::subscribe))
+    .route("/gossip/{topic}/ws", get(gossip::topic_ws))
+    .route("/network/announcements", get(announce::list_announcements))
+    .route("/network/connections", get(network::list_connections))
+    .route("/network/ping", post(network::ping))
+    .route("/network/status", get(network::network_status))
+    .route("/network/breakers", get(breaker::list_breakers))
+    .route("/peers", post(peers::add_peer).get(peers::list_peers))
+    .route("/peers/{node_id}", delete(peers::remove_peer))
+    .route("/blobs", get(blob::list_blobs))
+    .route("/blobs/partial", get(partial::list_partial))
+    .route("/blob/{hash}", get(blob::download_blob).delete(tenancy::delete_blob))
+    .route("/blob/{hash}/info", get(blob::blob_info))
+    .route("/blob/{hash}/bao", get(bao::download_verified))
+    .route("/blob/{hash}/slice", get(bao::download_slice))
+    .route("/blob/{hash}/thumb", get(thumb::get_thumbnail))
+    .route("/blob/{hash}/preview", get(preview::preview_blob))
+    .route("/raw/{hash}", get(blob::download_blob))
+    .route("/collection/{hash}", get(listing::list_collection))
+    .route("/collection/{hash}/", get(listing::list_collection))
+    .route("/collection/{hash}/{*name}", get(listing::get_collection_entry))
+    .route("/ipfs/{cid}", get(cid::download_cid))
+    .route("/blob/{hash}/push", post(push::push_blob))
+    .route("/blob/{hash}/restore", post(tenancy::restore_blob))
+    .route("/trash", get(tenancy::list_trash))
+    .route("/blob/{hash}/pin", post(pins::pin_blob).delete(pins::unpin_blob))
+    .route("/pins", get(pins::list_pins))
+    .route(
+        "/alias/{name}",
+        get(alias::get_alias).put(alias::set_alias).delete(alias::delete_alias),
+    )
+    .route("/aliases", get(alias::list_aliases))
+    .route("/a/{name}", get(alias::download_alias))
+    .route("/blob/{hash}/hold", post(holds::hold_blob).delete(holds::release_hold))
+    .route("/holds", get(holds::list_holds))
+    .route("/users", get(users::list_users))
+    .route("/users/{name}", put(users::put_user).delete(users::delete_user))
+    .route("/audit", get(audit::list_audit))
+    .route("/receipts/verify", post(receipt::verify_receipt))
+    .route("/blob/{hash}/proof", get(receipt::prove_availability))
+    .route("/proofs/verify", post(receipt::verify_proof))
+    .route("/blob/{hash}/providers", get(providers::list_providers))
+    .route("/blob/{hash}/providers/{node_id}", delete(providers::remove_provider))
+    .route("/cluster/members", get(cluster::list_members))
+    .route("/stats", get(stats::get_stats))
+    .route("/tenants", get(tenancy::list_tenants))
+    .route("/tenants/{name}/blobs", delete(tenancy::purge_tenant))
+    .route("/tenants/{name}/export", post(tenancy::export_tenant))
+    .route("/tenants/{name}/import", post(tenancy::import_tenant))
+    .route("/store/compact", post(compact::compact_store))
+    .route("/store/orphans", post(orphans::check_orphans))
+    .route("/jobs", get(jobs::list_jobs))
+    .route("/jobs/{id}", get(jobs::get_job))
+    .route("/schedule", get(schedule::list_schedule))
+    .route("/admin/reload", post(reload::reload_config))
+    .route("/admin/export-all", post(archive::export_all))
+    .route("/admin/import-all", post(archive::import_all))
+    .route("/admin/gc", post(gc::run_gc))
+    .route(
+        "/admin/read-only",
+        get(readonly::get_read_only).put(readonly::set_read_only),
+    )
+    .route(
+        "/admin/maintenance",
+        get(maintenance::get_maintenance).put(maintenance::set_maintenance),
+    )
+    .route(
+        "/admin/snapshots",
+        get(snapshot::list_snapshots).post(snapshot::create_snapshot),
+    )
+    .route("/admin/snapshots/{id}", delete(snapshot::delete_snapshot))
+    .route("/admin/snapshots/{id}/rollback", post(snapshot::rollback_snapshot))
+    .route("/admin/runtime", get(diagnostics::runtime_report))
+    .route("/docs", post(docs::create_namespace).get(docs::list_namespaces))
+    .route("/docs/join", post(docs::join_namespace))
+    .route("/authors", post(docs::create_author).get(docs::list_authors))
+    .route("/authors/import", post(docs::import_author))
+    .route("/authors/{author}/export", get(docs::export_author))
+    .route("/docs/{namespace}/share", post(docs::share_namespace))
+    .route("/docs/{namespace}/entries", get(docs::list_entries))
+    .route("/docs/{namespace}/events", get(docs::namespace_events))
+    .route(
+        "/docs/{namespace}/entries/{*key}",
+        get(docs::get_entry).put(docs::set_entry).delete(docs::delete_entry),
+    )
+    .route("/s3", get(s3::list_buckets))
+    .route(
+        "/s3/{bucket}",
+        get(s3::list_objects).put(s3::create_bucket).head(s3::head_bucket).delete(s3::delete_bucket),
+    )
+    .route(
+        "/s3/{bucket}/{*key}",
+        get(s3::get_object)
+            .head(s3::head_object)
+            .put(s3::put_object)
+            .delete(s3::delete_object)
+            .post(s3::not_implemented),
+    )
+    .route("/files", get(files::list_root))
+    .route("/files/", get(files::list_root))
+    .route(
+        "/files/{*path}",
+        get(files::get_file)
+            .put(files::put_file)
+            .post(files::move_file)
+            .delete(files::delete_file),
+    )
+    .route("/dav", any(webdav::handle))
+    .route("/dav/", any(webdav::handle))
+    .route("/dav/{*path}", any(webdav::handle))
+    .merge(app_state.plugins.routes())
+    .route_layer(middleware::from_fn_with_state(reloader.cache_control.clone(), caching::apply))
+    .route_layer(middleware::from_fn_with_state(app_state.metering.clone(), metering::apply))
+    .route_layer(middleware::from_fn_with_state(app_state.clone(), tenancy::apply))
+    .route_layer(middleware::from_fn_with_state(app_state.shedder.clone(), shedding::apply))
+    .route_layer(middleware::from_fn_with_state(app_state.read_only.clone(), readonly::apply))
+    .route_layer(middleware::from_fn(csrf::apply))
+    .route_layer(middleware::from_fn_with_state(app_state.users.clone(), users::apply))
+    // Health checks come without a key and past every limit
+    .route("/health", get(maintenance::health))
+    .route("/csrf-token", get(csrf::issue_token))
+    // The web UI loads without a key, so users can sign in to it
+    .route("/", get(ui::index))
+    .route("/ui/{*path}", get(ui::asset))
+    .route("/login", post(users::login))
+    .route("/register", post(users::register))
+    .route("/logout", post(users::logout).layer(middleware::from_fn(csrf::apply)))
+    .route("/session", get(users::get_session))
+    .with_state(app_state.clone())
+    .layer(middleware::from_fn_with_state(reloader.cors.clone(), cors::apply));
+
+    // Tenants can also be picked by path, as in /t/<name>/upload
+    let app = app_state.tenancy.names().into_iter().fold(app.clone(), |tenants, name| {
+        tenants.nest(&format!("{}{}", tenancy::PATH_PREFIX, name), app.clone())
+    });
+
+    // Behind a proxy the API can live under a prefix like /files
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        Router::new().nest(&base_path, app)
+    };
+
+    // gRPC clients always call /<package>.<service>/<method>, even behind a prefix
+    let metering_layer = middleware::from_fn_with_state(app_state.metering.clone(), metering::apply);
+    let tenancy_layer = middleware::from_fn_with_state(app_state.clone(), tenancy::apply);
+    let shedding_layer = middleware::from_fn_with_state(app_state.shedder.clone(), shedding::apply);
+    let read_only_layer = middleware::from_fn_with_state(app_state.read_only.clone(), readonly::apply);
+    let app = app.merge(
+        grpc::router(app_state.clone())
+            .route_layer(metering_layer)
+            .route_layer(tenancy_layer)
+            .route_layer(shedding_layer)
+            .route_layer(read_only_layer),
+    );
+
+    // Listings can get large; blob downloads negotiate their own encoding
+    let app = if config.compression.json {
+        let is_json = |_, _, headers: &axum::http::HeaderMap, _: &_| {
+            headers
+                .get(axum::http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("application/json"))
+        };
+        app.layer(CompressionLayer::new().compress_when(DefaultPredicate::new().and(is_json)))
+    } else {
+        app
+    };
+
+    let app = app.layer(middleware::from_fn_with_state(
+        app_state.maintenance.clone(),
+        maintenance::apply,
+    ));
+
+    // Maintenance pages and refusals get them too
+    let app = app.layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        security_headers::apply,
+    ));
+
+    // Outermost, so the log has the paths and bytes clients saw
+    let app = app.layer(middleware::from_fn_with_state(app_state, access_log::apply));
 
     // Start the server
-    let listener = TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await.unwrap();
+    server::serve(app, &config.http, shutdown).await?;
 
     // Gracefully shut down the node
     node.shutdown().await?;
@@ -106,44 +640,6 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn upload_file(
-    State(app_state): State<AppState>, // Extract shared state
-    mut multipart: Multipart,         // Extract multipart form data
-) -> Result<impl IntoResponse, axum::http::StatusCode> {
-    let blobs_client = app_state.blobs.client();
-
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let file_name = field.file_name().unwrap_or("unknown").to_string();
-        let data = field.bytes().await.unwrap();
-
-        // Attempt to add the bytes to the blob store
-        let blob = blobs_client
-            .add_bytes(data.clone())
-            .await
-            .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        let node_id: iroh::PublicKey = app_state.node_id;
-
-        // Attempt to generate the ticket
-        let ticket = BlobTicket::new(node_id.into(), blob.hash, blob.format)
-            .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        println!("Received file: {} ({} bytes)", file_name, data.len());
-
-        // Return the response with ticket, node_id, blob.hash, and blob.format
-        return Ok(Json(UploadResponse {
-            ticket: ticket.to_string(),
-            node_id: node_id.to_string(),
-            blob_hash: blob.hash.to_string(),
-            blob_format: blob.format.to_string(),
-        }));
-    }
-
-    // Return a bad request error if no file is uploaded
-    Err(axum::http::StatusCode::BAD_REQUEST)
-}
-
-
 async fn get_node_id(State(app_state): State<AppState>) -> impl IntoResponse {
     Json(serde_json::json!({
         "node_id": app_state.node_id.to_string(),
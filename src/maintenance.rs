@@ -0,0 +1,159 @@
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::config::MaintenanceConfig;
+use crate::protocols::Pause;
+use crate::s3::escape;
+use crate::AppState;
+
+/// Routes still answered during maintenance, below the base path.
+const EXEMPT: &[&str] = &["/health", "/admin/maintenance"];
+
+/// Whether the node is down for maintenance, answering every request but health checks
+/// with 503 and a message. With `pause_p2p` set, other nodes are turned away as well.
+#[derive(Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    message: Arc<RwLock<String>>,
+    /// Served to browsers instead of the message, loaded at startup.
+    page: Option<Arc<String>>,
+    pause_p2p: bool,
+    pause: Pause,
+    base_path: String,
+}
+
+impl Maintenance {
+    pub fn new(config: &MaintenanceConfig, pause: Pause, base_path: &str) -> Result<Self> {
+        let page = match &config.page {
+            Some(path) => Some(Arc::new(std::fs::read_to_string(path)?)),
+            None => None,
+        };
+        let maintenance = Self {
+            enabled: Default::default(),
+            message: Arc::new(RwLock::new(config.message.clone())),
+            page,
+            pause_p2p: config.pause_p2p,
+            pause,
+            base_path: base_path.to_string(),
+        };
+        maintenance.set(config.enabled, None);
+        Ok(maintenance)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Switches maintenance on or off, with `message` replacing the one answered.
+    pub fn set(&self, enabled: bool, message: Option<String>) {
+        if let Some(message) = message {
+            *self.message.write().unwrap() = message;
+        }
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            println!("Maintenance {}", if enabled { "started" } else { "ended" });
+        }
+        if self.pause_p2p {
+            self.pause.set(enabled);
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.message.read().unwrap().clone()
+    }
+
+    fn response(&self, wants_html: bool) -> Response {
+        let message = self.message();
+        if !wants_html {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": "maintenance", "message": message })),
+            )
+                .into_response();
+        }
+        let page = match &self.page {
+            Some(page) => page.to_string(),
+            None => format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Maintenance</title></head>\
+                 <body><h1>Maintenance</h1><p>{}</p></body></html>\n",
+                escape(&message)
+            ),
+        };
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            page,
+        )
+            .into_response()
+    }
+}
+
+/// Middleware answering everything but health checks and the switch itself with 503
+/// during maintenance.
+pub async fn apply(
+    State(maintenance): State<Maintenance>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if maintenance.is_enabled() {
+        let path = request.uri().path();
+        let route = path.strip_prefix(&maintenance.base_path).unwrap_or(path);
+        if !EXEMPT.contains(&route) {
+            let wants_html = request
+                .headers()
+                .get(header::ACCEPT)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|accept| accept.contains("text/html"));
+            return maintenance.response(wants_html);
+        }
+    }
+    next.run(request).await
+}
+
+/// `GET /health`: for load balancers and orchestrators, answered without a key and
+/// during maintenance too, which it reports.
+pub async fn health(State(app_state): State<AppState>) -> Json<serde_json::Value> {
+    let status = if app_state.maintenance.is_enabled() {
+        "maintenance"
+    } else {
+        "ok"
+    };
+    Json(serde_json::json!({
+        "status": status,
+        "read_only": app_state.read_only.is_enabled(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+    message: Option<String>,
+}
+
+/// `GET /admin/maintenance`
+pub async fn get_maintenance(State(app_state): State<AppState>) -> Json<serde_json::Value> {
+    let maintenance = &app_state.maintenance;
+    Json(serde_json::json!({
+        "enabled": maintenance.is_enabled(),
+        "message": maintenance.message(),
+        "p2p_paused": maintenance.pause.is_paused(),
+    }))
+}
+
+/// `PUT /admin/maintenance`: switches maintenance until the next restart, optionally
+/// with a new `message`.
+pub async fn set_maintenance(
+    State(app_state): State<AppState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Json<serde_json::Value> {
+    app_state.maintenance.set(request.enabled, request.message);
+    get_maintenance(State(app_state)).await
+}
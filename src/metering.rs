@@ -0,0 +1,385 @@
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use futures::TryStreamExt;
+use http_body::{Frame, SizeHint};
+use iroh_blobs::Hash;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use url::Url;
+
+use crate::blob::parse_hash;
+use crate::config::MeteringConfig;
+use crate::reload::Live;
+use crate::tenancy::{api_key, Tenant};
+use crate::AppState;
+
+/// Hex digits of an API key's hash that identify it in usage records, so the keys
+/// themselves never end up in files or webhooks.
+const KEY_ID_LEN: usize = 16;
+
+/// Who usage is billed to: a tenant (`None` for the operator) and the key used, if any.
+type Account = (Option<String>, Option<String>);
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Usage {
+    pub requests: u64,
+    pub http_egress_bytes: u64,
+    pub p2p_egress_bytes: u64,
+}
+
+/// The usage of an account over one period, as written out.
+#[derive(Serialize)]
+struct UsageRecord {
+    period_start: i64,
+    period_end: i64,
+    tenant: Option<String>,
+    key: Option<String>,
+    requests: u64,
+    http_egress_bytes: u64,
+    p2p_egress_bytes: u64,
+    /// Only on the row of the whole tenant, the one without a key.
+    stored_bytes: Option<u64>,
+}
+
+const CSV_HEADER: &str =
+    "period_start,period_end,tenant,key,requests,http_egress_bytes,p2p_egress_bytes,stored_bytes\n";
+
+impl UsageRecord {
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}\n",
+            self.period_start,
+            self.period_end,
+            self.tenant.as_deref().unwrap_or_default(),
+            self.key.as_deref().unwrap_or_default(),
+            self.requests,
+            self.http_egress_bytes,
+            self.p2p_egress_bytes,
+            self.stored_bytes
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// How an API key shows up in records: the start of its hash.
+pub fn key_id(key: &str) -> String {
+    Hash::new(key).to_hex()[..KEY_ID_LEN].to_string()
+}
+
+/// Usage by tenant and API key, for billing.
+///
+/// Requests and the bytes of their responses are counted as they are served. Blobs sent
+/// over iroh are billed to the tenants owning them, or the operator when nobody does.
+/// Every period the counts are written out with what each tenant stores, and start
+/// over. Totals since the start are kept either way, for `GET /stats`.
+#[derive(Clone)]
+pub struct Metering {
+    usage: Arc<Mutex<HashMap<Account, Usage>>>,
+    totals: Arc<Mutex<Usage>>,
+    config: Live<MeteringConfig>,
+    period_start: Arc<Mutex<i64>>,
+}
+
+impl Metering {
+    pub fn new(config: &MeteringConfig) -> Self {
+        Self {
+            usage: Default::default(),
+            totals: Default::default(),
+            config: Live::new(config.clone()),
+            period_start: Arc::new(Mutex::new(Utc::now().timestamp())),
+        }
+    }
+
+    /// Switches the file and webhook usage goes to. The interval of periodic reports
+    /// stays as it was started.
+    pub fn reload(&self, config: &MeteringConfig) {
+        self.config.set(config.clone());
+    }
+
+    fn is_reporting(&self) -> bool {
+        let config = self.config.get();
+        config.file.is_some() || config.webhook.is_some()
+    }
+
+    fn add(&self, account: &Account, add: impl Fn(&mut Usage)) {
+        add(&mut self.totals.lock().unwrap());
+        if self.is_reporting() {
+            add(self
+                .usage
+                .lock()
+                .unwrap()
+                .entry(account.clone())
+                .or_default());
+        }
+    }
+
+    /// Usage of everyone together since the gateway started.
+    pub fn totals(&self) -> Usage {
+        *self.totals.lock().unwrap()
+    }
+
+    /// Counts p2p transfers, and writes out usage every `interval_secs` while a file or
+    /// webhook is configured. An `interval_secs` of 0 leaves reports to the schedule.
+    pub fn spawn(&self, app_state: AppState) {
+        tokio::spawn(count_transfers(self.clone(), app_state.clone()));
+        if self.config.get().interval_secs > 0 {
+            tokio::spawn(report_periodically(self.clone(), app_state));
+        }
+    }
+
+    /// Writes out the usage since the last report and starts a new period, returning
+    /// how many records were written.
+    pub async fn report(&self, app_state: &AppState) -> Result<usize> {
+        if !self.is_reporting() {
+            bail!("usage reports need a metering file or webhook");
+        }
+        let config = self.config.get();
+        let period_start = *self.period_start.lock().unwrap();
+        let period_end = Utc::now().timestamp();
+        let records = records(self, app_state, period_start, period_end).await?;
+        *self.period_start.lock().unwrap() = period_end;
+        let mut failed = None;
+        if let Some(file) = &config.file {
+            if let Err(err) = write_records(file, &records).await {
+                failed = Some(anyhow!("failed to write usage to {}: {}", file.display(), err));
+            }
+        }
+        if let Some(webhook) = &config.webhook {
+            if let Err(err) = post_records(webhook, &records).await {
+                failed = Some(anyhow!("failed to send usage to {}: {}", webhook, err));
+            }
+        }
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(records.len()),
+        }
+    }
+
+    fn take(&self) -> HashMap<Account, Usage> {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+}
+
+/// Bills blobs sent over iroh. The hash of a transfer is only in the request event, so
+/// it is remembered until the transfer ends.
+async fn count_transfers(metering: Metering, app_state: AppState) {
+    let mut receiver = app_state.events.subscribe();
+    let mut requested: HashMap<(u64, u64), Hash> = HashMap::new();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("Metering missed {} transfer events", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let id = |data: &serde_json::Value| {
+            (
+                data["connection_id"].as_u64().unwrap_or_default(),
+                data["request_id"].as_u64().unwrap_or_default(),
+            )
+        };
+        match event.name {
+            "blob_requested" => {
+                if let Some(hash) = event.data["hash"]
+                    .as_str()
+                    .and_then(|hash| parse_hash(hash).ok())
+                {
+                    requested.insert(id(&event.data), hash);
+                }
+            }
+            "transfer_completed" | "transfer_aborted" => {
+                let Some(hash) = requested.remove(&id(&event.data)) else {
+                    continue;
+                };
+                let bytes = event.data["bytes_sent"].as_u64().unwrap_or_default();
+                let owners = app_state.tenancy.owners(&hash);
+                if owners.is_empty() {
+                    metering.add(&(None, None), |usage| usage.p2p_egress_bytes += bytes);
+                }
+                for owner in owners {
+                    metering.add(&(Some(owner), None), |usage| {
+                        usage.p2p_egress_bytes += bytes
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn report_periodically(metering: Metering, app_state: AppState) {
+    let interval = Duration::from_secs(metering.config.get().interval_secs);
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick is immediate
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if !metering.is_reporting() {
+            continue;
+        }
+        if let Err(err) = metering.report(&app_state).await {
+            println!("Failed to report usage: {}", err);
+        }
+    }
+}
+
+/// The usage of the period, with a row for every tenant and the operator even when
+/// they were idle, as they still store data.
+async fn records(
+    metering: &Metering,
+    app_state: &AppState,
+    period_start: i64,
+    period_end: i64,
+) -> Result<Vec<UsageRecord>> {
+    let mut stored = HashMap::new();
+    let store_bytes: u64 = app_state
+        .blobs
+        .client()
+        .list()
+        .await?
+        .map_ok(|blob| blob.size)
+        .try_fold(0, |total, size| async move { Ok(total + size) })
+        .await?;
+    stored.insert(None, store_bytes);
+    let mut usage = metering.take();
+    for name in app_state.tenancy.names() {
+        let bytes = app_state
+            .tenancy
+            .blobs(&name)
+            .iter()
+            .map(|(_, size)| size)
+            .sum();
+        stored.insert(Some(name.clone()), bytes);
+        usage.entry((Some(name), None)).or_default();
+    }
+    usage.entry((None, None)).or_default();
+
+    let mut records: Vec<UsageRecord> = usage
+        .into_iter()
+        .map(|((tenant, key), usage)| UsageRecord {
+            period_start,
+            period_end,
+            stored_bytes: key
+                .is_none()
+                .then(|| stored.get(&tenant).copied())
+                .flatten(),
+            tenant,
+            key,
+            requests: usage.requests,
+            http_egress_bytes: usage.http_egress_bytes,
+            p2p_egress_bytes: usage.p2p_egress_bytes,
+        })
+        .collect();
+    records.sort_by(|a, b| (&a.tenant, &a.key).cmp(&(&b.tenant, &b.key)));
+    Ok(records)
+}
+
+/// Appends records to `file`, as CSV when it ends in `.csv` and JSON lines otherwise.
+async fn write_records(file: &Path, records: &[UsageRecord]) -> Result<()> {
+    let csv = file.extension().is_some_and(|extension| extension == "csv");
+    let mut out = String::new();
+    if csv
+        && tokio::fs::metadata(file)
+            .await
+            .map_or(true, |meta| meta.len() == 0)
+    {
+        out.push_str(CSV_HEADER);
+    }
+    for record in records {
+        if csv {
+            out.push_str(&record.to_csv());
+        } else {
+            out.push_str(&serde_json::to_string(record)?);
+            out.push('\n');
+        }
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(PathBuf::from(file))
+        .await?;
+    file.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+async fn post_records(webhook: &Url, records: &[UsageRecord]) -> Result<()> {
+    reqwest::Client::new()
+        .post(webhook.clone())
+        .json(records)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// A response body counting the bytes sent to the client.
+struct Metered {
+    inner: Body,
+    metering: Metering,
+    account: Account,
+}
+
+impl http_body::Body for Metered {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                let len = data.len() as u64;
+                self.metering
+                    .add(&self.account, |usage| usage.http_egress_bytes += len);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Middleware counting requests and response bytes by tenant and key. Runs after
+/// tenancy has worked out the tenant.
+pub async fn apply(State(metering): State<Metering>, request: Request, next: Next) -> Response {
+    let tenant = request
+        .extensions()
+        .get::<Tenant>()
+        .cloned()
+        .unwrap_or_default();
+    let account = (tenant.0, api_key(request.headers()).map(key_id));
+    metering.add(&account, |usage| usage.requests += 1);
+
+    let response = next.run(request).await;
+    response.map(|inner| {
+        Body::new(Metered {
+            inner,
+            metering,
+            account,
+        })
+    })
+}
@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use iroh::NodeId;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_gossip::net::{Event, Gossip, GossipEvent, GossipReceiver};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::announce::Announcement;
+use crate::config::MirrorConfig;
+use crate::fetch::Fetcher;
+use crate::gossip::parse_topic;
+use crate::jobs::Jobs;
+
+/// Follows another gateway's announcement feed and fetches everything it announces.
+pub fn spawn(
+    gossip: &Gossip,
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    fetcher: Fetcher,
+    jobs: Jobs,
+    config: &MirrorConfig,
+) -> Result<()> {
+    let Some(topic) = &config.topic else {
+        return Ok(());
+    };
+    let topic_id = parse_topic(topic);
+    let (_sender, receiver) = gossip
+        .subscribe(topic_id, config.bootstrap.clone())?
+        .split();
+    let sources: HashSet<NodeId> = config.sources.iter().copied().collect();
+
+    println!("Mirroring announcements on gossip topic {}", topic_id);
+    tokio::spawn(follow(receiver, blobs, fetcher, jobs, sources));
+    Ok(())
+}
+
+async fn follow(
+    mut receiver: GossipReceiver,
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    fetcher: Fetcher,
+    jobs: Jobs,
+    sources: HashSet<NodeId>,
+) {
+    while let Some(event) = receiver.next().await {
+        let Ok(Event::Gossip(GossipEvent::Received(message))) = event else {
+            continue;
+        };
+        let Ok(announcement) = serde_json::from_slice::<Announcement>(&message.content) else {
+            continue;
+        };
+        let Ok(ticket) = BlobTicket::from_str(&announcement.ticket) else {
+            continue;
+        };
+        // An empty source list mirrors every gateway on the topic
+        if !sources.is_empty() && !sources.contains(&ticket.node_addr().node_id) {
+            continue;
+        }
+
+        let blobs = blobs.clone();
+        let fetcher = fetcher.clone();
+        jobs.spawn("mirror", async move { fetch(blobs, fetcher, ticket).await });
+    }
+}
+
+async fn fetch(
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    fetcher: Fetcher,
+    ticket: BlobTicket,
+) -> Result<serde_json::Value> {
+    let blobs_client = blobs.client();
+    let hash = ticket.hash();
+    if blobs_client.has(hash).await? {
+        return Ok(serde_json::json!({ "hash": hash.to_string(), "already_present": true }));
+    }
+
+    let outcome = fetcher
+        .fetch(hash, ticket.format(), vec![ticket.node_addr().clone()])
+        .await
+        .with_context(|| format!("Failed to mirror {}", hash))?;
+
+    println!("Mirrored {} from {}", hash, ticket.node_addr().node_id);
+    Ok(serde_json::json!({
+        "hash": hash.to_string(),
+        "downloaded_size": outcome.downloaded_size,
+        "resumed_size": outcome.resumed_size,
+        "retries": outcome.retries,
+    }))
+}
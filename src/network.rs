@@ -0,0 +1,359 @@
+use anyhow::{bail, Result};
+use async_graphql::SimpleObject;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use iroh::discovery::dns::DnsDiscovery;
+use iroh::discovery::pkarr::{PkarrPublisher, PkarrResolver};
+use iroh::discovery::Discovery;
+use iroh::endpoint::{
+    ConnectionType, DirectAddr, DirectAddrType, PathSelection, RemoteInfo, TransportConfig,
+};
+use iroh::{Endpoint, RelayMap, RelayUrl, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::config::{BandwidthConfig, NetworkConfig, RelayMode, Transport};
+use crate::push::parse_target;
+use crate::throttle::SendCap;
+use crate::AppState;
+
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn relay_mode(config: &NetworkConfig) -> Result<iroh::RelayMode> {
+    if config.transport == Transport::DirectOnly {
+        return Ok(iroh::RelayMode::Disabled);
+    }
+    Ok(match config.relay_mode {
+        RelayMode::Default => iroh::RelayMode::Default,
+        RelayMode::Staging => iroh::RelayMode::Staging,
+        RelayMode::Custom => {
+            if config.relays.is_empty() {
+                bail!("relay_mode is custom but no relays are configured");
+            }
+            let nodes = config.relays.iter().flat_map(|url| {
+                RelayMap::from_url(url.clone())
+                    .nodes()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            });
+            iroh::RelayMode::Custom(RelayMap::from_nodes(nodes)?)
+        }
+        RelayMode::Disabled if config.transport == Transport::RelayOnly => {
+            bail!("transport is relay_only but relays are disabled");
+        }
+        RelayMode::Disabled => iroh::RelayMode::Disabled,
+    })
+}
+
+/// Refuses settings that would announce direct addresses of a relay-only node.
+fn check_transport(config: &NetworkConfig) -> Result<()> {
+    if config.transport == Transport::RelayOnly && config.local_discovery {
+        bail!("transport is relay_only but local_discovery announces direct addresses");
+    }
+    Ok(())
+}
+
+/// A pkarr publisher that leaves out direct addresses on relay-only nodes.
+///
+/// iroh's publisher only drops them once a home relay is known, so until then a relay-only
+/// node publishes nothing at all.
+#[derive(Debug)]
+struct Publisher {
+    pkarr: PkarrPublisher,
+    relay_only: bool,
+}
+
+impl Discovery for Publisher {
+    fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
+        if !self.relay_only {
+            self.pkarr.publish(url, addrs);
+        } else if url.is_some() {
+            self.pkarr.publish(url, &BTreeSet::new());
+        }
+    }
+}
+
+/// Binds the node's endpoint according to the network config.
+pub async fn bind_endpoint(
+    secret_key: SecretKey,
+    config: &NetworkConfig,
+    bandwidth: &BandwidthConfig,
+) -> Result<Endpoint> {
+    check_transport(config)?;
+    let relay_only = config.transport == Transport::RelayOnly;
+    let mut builder = Endpoint::builder()
+        .secret_key(secret_key)
+        .relay_mode(relay_mode(config)?)
+        .path_selection(match config.transport {
+            Transport::RelayOnly => PathSelection::RelayOnly,
+            Transport::Any | Transport::DirectOnly => PathSelection::All,
+        })
+        .known_nodes(config.peers.clone())
+        .clear_discovery();
+
+    let discovery = &config.discovery;
+    if discovery.n0 {
+        builder = builder
+            .add_discovery(move |secret_key| {
+                let pkarr = PkarrPublisher::n0_dns(secret_key.clone());
+                Some(Publisher { pkarr, relay_only })
+            })
+            .add_discovery(|_| Some(DnsDiscovery::n0_dns()));
+    }
+    if discovery.dht {
+        // The DHT record carries only the home relay unless told otherwise.
+        builder = builder.discovery_dht();
+    }
+    for relay in discovery.pkarr_relays.iter().cloned() {
+        let resolver = PkarrResolver::new(relay.clone());
+        builder = builder
+            .add_discovery(move |secret_key| {
+                let pkarr = PkarrPublisher::new(secret_key.clone(), relay);
+                Some(Publisher { pkarr, relay_only })
+            })
+            .add_discovery(move |_| Some(resolver));
+    }
+    for origin in discovery.dns_origins.iter().cloned() {
+        builder = builder.add_discovery(move |_| Some(DnsDiscovery::new(origin)));
+    }
+    if config.local_discovery {
+        builder = builder.discovery_local_network();
+    }
+    if let Some(send_cap) = SendCap::new(bandwidth) {
+        let mut transport_config = TransportConfig::default();
+        transport_config.congestion_controller_factory(send_cap);
+        builder = builder.transport_config(transport_config);
+    }
+    builder.bind().await
+}
+
+#[derive(Serialize, SimpleObject)]
+pub struct ConnectionInfo {
+    node_id: String,
+    conn_type: &'static str,
+    direct_addr: Option<String>,
+    relay_url: Option<String>,
+    latency_ms: Option<f64>,
+    last_used_secs: Option<u64>,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Connection details of a remote node, or `None` when there is no verified path to it.
+fn connection_info(info: &RemoteInfo) -> Option<ConnectionInfo> {
+    let (conn_type, direct_addr, relay_url) = match &info.conn_type {
+        ConnectionType::None => return None,
+        ConnectionType::Direct(addr) => ("direct", Some(addr.to_string()), None),
+        ConnectionType::Relay(url) => ("relay", None, Some(url.to_string())),
+        ConnectionType::Mixed(addr, url) => {
+            ("mixed", Some(addr.to_string()), Some(url.to_string()))
+        }
+    };
+    Some(ConnectionInfo {
+        node_id: info.node_id.to_string(),
+        conn_type,
+        direct_addr,
+        relay_url,
+        latency_ms: info.latency.map(millis),
+        last_used_secs: info.last_used.map(|elapsed| elapsed.as_secs()),
+    })
+}
+
+/// Remote nodes we currently have a working network path to.
+///
+/// iroh doesn't keep per-node traffic counters, so only path details are reported.
+pub fn connections(endpoint: &Endpoint) -> Vec<ConnectionInfo> {
+    endpoint
+        .remote_info_iter()
+        .filter_map(|info| connection_info(&info))
+        .collect()
+}
+
+pub async fn list_connections(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(connections(&app_state.endpoint))
+}
+
+#[derive(Deserialize)]
+pub struct PingRequest {
+    target: String,
+}
+
+#[derive(Serialize)]
+pub struct PingResponse {
+    node_id: String,
+    reachable: bool,
+    connect_ms: Option<f64>,
+    rtt_ms: Option<f64>,
+    path: Option<ConnectionInfo>,
+    error: Option<String>,
+}
+
+/// Opens a QUIC connection to a node id or ticket and reports how it was reached.
+///
+/// The probe uses the blobs ALPN since every iroh blobs node accepts it.
+pub async fn ping(
+    State(app_state): State<AppState>,
+    Json(request): Json<PingRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let addr = parse_target(&request.target).ok_or(StatusCode::BAD_REQUEST)?;
+    let node_id = addr.node_id;
+    if node_id == app_state.node_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let start = Instant::now();
+    let connect = app_state.endpoint.connect(addr, iroh_blobs::ALPN);
+    let result = match tokio::time::timeout(PING_TIMEOUT, connect).await {
+        Ok(Ok(conn)) => Ok(conn),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    };
+
+    let response = match result {
+        Ok(conn) => {
+            let connect_ms = millis(start.elapsed());
+            let path = app_state
+                .endpoint
+                .remote_info(node_id)
+                .and_then(|info| connection_info(&info));
+            let response = PingResponse {
+                node_id: node_id.to_string(),
+                reachable: true,
+                connect_ms: Some(connect_ms),
+                rtt_ms: Some(millis(conn.rtt())),
+                path,
+                error: None,
+            };
+            conn.close(0u32.into(), b"ping");
+            response
+        }
+        Err(error) => PingResponse {
+            node_id: node_id.to_string(),
+            reachable: false,
+            connect_ms: None,
+            rtt_ms: None,
+            path: None,
+            error: Some(error),
+        },
+    };
+    Ok(Json(response))
+}
+
+#[derive(Serialize)]
+pub struct DirectAddrInfo {
+    addr: String,
+    #[serde(rename = "type")]
+    typ: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct PathCounts {
+    direct: usize,
+    relay: usize,
+    mixed: usize,
+}
+
+/// How this node appears from the internet, judged by the addresses STUN and port mapping
+/// found for it.
+fn nat_status(direct_addrs: &[DirectAddr]) -> &'static str {
+    let ips_of = |typ: DirectAddrType| -> Vec<IpAddr> {
+        direct_addrs
+            .iter()
+            .filter(|addr| addr.typ == typ)
+            .map(|addr| addr.addr.ip())
+            .collect()
+    };
+    let local = ips_of(DirectAddrType::Local);
+    let mut stun = ips_of(DirectAddrType::Stun);
+    stun.extend(ips_of(DirectAddrType::Stun4LocalPort));
+
+    if !ips_of(DirectAddrType::Portmapped).is_empty() {
+        "port_mapped"
+    } else if stun.iter().any(|ip| local.contains(ip)) {
+        "none"
+    } else if !stun.is_empty() {
+        "behind_nat"
+    } else {
+        "unknown"
+    }
+}
+
+/// Connectivity overview: home relay, direct addresses, NAT traversal and discovery.
+pub async fn network_status(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(status(&app_state))
+}
+
+pub fn status(app_state: &AppState) -> serde_json::Value {
+    let endpoint = &app_state.endpoint;
+    let home_relay = endpoint.home_relay().get().ok().flatten();
+    let direct_addrs: Vec<_> = endpoint
+        .direct_addresses()
+        .get()
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let nat = nat_status(&direct_addrs);
+
+    // Direct paths to remote nodes mean hole punching works from where this node sits
+    let mut paths = PathCounts::default();
+    for info in endpoint.remote_info_iter() {
+        match info.conn_type {
+            ConnectionType::Direct(_) => paths.direct += 1,
+            ConnectionType::Relay(_) => paths.relay += 1,
+            ConnectionType::Mixed(..) => paths.mixed += 1,
+            ConnectionType::None => {}
+        }
+    }
+
+    let (bound_v4, bound_v6) = endpoint.bound_sockets();
+    let discovery = endpoint.discovery().is_some();
+
+    serde_json::json!({
+        "node_id": app_state.node_id.to_string(),
+        "home_relay": home_relay.as_ref().map(|url| url.to_string()),
+        "bound_sockets": std::iter::once(bound_v4)
+            .chain(bound_v6)
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>(),
+        "direct_addresses": direct_addrs
+            .iter()
+            .map(|addr| DirectAddrInfo {
+                addr: addr.addr.to_string(),
+                typ: addr.typ.to_string(),
+            })
+            .collect::<Vec<_>>(),
+        "nat": nat,
+        "publicly_reachable": matches!(nat, "none" | "port_mapped"),
+        "paths": paths,
+        "discovery": {
+            "enabled": discovery,
+            // The node address is published through the home relay once one is selected
+            "published": discovery && home_relay.is_some(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(toml: &str) -> NetworkConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn relay_only_refuses_local_discovery() {
+        let config = network("transport = \"relay_only\"\nlocal_discovery = true");
+        assert!(check_transport(&config).is_err());
+
+        let config = network("transport = \"relay_only\"\ndiscovery = { dht = true }");
+        assert!(check_transport(&config).is_ok());
+        let config = network("local_discovery = true");
+        assert!(check_transport(&config).is_ok());
+    }
+}
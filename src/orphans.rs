@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse, Json};
+use futures::TryStreamExt;
+use iroh_blobs::hashseq::HashSeq;
+use iroh_blobs::store::ReadableStore;
+use iroh_blobs::{BlobFormat, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::s3;
+use crate::tracker::tagged_content;
+use crate::AppState;
+
+/// A complete blob in the store nothing refers to, which garbage collection would take.
+#[derive(Serialize)]
+pub struct UnreferencedBlob {
+    hash: Hash,
+    size: u64,
+}
+
+/// A record of the gateway about a blob the store doesn't have.
+#[derive(Serialize)]
+pub struct DanglingRecord {
+    /// `tenant`, `trash`, `pin`, `hold` or `s3`.
+    kind: &'static str,
+    hash: Hash,
+    /// The tenant, or the bucket and key of an S3 object.
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct OrphanReport {
+    unreferenced: Vec<UnreferencedBlob>,
+    dangling: Vec<DanglingRecord>,
+    /// Whether the unreferenced blobs were deleted and the dangling records dropped.
+    /// Legal holds are only ever reported.
+    reconciled: bool,
+}
+
+/// Cross-checks the store against what the gateway keeps about blobs: tenant uploads
+/// and trash, pins, legal holds and S3 objects. Tags, documents and fetches waiting to
+/// resume count as references too.
+pub async fn check(app_state: &AppState, reconcile: bool) -> Result<OrphanReport> {
+    let stored: HashMap<Hash, u64> = app_state
+        .blobs
+        .client()
+        .list()
+        .await?
+        .map_ok(|blob| (blob.hash, blob.size))
+        .try_collect()
+        .await?;
+
+    let mut dangling = Vec::new();
+    let mut record = |kind, hash: Hash, name: Option<String>| {
+        if !stored.contains_key(&hash) {
+            dangling.push(DanglingRecord { kind, hash, name });
+        }
+    };
+    for tenant in app_state.tenancy.names() {
+        for (hash, _) in app_state.tenancy.blobs(&tenant) {
+            record("tenant", hash, Some(tenant.clone()));
+        }
+        for hash in app_state.tenancy.trashed(&tenant) {
+            record("trash", hash, Some(tenant.clone()));
+        }
+    }
+    for pin in app_state.pins.list() {
+        record("pin", pin.hash, None);
+    }
+    for hold in app_state.holds.list() {
+        record("hold", hold.hash, None);
+    }
+    for (bucket, key, hash) in app_state.buckets.objects() {
+        record("s3", hash, Some(format!("{}/{}", bucket, key)));
+    }
+
+    let live = referenced(app_state).await?;
+    let mut unreferenced: Vec<UnreferencedBlob> = stored
+        .iter()
+        .filter(|(hash, _)| !live.contains(*hash))
+        .map(|(hash, size)| UnreferencedBlob {
+            hash: *hash,
+            size: *size,
+        })
+        .collect();
+    unreferenced.sort_by_key(|blob| blob.hash);
+
+    if reconcile {
+        for blob in &unreferenced {
+            app_state.blobs.client().delete_blob(blob.hash).await?;
+        }
+        for record in &dangling {
+            drop_record(app_state, record).await?;
+        }
+    }
+    println!(
+        "Found {} unreferenced blobs and {} records of missing blobs",
+        unreferenced.len(),
+        dangling.len()
+    );
+    Ok(OrphanReport {
+        unreferenced,
+        dangling,
+        reconciled: reconcile,
+    })
+}
+
+/// Everything kept on purpose: tagged content with the children of hash sequences,
+/// content of imports still under way, and what documents and failed fetches protect
+/// from garbage collection.
+pub async fn referenced(app_state: &AppState) -> Result<BTreeSet<Hash>> {
+    let mut live = BTreeSet::new();
+    let mut roots = tagged_content(&app_state.blobs).await?;
+    roots.extend(app_state.blobs.store().temp_tags());
+    for content in roots {
+        live.insert(content.hash);
+        if content.format == BlobFormat::HashSeq {
+            if let Ok(bytes) = app_state.blobs.client().read_to_bytes(content.hash).await {
+                if let Ok(hash_seq) = HashSeq::try_from(bytes) {
+                    live.extend(hash_seq.iter());
+                }
+            }
+        }
+    }
+    app_state.docs.protect_cb()(&mut live).await;
+    app_state.fetcher.protect_cb()(&mut live).await;
+    Ok(live)
+}
+
+async fn drop_record(app_state: &AppState, record: &DanglingRecord) -> Result<()> {
+    match (record.kind, &record.name) {
+        ("tenant" | "trash", Some(tenant)) => {
+            app_state.tenancy.drop_blob(tenant, record.hash).await?;
+        }
+        ("pin", _) => {
+            app_state.pins.unpin(&record.hash).await?;
+        }
+        ("s3", Some(object)) => {
+            if let Some((bucket, key)) = object.split_once('/') {
+                s3::remove_object(app_state, bucket, key)
+                    .await
+                    .map_err(|err| anyhow!("failed to remove {}: {}", object, err.status()))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct OrphansRequest {
+    reconcile: bool,
+}
+
+/// `POST /store/orphans`: starts a job checking for orphans, with an optional JSON body
+/// asking to `reconcile` them. The report is the job's result.
+pub async fn check_orphans(
+    State(app_state): State<AppState>,
+    body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let request: OrphansRequest = if body.is_empty() {
+        OrphansRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?
+    };
+    let state = app_state.clone();
+    let job_id = app_state.jobs.spawn("orphans", async move {
+        let report = check(&state, request.reconcile).await?;
+        Ok(serde_json::to_value(report)?)
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "job_id": job_id })),
+    ))
+}
@@ -0,0 +1,169 @@
+use anyhow::{bail, Result};
+use bao_tree::{ChunkNum, ChunkRanges};
+use iroh::{Endpoint, NodeAddr};
+use iroh_blobs::get::db::valid_ranges;
+use iroh_blobs::get::fsm::{self, ConnectedNext, EndBlobNext};
+use iroh_blobs::get::request::get_verified_size;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::protocol::{GetRequest, RangeSpecSeq};
+use iroh_blobs::store::fs::Store;
+use iroh_blobs::store::{BaoBatchWriter, EntryStatus, MapEntryMut, MapMut};
+use iroh_blobs::Hash;
+use serde::Serialize;
+
+use crate::timeouts::{Phase, Timeouts};
+
+/// Chunks in a chunk group, the unit the store verifies blobs in. Slices start at group
+/// boundaries.
+const GROUP_CHUNKS: u64 = 16;
+
+/// What one provider contributed to a parallel download.
+#[derive(Debug, Serialize, Clone)]
+pub struct Share {
+    pub node_id: String,
+    pub downloaded_size: u64,
+    pub error: Option<String>,
+}
+
+/// Bytes of a `size` byte blob within `ranges`.
+pub fn range_bytes(ranges: &ChunkRanges, size: u64) -> u64 {
+    let end = ChunkNum::chunks(size);
+    ranges
+        .boundaries()
+        .chunks(2)
+        .map(|range| {
+            let start = range[0].to_bytes();
+            let end = range.get(1).unwrap_or(&end).to_bytes().min(size);
+            end.saturating_sub(start)
+        })
+        .sum()
+}
+
+/// Downloads what the store misses of the raw blob `hash` from all `nodes` at once,
+/// each sending a slice of it. Blobs smaller than `min_size` are left alone.
+///
+/// Slices that fail are left missing, for the caller to fetch some other way. The blob
+/// is marked complete when every slice arrived.
+pub async fn download(
+    blobs: &Blobs<Store>,
+    timeouts: &Timeouts,
+    hash: Hash,
+    nodes: Vec<NodeAddr>,
+    min_size: u64,
+) -> Result<Vec<Share>> {
+    if nodes.len() < 2 {
+        return Ok(Vec::new());
+    }
+    let store = blobs.store().clone();
+    let endpoint = blobs.endpoint().clone();
+    let timeouts = *timeouts;
+    // Writing to the store isn't Send, so like iroh's own downloads this runs on the
+    // local pool
+    blobs
+        .rt()
+        .spawn(move || async move {
+            download_slices(&store, &endpoint, &timeouts, hash, &nodes, min_size).await
+        })
+        .await?
+}
+
+async fn download_slices(
+    store: &Store,
+    endpoint: &Endpoint,
+    timeouts: &Timeouts,
+    hash: Hash,
+    nodes: &[NodeAddr],
+    min_size: u64,
+) -> Result<Vec<Share>> {
+    if store.entry_status(&hash).await? == EntryStatus::Complete {
+        return Ok(Vec::new());
+    }
+    // Who sends what depends on the size, which the first node tells us
+    let clock = timeouts.start();
+    let conn = clock
+        .within(
+            Phase::Connect,
+            endpoint.connect(nodes[0].clone(), iroh_blobs::ALPN),
+        )
+        .await??;
+    let (size, _) = clock
+        .within(Phase::FirstByte, get_verified_size(&conn, &hash))
+        .await??;
+    if size < min_size {
+        return Ok(Vec::new());
+    }
+
+    let entry = store.get_or_create(hash, size).await?;
+    let missing: ChunkRanges = ChunkRanges::from(..ChunkNum::chunks(size))
+        .difference(&valid_ranges::<Store>(&entry).await?);
+    let groups = ChunkNum::chunks(size).0.div_ceil(GROUP_CHUNKS);
+    let per_node = groups.div_ceil(nodes.len() as u64) * GROUP_CHUNKS;
+    let slices = nodes.iter().enumerate().filter_map(|(i, node)| {
+        let start = ChunkNum(i as u64 * per_node);
+        let end = ChunkNum((i as u64 + 1) * per_node);
+        let ranges: ChunkRanges = missing.intersection(&ChunkRanges::from(start..end));
+        (!ranges.is_empty()).then(|| (node.clone(), ranges))
+    });
+    let shares = slices.map(|(node, ranges)| {
+        let entry = entry.clone();
+        async move {
+            let node_id = node.node_id.to_string();
+            match fetch_slice(endpoint, timeouts, &entry, hash, node, ranges.clone()).await {
+                Ok(()) => Share {
+                    node_id,
+                    downloaded_size: range_bytes(&ranges, size),
+                    error: None,
+                },
+                Err(err) => Share {
+                    node_id,
+                    downloaded_size: 0,
+                    error: Some(format!("{:#}", err)),
+                },
+            }
+        }
+    });
+    let shares = futures::future::join_all(shares).await;
+
+    if shares.iter().all(|share| share.error.is_none()) {
+        store.insert_complete(entry).await?;
+    }
+    Ok(shares)
+}
+
+/// Gets `ranges` of `hash` from `node` into `entry`.
+async fn fetch_slice(
+    endpoint: &Endpoint,
+    timeouts: &Timeouts,
+    entry: &<Store as MapMut>::EntryMut,
+    hash: Hash,
+    node: NodeAddr,
+    ranges: ChunkRanges,
+) -> Result<()> {
+    let clock = timeouts.start();
+    let conn = clock
+        .within(Phase::Connect, endpoint.connect(node, iroh_blobs::ALPN))
+        .await??;
+    let request = fsm::start(
+        conn,
+        GetRequest::new(hash, RangeSpecSeq::from_ranges([ranges])),
+    );
+    let connected = clock.within(Phase::FirstByte, request.next()).await??;
+    let ConnectedNext::StartRoot(start) =
+        clock.within(Phase::FirstByte, connected.next()).await??
+    else {
+        bail!("{} did not send the blob", hash);
+    };
+    let (content, _) = clock
+        .within(Phase::FirstByte, start.next().next())
+        .await??;
+    let mut writer = entry.batch_writer().await?;
+    let end = clock
+        .within(Phase::Transfer, content.write_all_batch(&mut writer))
+        .await??;
+    writer.sync().await?;
+    let EndBlobNext::Closing(closing) = end.next() else {
+        bail!("{} sent more than was asked for", hash);
+    };
+    closing.next().await?;
+    Ok(())
+}
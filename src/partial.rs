@@ -0,0 +1,159 @@
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use iroh_blobs::get::db::valid_ranges;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::store::fs::Store;
+use iroh_blobs::store::{MapEntry, MapMut, ReadableStore};
+use iroh_blobs::Hash;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::config::PartialConfig;
+use crate::jobs::now_secs;
+use crate::parallel::range_bytes;
+use crate::tracker::tagged_content;
+use crate::AppState;
+
+/// Where the store keeps the files of blobs too large to inline, partial ones included.
+const DATA_DIR: &str = "data/data";
+
+/// Files the store writes for a blob, named `<hash>.<extension>`.
+const FILE_EXTENSIONS: [&str; 3] = ["data", "obao4", "sizes4"];
+
+/// How often partial blobs are checked for having gone idle.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// An entry of the store that is missing some of its data.
+#[derive(Serialize, Clone)]
+pub struct PartialBlob {
+    pub hash: Hash,
+    /// The best known size of the whole blob.
+    pub size: u64,
+    pub present_bytes: u64,
+    /// When its files were last written to, `None` while it is only in memory.
+    pub last_activity: Option<u64>,
+}
+
+/// The incomplete entries of the store, the longest idle first.
+pub async fn list(blobs: &Blobs<Store>) -> Result<Vec<PartialBlob>> {
+    let store = blobs.store().clone();
+    // Entries opened for writing aren't Send, so this runs on the local pool
+    let mut partial = blobs
+        .rt()
+        .spawn(move || async move { present(&store).await })
+        .await??;
+    for blob in &mut partial {
+        blob.last_activity = last_activity(&blob.hash).await;
+    }
+    partial.sort_by_key(|blob| blob.last_activity);
+    Ok(partial)
+}
+
+async fn present(store: &Store) -> Result<Vec<PartialBlob>> {
+    let mut partial = Vec::new();
+    for hash in store.partial_blobs().await? {
+        let hash = hash?;
+        let Some(entry) = store.get_mut(&hash).await? else {
+            continue;
+        };
+        if entry.is_complete() {
+            continue;
+        }
+        let size = entry.size().value();
+        let ranges = valid_ranges::<Store>(&entry).await?;
+        partial.push(PartialBlob {
+            hash,
+            size,
+            present_bytes: range_bytes(&ranges, size),
+            last_activity: None,
+        });
+    }
+    Ok(partial)
+}
+
+/// The last time any file of the blob was modified, in seconds since the Unix epoch.
+async fn last_activity(hash: &Hash) -> Option<u64> {
+    let mut last = None;
+    for extension in FILE_EXTENSIONS {
+        let path = Path::new(DATA_DIR).join(format!("{}.{}", hash.to_hex(), extension));
+        let Ok(modified) = tokio::fs::metadata(&path)
+            .await
+            .and_then(|meta| meta.modified())
+        else {
+            continue;
+        };
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        last = last.max(Some(secs));
+    }
+    last
+}
+
+/// Removes idle partial blobs every [`CLEANUP_INTERVAL`], when `max_idle_secs` is set.
+pub fn spawn(config: &PartialConfig, app_state: AppState) {
+    if config.max_idle_secs > 0 {
+        tokio::spawn(clean_up_periodically(app_state, config.max_idle_secs));
+    }
+}
+
+async fn clean_up_periodically(app_state: AppState, max_idle_secs: u64) {
+    let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match clean_up(&app_state, max_idle_secs).await {
+            Ok((0, _)) => {}
+            Ok((removed, bytes)) => {
+                println!("Removed {} idle partial blobs, {} bytes", removed, bytes)
+            }
+            Err(err) => println!("Failed to clean up partial blobs: {}", err),
+        }
+    }
+}
+
+/// Deletes the partial blobs idle for longer than `max_idle_secs`, returning how many
+/// and the bytes they held.
+async fn clean_up(app_state: &AppState, max_idle_secs: u64) -> Result<(u64, u64)> {
+    let tagged: HashSet<Hash> = tagged_content(&app_state.blobs)
+        .await?
+        .into_iter()
+        .map(|content| content.hash)
+        .collect();
+    let cutoff = now_secs().saturating_sub(max_idle_secs);
+    let mut removed = 0;
+    let mut bytes = 0;
+    for blob in list(&app_state.blobs).await? {
+        // Entries only in memory are being written to right now
+        let Some(last_activity) = blob.last_activity else {
+            continue;
+        };
+        // Sorted by activity, the rest is more recent
+        if last_activity > cutoff {
+            break;
+        }
+        // Failed fetches that were meant to resume have been idle as long
+        if tagged.contains(&blob.hash) || app_state.fetcher.is_fetching(&blob.hash) {
+            continue;
+        }
+        app_state.blobs.client().delete_blob(blob.hash).await?;
+        app_state.fetcher.forget_partial(&blob.hash);
+        removed += 1;
+        bytes += blob.present_bytes;
+    }
+    Ok((removed, bytes))
+}
+
+/// `GET /blobs/partial`: downloads that stopped short, with how much of them arrived and
+/// when they last made progress.
+pub async fn list_partial(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let partial = list(&app_state.blobs).await.map_err(|err| {
+        println!("Failed to list partial blobs: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(partial))
+}
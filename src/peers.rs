@@ -0,0 +1,128 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use iroh::{Endpoint, NodeAddr, NodeId};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::gossip::parse_node_id;
+use crate::persist::StateFile;
+use crate::AppState;
+
+#[derive(Serialize)]
+pub struct PeerInfo {
+    #[serde(flatten)]
+    addr: NodeAddr,
+    conn_type: String,
+}
+
+/// Node addresses registered over the API.
+///
+/// They are fed into the endpoint's address book so connecting to them skips discovery,
+/// and written to disk so they are known again after a restart.
+#[derive(Clone)]
+pub struct Peers {
+    endpoint: Endpoint,
+    peers: Arc<RwLock<BTreeMap<NodeId, NodeAddr>>>,
+    file: StateFile,
+}
+
+impl Peers {
+    pub fn load(endpoint: Endpoint, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut peers = BTreeMap::new();
+        if path.exists() {
+            let saved: Vec<NodeAddr> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            for addr in saved {
+                if let Err(err) = endpoint.add_node_addr(addr.clone()) {
+                    println!("Failed to add known peer {}: {}", addr.node_id, err);
+                }
+                peers.insert(addr.node_id, addr);
+            }
+        }
+        let peers = Arc::new(RwLock::new(peers));
+        let file = StateFile::spawn(path, {
+            let peers = peers.clone();
+            move || to_json(&peers.read().unwrap())
+        });
+        Ok(Self {
+            endpoint,
+            peers,
+            file,
+        })
+    }
+
+    pub fn add(&self, addr: NodeAddr) -> Result<()> {
+        self.endpoint.add_node_addr(addr.clone())?;
+        let mut peers = self.peers.write().unwrap();
+        peers.insert(addr.node_id, addr);
+        self.file.changed();
+        Ok(())
+    }
+
+    /// Forgets a peer. Addresses iroh already learned for it expire on their own.
+    pub fn remove(&self, node_id: &NodeId) -> bool {
+        let mut peers = self.peers.write().unwrap();
+        if peers.remove(node_id).is_none() {
+            return false;
+        }
+        self.file.changed();
+        true
+    }
+
+    pub fn list(&self) -> Vec<PeerInfo> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .values()
+            .map(|addr| PeerInfo {
+                addr: addr.clone(),
+                conn_type: self
+                    .endpoint
+                    .remote_info(addr.node_id)
+                    .map(|info| info.conn_type.to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+            })
+            .collect()
+    }
+}
+
+fn to_json(peers: &BTreeMap<NodeId, NodeAddr>) -> Result<Vec<u8>> {
+    let addrs: Vec<&NodeAddr> = peers.values().collect();
+    Ok(serde_json::to_vec_pretty(&addrs)?)
+}
+
+pub async fn add_peer(
+    State(app_state): State<AppState>,
+    Json(addr): Json<NodeAddr>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if addr.node_id == app_state.node_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    app_state
+        .peers
+        .add(addr.clone())
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    println!("Added known peer {}", addr.node_id);
+    Ok(Json(addr))
+}
+
+pub async fn list_peers(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.peers.list())
+}
+
+pub async fn remove_peer(
+    State(app_state): State<AppState>,
+    Path(node_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let node_id = parse_node_id(&node_id).ok_or(StatusCode::BAD_REQUEST)?;
+    if !app_state.peers.remove(&node_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long changes are collected before the file is written, so a burst of them costs
+/// one write.
+const FLUSH_DELAY: Duration = Duration::from_millis(500);
+/// How long a file that failed to be written waits before the next try.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Replaces the file at `path` with `contents` through a temporary file next to it, so
+/// a crash midway leaves the old file rather than half of the new one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// A file kept in step with some state by a background task.
+///
+/// Changing the state only marks the file as out of date; the task takes what to write
+/// and writes it off the async threads, without the lock on the state held.
+#[derive(Clone)]
+pub struct StateFile {
+    changed: Arc<Notify>,
+}
+
+impl StateFile {
+    /// Starts writing what `contents` returns to `path` after changes.
+    pub fn spawn(
+        path: impl Into<PathBuf>,
+        contents: impl Fn() -> Result<Vec<u8>> + Send + 'static,
+    ) -> Self {
+        let path = path.into();
+        let changed = Arc::new(Notify::new());
+        let notified = changed.clone();
+        tokio::spawn(async move {
+            loop {
+                notified.notified().await;
+                tokio::time::sleep(FLUSH_DELAY).await;
+                let written = match contents() {
+                    Ok(contents) => {
+                        let path = path.clone();
+                        tokio::task::spawn_blocking(move || write_atomic(&path, &contents))
+                            .await
+                            .map_err(anyhow::Error::from)
+                            .and_then(|written| written)
+                    }
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = written {
+                    println!("Failed to write {}: {:#}", path.display(), err);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    notified.notify_one();
+                }
+            }
+        });
+        Self { changed }
+    }
+
+    /// Marks the file as out of date.
+    pub fn changed(&self) {
+        self.changed.notify_one();
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::util::Tag;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blob::parse_hash;
+use crate::persist::StateFile;
+use crate::tenancy::Tenant;
+use crate::AppState;
+
+/// Tags pinned blobs are kept under, so garbage collection leaves them alone.
+const TAG_PREFIX: &str = "pin/";
+
+fn pin_tag(hash: &Hash) -> Tag {
+    Tag::from(format!("{}{}", TAG_PREFIX, hash))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pin {
+    pub hash: Hash,
+    pub format: BlobFormat,
+    pub reason: Option<String>,
+    pub owner: Option<String>,
+    pub pinned_at: u64,
+}
+
+impl Pin {
+    /// The pin as `tenant` may see it: other tenants' names are left out, so a tenant
+    /// can't learn who else keeps a blob.
+    pub fn seen_by(mut self, tenant: &Tenant) -> Self {
+        if tenant.0.is_some() && self.owner != tenant.0 {
+            self.owner = None;
+        }
+        self
+    }
+}
+
+/// Blobs kept no matter what, with who pinned them and why.
+///
+/// Each pin is a tag on the blob, which is what protects it. The reasons are written to
+/// disk next to the store.
+#[derive(Clone)]
+pub struct Pins {
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    pins: Arc<RwLock<BTreeMap<Hash, Pin>>>,
+    file: StateFile,
+}
+
+impl Pins {
+    pub fn load(
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self> {
+        let path = path.into();
+        let mut pins = BTreeMap::new();
+        if path.exists() {
+            let saved: Vec<Pin> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            for pin in saved {
+                pins.insert(pin.hash, pin);
+            }
+        }
+        let pins = Arc::new(RwLock::new(pins));
+        let file = StateFile::spawn(path, {
+            let pins = pins.clone();
+            move || to_json(&pins.read().unwrap())
+        });
+        Ok(Self { blobs, pins, file })
+    }
+
+    /// Pins `hash`, or updates the reason and owner of an existing pin.
+    pub async fn pin(
+        &self,
+        hash: Hash,
+        format: BlobFormat,
+        reason: Option<String>,
+        owner: Option<String>,
+    ) -> Result<Pin> {
+        let batch = self.blobs.client().batch().await?;
+        let temp_tag = batch.temp_tag(HashAndFormat { hash, format }).await?;
+        batch.persist_to(temp_tag, pin_tag(&hash)).await?;
+
+        let pinned_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let pin = Pin {
+            hash,
+            format,
+            reason,
+            owner,
+            pinned_at,
+        };
+        self.pins.write().unwrap().insert(hash, pin.clone());
+        self.file.changed();
+        Ok(pin)
+    }
+
+    /// Drops a pin. The blob goes with the next garbage collection unless something
+    /// else keeps it.
+    pub async fn unpin(&self, hash: &Hash) -> Result<bool> {
+        if !self.pins.read().unwrap().contains_key(hash) {
+            return Ok(false);
+        }
+        self.blobs.client().tags().delete(pin_tag(hash)).await?;
+        self.pins.write().unwrap().remove(hash);
+        self.file.changed();
+        Ok(true)
+    }
+
+    /// Drops every pin `owner` made, for when it lets go of everything it owns.
+    pub async fn unpin_owned_by(&self, owner: &str) -> Result<usize> {
+        let owned: Vec<Hash> = self
+            .pins
+            .read()
+            .unwrap()
+            .values()
+            .filter(|pin| pin.owner.as_deref() == Some(owner))
+            .map(|pin| pin.hash)
+            .collect();
+        for hash in &owned {
+            self.unpin(hash).await?;
+        }
+        Ok(owned.len())
+    }
+
+    /// Keeps a pin made elsewhere, like on the node an archive was exported from, whose
+    /// tag came along with it. A blob pinned here already keeps its own pin.
+    pub fn adopt(&self, pin: Pin) -> bool {
+        let mut pins = self.pins.write().unwrap();
+        if pins.contains_key(&pin.hash) {
+            return false;
+        }
+        pins.insert(pin.hash, pin);
+        self.file.changed();
+        true
+    }
+
+    /// Replaces every pin with `pins`, for rolling back to a snapshot which restores
+    /// their tags too.
+    pub fn restore(&self, pins: Vec<Pin>) {
+        *self.pins.write().unwrap() = pins.into_iter().map(|pin| (pin.hash, pin)).collect();
+        self.file.changed();
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<Pin> {
+        self.pins.read().unwrap().get(hash).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Pin> {
+        self.pins.read().unwrap().values().cloned().collect()
+    }
+
+    /// The pins as they are written to disk, also when the file is yet to catch up.
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        to_json(&self.pins.read().unwrap())
+    }
+}
+
+fn to_json(pins: &BTreeMap<Hash, Pin>) -> Result<Vec<u8>> {
+    let pins: Vec<&Pin> = pins.values().collect();
+    Ok(serde_json::to_vec_pretty(&pins)?)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PinRequest {
+    reason: Option<String>,
+    owner: Option<String>,
+    hash_seq: bool,
+}
+
+/// `POST /blob/{hash}/pin`: pins a stored blob, with an optional JSON body giving the
+/// `reason` and `owner`. Tenants pin under their own name.
+pub async fn pin_blob(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Path(hash): Path<String>,
+    body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let request: PinRequest = if body.is_empty() {
+        PinRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?
+    };
+    if let Some(name) = &tenant.0 {
+        if !app_state.tenancy.owns(name, &hash) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        // Tenants can't take over each other's pins
+        if let Some(pin) = app_state.pins.get(&hash) {
+            if pin.owner.as_ref() != Some(name) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+    }
+    let has_blob = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !has_blob {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let format = if request.hash_seq {
+        BlobFormat::HashSeq
+    } else {
+        BlobFormat::Raw
+    };
+    let owner = tenant.0.or(request.owner);
+    let pin = app_state
+        .pins
+        .pin(hash, format, request.reason, owner)
+        .await
+        .map_err(|err| {
+            println!("Failed to pin {}: {}", hash, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(pin))
+}
+
+/// `DELETE /blob/{hash}/pin`. Tenants can only drop their own pins.
+pub async fn unpin_blob(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let pin = app_state.pins.get(&hash).ok_or(StatusCode::NOT_FOUND)?;
+    if tenant.0.is_some() && pin.owner != tenant.0 {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let removed = app_state.pins.unpin(&hash).await.map_err(|err| {
+        println!("Failed to unpin {}: {}", hash, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !removed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /pins`: every pin, or a tenant's own.
+pub async fn list_pins(State(app_state): State<AppState>, tenant: Tenant) -> impl IntoResponse {
+    let pins: Vec<Pin> = app_state
+        .pins
+        .list()
+        .into_iter()
+        .filter(|pin| tenant.0.is_none() || pin.owner == tenant.0)
+        .collect();
+    Json(pins)
+}
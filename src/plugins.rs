@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use axum::{body::Bytes, http::StatusCode, Router};
+use futures::future::BoxFuture;
+use iroh_blobs::Hash;
+use std::sync::Arc;
+
+use crate::config::PluginsConfig;
+use crate::AppState;
+
+/// An extension of the gateway, compiled in and switched on in `[plugins]`.
+///
+/// Every part is optional: a plugin can serve routes of its own, look at uploads
+/// before they are stored and at blobs before they are served over HTTP, and run in
+/// the background.
+pub trait Plugin: Send + Sync {
+    /// Routes added to the API, behind the same middleware as the gateway's own.
+    fn routes(&self) -> Router<AppState> {
+        Router::new()
+    }
+
+    /// Called with every upload before it is stored. An error status refuses it.
+    fn on_ingest<'a>(
+        &'a self,
+        _file_name: Option<&'a str>,
+        _data: &'a Bytes,
+    ) -> BoxFuture<'a, Result<(), StatusCode>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called before a stored blob is served over HTTP. An error status refuses it.
+    fn on_download(&self, _hash: Hash) -> BoxFuture<'_, Result<(), StatusCode>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called once the gateway is up, for background tasks to be spawned from.
+    fn spawn(&self, _app_state: AppState) {}
+}
+
+/// A plugin that can be enabled by `name`. `build` gets its settings table, empty when
+/// there is none.
+struct Registration {
+    name: &'static str,
+    build: fn(&toml::Value) -> Result<Box<dyn Plugin>>,
+}
+
+/// The plugins compiled into this build. Adding one is a matter of implementing
+/// [`Plugin`] in a module and listing it here.
+const REGISTRY: &[Registration] = &[];
+
+/// The enabled plugins, in the order they are listed in the config.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    plugins: Arc<Vec<Box<dyn Plugin>>>,
+}
+
+impl Plugins {
+    pub fn load(config: &PluginsConfig) -> Result<Self> {
+        let mut plugins = Vec::new();
+        for name in &config.enabled {
+            let Some(registration) = REGISTRY
+                .iter()
+                .find(|registration| registration.name == name)
+            else {
+                bail!("Unknown plugin {}", name);
+            };
+            let settings = config
+                .settings
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| toml::Value::Table(Default::default()));
+            plugins.push((registration.build)(&settings)?);
+            println!("Plugin {} enabled", name);
+        }
+        Ok(Self {
+            plugins: Arc::new(plugins),
+        })
+    }
+
+    pub fn routes(&self) -> Router<AppState> {
+        self.plugins.iter().fold(Router::new(), |router, plugin| {
+            router.merge(plugin.routes())
+        })
+    }
+
+    pub async fn check_ingest(
+        &self,
+        file_name: Option<&str>,
+        data: &Bytes,
+    ) -> Result<(), StatusCode> {
+        for plugin in self.plugins.iter() {
+            plugin.on_ingest(file_name, data).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn check_download(&self, hash: Hash) -> Result<(), StatusCode> {
+        for plugin in self.plugins.iter() {
+            plugin.on_download(hash).await?;
+        }
+        Ok(())
+    }
+
+    pub fn spawn(&self, app_state: &AppState) {
+        for plugin in self.plugins.iter() {
+            plugin.spawn(app_state.clone());
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use axum::http::StatusCode;
+use std::path::Path;
+
+use crate::config::UploadConfig;
+use crate::encoding::looks_like_text;
+
+/// Bytes at the start of an upload looked at to tell what it is.
+const SNIFF_LEN: usize = 8192;
+
+/// The content type of `data`, as told by its content rather than by the client. Text
+/// without a more specific signature is `text/plain`.
+pub fn content_type(data: &[u8]) -> &'static str {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type();
+    }
+    if looks_like_text(&data[..data.len().min(SNIFF_LEN)]) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Whether `content_type` matches `pattern`, which may be `*`, `type/*` or a full type.
+fn type_matches(pattern: &str, content_type: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_suffix("/*") {
+        Some(top) => content_type
+            .split_once('/')
+            .is_some_and(|(kind, _)| kind == top),
+        None => pattern == content_type,
+    }
+}
+
+fn extension_matches(pattern: &str, extension: &str) -> bool {
+    pattern.trim().trim_start_matches('.').to_ascii_lowercase() == extension
+}
+
+/// Whether `value` gets past a deny list and, when one is configured, an allow list.
+fn permitted(
+    allowed: &[String],
+    denied: &[String],
+    value: &str,
+    matches: fn(&str, &str) -> bool,
+) -> bool {
+    if denied.iter().any(|pattern| matches(pattern, value)) {
+        return false;
+    }
+    allowed.is_empty() || allowed.iter().any(|pattern| matches(pattern, value))
+}
+
+/// Checks an upload against the size, type and extension limits of `config`, answering
+/// files that are too large with 413 and those of a type or extension not accepted with
+/// 415.
+pub fn check(
+    config: &UploadConfig,
+    file_name: Option<&str>,
+    data: &[u8],
+) -> Result<(), StatusCode> {
+    if config.max_file_size > 0 && data.len() as u64 > config.max_file_size {
+        println!(
+            "Rejected upload of {} bytes, more than the limit of {}",
+            data.len(),
+            config.max_file_size
+        );
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // Files without a name or extension only get past an empty allow list
+    let extension = file_name
+        .and_then(|name| Path::new(name).extension())
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if !permitted(
+        &config.allowed_extensions,
+        &config.denied_extensions,
+        &extension,
+        extension_matches,
+    ) {
+        println!("Rejected upload {:?}: extension not accepted", file_name);
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    if config.allowed_types.is_empty() && config.denied_types.is_empty() {
+        return Ok(());
+    }
+    let content_type = content_type(data);
+    if !permitted(
+        &config.allowed_types,
+        &config.denied_types,
+        content_type,
+        type_matches,
+    ) {
+        println!(
+            "Rejected upload {:?}: {} not accepted",
+            file_name, content_type
+        );
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::ImageFormat;
+use iroh_blobs::rpc::client::blobs::ReadAtLen;
+
+use crate::blob::{etag, etag_matches, make_local, not_modified, parse_hash};
+use crate::encoding::looks_like_text;
+use crate::AppState;
+
+/// Bytes at the start of a blob looked at to tell what it is.
+const SNIFF_LEN: u64 = 512;
+
+/// What a preview is served as.
+enum Kind {
+    Image(&'static str),
+    Text,
+    Pdf,
+    /// Everything else, like archives or executables.
+    Download,
+}
+
+impl Kind {
+    fn sniff(prefix: &[u8]) -> Self {
+        if prefix.starts_with(b"%PDF-") {
+            return Kind::Pdf;
+        }
+        // Only raster formats, which can't carry scripts
+        let image = match image::guess_format(prefix) {
+            Ok(ImageFormat::Png) => Some("image/png"),
+            Ok(ImageFormat::Jpeg) => Some("image/jpeg"),
+            Ok(ImageFormat::Gif) => Some("image/gif"),
+            Ok(ImageFormat::WebP) => Some("image/webp"),
+            Ok(ImageFormat::Avif) => Some("image/avif"),
+            Ok(ImageFormat::Bmp) => Some("image/bmp"),
+            Ok(ImageFormat::Ico) => Some("image/x-icon"),
+            _ => None,
+        };
+        match image {
+            Some(content_type) => Kind::Image(content_type),
+            // Served as plain text, so HTML shows as its source
+            None if looks_like_text(prefix) => Kind::Text,
+            None => Kind::Download,
+        }
+    }
+}
+
+/// `GET /blob/{hash}/preview`: the blob for showing in a browser.
+///
+/// Content types come from the content itself and only raster images, plain text and
+/// PDFs are shown inline. Markup like HTML or SVG is shown as its source, and everything
+/// else is sent as a download, so no blob can run scripts on the gateway's origin.
+pub async fn preview_blob(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&hash)) == Some(true) {
+        return Ok(not_modified(&hash));
+    }
+    if let Some(redirect) = make_local(&app_state, hash, "/preview").await? {
+        return Ok(redirect.into_response());
+    }
+
+    let blobs_client = app_state.blobs.client();
+    let prefix = blobs_client
+        .read_at_to_bytes(hash, 0, ReadAtLen::AtMost(SNIFF_LEN))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (content_type, disposition) = match Kind::sniff(&prefix) {
+        Kind::Image(content_type) => (content_type, "inline".to_string()),
+        Kind::Text => ("text/plain; charset=utf-8", "inline".to_string()),
+        Kind::Pdf => ("application/pdf", "inline".to_string()),
+        Kind::Download => (
+            "application/octet-stream",
+            format!("attachment; filename=\"{}\"", hash),
+        ),
+    };
+
+    let reader = blobs_client
+        .read(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CONTENT_LENGTH, reader.size().to_string()),
+            (header::ETAG, etag(&hash)),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(app_state.throttle.upload().stream(reader)),
+    )
+        .into_response();
+    // Browsers' PDF viewers don't work in a sandbox, nothing else needs more than that
+    if content_type != "application/pdf" {
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            header::HeaderValue::from_static("sandbox"),
+        );
+    }
+    Ok(response)
+}
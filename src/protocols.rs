@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use iroh::endpoint::Connecting;
+use iroh::protocol::{ProtocolHandler, Router as IrohRouter};
+use iroh::Endpoint;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The protocols the node's endpoint accepts, by ALPN.
+///
+/// The gateway registers its own here, and code running it through [`crate::run`] can
+/// hand in more, such as an RPC protocol of its own, to be served next to them.
+#[derive(Clone, Default, Debug)]
+pub struct Protocols {
+    handlers: Vec<(Vec<u8>, Shared)>,
+}
+
+impl Protocols {
+    /// Accepts connections negotiating `alpn` with `handler`.
+    pub fn accept(mut self, alpn: impl AsRef<[u8]>, handler: impl ProtocolHandler) -> Self {
+        self.handlers
+            .push((alpn.as_ref().to_vec(), Shared(Arc::new(handler))));
+        self
+    }
+
+    /// Adds the protocols of `other`. An ALPN can only be taken once.
+    pub fn merge(mut self, other: Protocols) -> Result<Self> {
+        for (alpn, handler) in other.handlers {
+            if self.handlers.iter().any(|(taken, _)| *taken == alpn) {
+                bail!(
+                    "Protocol {} is registered more than once",
+                    String::from_utf8_lossy(&alpn)
+                );
+            }
+            self.handlers.push((alpn, handler));
+        }
+        Ok(self)
+    }
+
+    /// Starts accepting connections for all the protocols on `endpoint`, unless they
+    /// are turned away by `pause`.
+    pub async fn spawn(self, endpoint: Endpoint, pause: Pause) -> Result<IrohRouter> {
+        let mut builder = IrohRouter::builder(endpoint);
+        for (alpn, handler) in self.handlers {
+            builder = builder.accept(
+                alpn,
+                Pausable {
+                    handler,
+                    pause: pause.clone(),
+                },
+            );
+        }
+        builder.spawn().await
+    }
+}
+
+/// Turns away new connections of other nodes while set, as during maintenance.
+/// Connections already open carry on.
+#[derive(Clone, Default, Debug)]
+pub struct Pause(Arc<AtomicBool>);
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Pausable {
+    handler: Shared,
+    pause: Pause,
+}
+
+impl ProtocolHandler for Pausable {
+    fn accept(&self, conn: Connecting) -> BoxFuture<'static, Result<()>> {
+        if !self.pause.is_paused() {
+            return self.handler.accept(conn);
+        }
+        Box::pin(async move {
+            let conn = conn.await?;
+            conn.close(503u32.into(), b"paused for maintenance");
+            Ok(())
+        })
+    }
+
+    fn shutdown(&self) -> BoxFuture<'static, ()> {
+        self.handler.shutdown()
+    }
+}
+
+/// A handler shared by clones of [`Protocols`].
+#[derive(Clone, Debug)]
+struct Shared(Arc<dyn ProtocolHandler>);
+
+impl ProtocolHandler for Shared {
+    fn accept(&self, conn: Connecting) -> BoxFuture<'static, Result<()>> {
+        self.0.accept(conn)
+    }
+
+    fn shutdown(&self) -> BoxFuture<'static, ()> {
+        self.0.shutdown()
+    }
+}
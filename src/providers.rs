@@ -0,0 +1,247 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use iroh::{NodeAddr, NodeId};
+use iroh_blobs::{BlobFormat, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blob::parse_hash;
+use crate::gossip::parse_node_id;
+use crate::persist::StateFile;
+use crate::AppState;
+
+/// Providers remembered per blob, the most recently seen ones win.
+const MAX_PROVIDERS: usize = 16;
+/// Blobs remembered at most, as any peer on the announce topic can add to them. The
+/// blob whose providers were seen longest ago goes first.
+const MAX_BLOBS: usize = 100_000;
+
+/// How a provider of a blob became known.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// A ticket or node id given in a fetch request.
+    Ticket,
+    /// The blob was fetched from it.
+    Fetch,
+    /// It announced the blob on the announce topic.
+    Announcement,
+    /// A content tracker knew it had the blob.
+    Tracker,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Provider {
+    #[serde(flatten)]
+    pub addr: NodeAddr,
+    pub source: Source,
+    pub seen_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KnownBlob {
+    pub hash: Hash,
+    pub format: BlobFormat,
+    pub providers: Vec<Provider>,
+}
+
+/// Which remote nodes are known to hold which blobs, so blobs can be fetched by hash
+/// alone.
+///
+/// Written to disk after changes so the registry survives restarts.
+#[derive(Clone)]
+pub struct Providers {
+    known: Arc<RwLock<HashMap<Hash, KnownBlob>>>,
+    file: StateFile,
+}
+
+impl Providers {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut known = HashMap::new();
+        if path.exists() {
+            let saved: Vec<KnownBlob> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            for blob in saved {
+                known.insert(blob.hash, blob);
+            }
+        }
+        let known = Arc::new(RwLock::new(known));
+        let file = StateFile::spawn(path, {
+            let known = known.clone();
+            move || to_json(&known.read().unwrap())
+        });
+        Ok(Self { known, file })
+    }
+
+    /// Remembers that `addr` holds `hash`. A fetch from a node outranks having seen it
+    /// in a ticket or an announcement.
+    pub fn record(&self, hash: Hash, format: BlobFormat, addr: NodeAddr, source: Source) {
+        let seen_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut known = self.known.write().unwrap();
+        let blob = known.entry(hash).or_insert_with(|| KnownBlob {
+            hash,
+            format,
+            providers: Vec::new(),
+        });
+        blob.format = format;
+        let source = match blob
+            .providers
+            .iter()
+            .position(|provider| provider.addr.node_id == addr.node_id)
+        {
+            Some(i) => {
+                let previous = blob.providers.remove(i);
+                if previous.source == Source::Fetch {
+                    Source::Fetch
+                } else {
+                    source
+                }
+            }
+            None => source,
+        };
+        blob.providers.insert(
+            0,
+            Provider {
+                addr,
+                source,
+                seen_at,
+            },
+        );
+        blob.providers.truncate(MAX_PROVIDERS);
+        evict(&mut known);
+        self.file.changed();
+    }
+
+    /// Adds the providers another gateway knew of for a blob, returning how many were
+    /// new. Those known here already keep what was seen of them here.
+    pub fn merge(&self, other: KnownBlob) -> usize {
+        let mut known = self.known.write().unwrap();
+        let blob = known.entry(other.hash).or_insert_with(|| KnownBlob {
+            hash: other.hash,
+            format: other.format,
+            providers: Vec::new(),
+        });
+        let mut added = 0;
+        for provider in other.providers {
+            if blob.providers.len() >= MAX_PROVIDERS {
+                break;
+            }
+            if blob
+                .providers
+                .iter()
+                .all(|known| known.addr.node_id != provider.addr.node_id)
+            {
+                blob.providers.push(provider);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            evict(&mut known);
+            self.file.changed();
+        }
+        added
+    }
+
+    /// The blob's format and the nodes to fetch it from, most recently seen first.
+    pub fn get(&self, hash: &Hash) -> Option<(BlobFormat, Vec<NodeAddr>)> {
+        let known = self.known.read().unwrap();
+        let blob = known.get(hash)?;
+        let addrs = blob
+            .providers
+            .iter()
+            .map(|provider| provider.addr.clone())
+            .collect();
+        Some((blob.format, addrs))
+    }
+
+    pub fn blob(&self, hash: &Hash) -> Option<KnownBlob> {
+        self.known.read().unwrap().get(hash).cloned()
+    }
+
+    /// Forgets a provider of a blob, for nodes that no longer have it.
+    pub fn remove(&self, hash: &Hash, node_id: &NodeId) -> bool {
+        let mut known = self.known.write().unwrap();
+        let Some(blob) = known.get_mut(hash) else {
+            return false;
+        };
+        let len = blob.providers.len();
+        blob.providers
+            .retain(|provider| provider.addr.node_id != *node_id);
+        if blob.providers.len() == len {
+            return false;
+        }
+        if blob.providers.is_empty() {
+            known.remove(hash);
+        }
+        self.file.changed();
+        true
+    }
+
+    /// The registry as it is written to disk.
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        to_json(&self.known.read().unwrap())
+    }
+
+}
+
+/// Forgets the blobs seen longest ago while there are more than [`MAX_BLOBS`].
+fn evict(known: &mut HashMap<Hash, KnownBlob>) {
+    while known.len() > MAX_BLOBS {
+        let last_seen = |blob: &KnownBlob| {
+            blob.providers
+                .iter()
+                .map(|provider| provider.seen_at)
+                .max()
+                .unwrap_or_default()
+        };
+        let Some(oldest) = known
+            .values()
+            .min_by_key(|blob| last_seen(blob))
+            .map(|blob| blob.hash)
+        else {
+            break;
+        };
+        known.remove(&oldest);
+    }
+}
+
+fn to_json(known: &HashMap<Hash, KnownBlob>) -> Result<Vec<u8>> {
+    let blobs: Vec<&KnownBlob> = known.values().collect();
+    Ok(serde_json::to_vec_pretty(&blobs)?)
+}
+
+/// `GET /blob/{hash}/providers`: the remote nodes known to hold a blob.
+pub async fn list_providers(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let blob = app_state
+        .providers
+        .blob(&hash)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(blob))
+}
+
+pub async fn remove_provider(
+    State(app_state): State<AppState>,
+    Path((hash, node_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let node_id = parse_node_id(&node_id).ok_or(StatusCode::BAD_REQUEST)?;
+    if !app_state.providers.remove(&hash, &node_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,135 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::config::ProxyConfig;
+use crate::AppState;
+
+/// Which reverse proxies are believed about the client, and the prefix the API is
+/// served under.
+#[derive(Clone)]
+pub struct Proxy {
+    trusted: Arc<HashSet<IpAddr>>,
+    base_path: String,
+    tls: bool,
+}
+
+impl Proxy {
+    pub fn new(config: &ProxyConfig, tls: bool) -> Self {
+        Self {
+            trusted: Arc::new(config.trusted.iter().copied().collect()),
+            base_path: normalize_base_path(&config.base_path),
+            tls,
+        }
+    }
+
+    /// The prefix routes are served under, empty or starting with a `/`.
+    pub fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.contains(ip)
+    }
+
+    /// The client behind a request from `peer`, taken from its headers when `peer` is a
+    /// trusted proxy.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        match peer {
+            Some(ip) if self.is_trusted(&ip) => forwarded_for(self, headers).or(peer),
+            _ => peer,
+        }
+    }
+}
+
+fn normalize_base_path(base_path: &str) -> String {
+    let base_path = base_path.trim_matches('/');
+    if base_path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", base_path)
+    }
+}
+
+/// The client behind a request, as seen through trusted reverse proxies.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub proto: String,
+    pub host: Option<String>,
+    base_path: String,
+}
+
+impl ClientInfo {
+    /// The absolute URL clients reach `path` of the API at.
+    pub fn url(&self, path: &str) -> Option<String> {
+        let host = self.host.as_deref()?;
+        Some(format!(
+            "{}://{}{}{}",
+            self.proto, host, self.base_path, path
+        ))
+    }
+}
+
+fn first_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let value = value.split(',').next()?.trim();
+    (!value.is_empty()).then_some(value)
+}
+
+/// The client address from `X-Forwarded-For`: the rightmost entry not added by a
+/// trusted proxy, since anything left of it could have been made up by the client.
+fn forwarded_for(proxy: &Proxy, headers: &HeaderMap) -> Option<IpAddr> {
+    let value = headers.get("x-forwarded-for")?.to_str().ok()?;
+    let hops: Vec<IpAddr> = value
+        .split(',')
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+    hops.iter()
+        .rev()
+        .find(|ip| !proxy.is_trusted(ip))
+        .or(hops.first())
+        .copied()
+}
+
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let proxy = &app_state.proxy;
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let trusted = peer.is_some_and(|ip| proxy.is_trusted(&ip));
+        let headers = &parts.headers;
+
+        let ip = proxy.client_ip(peer, headers);
+        let proto = trusted
+            .then(|| first_header(headers, "x-forwarded-proto"))
+            .flatten()
+            .unwrap_or(if proxy.tls { "https" } else { "http" })
+            .to_string();
+        let host = trusted
+            .then(|| first_header(headers, "x-forwarded-host"))
+            .flatten()
+            .or_else(|| first_header(headers, header::HOST.as_str()))
+            .or_else(|| parts.uri.authority().map(|authority| authority.as_str()))
+            .map(str::to_string);
+
+        Ok(Self {
+            ip,
+            proto,
+            host,
+            base_path: proxy.base_path.clone(),
+        })
+    }
+}
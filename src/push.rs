@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures::future::BoxFuture;
+use iroh::endpoint::{get_remote_node_id, Connecting};
+use iroh::protocol::ProtocolHandler;
+use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh_base::ticket::NodeTicket;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_blobs::{BlobFormat, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use crate::blob::{etag_matches, parse_hash};
+use crate::breaker::Breakers;
+use crate::fetch::Fetcher;
+use crate::gossip::parse_node_id;
+use crate::reload::Live;
+use crate::timeouts::{Phase, Timeouts};
+use crate::upload::publish;
+use crate::AppState;
+
+/// ALPN of the push protocol.
+///
+/// The pushing node sends the hash it wants replicated and the receiving node fetches it
+/// from the pusher over the regular blobs protocol, so content is verified as usual.
+pub const ALPN: &[u8] = b"iroh-api/push/0";
+
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+#[derive(Serialize, Deserialize)]
+struct PushMessage {
+    hash: Hash,
+    format: BlobFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PushReply {
+    error: Option<String>,
+}
+
+/// Accepts pushes from the configured set of trusted nodes.
+///
+/// Pushed blobs are announced and reported like uploads once the app state is filled
+/// in, which happens after the node is up.
+#[derive(Clone)]
+pub struct PushReceiver {
+    fetcher: Fetcher,
+    accept_from: Live<HashSet<NodeId>>,
+    state: Arc<OnceLock<AppState>>,
+}
+
+impl fmt::Debug for PushReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PushReceiver").finish_non_exhaustive()
+    }
+}
+
+impl PushReceiver {
+    pub fn new(fetcher: Fetcher, accept_from: &[NodeId]) -> Self {
+        Self {
+            fetcher,
+            accept_from: Live::new(accept_from.iter().copied().collect()),
+            state: Default::default(),
+        }
+    }
+
+    pub fn set_state(&self, app_state: AppState) {
+        let _ = self.state.set(app_state);
+    }
+
+    pub fn set_accept_from(&self, accept_from: &[NodeId]) {
+        self.accept_from.set(accept_from.iter().copied().collect());
+    }
+
+    async fn handle(self, conn: Connecting) -> Result<()> {
+        let conn = conn.await?;
+        let remote = get_remote_node_id(&conn)?;
+        let (mut send, mut recv) = conn.accept_bi().await?;
+        let message: PushMessage =
+            serde_json::from_slice(&recv.read_to_end(MAX_MESSAGE_SIZE).await?)?;
+
+        let result = if self.accept_from.get().contains(&remote) {
+            self.fetch(remote, &message).await
+        } else {
+            Err(anyhow!("node {} is not allowed to push", remote))
+        };
+        println!(
+            "Push of {} from {}: {}",
+            message.hash,
+            remote,
+            result
+                .as_ref()
+                .map_or_else(|err| err.to_string(), |_| "ok".to_string())
+        );
+
+        let reply = PushReply {
+            error: result.err().map(|err| err.to_string()),
+        };
+        send.write_all(&serde_json::to_vec(&reply)?).await?;
+        send.finish()?;
+        conn.closed().await;
+        Ok(())
+    }
+
+    async fn fetch(&self, remote: NodeId, message: &PushMessage) -> Result<()> {
+        let fetched = self
+            .fetcher
+            .fetch(message.hash, message.format, vec![remote.into()])
+            .await?;
+        // Blobs that were here already aren't new to anyone. Pushed blobs aren't
+        // replicated further, the pusher takes care of that.
+        if fetched.downloaded_size > 0 {
+            if let Some(app_state) = self.state.get() {
+                let ticket =
+                    BlobTicket::new(app_state.node_id.into(), message.hash, message.format)?;
+                let size = fetched.local_size + fetched.downloaded_size;
+                publish(app_state, &ticket, size, None, "push").await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for PushReceiver {
+    fn accept(&self, conn: Connecting) -> BoxFuture<'static, Result<()>> {
+        Box::pin(self.clone().handle(conn))
+    }
+}
+
+/// Asks `target` to fetch `hash` from us and waits until it has the content, within the
+/// connect and transfer timeouts. Targets that can't be reached count against their
+/// breaker, refusals don't.
+pub async fn push_to(
+    endpoint: &Endpoint,
+    breakers: &Breakers,
+    timeouts: &Timeouts,
+    target: NodeAddr,
+    hash: Hash,
+    format: BlobFormat,
+) -> Result<()> {
+    let node_id = target.node_id;
+    breakers.check(node_id)?;
+    let reply = request_push(endpoint, timeouts, target, hash, format).await;
+    breakers.record(node_id, reply.is_ok());
+    match reply?.error {
+        Some(error) => Err(anyhow!(error)),
+        None => Ok(()),
+    }
+}
+
+async fn request_push(
+    endpoint: &Endpoint,
+    timeouts: &Timeouts,
+    target: NodeAddr,
+    hash: Hash,
+    format: BlobFormat,
+) -> Result<PushReply> {
+    let clock = timeouts.start();
+    let conn = clock
+        .within(Phase::Connect, endpoint.connect(target, ALPN))
+        .await??;
+    let (mut send, mut recv) = conn.open_bi().await?;
+    send.write_all(&serde_json::to_vec(&PushMessage { hash, format })?)
+        .await?;
+    send.finish()?;
+
+    // The reply comes once the target fetched the blob
+    let reply = clock
+        .within(Phase::Transfer, recv.read_to_end(MAX_MESSAGE_SIZE))
+        .await??;
+    let reply: PushReply = serde_json::from_slice(&reply)?;
+    conn.close(0u32.into(), b"done");
+    Ok(reply)
+}
+
+/// Parses a push target given as a node id, node ticket, or blob ticket.
+pub fn parse_target(target: &str) -> Option<NodeAddr> {
+    if let Some(node_id) = parse_node_id(target) {
+        return Some(node_id.into());
+    }
+    if let Ok(ticket) = NodeTicket::from_str(target) {
+        return Some(ticket.node_addr().clone());
+    }
+    BlobTicket::from_str(target)
+        .ok()
+        .map(|ticket| ticket.node_addr().clone())
+}
+
+#[derive(Deserialize)]
+pub struct PushRequest {
+    targets: Vec<String>,
+    #[serde(default)]
+    hash_seq: bool,
+}
+
+#[derive(Serialize)]
+pub struct PushResult {
+    target: String,
+    ok: bool,
+    error: Option<String>,
+}
+
+pub async fn push_blob(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<PushRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if etag_matches(&headers, header::IF_MATCH, Some(&hash)) == Some(false) {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+    let format = if request.hash_seq {
+        BlobFormat::HashSeq
+    } else {
+        BlobFormat::Raw
+    };
+    let has_blob = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !has_blob {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Push to all targets concurrently and report each outcome separately
+    let pushes = request.targets.into_iter().map(|target| {
+        let endpoint = app_state.endpoint.clone();
+        let breakers = app_state.breakers.clone();
+        let timeouts = app_state.timeouts;
+        async move {
+            let result = match parse_target(&target) {
+                Some(addr) => push_to(&endpoint, &breakers, &timeouts, addr, hash, format).await,
+                None => Err(anyhow!("invalid target")),
+            };
+            PushResult {
+                target,
+                ok: result.is_ok(),
+                error: result.err().map(|err| err.to_string()),
+            }
+        }
+    });
+    let results = futures::future::join_all(pushes).await;
+
+    Ok(Json(results))
+}
@@ -0,0 +1,110 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::config::ReadOnlyConfig;
+use crate::AppState;
+
+/// How long clients are asked to wait, read-only spells tend to be short.
+const RETRY_AFTER_SECS: u64 = 60;
+
+/// Routes taking a POST that change nothing in the store, by route pattern.
+const READ_ONLY_POSTS: &[&str] = &[
+    "/hash",
+    "/verify/{hash}",
+    "/graphql",
+    "/admin/reload",
+    "/admin/export-all",
+    "/admin/snapshots",
+];
+
+/// Whether the node only serves what it has, refusing uploads, deletes and every other
+/// change over HTTP. Serving blobs to other nodes and background work like garbage
+/// collection carry on.
+#[derive(Clone)]
+pub struct ReadOnly(Arc<AtomicBool>);
+
+impl ReadOnly {
+    pub fn new(config: &ReadOnlyConfig) -> Self {
+        Self(Arc::new(AtomicBool::new(config.enabled)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        if self.0.swap(enabled, Ordering::Relaxed) != enabled {
+            println!(
+                "Read-only mode {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+}
+
+/// Requests that change something, by method, route pattern and the path of gRPC
+/// methods.
+fn is_mutating(method: &Method, route: &str, path: &str) -> bool {
+    // WebDAV clients list folders with PROPFIND
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+        || method.as_str() == "PROPFIND"
+    {
+        return false;
+    }
+    // Or it could never be switched off
+    if route.ends_with("/admin/read-only") {
+        return false;
+    }
+    if path.contains("/IrohApi/") {
+        return path.ends_with("/IrohApi/Upload") || path.ends_with("/IrohApi/Fetch");
+    }
+    !(*method == Method::POST && READ_ONLY_POSTS.iter().any(|post| route.ends_with(post)))
+}
+
+/// Middleware refusing changes with 503 while the node is read-only.
+pub async fn apply(State(read_only): State<ReadOnly>, request: Request, next: Next) -> Response {
+    if read_only.is_enabled() {
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str())
+            .unwrap_or_default();
+        if is_mutating(request.method(), route, request.uri().path()) {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+                Json(serde_json::json!({ "error": "the node is read-only" })),
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub struct ReadOnlyRequest {
+    enabled: bool,
+}
+
+/// `GET /admin/read-only`
+pub async fn get_read_only(State(app_state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "enabled": app_state.read_only.is_enabled() }))
+}
+
+/// `PUT /admin/read-only`: switches read-only mode until the next restart, when the
+/// config decides again.
+pub async fn set_read_only(
+    State(app_state): State<AppState>,
+    Json(request): Json<ReadOnlyRequest>,
+) -> Json<serde_json::Value> {
+    app_state.read_only.set(request.enabled);
+    Json(serde_json::json!({ "enabled": request.enabled }))
+}
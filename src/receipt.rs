@@ -0,0 +1,265 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use data_encoding::HEXLOWER;
+use iroh::{NodeId, SecretKey};
+use iroh_base::Signature;
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::blob::parse_hash;
+use crate::jobs::now_secs;
+use crate::AppState;
+
+/// Put in front of receipts before signing, so no other statement signed with the
+/// node key can pass for one. Receipts and proofs encode alike otherwise.
+const RECEIPT_DOMAIN: &[u8] = b"iroh-api/receipt/0";
+/// Put in front of availability proofs before signing, as [`RECEIPT_DOMAIN`].
+const AVAILABILITY_DOMAIN: &[u8] = b"iroh-api/availability/0";
+
+/// Signs `domain` followed by the postcard encoding of `statement`, returning the
+/// signature in hex.
+fn sign(secret_key: &SecretKey, domain: &[u8], statement: &impl Serialize) -> Result<String> {
+    let signature = secret_key.sign(&[domain, &postcard::to_allocvec(statement)?].concat());
+    Ok(HEXLOWER.encode(&signature.to_bytes()))
+}
+
+fn verify(
+    node_id: &NodeId,
+    domain: &[u8],
+    statement: &impl Serialize,
+    signature: &str,
+) -> Result<()> {
+    let signature = Signature::from_slice(&HEXLOWER.decode(signature.as_bytes())?)?;
+    node_id.verify(&[domain, &postcard::to_allocvec(statement)?].concat(), &signature)?;
+    Ok(())
+}
+
+/// What a node signs when it accepts an upload.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Accepted {
+    pub node_id: NodeId,
+    pub hash: Hash,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Who uploaded: the tenant, the client's address, or the node that forwarded it.
+    pub uploader: Option<String>,
+}
+
+/// Proof that `node_id` accepted an upload, for uploaders to keep.
+///
+/// The signature, in hex, is made with the node's iroh key over `iroh-api/receipt/0`
+/// followed by the postcard encoding of the other fields, so anyone knowing the node id
+/// can check it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Receipt {
+    #[serde(flatten)]
+    pub accepted: Accepted,
+    pub signature: String,
+}
+
+impl Receipt {
+    pub fn sign(
+        secret_key: &SecretKey,
+        hash: Hash,
+        size: u64,
+        uploader: Option<String>,
+    ) -> Result<Self> {
+        let accepted = Accepted {
+            node_id: secret_key.public(),
+            hash,
+            size,
+            timestamp: now_secs(),
+            uploader,
+        };
+        let signature = sign(secret_key, RECEIPT_DOMAIN, &accepted)?;
+        Ok(Self {
+            accepted,
+            signature,
+        })
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        verify(
+            &self.accepted.node_id,
+            RECEIPT_DOMAIN,
+            &self.accepted,
+            &self.signature,
+        )
+    }
+}
+
+/// What a node signs to say it holds a blob completely.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Holding {
+    pub node_id: NodeId,
+    pub hash: Hash,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Chosen by whoever asked, so the proof can't have been made in advance.
+    pub nonce: Option<String>,
+}
+
+/// A node's signed statement that it held a blob at some time, signed like a
+/// [`Receipt`] but after `iroh-api/availability/0`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AvailabilityProof {
+    #[serde(flatten)]
+    pub holding: Holding,
+    pub signature: String,
+}
+
+impl AvailabilityProof {
+    pub fn verify(&self) -> Result<()> {
+        verify(
+            &self.holding.node_id,
+            AVAILABILITY_DOMAIN,
+            &self.holding,
+            &self.signature,
+        )
+    }
+}
+
+/// `POST /receipts/verify`: whether a receipt is genuine, whether this node signed it,
+/// and whether the blob is still stored here.
+pub async fn verify_receipt(
+    State(app_state): State<AppState>,
+    Json(receipt): Json<Receipt>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let error = receipt.verify().err().map(|err| err.to_string());
+    let stored = app_state
+        .blobs
+        .client()
+        .has(receipt.accepted.hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(serde_json::json!({
+        "valid": error.is_none(),
+        "error": error,
+        "signed_here": receipt.accepted.node_id == app_state.node_id,
+        "stored": stored,
+        "receipt": receipt,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ProofParams {
+    nonce: Option<String>,
+}
+
+/// Longest nonce signed into a proof.
+const MAX_NONCE_LEN: usize = 256;
+
+/// `GET /blob/{hash}/proof?nonce=`: a signed statement that this node holds the
+/// complete blob right now, for monitors auditing mirrors. `404` when it doesn't.
+pub async fn prove_availability(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    Query(params): Query<ProofParams>,
+) -> Result<Json<AvailabilityProof>, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if params
+        .nonce
+        .as_ref()
+        .is_some_and(|nonce| nonce.len() > MAX_NONCE_LEN)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let status = app_state
+        .blobs
+        .client()
+        .status(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let BlobStatus::Complete { size } = status else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let secret_key = app_state.endpoint.secret_key();
+    let holding = Holding {
+        node_id: secret_key.public(),
+        hash,
+        size,
+        timestamp: now_secs(),
+        nonce: params.nonce,
+    };
+    let signature = sign(secret_key, AVAILABILITY_DOMAIN, &holding)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(AvailabilityProof { holding, signature }))
+}
+
+/// `POST /proofs/verify`: whether an availability proof from any node is genuine.
+pub async fn verify_proof(Json(proof): Json<AvailabilityProof>) -> Json<serde_json::Value> {
+    let error = proof.verify().err().map(|err| err.to_string());
+    Json(serde_json::json!({
+        "valid": error.is_none(),
+        "error": error,
+        "proof": proof,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof(secret_key: &SecretKey, nonce: &str) -> AvailabilityProof {
+        let holding = Holding {
+            node_id: secret_key.public(),
+            hash: Hash::new(b"blob"),
+            size: 4,
+            timestamp: now_secs(),
+            nonce: Some(nonce.to_string()),
+        };
+        let signature = sign(secret_key, AVAILABILITY_DOMAIN, &holding).unwrap();
+        AvailabilityProof { holding, signature }
+    }
+
+    #[test]
+    fn receipts_verify() {
+        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+        let receipt = Receipt::sign(&secret_key, Hash::new(b"blob"), 4, Some("photos".into())).unwrap();
+        receipt.verify().unwrap();
+
+        let mut changed = receipt.clone();
+        changed.accepted.uploader = Some("docs".to_string());
+        assert!(changed.verify().is_err());
+    }
+
+    #[test]
+    fn proofs_do_not_pass_for_receipts() {
+        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+        let proof = proof(&secret_key, "victim");
+        proof.verify().unwrap();
+
+        // Both encode alike, so only the domain tells them apart
+        let receipt = Receipt {
+            accepted: Accepted {
+                node_id: proof.holding.node_id,
+                hash: proof.holding.hash,
+                size: proof.holding.size,
+                timestamp: proof.holding.timestamp,
+                uploader: proof.holding.nonce.clone(),
+            },
+            signature: proof.signature.clone(),
+        };
+        assert!(receipt.verify().is_err());
+
+        let receipt = Receipt::sign(&secret_key, Hash::new(b"blob"), 4, Some("victim".into())).unwrap();
+        let proof = AvailabilityProof {
+            holding: Holding {
+                node_id: receipt.accepted.node_id,
+                hash: receipt.accepted.hash,
+                size: receipt.accepted.size,
+                timestamp: receipt.accepted.timestamp,
+                nonce: receipt.accepted.uploader.clone(),
+            },
+            signature: receipt.signature,
+        };
+        assert!(proof.verify().is_err());
+    }
+}
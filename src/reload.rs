@@ -0,0 +1,148 @@
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use tower_http::cors::CorsLayer;
+
+use crate::access_log::AccessLog;
+use crate::caching::CacheControl;
+use crate::config::{Config, UploadConfig};
+use crate::encoding::Compressor;
+use crate::forward::ForwardReceiver;
+use crate::metering::Metering;
+use crate::push::PushReceiver;
+use crate::roles::{RoleTable, Roles};
+use crate::screening::Screening;
+use crate::security_headers::SecurityHeaders;
+use crate::tenancy::{Access, Tenancy};
+use crate::throttle::Throttle;
+use crate::AppState;
+
+/// A setting that can be replaced while the gateway runs. Work that already read it
+/// carries on with the value it got.
+pub struct Live<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+impl<T> Clone for Live<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Live<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl<T: Default> Default for Live<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Re-reads the config file and applies the settings that don't need a restart: CORS,
+/// bandwidth caps of HTTP transfers, upload timeouts, download compression,
+/// `Cache-Control`, security headers, the push and forward allowlists, tenants with
+/// their keys and quotas, admin keys, roles, the screening and metering webhooks and
+/// the access log, whose file is reopened.
+///
+/// Listeners, TLS, storage, networking, the sizes of the worker pools, the path
+/// prefixes of tenants added since the start and the metering interval stay as they
+/// were started.
+#[derive(Clone)]
+pub struct Reloader {
+    pub cors: Live<CorsLayer>,
+    pub cache_control: CacheControl,
+    pub throttle: Throttle,
+    pub upload: Live<UploadConfig>,
+    pub compressor: Compressor,
+    pub push_receiver: PushReceiver,
+    pub forward_receiver: ForwardReceiver,
+    pub access_log: AccessLog,
+    pub security_headers: SecurityHeaders,
+    pub roles: Roles,
+    pub tenancy: Tenancy,
+    pub screening: Screening,
+    pub metering: Metering,
+}
+
+impl Reloader {
+    pub fn reload(&self) -> Result<()> {
+        let config = Config::load()?;
+        // Everything is checked before anything changes, a broken file changes nothing
+        let cors = crate::cors::layer(&config.cors)?;
+        let cache_routes =
+            crate::caching::routes(&config.cache_control, self.cache_control.base_path())?;
+        let security_policy = crate::security_headers::policy(&config.security_headers)?;
+        let roles = RoleTable::new(&config.roles)?;
+        let access = Access::new(&config.tenancy, &roles)?;
+        let screening = self.screening.settings(&config.screening)?;
+
+        self.cors.set(cors);
+        self.cache_control.set_routes(cache_routes);
+        self.throttle.reload(&config.bandwidth);
+        self.upload.set(config.upload);
+        self.compressor.reload(&config.compression);
+        self.push_receiver.set_accept_from(&config.push.accept_from);
+        self.forward_receiver
+            .set_accept_from(&config.forward.accept_from);
+        self.access_log.reload(&config.access_log);
+        self.security_headers.set_policy(security_policy);
+        self.roles.set(roles);
+        self.tenancy.set_access(access);
+        self.screening.set_settings(screening);
+        self.metering.reload(&config.metering);
+        println!("Reloaded config");
+        Ok(())
+    }
+
+    /// Reloads whenever the process gets SIGHUP, the usual way to ask a daemon to.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if let Err(err) = self.reload() {
+                    println!("Failed to reload config: {:#}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// `POST /admin/reload`, an admin route like the rest of `/admin`.
+pub async fn reload_config(
+    State(app_state): State<AppState>,
+) -> Result<Json<serde_json::Value>, Response> {
+    app_state.reloader.reload().map_err(|err| {
+        println!("Failed to reload config: {:#}", err);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": format!("{:#}", err) })),
+        )
+            .into_response()
+    })?;
+    Ok(Json(serde_json::json!({ "reloaded": true })))
+}
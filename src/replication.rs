@@ -0,0 +1,195 @@
+use anyhow::{bail, Context, Result};
+use iroh::{Endpoint, NodeAddr};
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::{BlobFormat, Hash};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::breaker::Breakers;
+use crate::config::ReplicationConfig;
+use crate::jobs::Jobs;
+use crate::push::{parse_target, push_to};
+use crate::timeouts::Timeouts;
+use crate::tracker::tagged_content;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaState {
+    Pending,
+    Replicated,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ReplicaStatus {
+    target: String,
+    state: ReplicaState,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ReplicationStatus {
+    factor: usize,
+    job_id: Option<u64>,
+    replicas: Vec<ReplicaStatus>,
+}
+
+/// Pushes every new upload to the configured replica peers until `factor` of them hold it.
+#[derive(Clone)]
+pub struct Replicator {
+    endpoint: Endpoint,
+    breakers: Breakers,
+    timeouts: Timeouts,
+    peers: Arc<Vec<(String, NodeAddr)>>,
+    factor: usize,
+    status: Arc<RwLock<HashMap<Hash, ReplicationStatus>>>,
+}
+
+impl Replicator {
+    pub fn new(
+        endpoint: Endpoint,
+        breakers: Breakers,
+        timeouts: Timeouts,
+        config: &ReplicationConfig,
+    ) -> Result<Self> {
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| {
+                let addr =
+                    parse_target(peer).with_context(|| format!("Invalid replica peer {}", peer))?;
+                Ok((peer.clone(), addr))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let factor = config.factor.unwrap_or(peers.len()).min(peers.len());
+
+        Ok(Self {
+            endpoint,
+            breakers,
+            timeouts,
+            peers: Arc::new(peers),
+            factor,
+            status: Default::default(),
+        })
+    }
+
+    /// Queues replication of a blob. Does nothing when no replica peers are configured.
+    pub fn replicate(&self, jobs: &Jobs, hash: Hash, format: BlobFormat) {
+        if self.factor == 0 {
+            return;
+        }
+        self.status.write().unwrap().insert(
+            hash,
+            ReplicationStatus {
+                factor: self.factor,
+                job_id: None,
+                replicas: Vec::new(),
+            },
+        );
+
+        let replicator = self.clone();
+        let job_id = jobs.spawn("replicate", async move {
+            let replicated = replicator.run(hash, format).await;
+            let result = serde_json::json!({
+                "hash": hash.to_string(),
+                "replicated": replicated,
+                "factor": replicator.factor,
+            });
+            if replicated < replicator.factor {
+                bail!(
+                    "only {} of {} replicas succeeded",
+                    replicated,
+                    replicator.factor
+                );
+            }
+            Ok(result)
+        });
+        if let Some(status) = self.status.write().unwrap().get_mut(&hash) {
+            status.job_id = Some(job_id);
+        }
+    }
+
+    /// Queues replication of every tagged blob that isn't on enough replicas and has no
+    /// replication under way, returning how many were queued.
+    pub async fn reconcile(
+        &self,
+        jobs: &Jobs,
+        blobs: &Blobs<iroh_blobs::store::fs::Store>,
+    ) -> Result<usize> {
+        if self.factor == 0 {
+            bail!("no replica peers are configured");
+        }
+        let mut queued = 0;
+        for content in tagged_content(blobs).await? {
+            if let Some(status) = self.status(&content.hash) {
+                let replicated = status
+                    .replicas
+                    .iter()
+                    .filter(|replica| replica.state == ReplicaState::Replicated)
+                    .count();
+                let running = status.job_id.is_some_and(|id| !jobs.is_finished(id));
+                if replicated >= status.factor || running {
+                    continue;
+                }
+            }
+            self.replicate(jobs, content.hash, content.format);
+            queued += 1;
+        }
+        Ok(queued)
+    }
+
+    /// Tries the peers in order until enough replicas succeeded, returning how many did.
+    async fn run(&self, hash: Hash, format: BlobFormat) -> usize {
+        let mut replicated = 0;
+        for (target, addr) in self.peers.iter() {
+            if replicated == self.factor {
+                break;
+            }
+            self.record(hash, target, ReplicaState::Pending, None);
+            let pushed = push_to(
+                &self.endpoint,
+                &self.breakers,
+                &self.timeouts,
+                addr.clone(),
+                hash,
+                format,
+            );
+            match pushed.await {
+                Ok(()) => {
+                    replicated += 1;
+                    self.record(hash, target, ReplicaState::Replicated, None);
+                }
+                Err(err) => {
+                    println!("Failed to replicate {} to {}: {}", hash, target, err);
+                    self.record(hash, target, ReplicaState::Failed, Some(err.to_string()));
+                }
+            }
+        }
+        replicated
+    }
+
+    fn record(&self, hash: Hash, target: &str, state: ReplicaState, error: Option<String>) {
+        let mut status = self.status.write().unwrap();
+        let Some(status) = status.get_mut(&hash) else {
+            return;
+        };
+        let replica = ReplicaStatus {
+            target: target.to_string(),
+            state,
+            error,
+        };
+        match status
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.target == target)
+        {
+            Some(existing) => *existing = replica,
+            None => status.replicas.push(replica),
+        }
+    }
+
+    pub fn status(&self, hash: &Hash) -> Option<ReplicationStatus> {
+        self.status.read().unwrap().get(hash).cloned()
+    }
+}
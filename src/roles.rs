@@ -0,0 +1,227 @@
+use anyhow::{bail, Result};
+use axum::http::Method;
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{RolesConfig, RouteGroup};
+use crate::csrf::is_safe;
+use crate::reload::Live;
+use crate::tenancy::TENANT_ROUTES;
+
+/// The role of admin keys, and of everyone on gateways without keys.
+pub const ADMIN: &str = "admin";
+/// The role of tenant keys, and of requests using tenants without keys.
+pub const UPLOADER: &str = "uploader";
+pub const READER: &str = "reader";
+
+const DEFAULT_GROUPS: &[(&str, &[RouteGroup])] = &[
+    (
+        ADMIN,
+        &[RouteGroup::Admin, RouteGroup::Mutating, RouteGroup::Read],
+    ),
+    (UPLOADER, &[RouteGroup::Mutating, RouteGroup::Read]),
+    (READER, &[RouteGroup::Read]),
+];
+
+/// Routes of another group than their methods say: hashing and verifying don't store
+/// anything.
+const DEFAULT_ROUTES: &[(&str, RouteGroup)] = &[
+    ("/hash", RouteGroup::Read),
+    ("/verify/{hash}", RouteGroup::Read),
+];
+
+/// Roles limiting API keys and users to groups of routes: admin routes working on the
+/// whole store, mutating routes changing what a tenant stores, and read routes.
+///
+/// Roles come on top of tenancy. What a request sees still depends on its tenant, and
+/// tenants never get to admin routes, whatever their role. They can be replaced while
+/// the gateway runs.
+#[derive(Clone)]
+pub struct Roles(Live<RoleTable>);
+
+/// The roles of one version of the config.
+pub struct RoleTable {
+    groups: HashMap<String, HashSet<RouteGroup>>,
+    routes: HashMap<String, RouteGroup>,
+    keys: HashMap<String, String>,
+    default_user_role: String,
+}
+
+impl RoleTable {
+    pub fn new(config: &RolesConfig) -> Result<Self> {
+        let mut groups: HashMap<String, HashSet<RouteGroup>> = DEFAULT_GROUPS
+            .iter()
+            .map(|(role, groups)| (role.to_string(), groups.iter().copied().collect()))
+            .collect();
+        groups.extend(
+            config
+                .groups
+                .iter()
+                .map(|(role, groups)| (role.clone(), groups.iter().copied().collect())),
+        );
+        let mut routes: HashMap<String, RouteGroup> = DEFAULT_ROUTES
+            .iter()
+            .map(|(route, group)| (route.to_string(), *group))
+            .collect();
+        routes.extend(config.routes.clone());
+
+        for role in config
+            .keys
+            .values()
+            .chain(std::iter::once(&config.default_user_role))
+        {
+            if !groups.contains_key(role) {
+                bail!(
+                    "Unknown role {:?}, roles are set under [roles.groups]",
+                    role
+                );
+            }
+        }
+        Ok(Self {
+            groups,
+            routes,
+            keys: config.keys.clone(),
+            default_user_role: config.default_user_role.clone(),
+        })
+    }
+
+    /// The API keys given roles, to check they are all configured.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(String::as_str)
+    }
+}
+
+impl Roles {
+    pub fn new(config: &RolesConfig) -> Result<Self> {
+        Ok(Self(Live::new(RoleTable::new(config)?)))
+    }
+
+    /// Replaces the roles, as reloading the config does. Requests already authorized
+    /// keep going.
+    pub fn set(&self, table: RoleTable) {
+        self.0.set(table);
+    }
+
+    pub fn exists(&self, role: &str) -> bool {
+        self.0.get().groups.contains_key(role)
+    }
+
+    /// The role configured for an API key, if it has one of its own.
+    pub fn of_key(&self, key: &str) -> Option<String> {
+        self.0.get().keys.get(key).cloned()
+    }
+
+    pub fn default_user_role(&self) -> String {
+        self.0.get().default_user_role.clone()
+    }
+
+    /// The group of a request with `method` to `route`, a route as registered and
+    /// without the prefixes of the base path or tenant.
+    pub fn group(&self, route: &str, method: &Method) -> RouteGroup {
+        if let Some(group) = self.0.get().routes.get(route) {
+            *group
+        } else if !TENANT_ROUTES.contains(&route) {
+            RouteGroup::Admin
+        } else if is_safe(method) {
+            RouteGroup::Read
+        } else {
+            RouteGroup::Mutating
+        }
+    }
+
+    /// Whether `role` may use routes of `group`. Unknown roles, like ones taken out of
+    /// the config since, may use none.
+    pub fn allows(&self, role: &str, group: RouteGroup) -> bool {
+        self.0
+            .get()
+            .groups
+            .get(role)
+            .is_some_and(|groups| groups.contains(&group))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles(config: &str) -> Roles {
+        Roles::new(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn built_in_roles_reach_their_groups() {
+        let roles = roles("");
+        assert!(roles.allows(ADMIN, RouteGroup::Admin));
+        assert!(roles.allows(ADMIN, RouteGroup::Mutating));
+        assert!(!roles.allows(UPLOADER, RouteGroup::Admin));
+        assert!(roles.allows(UPLOADER, RouteGroup::Mutating));
+        assert!(roles.allows(UPLOADER, RouteGroup::Read));
+        assert!(!roles.allows(READER, RouteGroup::Mutating));
+        assert!(roles.allows(READER, RouteGroup::Read));
+        assert!(!roles.allows("nobody", RouteGroup::Read));
+        assert_eq!(roles.default_user_role(), UPLOADER);
+    }
+
+    #[test]
+    fn routes_fall_in_groups() {
+        let roles = roles(
+            r#"
+            [routes]
+            "/pins" = "admin"
+            "#,
+        );
+        assert_eq!(roles.group("/upload", &Method::POST), RouteGroup::Mutating);
+        assert_eq!(roles.group("/blobs", &Method::GET), RouteGroup::Read);
+        assert_eq!(roles.group("/hash", &Method::POST), RouteGroup::Read);
+        assert_eq!(roles.group("/admin/reload", &Method::GET), RouteGroup::Admin);
+        assert_eq!(roles.group("/pins", &Method::GET), RouteGroup::Admin);
+    }
+
+    #[test]
+    fn configured_roles_and_keys() {
+        let roles = roles(
+            r#"
+            default_user_role = "auditor"
+
+            [groups]
+            auditor = ["admin", "read"]
+
+            [keys]
+            "audit-key" = "auditor"
+            "#,
+        );
+        assert!(roles.exists("auditor"));
+        assert!(roles.allows("auditor", RouteGroup::Admin));
+        assert!(!roles.allows("auditor", RouteGroup::Mutating));
+        assert_eq!(roles.of_key("audit-key").as_deref(), Some("auditor"));
+        assert_eq!(roles.of_key("other-key"), None);
+        assert_eq!(roles.default_user_role(), "auditor");
+    }
+
+    #[test]
+    fn unknown_roles_are_refused() {
+        let unknown_key_role: RolesConfig = toml::from_str(
+            r#"
+            [keys]
+            "some-key" = "auditor"
+            "#,
+        )
+        .unwrap();
+        assert!(RoleTable::new(&unknown_key_role).is_err());
+        let unknown_default: RolesConfig = toml::from_str(r#"default_user_role = "auditor""#).unwrap();
+        assert!(RoleTable::new(&unknown_default).is_err());
+    }
+
+    #[test]
+    fn replaced_roles_take_effect() {
+        let roles = roles(
+            r#"
+            [groups]
+            auditor = ["read"]
+            "#,
+        );
+        assert!(roles.allows("auditor", RouteGroup::Read));
+        roles.set(RoleTable::new(&RolesConfig::default()).unwrap());
+        assert!(!roles.exists("auditor"));
+        assert!(!roles.allows("auditor", RouteGroup::Read));
+    }
+}
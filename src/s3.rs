@@ -0,0 +1,1086 @@
+use anyhow::Result;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use iroh_blobs::rpc::client::blobs::ReadAtLen;
+use iroh_blobs::util::Tag;
+use iroh_blobs::{Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::blob::{etag, etag_matches, parse_hash};
+use crate::persist::StateFile;
+use crate::upload::{ingest_named, max_buffered_len, read_body};
+use crate::AppState;
+
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+/// The most keys one listing returns, as with S3.
+const MAX_KEYS: usize = 1000;
+const MAX_KEY_LEN: usize = 1024;
+/// What S3 assumes for objects stored without a `Content-Type`.
+pub const DEFAULT_CONTENT_TYPE: &str = "binary/octet-stream";
+
+/// An S3 error, sent as the XML document S3 clients know how to read.
+#[derive(Debug)]
+pub struct S3Error {
+    status: StatusCode,
+    code: &'static str,
+    message: &'static str,
+}
+
+impl S3Error {
+    const fn new(status: StatusCode, code: &'static str, message: &'static str) -> Self {
+        Self {
+            status,
+            code,
+            message,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    const NO_SUCH_BUCKET: S3Error = S3Error::new(
+        StatusCode::NOT_FOUND,
+        "NoSuchBucket",
+        "The specified bucket does not exist.",
+    );
+    const NO_SUCH_KEY: S3Error = S3Error::new(
+        StatusCode::NOT_FOUND,
+        "NoSuchKey",
+        "The specified key does not exist.",
+    );
+    const BUCKET_NOT_EMPTY: S3Error = S3Error::new(
+        StatusCode::CONFLICT,
+        "BucketNotEmpty",
+        "The bucket you tried to delete is not empty.",
+    );
+    const INVALID_BUCKET_NAME: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "InvalidBucketName",
+        "The specified bucket is not valid.",
+    );
+    const KEY_TOO_LONG: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "KeyTooLongError",
+        "Your key is too long.",
+    );
+    const INVALID_ARGUMENT: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "InvalidArgument",
+        "Invalid argument.",
+    );
+    const INVALID_RANGE: S3Error = S3Error::new(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        "InvalidRange",
+        "The requested range is not satisfiable.",
+    );
+    const INCOMPLETE_BODY: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "IncompleteBody",
+        "You did not provide the number of bytes specified by the Content-Length HTTP header.",
+    );
+    const REQUEST_TIMEOUT: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "RequestTimeout",
+        "Your socket connection to the server was not read from or written to within the timeout period.",
+    );
+    const SLOW_DOWN: S3Error = S3Error::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "SlowDown",
+        "Please reduce your request rate.",
+    );
+    const ENTITY_TOO_LARGE: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "EntityTooLarge",
+        "Your proposed upload exceeds the maximum allowed object size.",
+    );
+    const CONTENT_REJECTED: S3Error = S3Error::new(
+        StatusCode::FORBIDDEN,
+        "AccessDenied",
+        "The content was rejected by a content check.",
+    );
+    const SIGNED_STREAM: S3Error = S3Error::new(
+        StatusCode::BAD_REQUEST,
+        "InvalidRequest",
+        "Chunk signatures are not checked, send the payload unsigned.",
+    );
+    const OBJECT_LOCKED: S3Error = S3Error::new(
+        StatusCode::FORBIDDEN,
+        "AccessDenied",
+        "The object is under legal hold.",
+    );
+    const NOT_IMPLEMENTED: S3Error = S3Error::new(
+        StatusCode::NOT_IMPLEMENTED,
+        "NotImplemented",
+        "A header or operation you provided implies functionality that is not implemented.",
+    );
+    const INTERNAL_ERROR: S3Error = S3Error::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "InternalError",
+        "We encountered an internal error. Please try again.",
+    );
+}
+
+impl From<anyhow::Error> for S3Error {
+    fn from(err: anyhow::Error) -> Self {
+        println!("S3 request failed: {:#}", err);
+        S3Error::INTERNAL_ERROR
+    }
+}
+
+/// Errors of the helpers shared with the rest of the API.
+impl From<StatusCode> for S3Error {
+    fn from(status: StatusCode) -> Self {
+        match status {
+            StatusCode::REQUEST_TIMEOUT => S3Error::REQUEST_TIMEOUT,
+            StatusCode::BAD_REQUEST => S3Error::INCOMPLETE_BODY,
+            StatusCode::SERVICE_UNAVAILABLE => S3Error::SLOW_DOWN,
+            StatusCode::PAYLOAD_TOO_LARGE => S3Error::ENTITY_TOO_LARGE,
+            StatusCode::UNPROCESSABLE_ENTITY
+            | StatusCode::UNSUPPORTED_MEDIA_TYPE
+            | StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => S3Error::CONTENT_REJECTED,
+            _ => S3Error::INTERNAL_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        let body = format!(
+            "<Error><Code>{}</Code><Message>{}</Message></Error>",
+            self.code, self.message
+        );
+        xml(self.status, body)
+    }
+}
+
+fn xml(status: StatusCode, body: String) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/xml")],
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body),
+    )
+        .into_response()
+}
+
+/// Escapes text for XML, which does for HTML too.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+fn iso8601(secs: i64) -> String {
+    timestamp(secs).format("%Y-%m-%dT%H:%M:%S.000Z").to_string()
+}
+
+fn http_date(secs: i64) -> String {
+    timestamp(secs)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Object {
+    pub hash: Hash,
+    pub size: u64,
+    pub content_type: String,
+    /// Seconds since the Unix epoch.
+    pub last_modified: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Bucket {
+    created: i64,
+    objects: BTreeMap<String, Object>,
+}
+
+/// What [`Buckets::merge`] did.
+#[derive(Default)]
+pub struct MergedObjects {
+    pub added: usize,
+    /// Objects left out as `bucket/key` and blob.
+    pub missing: Vec<(String, Hash)>,
+}
+
+/// The tag keeping the blob of an object alive.
+fn object_tag(bucket: &str, key: &str) -> Tag {
+    Tag::from(format!("s3/{}/{}", bucket, key))
+}
+
+/// Bucket names as S3 allows them, so any client accepts them too.
+fn is_valid_bucket_name(name: &str) -> bool {
+    (3..=63).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'.' || b == b'-')
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+/// The virtual buckets of the S3 API.
+///
+/// Objects are blobs kept under a tag named after their bucket and key. The index maps
+/// keys to them along with the metadata S3 clients expect, and is written to disk in
+/// the background after changes.
+#[derive(Clone)]
+pub struct Buckets {
+    buckets: Arc<RwLock<BTreeMap<String, Bucket>>>,
+    file: StateFile,
+}
+
+impl Buckets {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let buckets = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        let buckets = Arc::new(RwLock::new(buckets));
+        let file = StateFile::spawn(path, {
+            let buckets = buckets.clone();
+            move || to_json(&buckets.read().unwrap())
+        });
+        Ok(Self { buckets, file })
+    }
+
+    /// The names of all buckets with their creation times.
+    pub fn names(&self) -> Vec<(String, i64)> {
+        let buckets = self.buckets.read().unwrap();
+        buckets
+            .iter()
+            .map(|(name, bucket)| (name.clone(), bucket.created))
+            .collect()
+    }
+
+    pub fn exists(&self, bucket: &str) -> bool {
+        self.buckets.read().unwrap().contains_key(bucket)
+    }
+
+    /// Creating a bucket that exists already succeeds, as it does in S3's default region.
+    pub fn create(&self, bucket: &str) -> Result<(), S3Error> {
+        if !is_valid_bucket_name(bucket) {
+            return Err(S3Error::INVALID_BUCKET_NAME);
+        }
+        let mut buckets = self.buckets.write().unwrap();
+        if buckets.contains_key(bucket) {
+            return Ok(());
+        }
+        buckets.insert(
+            bucket.to_string(),
+            Bucket {
+                created: Utc::now().timestamp(),
+                objects: BTreeMap::new(),
+            },
+        );
+        self.file.changed();
+        Ok(())
+    }
+
+    pub fn remove(&self, bucket: &str) -> Result<(), S3Error> {
+        let mut buckets = self.buckets.write().unwrap();
+        match buckets.get(bucket) {
+            None => return Err(S3Error::NO_SUCH_BUCKET),
+            Some(entry) if !entry.objects.is_empty() => return Err(S3Error::BUCKET_NOT_EMPTY),
+            Some(_) => {}
+        }
+        buckets.remove(bucket);
+        self.file.changed();
+        Ok(())
+    }
+
+    pub fn object(&self, bucket: &str, key: &str) -> Result<Object, S3Error> {
+        let buckets = self.buckets.read().unwrap();
+        let bucket = buckets.get(bucket).ok_or(S3Error::NO_SUCH_BUCKET)?;
+        bucket.objects.get(key).cloned().ok_or(S3Error::NO_SUCH_KEY)
+    }
+
+    /// The keys in a bucket starting with `prefix`, in order.
+    pub fn keys(&self, bucket: &str, prefix: &str) -> Result<Vec<String>, S3Error> {
+        let buckets = self.buckets.read().unwrap();
+        let bucket = buckets.get(bucket).ok_or(S3Error::NO_SUCH_BUCKET)?;
+        Ok(bucket
+            .objects
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    /// What is right inside the folder `prefix` (empty or ending in `/`) of a bucket,
+    /// splitting keys at `/` like paths: objects by name, and subfolders by name
+    /// without an object. A key equal to `prefix` only marks the folder as existing.
+    pub fn folder(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Option<Object>)>, S3Error> {
+        let buckets = self.buckets.read().unwrap();
+        let bucket = buckets.get(bucket).ok_or(S3Error::NO_SUCH_BUCKET)?;
+        let mut entries: Vec<(String, Option<Object>)> = Vec::new();
+        for (key, object) in bucket
+            .objects
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        {
+            let Some(rest) = key.strip_prefix(prefix) else {
+                break;
+            };
+            match rest.split_once('/') {
+                _ if rest.is_empty() => {}
+                Some((folder, _)) => {
+                    if entries.last().is_none_or(|(name, _)| name != folder) {
+                        entries.push((folder.to_string(), None));
+                    }
+                }
+                None => entries.push((rest.to_string(), Some(object.clone()))),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Every object as bucket, key and blob.
+    pub fn objects(&self) -> Vec<(String, String, Hash)> {
+        let buckets = self.buckets.read().unwrap();
+        buckets
+            .iter()
+            .flat_map(|(name, bucket)| {
+                bucket
+                    .objects
+                    .iter()
+                    .map(move |(key, object)| (name.clone(), key.clone(), object.hash))
+            })
+            .collect()
+    }
+
+    /// Adds the objects of an index another gateway wrote, like the one in an archive,
+    /// creating their buckets as they were. Their tags come along separately, a key
+    /// taken here keeps its object, and objects whose blob isn't `present` are left out.
+    pub fn merge(&self, index: &[u8], present: impl Fn(&Hash) -> bool) -> Result<MergedObjects> {
+        let archived: BTreeMap<String, Bucket> = serde_json::from_slice(index)?;
+        let mut merged = MergedObjects::default();
+        let mut buckets = self.buckets.write().unwrap();
+        for (name, archived) in archived {
+            let bucket = buckets.entry(name.clone()).or_insert_with(|| Bucket {
+                created: archived.created,
+                objects: BTreeMap::new(),
+            });
+            for (key, object) in archived.objects {
+                if bucket.objects.contains_key(&key) {
+                    continue;
+                }
+                if present(&object.hash) {
+                    bucket.objects.insert(key, object);
+                    merged.added += 1;
+                } else {
+                    merged
+                        .missing
+                        .push((format!("{}/{}", name, key), object.hash));
+                }
+            }
+        }
+        self.file.changed();
+        Ok(merged)
+    }
+
+    /// Replaces every bucket and object with those of an index written before, for
+    /// rolling back to a snapshot which restores their tags too.
+    pub fn restore(&self, index: &[u8]) -> Result<()> {
+        let restored: BTreeMap<String, Bucket> = serde_json::from_slice(index)?;
+        let mut buckets = self.buckets.write().unwrap();
+        *buckets = restored;
+        self.file.changed();
+        Ok(())
+    }
+
+    fn insert(&self, bucket: &str, key: String, object: Object) -> Result<(), S3Error> {
+        let mut buckets = self.buckets.write().unwrap();
+        let entry = buckets.get_mut(bucket).ok_or(S3Error::NO_SUCH_BUCKET)?;
+        entry.objects.insert(key, object);
+        self.file.changed();
+        Ok(())
+    }
+
+    /// Whether there was an object to remove.
+    fn remove_object(&self, bucket: &str, key: &str) -> Result<bool, S3Error> {
+        let mut buckets = self.buckets.write().unwrap();
+        let entry = buckets.get_mut(bucket).ok_or(S3Error::NO_SUCH_BUCKET)?;
+        if entry.objects.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.file.changed();
+        Ok(true)
+    }
+
+    /// The buckets and their objects as they are written to disk.
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        to_json(&self.buckets.read().unwrap())
+    }
+}
+
+fn to_json(buckets: &BTreeMap<String, Bucket>) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(buckets)?)
+}
+
+/// Stores `data` as an object through the same ingest path as uploads. Replacing an
+/// object moves its tag to the new blob, leaving the old one to garbage collection.
+pub async fn store_object(
+    app_state: &AppState,
+    bucket: &str,
+    key: &str,
+    data: Bytes,
+    content_type: String,
+) -> Result<Hash, S3Error> {
+    if key.len() > MAX_KEY_LEN {
+        return Err(S3Error::KEY_TOO_LONG);
+    }
+    if !app_state.buckets.exists(bucket) {
+        return Err(S3Error::NO_SUCH_BUCKET);
+    }
+    let size = data.len() as u64;
+    let file_name = key.rsplit('/').next().map(str::to_string);
+    let response = ingest_named(
+        app_state,
+        None,
+        file_name,
+        data,
+        Some(object_tag(bucket, key)),
+    )
+    .await?;
+    let hash = parse_hash(&response.blob_hash)?;
+    app_state.buckets.insert(
+        bucket,
+        key.to_string(),
+        Object {
+            hash,
+            size,
+            content_type,
+            last_modified: Utc::now().timestamp(),
+        },
+    )?;
+    Ok(hash)
+}
+
+/// Makes `to` an object holding the blob of `from`, without copying any data.
+pub async fn copy_object(
+    app_state: &AppState,
+    (from_bucket, from_key): (&str, &str),
+    (to_bucket, to_key): (&str, &str),
+) -> Result<(), S3Error> {
+    if to_key.len() > MAX_KEY_LEN {
+        return Err(S3Error::KEY_TOO_LONG);
+    }
+    if !app_state.buckets.exists(to_bucket) {
+        return Err(S3Error::NO_SUCH_BUCKET);
+    }
+    let object = app_state.buckets.object(from_bucket, from_key)?;
+    let batch = app_state.blobs.client().batch().await?;
+    let temp_tag = batch.temp_tag(HashAndFormat::raw(object.hash)).await?;
+    batch
+        .persist_to(temp_tag, object_tag(to_bucket, to_key))
+        .await?;
+    app_state.buckets.insert(
+        to_bucket,
+        to_key.to_string(),
+        Object {
+            last_modified: Utc::now().timestamp(),
+            ..object
+        },
+    )
+}
+
+/// Moves the object at `from` to `to`, or when there is none every object below `from`
+/// as a folder to below `to`. Blobs are re-tagged under their new keys, no data is
+/// copied. Returns how many objects moved.
+pub async fn move_objects(
+    app_state: &AppState,
+    (from_bucket, from_key): (&str, &str),
+    (to_bucket, to_key): (&str, &str),
+) -> Result<usize, S3Error> {
+    let moves = if app_state.buckets.object(from_bucket, from_key).is_ok() {
+        vec![(from_key.to_string(), to_key.to_string())]
+    } else {
+        let from_prefix = folder_prefix(from_key);
+        let to_prefix = folder_prefix(to_key);
+        app_state
+            .buckets
+            .keys(from_bucket, &from_prefix)?
+            .into_iter()
+            .map(|key| {
+                let moved = format!("{}{}", to_prefix, &key[from_prefix.len()..]);
+                (key, moved)
+            })
+            .collect()
+    };
+    for (from_key, to_key) in &moves {
+        copy_object(app_state, (from_bucket, from_key), (to_bucket, to_key)).await?;
+        untag_object(app_state, from_bucket, from_key).await?;
+    }
+    Ok(moves.len())
+}
+
+/// The prefix of the keys inside a folder, where the empty key is the whole bucket.
+pub fn folder_prefix(key: &str) -> String {
+    if key.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", key.trim_end_matches('/'))
+    }
+}
+
+/// Removes an object and its tag, unless its blob is under legal hold. Returns whether
+/// there was one.
+pub async fn remove_object(app_state: &AppState, bucket: &str, key: &str) -> Result<bool, S3Error> {
+    if let Ok(object) = app_state.buckets.object(bucket, key) {
+        if app_state.holds.is_held(&object.hash) {
+            return Err(S3Error::OBJECT_LOCKED);
+        }
+    }
+    untag_object(app_state, bucket, key).await
+}
+
+/// Removes an object and its tag, also for moves, where the blob stays under the new
+/// key.
+async fn untag_object(app_state: &AppState, bucket: &str, key: &str) -> Result<bool, S3Error> {
+    if !app_state.buckets.remove_object(bucket, key)? {
+        return Ok(false);
+    }
+    app_state
+        .blobs
+        .client()
+        .tags()
+        .delete(object_tag(bucket, key))
+        .await?;
+    Ok(true)
+}
+
+/// `GET /s3`: ListBuckets.
+pub async fn list_buckets(State(app_state): State<AppState>) -> Response {
+    let mut body = format!(
+        "<ListAllMyBucketsResult xmlns=\"{}\"><Owner><ID>{}</ID><DisplayName>iroh-api</DisplayName></Owner><Buckets>",
+        XMLNS, app_state.node_id
+    );
+    for (name, created) in app_state.buckets.names() {
+        let _ = write!(
+            body,
+            "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+            escape(&name),
+            iso8601(created)
+        );
+    }
+    body.push_str("</Buckets></ListAllMyBucketsResult>");
+    xml(StatusCode::OK, body)
+}
+
+/// `PUT /s3/{bucket}`: CreateBucket. Location constraints in the body are ignored.
+pub async fn create_bucket(
+    State(app_state): State<AppState>,
+    Path(bucket): Path<String>,
+) -> Result<Response, S3Error> {
+    app_state.buckets.create(&bucket)?;
+    Ok((StatusCode::OK, [(header::LOCATION, format!("/{}", bucket))]).into_response())
+}
+
+/// `HEAD /s3/{bucket}`: HeadBucket.
+pub async fn head_bucket(
+    State(app_state): State<AppState>,
+    Path(bucket): Path<String>,
+) -> StatusCode {
+    if app_state.buckets.exists(&bucket) {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// `DELETE /s3/{bucket}`: DeleteBucket, which only removes empty buckets.
+pub async fn delete_bucket(
+    State(app_state): State<AppState>,
+    Path(bucket): Path<String>,
+) -> Result<StatusCode, S3Error> {
+    app_state.buckets.remove(&bucket)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /s3/{bucket}`: ListObjectsV2, or ListObjects for clients that don't ask for
+/// version 2. Answers GetBucketLocation too, which clients call before anything else.
+pub async fn list_objects(
+    State(app_state): State<AppState>,
+    Path(bucket): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, S3Error> {
+    if query.contains_key("location") {
+        if !app_state.buckets.exists(&bucket) {
+            return Err(S3Error::NO_SUCH_BUCKET);
+        }
+        let body = format!("<LocationConstraint xmlns=\"{}\"/>", XMLNS);
+        return Ok(xml(StatusCode::OK, body));
+    }
+
+    let v2 = query.get("list-type").is_some_and(|version| version == "2");
+    let param = |name: &str| query.get(name).map(String::as_str).unwrap_or_default();
+    let prefix = param("prefix");
+    let delimiter = param("delimiter");
+    let max_keys = match query.get("max-keys") {
+        Some(max_keys) => max_keys
+            .parse::<usize>()
+            .map_err(|_| S3Error::INVALID_ARGUMENT)?
+            .min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    // Continuation tokens are the last key or common prefix of the page before
+    let token = match query.get("continuation-token") {
+        Some(token) => Some(
+            BASE64URL_NOPAD
+                .decode(token.as_bytes())
+                .ok()
+                .and_then(|token| String::from_utf8(token).ok())
+                .ok_or(S3Error::INVALID_ARGUMENT)?,
+        ),
+        None => None,
+    };
+    let start_after = token.clone().or_else(|| {
+        query
+            .get(if v2 { "start-after" } else { "marker" })
+            .cloned()
+    });
+
+    let buckets = app_state.buckets.buckets.read().unwrap();
+    let objects = &buckets.get(&bucket).ok_or(S3Error::NO_SUCH_BUCKET)?.objects;
+    let lower = match &start_after {
+        Some(after) if after.as_str() >= prefix => Bound::Excluded(after.as_str()),
+        _ => Bound::Included(prefix),
+    };
+    let mut contents = Vec::new();
+    let mut common_prefixes: Vec<&str> = Vec::new();
+    let mut last = None;
+    let mut truncated = false;
+    for (key, object) in objects.range::<str, _>((lower, Bound::Unbounded)) {
+        let Some(rest) = key.strip_prefix(prefix) else {
+            break;
+        };
+        // Keys rolled up into the common prefix the last page ended on were listed with it
+        if token.as_deref().is_some_and(|token| {
+            !delimiter.is_empty() && token.ends_with(delimiter) && key.starts_with(token)
+        }) {
+            continue;
+        }
+        let common_prefix = (!delimiter.is_empty())
+            .then(|| rest.find(delimiter))
+            .flatten()
+            .map(|end| &key[..prefix.len() + end + delimiter.len()]);
+        if let Some(common_prefix) = common_prefix {
+            if common_prefixes.last() == Some(&common_prefix) {
+                continue;
+            }
+        }
+        if contents.len() + common_prefixes.len() == max_keys {
+            truncated = max_keys > 0;
+            break;
+        }
+        match common_prefix {
+            Some(common_prefix) => {
+                common_prefixes.push(common_prefix);
+                last = Some(common_prefix);
+            }
+            None => {
+                contents.push((key, object));
+                last = Some(key.as_str());
+            }
+        }
+    }
+
+    let mut body = format!(
+        "<ListBucketResult xmlns=\"{}\"><Name>{}</Name><Prefix>{}</Prefix>",
+        XMLNS,
+        escape(&bucket),
+        escape(prefix)
+    );
+    if !delimiter.is_empty() {
+        let _ = write!(body, "<Delimiter>{}</Delimiter>", escape(delimiter));
+    }
+    let _ = write!(
+        body,
+        "<MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        max_keys, truncated
+    );
+    let next = last.filter(|_| truncated);
+    if v2 {
+        let _ = write!(
+            body,
+            "<KeyCount>{}</KeyCount>",
+            contents.len() + common_prefixes.len()
+        );
+        if let Some(token) = query.get("continuation-token") {
+            let _ = write!(
+                body,
+                "<ContinuationToken>{}</ContinuationToken>",
+                escape(token)
+            );
+        }
+        if let Some(next) = next {
+            let _ = write!(
+                body,
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                BASE64URL_NOPAD.encode(next.as_bytes())
+            );
+        }
+        if let Some(start_after) = query.get("start-after") {
+            let _ = write!(body, "<StartAfter>{}</StartAfter>", escape(start_after));
+        }
+    } else {
+        let _ = write!(body, "<Marker>{}</Marker>", escape(param("marker")));
+        if let Some(next) = next {
+            let _ = write!(body, "<NextMarker>{}</NextMarker>", escape(next));
+        }
+    }
+    for (key, object) in contents {
+        let _ = write!(
+            body,
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            escape(key),
+            iso8601(object.last_modified),
+            escape(&etag(&object.hash)),
+            object.size
+        );
+    }
+    for common_prefix in common_prefixes {
+        let _ = write!(
+            body,
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            escape(common_prefix)
+        );
+    }
+    body.push_str("</ListBucketResult>");
+    Ok(xml(StatusCode::OK, body))
+}
+
+/// Whether the body is framed in `aws-chunked` encoding, as streaming uploads of
+/// signing clients are.
+fn is_aws_chunked(headers: &HeaderMap) -> bool {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    header(header::CONTENT_ENCODING.as_str()).contains("aws-chunked")
+        || header("x-amz-content-sha256").starts_with("STREAMING-")
+}
+
+/// Whether the chunks of an `aws-chunked` body are signed. Their signatures can't be
+/// checked without the client's secret key, so with keys configured the body is refused
+/// rather than taken as signed.
+fn is_signed_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-content-sha256")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.starts_with("STREAMING-") && !value.starts_with("STREAMING-UNSIGNED-")
+        })
+}
+
+/// The framing of an `aws-chunked` body adds at most a 64th to its data: chunks other
+/// than the last carry at least 8 KiB of data and less than 100 bytes of framing.
+const AWS_CHUNK_OVERHEAD_RATIO: u64 = 64;
+
+/// The longest body read for an object of at most `max_file_size` bytes, or
+/// [`crate::upload::MAX_BUFFERED_SIZE`] without a limit, as bodies are read into memory,
+/// with room for the `aws-chunked` framing.
+fn max_body_len(max_file_size: u64, chunked: bool) -> u64 {
+    let max = max_buffered_len(max_file_size);
+    if chunked {
+        max.saturating_add(max / AWS_CHUNK_OVERHEAD_RATIO + 4096)
+    } else {
+        max
+    }
+}
+
+/// Strips the `aws-chunked` framing off a body. Chunk signatures and trailing checksums
+/// aren't checked, so signed chunks are only taken on gateways without keys.
+fn decode_aws_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(body.len());
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let line = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(data);
+        }
+        if body.len() < size + 2 || &body[size..size + 2] != b"\r\n" {
+            return None;
+        }
+        data.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+/// `PUT /s3/{bucket}/{key}`: PutObject.
+pub async fn put_object(
+    State(app_state): State<AppState>,
+    Path((bucket, key)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    if headers.contains_key("x-amz-copy-source") {
+        return Err(S3Error::NOT_IMPLEMENTED);
+    }
+    if !app_state.buckets.exists(&bucket) {
+        return Err(S3Error::NO_SUCH_BUCKET);
+    }
+
+    let _slot = app_state
+        .ingest_slots
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| S3Error::SLOW_DOWN)?;
+    // Objects too large to be stored are turned away before their body is read where
+    // the headers tell, and as soon as the body grows too large otherwise
+    let upload = app_state.upload.get();
+    let read_timeout = Duration::from_secs(upload.read_timeout_secs.max(1));
+    let chunked = is_aws_chunked(&headers);
+    if chunked && is_signed_stream(&headers) && app_state.tenancy.is_enabled() {
+        return Err(S3Error::SIGNED_STREAM);
+    }
+    let max_file_size = max_buffered_len(upload.max_file_size);
+    let declared_len = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    let decoded_len = declared_len("x-amz-decoded-content-length");
+    let object_len = if chunked {
+        decoded_len
+    } else {
+        declared_len(header::CONTENT_LENGTH.as_str())
+    };
+    if object_len.is_some_and(|len| len > max_file_size) {
+        return Err(S3Error::ENTITY_TOO_LARGE);
+    }
+    let mut data = Vec::new();
+    read_body(
+        &app_state,
+        body.into_data_stream(),
+        read_timeout,
+        max_body_len(upload.max_file_size, chunked),
+        |chunk| data.extend_from_slice(chunk),
+    )
+    .await?;
+    if chunked {
+        data = decode_aws_chunked(&data).ok_or(S3Error::INCOMPLETE_BODY)?;
+        if decoded_len.is_some_and(|len| len != data.len() as u64) {
+            return Err(S3Error::INCOMPLETE_BODY);
+        }
+        if data.len() as u64 > max_file_size {
+            return Err(S3Error::ENTITY_TOO_LARGE);
+        }
+    }
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(DEFAULT_CONTENT_TYPE)
+        .to_string();
+    let hash = store_object(&app_state, &bucket, &key, Bytes::from(data), content_type).await?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(&hash))]).into_response())
+}
+
+/// The byte range `start..end` a `Range` header asks for. Headers this can't serve, like
+/// ones asking for several ranges, are ignored and the whole object is sent.
+fn parse_range(value: &str, size: u64) -> Result<Option<(u64, u64)>, S3Error> {
+    let Some((start, end)) = value
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return Ok(None);
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(len) => (size.saturating_sub(len), size),
+            Err(_) => return Ok(None),
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match end {
+                "" => size,
+                end => match end.parse::<u64>() {
+                    Ok(last) if last >= start => last.saturating_add(1).min(size),
+                    _ => return Ok(None),
+                },
+            };
+            (start, end)
+        }
+    };
+    if start >= size || start == end {
+        return Err(S3Error::INVALID_RANGE);
+    }
+    Ok(Some((start, end)))
+}
+
+fn object_headers(object: &Object, len: u64) -> [(header::HeaderName, String); 5] {
+    [
+        (header::CONTENT_TYPE, object.content_type.clone()),
+        (header::CONTENT_LENGTH, len.to_string()),
+        (header::ETAG, etag(&object.hash)),
+        (header::LAST_MODIFIED, http_date(object.last_modified)),
+        (header::ACCEPT_RANGES, "bytes".to_string()),
+    ]
+}
+
+/// `HEAD /s3/{bucket}/{key}`: HeadObject.
+pub async fn head_object(
+    State(app_state): State<AppState>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    // Answers to HEAD carry no body, so errors are bare status codes
+    let object = app_state
+        .buckets
+        .object(&bucket, &key)
+        .map_err(|err| err.status)?;
+    Ok(object_headers(&object, object.size).into_response())
+}
+
+/// `GET /s3/{bucket}/{key}`: GetObject, with support for a single byte range.
+pub async fn get_object(
+    State(app_state): State<AppState>,
+    Path((bucket, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, S3Error> {
+    let object = app_state.buckets.object(&bucket, &key)?;
+    app_state
+        .screening
+        .check_serve(&app_state, object.hash)
+        .await?;
+    app_state.plugins.check_download(object.hash).await?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&object.hash)) == Some(true) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag(&object.hash))],
+        )
+            .into_response());
+    }
+    let range = match headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => parse_range(value, object.size)?,
+        None => None,
+    };
+
+    let (start, end) = range.unwrap_or((0, object.size));
+    let reader = app_state
+        .blobs
+        .client()
+        .read_at(object.hash, start, ReadAtLen::Exact(end - start))
+        .await?;
+    let body = Body::from_stream(app_state.throttle.upload().stream(reader));
+    let mut response = (object_headers(&object, end - start), body).into_response();
+    if range.is_some() {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, object.size)
+                .parse()
+                .map_err(|_| S3Error::INTERNAL_ERROR)?,
+        );
+    }
+    Ok(response)
+}
+
+/// `DELETE /s3/{bucket}/{key}`: DeleteObject. Like S3, deleting a missing key succeeds.
+pub async fn delete_object(
+    State(app_state): State<AppState>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Result<StatusCode, S3Error> {
+    remove_object(&app_state, &bucket, &key).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Answers operations outside the supported subset, like multipart uploads, so clients
+/// fail with a clear error.
+pub async fn not_implemented() -> S3Error {
+    S3Error::NOT_IMPLEMENTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::MAX_BUFFERED_SIZE;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn bodies_are_capped_without_a_limit() {
+        assert_eq!(max_body_len(0, false), MAX_BUFFERED_SIZE);
+        assert!(max_body_len(0, true) > MAX_BUFFERED_SIZE);
+        assert!(max_body_len(0, true) < MAX_BUFFERED_SIZE + MAX_BUFFERED_SIZE / 32);
+        assert_eq!(max_body_len(1000, false), 1000);
+    }
+
+    #[test]
+    fn signed_chunks_are_told_apart() {
+        let signed = headers(&[
+            ("content-encoding", "aws-chunked"),
+            ("x-amz-content-sha256", "STREAMING-AWS4-HMAC-SHA256-PAYLOAD"),
+        ]);
+        assert!(is_aws_chunked(&signed));
+        assert!(is_signed_stream(&signed));
+        let trailer = headers(&[(
+            "x-amz-content-sha256",
+            "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER",
+        )]);
+        assert!(is_signed_stream(&trailer));
+
+        let unsigned = headers(&[("x-amz-content-sha256", "STREAMING-UNSIGNED-PAYLOAD-TRAILER")]);
+        assert!(is_aws_chunked(&unsigned));
+        assert!(!is_signed_stream(&unsigned));
+        assert!(!is_signed_stream(&headers(&[("x-amz-content-sha256", "UNSIGNED-PAYLOAD")])));
+        assert!(!is_aws_chunked(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn chunked_bodies_are_decoded() {
+        let body = b"5;chunk-signature=00\r\nhello\r\n6;chunk-signature=00\r\n world\r\n0;chunk-signature=00\r\n\r\n";
+        assert_eq!(decode_aws_chunked(body).as_deref(), Some(&b"hello world"[..]));
+        assert_eq!(decode_aws_chunked(b"5\r\nhel"), None);
+        assert_eq!(decode_aws_chunked(b"5\r\nhelloXX0\r\n"), None);
+    }
+}
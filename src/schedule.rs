@@ -0,0 +1,361 @@
+use anyhow::{bail, Context, Result};
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Datelike, TimeDelta, TimeZone, Timelike, Utc};
+use futures::StreamExt;
+use iroh_blobs::net_protocol::ProtectCb;
+use iroh_blobs::store::ValidateProgress;
+use iroh_blobs::Hash;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{watch, Semaphore};
+
+use crate::config::{ScheduleConfig, ScheduledTask};
+use crate::orphans;
+use crate::AppState;
+
+/// A five field cron expression: minute, hour, day of month, month and day of week, in
+/// UTC. Fields take `*`, values, ranges like `1-5`, steps like `*/15` and lists of
+/// those. Sunday is 0 or 7.
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Like cron, a restricted day of month and day of week match either one.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("expected 5 fields, got {}", fields.len());
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+/// The values a field matches, as bits.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .with_context(|| format!("invalid step in {}", part))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let value = |value: &str| {
+            value
+                .parse::<u32>()
+                .with_context(|| format!("invalid value in {}", part))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // `5/15` runs from 5 to the end of the range
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start < min || end > max || start > end {
+            bail!("{} is outside {}-{}", part, min, max);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// The first minute after `after` the expression matches. `None` for expressions
+    /// that never do, like the 30th of February.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        // Leap days come around within this
+        let limit = time + TimeDelta::days(4 * 366);
+        while time < limit {
+            if self.months & (1 << time.month()) == 0 {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.matches_day(&time) {
+                time = time
+                    .date_naive()
+                    .succ_opt()?
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += TimeDelta::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Holds garbage collection back until a scheduled run lets one cycle through.
+///
+/// Its protect callback has to be the first one added, so the others list what they
+/// protect once the cycle actually starts.
+#[derive(Clone)]
+pub struct GcWindow {
+    runs: Arc<Semaphore>,
+    completed: Arc<watch::Sender<u64>>,
+}
+
+impl GcWindow {
+    pub fn new() -> Self {
+        Self {
+            runs: Arc::new(Semaphore::new(0)),
+            completed: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Blocks each garbage collection cycle until [`GcWindow::run`] is called.
+    pub fn protect_cb(&self) -> ProtectCb {
+        let runs = self.runs.clone();
+        Box::new(move |_live| {
+            let runs = runs.clone();
+            Box::pin(async move {
+                if let Ok(permit) = runs.acquire().await {
+                    permit.forget();
+                }
+            })
+        })
+    }
+
+    /// To be called when a garbage collection cycle finished.
+    pub fn completed(&self) {
+        self.completed.send_modify(|runs| *runs += 1);
+    }
+
+    /// Lets one cycle through and waits for it to finish.
+    pub async fn run(&self) {
+        let mut completed = self.completed.subscribe();
+        self.runs.add_permits(1);
+        let _ = completed.changed().await;
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScheduledJob {
+    task: &'static str,
+    cron: String,
+    enabled: bool,
+    next_run: Option<u64>,
+    last_job_id: Option<u64>,
+}
+
+/// Runs the `[[schedule]]` tasks as jobs, so every run shows up in `GET /jobs`.
+#[derive(Clone)]
+pub struct Scheduler {
+    tasks: Arc<Vec<(ScheduledTask, Cron, bool)>>,
+    entries: Arc<RwLock<Vec<ScheduledJob>>>,
+    gc_window: Option<GcWindow>,
+}
+
+impl Scheduler {
+    /// What lets garbage collection cycles through, when they only run on demand.
+    pub fn gc_window(&self) -> Option<&GcWindow> {
+        self.gc_window.as_ref()
+    }
+
+    /// Checks the cron expressions. Scheduled garbage collection needs the `gc_window`
+    /// it was started with.
+    pub fn new(config: &[ScheduleConfig], gc_window: Option<GcWindow>) -> Result<Self> {
+        let mut tasks = Vec::new();
+        for entry in config {
+            let cron: Cron = entry
+                .cron
+                .parse()
+                .with_context(|| format!("Invalid cron expression {:?}", entry.cron))?;
+            if cron.next_after(Utc::now()).is_none() {
+                bail!("Cron expression {:?} never matches", entry.cron);
+            }
+            if entry.enabled && entry.task == ScheduledTask::Gc && gc_window.is_none() {
+                bail!("Scheduled gc needs gc.interval_secs");
+            }
+            tasks.push((entry.task, cron, entry.enabled));
+        }
+        let entries = config
+            .iter()
+            .map(|entry| ScheduledJob {
+                task: entry.task.name(),
+                cron: entry.cron.clone(),
+                enabled: entry.enabled,
+                next_run: None,
+                last_job_id: None,
+            })
+            .collect();
+        Ok(Self {
+            tasks: Arc::new(tasks),
+            entries: Arc::new(RwLock::new(entries)),
+            gc_window,
+        })
+    }
+
+    /// Starts waiting for the enabled tasks.
+    pub fn spawn(&self, app_state: &AppState) {
+        for (index, (task, cron, enabled)) in self.tasks.iter().enumerate() {
+            if *enabled {
+                let run = self
+                    .clone()
+                    .run(index, *task, cron.clone(), app_state.clone());
+                tokio::spawn(run);
+            }
+        }
+        if !self.tasks.is_empty() {
+            println!("Scheduled {} tasks", self.tasks.len());
+        }
+    }
+
+    async fn run(self, index: usize, task: ScheduledTask, cron: Cron, app_state: AppState) {
+        let mut after = Utc::now();
+        loop {
+            let Some(next) = cron.next_after(after.max(Utc::now())) else {
+                return;
+            };
+            self.update(index, |job| job.next_run = Some(next.timestamp() as u64));
+            tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+            after = next;
+
+            // Runs taking longer than the schedule skip the ones they overlap
+            let last = self.entries.read().unwrap()[index].last_job_id;
+            if last.is_some_and(|id| !app_state.jobs.is_finished(id)) {
+                println!(
+                    "Skipping scheduled {}, the last run is still going",
+                    task.name()
+                );
+                continue;
+            }
+            let scheduler = self.clone();
+            let state = app_state.clone();
+            let id = app_state.jobs.spawn(task.name(), async move {
+                scheduler.run_task(task, &state).await
+            });
+            self.update(index, |job| job.last_job_id = Some(id));
+        }
+    }
+
+    async fn run_task(
+        &self,
+        task: ScheduledTask,
+        app_state: &AppState,
+    ) -> Result<serde_json::Value> {
+        match task {
+            ScheduledTask::Gc => {
+                self.gc_window
+                    .as_ref()
+                    .context("garbage collection is off")?
+                    .run()
+                    .await;
+                Ok(serde_json::json!({}))
+            }
+            ScheduledTask::Validate => validate(app_state).await,
+            ScheduledTask::UsageReport => {
+                let records = app_state.metering.report(app_state).await?;
+                Ok(serde_json::json!({ "records": records }))
+            }
+            ScheduledTask::Replication => {
+                let queued = app_state
+                    .replicator
+                    .reconcile(&app_state.jobs, &app_state.blobs)
+                    .await?;
+                Ok(serde_json::json!({ "queued": queued }))
+            }
+            ScheduledTask::Orphans => Ok(serde_json::to_value(
+                orphans::check(app_state, false).await?,
+            )?),
+        }
+    }
+
+    fn update(&self, index: usize, f: impl FnOnce(&mut ScheduledJob)) {
+        if let Some(job) = self.entries.write().unwrap().get_mut(index) {
+            f(job);
+        }
+    }
+
+    pub fn list(&self) -> Vec<ScheduledJob> {
+        self.entries.read().unwrap().clone()
+    }
+}
+
+/// Checks every blob in the store against its hash, without repairing anything.
+async fn validate(app_state: &AppState) -> Result<serde_json::Value> {
+    let mut progress = app_state.blobs.client().validate(false).await?;
+    let mut entries: HashMap<u64, Hash> = HashMap::new();
+    let mut checked = 0;
+    let mut partial = 0;
+    let mut invalid = Vec::new();
+    while let Some(event) = progress.next().await {
+        match event? {
+            ValidateProgress::Entry { id, hash, .. } => {
+                entries.insert(id, hash);
+            }
+            ValidateProgress::EntryDone { id, error } => {
+                checked += 1;
+                let hash = entries.remove(&id);
+                if let Some(error) = error {
+                    println!("Blob {:?} failed validation: {}", hash, error);
+                    invalid.push(serde_json::json!({
+                        "hash": hash.map(|hash| hash.to_string()),
+                        "error": error,
+                    }));
+                }
+            }
+            ValidateProgress::PartialEntryDone { .. } => partial += 1,
+            ValidateProgress::Abort(err) => bail!("validation aborted: {}", err),
+            _ => {}
+        }
+    }
+    Ok(serde_json::json!({
+        "checked": checked,
+        "partial": partial,
+        "invalid": invalid,
+    }))
+}
+
+/// `GET /schedule`: the scheduled tasks, when they run next and their last job.
+pub async fn list_schedule(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(app_state.scheduler.list())
+}
@@ -0,0 +1,304 @@
+use anyhow::{bail, Result};
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use futures::future::{BoxFuture, FutureExt};
+use iroh_blobs::rpc::client::blobs::{BlobStatus, ReadAtLen};
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::config::ScreeningConfig;
+use crate::reload::Live;
+use crate::AppState;
+
+/// Bytes of a blob handed to screens when it is served. Larger blobs are judged by
+/// their start.
+const MAX_SERVE_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// An upload, before it is stored.
+    Ingest,
+    /// A stored blob, before it is sent to a client.
+    Serve,
+}
+
+/// Content a screen is asked about.
+pub struct Content<'a> {
+    pub stage: Stage,
+    pub hash: Hash,
+    /// The full size, even when `data` only holds the start.
+    pub size: u64,
+    /// The name the file was uploaded under, when known.
+    pub file_name: Option<&'a str>,
+    pub data: &'a Bytes,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Decision {
+    Allow,
+    /// Refuse the content, for the reason given.
+    Deny(String),
+}
+
+/// A moderation or data loss prevention check, run on every upload before it is stored
+/// and on blobs before they are served.
+///
+/// Deployments with checks of their own implement this and hand it to
+/// [`Screening::new`]. Errors count as failures of the check, which `fail_open` decides
+/// about.
+pub trait Screen: Send + Sync {
+    fn screen<'a>(&'a self, content: &'a Content<'a>) -> BoxFuture<'a, Result<Decision>>;
+
+    /// Whether the screen looks at content at all. Screens that don't spare the store
+    /// reading blobs for them.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+}
+
+/// Allows everything, for deployments without checks.
+pub struct NoScreen;
+
+impl Screen for NoScreen {
+    fn screen<'a>(&'a self, _content: &'a Content<'a>) -> BoxFuture<'a, Result<Decision>> {
+        async { Ok(Decision::Allow) }.boxed()
+    }
+
+    fn is_enabled(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Deserialize)]
+struct WebhookReply {
+    allow: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Asks an HTTP service. The content is POSTed as the body, with what is known about it
+/// in `X-Screen-*` headers, and the service answers `{"allow": bool, "reason": "..."}`.
+pub struct WebhookScreen {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl WebhookScreen {
+    pub fn new(url: Url, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url,
+        })
+    }
+}
+
+impl Screen for WebhookScreen {
+    fn screen<'a>(&'a self, content: &'a Content<'a>) -> BoxFuture<'a, Result<Decision>> {
+        async move {
+            let stage = match content.stage {
+                Stage::Ingest => "ingest",
+                Stage::Serve => "serve",
+            };
+            let mut request = self
+                .client
+                .post(self.url.clone())
+                .header("content-type", "application/octet-stream")
+                .header("x-screen-stage", stage)
+                .header("x-screen-hash", content.hash.to_string())
+                .header("x-screen-size", content.size.to_string())
+                .body(content.data.clone());
+            if let Some(file_name) = content.file_name {
+                request = request.header("x-screen-file-name", file_name);
+            }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                bail!("webhook answered {}", response.status());
+            }
+            let reply: WebhookReply = response.json().await?;
+            Ok(if reply.allow {
+                Decision::Allow
+            } else {
+                Decision::Deny(reply.reason.unwrap_or_default())
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Runs the configured [`Screen`] on uploads and downloads.
+///
+/// A blob's content never changes, so verdicts on serving it are remembered for a while
+/// instead of reading and sending it for every request.
+#[derive(Clone)]
+pub struct Screening {
+    settings: Live<Settings>,
+    /// Whether the screen comes from the config, and goes with it when reloading.
+    configured: bool,
+    served: Arc<Mutex<HashMap<Hash, (Instant, Decision)>>>,
+}
+
+/// The screen and how its answers are used, which reloading the config replaces.
+pub struct Settings {
+    screen: Arc<dyn Screen>,
+    fail_open: bool,
+    cache_for: Duration,
+}
+
+impl Settings {
+    fn new(screen: Arc<dyn Screen>, config: &ScreeningConfig) -> Self {
+        Self {
+            screen,
+            fail_open: config.fail_open,
+            cache_for: Duration::from_secs(config.cache_secs),
+        }
+    }
+}
+
+/// The screen `config` asks for: the webhook when one is set, otherwise none.
+fn configured_screen(config: &ScreeningConfig) -> Result<Arc<dyn Screen>> {
+    Ok(match &config.webhook {
+        Some(url) => Arc::new(WebhookScreen::new(
+            url.clone(),
+            Duration::from_secs(config.timeout_secs.max(1)),
+        )?),
+        None => Arc::new(NoScreen),
+    })
+}
+
+impl Screening {
+    pub fn new(screen: Arc<dyn Screen>, config: &ScreeningConfig) -> Self {
+        Self {
+            settings: Live::new(Settings::new(screen, config)),
+            configured: false,
+            served: Default::default(),
+        }
+    }
+
+    /// The screen `config` asks for: the webhook when one is set, otherwise none.
+    pub fn from_config(config: &ScreeningConfig) -> Result<Self> {
+        Ok(Self {
+            configured: true,
+            ..Self::new(configured_screen(config)?, config)
+        })
+    }
+
+    /// What `config` changes to. A screen handed to [`Screening::new`] stays, only how
+    /// its answers are used changes.
+    pub fn settings(&self, config: &ScreeningConfig) -> Result<Settings> {
+        let screen = if self.configured {
+            configured_screen(config)?
+        } else {
+            self.settings.get().screen.clone()
+        };
+        Ok(Settings::new(screen, config))
+    }
+
+    /// Switches to new settings, forgetting the verdicts of the previous screen.
+    pub fn set_settings(&self, settings: Settings) {
+        self.settings.set(settings);
+        self.served.lock().unwrap().clear();
+    }
+
+    async fn decide(&self, content: &Content<'_>) -> Result<(), StatusCode> {
+        let denied = |reason: &str| {
+            println!(
+                "Screening denied {} on {:?}: {}",
+                content.hash, content.stage, reason
+            );
+            match content.stage {
+                Stage::Ingest => StatusCode::UNPROCESSABLE_ENTITY,
+                Stage::Serve => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            }
+        };
+        let settings = self.settings.get();
+        match settings.screen.screen(content).await {
+            Ok(Decision::Allow) => Ok(()),
+            Ok(Decision::Deny(reason)) => Err(denied(&reason)),
+            Err(err) if settings.fail_open => {
+                println!("Letting {} through unscreened: {}", content.hash, err);
+                Ok(())
+            }
+            Err(err) => {
+                println!("Failed to screen {}: {}", content.hash, err);
+                Err(StatusCode::SERVICE_UNAVAILABLE)
+            }
+        }
+    }
+
+    /// Screens an upload, answering denied ones with 422.
+    pub async fn check_ingest(
+        &self,
+        file_name: Option<&str>,
+        data: &Bytes,
+    ) -> Result<(), StatusCode> {
+        if !self.settings.get().screen.is_enabled() {
+            return Ok(());
+        }
+        let content = Content {
+            stage: Stage::Ingest,
+            hash: Hash::new(data),
+            size: data.len() as u64,
+            file_name,
+            data,
+        };
+        self.decide(&content).await
+    }
+
+    /// Screens a stored blob before it is sent, answering denied ones with 451.
+    pub async fn check_serve(&self, app_state: &AppState, hash: Hash) -> Result<(), StatusCode> {
+        let cache_for = {
+            let settings = self.settings.get();
+            if !settings.screen.is_enabled() {
+                return Ok(());
+            }
+            settings.cache_for
+        };
+        if let Some((checked, decision)) = self.served.lock().unwrap().get(&hash) {
+            if checked.elapsed() < cache_for {
+                return match decision {
+                    Decision::Allow => Ok(()),
+                    Decision::Deny(_) => Err(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS),
+                };
+            }
+        }
+
+        let blobs_client = app_state.blobs.client();
+        let status = blobs_client
+            .status(hash)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let size = match status {
+            BlobStatus::NotFound => return Err(StatusCode::NOT_FOUND),
+            BlobStatus::Partial { size } => size.value(),
+            BlobStatus::Complete { size } => size,
+        };
+        let data = blobs_client
+            .read_at_to_bytes(hash, 0, ReadAtLen::AtMost(MAX_SERVE_BYTES))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let content = Content {
+            stage: Stage::Serve,
+            hash,
+            size,
+            file_name: None,
+            data: &data,
+        };
+        let result = self.decide(&content).await;
+
+        // Failures of the check itself are not remembered, the next request tries again
+        let decision = match result {
+            Ok(()) => Decision::Allow,
+            Err(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS) => Decision::Deny(String::new()),
+            Err(status) => return Err(status),
+        };
+        let mut served = self.served.lock().unwrap();
+        served.retain(|_, (checked, _)| checked.elapsed() < cache_for);
+        served.insert(hash, (Instant::now(), decision));
+        result
+    }
+}
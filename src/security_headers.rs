@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::config::SecurityHeadersConfig;
+use crate::proxy::ClientInfo;
+use crate::reload::Live;
+use crate::AppState;
+
+/// The headers every response gets, as parsed from the config.
+pub struct Policy {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// Only sent over HTTPS, browsers ignore it on plain connections anyway.
+    hsts: Option<HeaderValue>,
+}
+
+/// Checks the config's header values. An empty value leaves its header out.
+pub fn policy(config: &SecurityHeadersConfig) -> Result<Policy> {
+    let parse = |name: &str, value: &str| -> Result<Option<HeaderValue>> {
+        if !config.enabled || value.is_empty() {
+            return Ok(None);
+        }
+        let value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for security_headers.{}", name))?;
+        Ok(Some(value))
+    };
+    let headers = [
+        (
+            header::CONTENT_SECURITY_POLICY,
+            parse("content_security_policy", &config.content_security_policy)?,
+        ),
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            parse("content_type_options", &config.content_type_options)?,
+        ),
+        (
+            header::REFERRER_POLICY,
+            parse("referrer_policy", &config.referrer_policy)?,
+        ),
+        (
+            header::X_FRAME_OPTIONS,
+            parse("frame_options", &config.frame_options)?,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name, value?)))
+    .collect();
+    Ok(Policy {
+        headers,
+        hsts: parse(
+            "strict_transport_security",
+            &config.strict_transport_security,
+        )?,
+    })
+}
+
+/// Security headers for browsers, which matter once the gateway serves content users
+/// uploaded next to its web UI: a content security policy, HSTS, `nosniff`, the
+/// referrer policy and frame options.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    policy: Live<Policy>,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: &SecurityHeadersConfig) -> Result<Self> {
+        Ok(Self {
+            policy: Live::new(policy(config)?),
+        })
+    }
+
+    pub fn set_policy(&self, policy: Policy) {
+        self.policy.set(policy);
+    }
+}
+
+/// Middleware adding the headers to every response. Handlers that set one of them
+/// themselves, like previews with their sandbox, keep their own.
+pub async fn apply(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Response {
+    let policy = app_state.reloader.security_headers.policy.get();
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, value) in &policy.headers {
+        if !headers.contains_key(name) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+    if let Some(hsts) = &policy.hsts {
+        if client.proto == "https" && !headers.contains_key(header::STRICT_TRANSPORT_SECURITY) {
+            headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+        }
+    }
+    response
+}
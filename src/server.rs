@@ -0,0 +1,150 @@
+use anyhow::{bail, Context, Result};
+use axum::http::{header, HeaderValue};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use futures::future::{BoxFuture, FutureExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+use crate::config::HttpConfig;
+
+/// How long open requests get to finish on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Creates a socket for `addr`. IPv6 sockets only take IPv6 traffic, so `[::]` and
+/// `0.0.0.0` can be bound side by side on the same port.
+fn bind_socket(addr: SocketAddr, ty: Type, protocol: Protocol) -> Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind {}", addr))?;
+    Ok(socket)
+}
+
+fn bind_tcp(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    let socket = bind_socket(addr, Type::STREAM, Protocol::TCP)?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+fn bind_udp(addr: SocketAddr) -> Result<std::net::UdpSocket> {
+    Ok(bind_socket(addr, Type::DGRAM, Protocol::UDP)?.into())
+}
+
+/// Serves the API on every configured listen address, or on the sockets systemd
+/// passed when socket activated.
+///
+/// HTTP/1.1 and HTTP/2 are both spoken on every connection: over TLS HTTP/2 is
+/// negotiated with ALPN, over plain TCP clients may start HTTP/2 right away (h2c).
+/// With `http3` on, HTTP/3 is advertised to clients with `Alt-Svc`.
+pub async fn serve(
+    app: Router,
+    config: &HttpConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    if config.listen.is_empty() {
+        bail!("http.listen needs at least one address");
+    }
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            // iroh brings its own crypto provider, the listener uses ring as well
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let tls = RustlsConfig::from_pem_file(cert, key)
+                .await
+                .context("Failed to load TLS certificate")?;
+            Some((tls, cert, key))
+        }
+        (None, None) if config.http3 => bail!("http.http3 needs http.tls_cert and http.tls_key"),
+        (None, None) => None,
+        _ => bail!("http.tls_cert and http.tls_key must be set together"),
+    };
+    let http3 = match &tls {
+        Some((_, cert, key)) if config.http3 => Some(crate::http3::server_config(cert, key)?),
+        _ => None,
+    };
+
+    let (tcp, udp) = match crate::systemd::listeners()? {
+        Some(sockets) => {
+            println!("Using sockets passed by systemd");
+            sockets
+        }
+        None => {
+            let tcp = config.listen.iter().map(|addr| bind_tcp(*addr));
+            let udp = config
+                .listen
+                .iter()
+                .filter(|_| http3.is_some())
+                .map(|addr| bind_udp(*addr));
+            (tcp.collect::<Result<_>>()?, udp.collect::<Result<_>>()?)
+        }
+    };
+
+    let handle = Handle::new();
+    let mut servers: Vec<BoxFuture<'static, Result<()>>> = Vec::new();
+    let mut http3_ports = HashSet::new();
+    for socket in udp {
+        let addr = socket.local_addr()?;
+        let Some(http3) = &http3 else {
+            println!("Ignoring UDP socket {}, http.http3 is off", addr);
+            continue;
+        };
+        println!("Serving HTTP/3 on {}", addr);
+        http3_ports.insert(addr.port());
+        // QUIC connections are simply closed with the endpoint when the node exits
+        let server = crate::http3::serve(app.clone(), socket, http3.clone());
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                println!("HTTP/3 listener failed: {}", err);
+            }
+        });
+    }
+
+    for listener in tcp {
+        let addr = listener.local_addr()?;
+        let Some((tls, _, _)) = &tls else {
+            println!("Serving HTTP on {}", addr);
+            let server = axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .serve(
+                    app.clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                );
+            servers.push(server.map(|result| Ok(result?)).boxed());
+            continue;
+        };
+
+        let mut tcp_app = app.clone();
+        if http3_ports.contains(&addr.port()) {
+            let alt_svc = format!("h3=\":{}\"; ma=86400", addr.port());
+            tcp_app = tcp_app.layer(SetResponseHeaderLayer::if_not_present(
+                header::ALT_SVC,
+                HeaderValue::from_str(&alt_svc)?,
+            ));
+        }
+        println!("Serving HTTPS on {}", addr);
+        let server = axum_server::from_tcp_rustls(listener, tls.clone())
+            .handle(handle.clone())
+            .serve(tcp_app.into_make_service_with_connect_info::<SocketAddr>());
+        servers.push(server.map(|result| Ok(result?)).boxed());
+    }
+
+    crate::systemd::notify_ready();
+    let draining = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        println!("Shutting down, waiting for open requests");
+        draining.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    });
+    futures::future::try_join_all(servers).await?;
+    Ok(())
+}
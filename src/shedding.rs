@@ -0,0 +1,208 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::LoadSheddingConfig;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Stands in for free space or memory that couldn't be measured.
+const UNKNOWN: u64 = u64::MAX;
+
+/// What is being refused, each level including the ones before it.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Shedding {
+    Nothing,
+    Uploads,
+    Fetches,
+}
+
+/// Work that can wait until the node has room again.
+enum Work {
+    Upload,
+    Fetch,
+}
+
+/// Requests that can be refused, by route pattern and the path of gRPC methods.
+fn classify(method: &Method, route: &str, path: &str) -> Option<Work> {
+    let is = |pattern: &str| route.ends_with(pattern);
+    match *method {
+        Method::POST if is("/upload") || path.ends_with("/IrohApi/Upload") => Some(Work::Upload),
+        Method::PUT
+            if is("/s3/{bucket}/{*key}")
+                || is("/dav/{*path}")
+                || is("/docs/{namespace}/entries/{*key}") =>
+        {
+            Some(Work::Upload)
+        }
+        Method::POST
+            if is("/fetch") || is("/tenants/{name}/import") || path.ends_with("/IrohApi/Fetch") =>
+        {
+            Some(Work::Fetch)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+pub struct LoadStatus {
+    free_disk_bytes: Option<u64>,
+    free_memory_bytes: Option<u64>,
+    in_flight: usize,
+    shedding: Shedding,
+}
+
+/// Watches free disk space, memory and the requests being handled, and refuses new
+/// uploads, then fetches, with 503 before the node tips over.
+#[derive(Clone)]
+pub struct LoadShedder {
+    config: Arc<LoadSheddingConfig>,
+    data_dir: PathBuf,
+    free_disk: Arc<AtomicU64>,
+    free_memory: Arc<AtomicU64>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// `MemAvailable` of `/proc/meminfo`, the memory that can be had without swapping.
+fn free_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+fn known(value: u64) -> Option<u64> {
+    (value != UNKNOWN).then_some(value)
+}
+
+/// How much to shed with `value` left of what should stay above `min`.
+fn shedding_below(value: u64, min: u64) -> Shedding {
+    if min == 0 || value == UNKNOWN {
+        Shedding::Nothing
+    } else if value < min / 2 {
+        Shedding::Fetches
+    } else if value < min {
+        Shedding::Uploads
+    } else {
+        Shedding::Nothing
+    }
+}
+
+impl LoadShedder {
+    pub fn new(config: &LoadSheddingConfig, data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            data_dir: data_dir.into(),
+            free_disk: Arc::new(AtomicU64::new(UNKNOWN)),
+            free_memory: Arc::new(AtomicU64::new(UNKNOWN)),
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Measures free space and memory every second, when thresholds for them are set.
+    pub fn spawn(&self) {
+        if self.config.min_free_disk_bytes == 0 && self.config.min_free_memory_bytes == 0 {
+            return;
+        }
+        let shedder = self.clone();
+        tokio::spawn(async move {
+            let mut shedding = Shedding::Nothing;
+            loop {
+                shedder.sample();
+                let now = shedder.shedding();
+                if now != shedding {
+                    println!("Load shedding changed from {:?} to {:?}", shedding, now);
+                    shedding = now;
+                }
+                tokio::time::sleep(SAMPLE_INTERVAL).await;
+            }
+        });
+    }
+
+    fn sample(&self) {
+        let free_disk = fs2::available_space(&self.data_dir).unwrap_or(UNKNOWN);
+        self.free_disk.store(free_disk, Ordering::Relaxed);
+        let free_memory = free_memory().unwrap_or(UNKNOWN);
+        self.free_memory.store(free_memory, Ordering::Relaxed);
+    }
+
+    pub fn shedding(&self) -> Shedding {
+        let config = &self.config;
+        let in_flight = self.in_flight.load(Ordering::Relaxed);
+        let by_requests = match config.max_in_flight {
+            0 => Shedding::Nothing,
+            max if in_flight > max * 2 => Shedding::Fetches,
+            max if in_flight > max => Shedding::Uploads,
+            _ => Shedding::Nothing,
+        };
+        by_requests
+            .max(shedding_below(
+                self.free_disk.load(Ordering::Relaxed),
+                config.min_free_disk_bytes,
+            ))
+            .max(shedding_below(
+                self.free_memory.load(Ordering::Relaxed),
+                config.min_free_memory_bytes,
+            ))
+    }
+
+    pub fn status(&self) -> LoadStatus {
+        LoadStatus {
+            free_disk_bytes: known(self.free_disk.load(Ordering::Relaxed)),
+            free_memory_bytes: known(self.free_memory.load(Ordering::Relaxed)),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            shedding: self.shedding(),
+        }
+    }
+}
+
+/// Counts a request as in flight until its response is ready.
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware refusing uploads and fetches while the node is under pressure.
+pub async fn apply(State(shedder): State<LoadShedder>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str())
+        .unwrap_or_default();
+    let refused = match classify(request.method(), route, request.uri().path()) {
+        Some(Work::Upload) => shedder.shedding() >= Shedding::Uploads,
+        Some(Work::Fetch) => shedder.shedding() >= Shedding::Fetches,
+        None => false,
+    };
+    if refused {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(
+                header::RETRY_AFTER,
+                shedder.config.retry_after_secs.to_string(),
+            )],
+        )
+            .into_response();
+    }
+
+    shedder.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(shedder.in_flight.clone());
+    next.run(request).await
+}
@@ -0,0 +1,399 @@
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
+use iroh_blobs::store::{EntryStatus, MapMut};
+use iroh_blobs::util::Tag;
+use iroh_blobs::{Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+
+use crate::archive::{string_tags, ArchivedTag, Tagger};
+use crate::holds::{self, Hold};
+use crate::pins::Pin;
+use crate::AppState;
+
+const SNAPSHOT_DIR: &str = "data/snapshots";
+
+/// Where the store keeps the files of complete blobs, as `<hash>.data`.
+const BLOB_DIR: &str = "data/data";
+
+/// The records a snapshot keeps by the file they go in, taken from memory since their
+/// files in the data directory are written a moment after changes. The audit log isn't
+/// among them, it only ever grows.
+pub fn records(app_state: &AppState) -> Result<[(&'static str, Vec<u8>); 3]> {
+    Ok([
+        ("pins.json", app_state.pins.to_json()?),
+        ("holds.json", app_state.holds.to_json()?),
+        ("s3.json", app_state.buckets.to_json()?),
+    ])
+}
+
+/// A point-in-time copy of the store to roll back to, in `data/snapshots/<id>`.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub label: Option<String>,
+    pub created_at: i64,
+    pub blobs: u64,
+    pub bytes: u64,
+    /// Blobs sharing the store's file through a hard link, which take no space of their
+    /// own until the store lets go of them. The others were copied.
+    pub linked: u64,
+}
+
+fn snapshot_dir(id: &str) -> PathBuf {
+    PathBuf::from(SNAPSHOT_DIR).join(id)
+}
+
+/// Ids are made by [`take`], anything else can't be one.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Takes a snapshot of every complete blob with the tags and the records of pins,
+/// legal holds and S3 objects. The files of complete blobs never change, so they are
+/// hard linked where the filesystem allows; blobs kept inline in the database or
+/// elsewhere are copied.
+pub async fn take(app_state: &AppState, label: Option<String>) -> Result<Snapshot> {
+    let now = Utc::now();
+    let base = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut id = base.clone();
+    let mut n = 1;
+    while snapshot_dir(&id).exists() {
+        n += 1;
+        id = format!("{}-{}", base, n);
+    }
+    // Written next to where it goes, so a failed snapshot never looks whole
+    let dir = PathBuf::from(SNAPSHOT_DIR).join(format!("{}.partial", id));
+    tokio::fs::create_dir_all(dir.join("blobs")).await?;
+    match write(app_state, &dir, id.clone(), label, now.timestamp()).await {
+        Ok(snapshot) => {
+            tokio::fs::rename(&dir, snapshot_dir(&id)).await?;
+            Ok(snapshot)
+        }
+        Err(err) => {
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+            Err(err)
+        }
+    }
+}
+
+async fn write(
+    app_state: &AppState,
+    dir: &std::path::Path,
+    id: String,
+    label: Option<String>,
+    created_at: i64,
+) -> Result<Snapshot> {
+    let tags = string_tags(&app_state.blobs).await?;
+    tokio::fs::write(dir.join("tags.json"), serde_json::to_vec_pretty(&tags)?).await?;
+    for (file, contents) in records(app_state)? {
+        tokio::fs::write(dir.join(file), contents).await?;
+    }
+
+    let mut snapshot = Snapshot {
+        id,
+        label,
+        created_at,
+        blobs: 0,
+        bytes: 0,
+        linked: 0,
+    };
+    let blobs: Vec<(Hash, u64)> = app_state
+        .blobs
+        .client()
+        .list()
+        .await?
+        .map_ok(|blob| (blob.hash, blob.size))
+        .try_collect()
+        .await?;
+    for (hash, size) in blobs {
+        let target = dir.join("blobs").join(hash.to_hex());
+        let source = PathBuf::from(BLOB_DIR).join(format!("{}.data", hash.to_hex()));
+        if tokio::fs::hard_link(&source, &target).await.is_ok() {
+            snapshot.linked += 1;
+        } else if !copy_blob(app_state, hash, &target).await? {
+            // Deleted since it was listed
+            continue;
+        }
+        snapshot.blobs += 1;
+        snapshot.bytes += size;
+    }
+    tokio::fs::write(
+        dir.join("snapshot.json"),
+        serde_json::to_vec_pretty(&snapshot)?,
+    )
+    .await?;
+    Ok(snapshot)
+}
+
+/// Writes a blob to `target`, returning whether the store still had it.
+async fn copy_blob(app_state: &AppState, hash: Hash, target: &std::path::Path) -> Result<bool> {
+    let Ok(mut reader) = app_state.blobs.client().read(hash).await else {
+        return Ok(false);
+    };
+    let mut file = tokio::fs::File::create(target).await?;
+    while let Some(chunk) = reader.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.sync_all().await?;
+    Ok(true)
+}
+
+async fn read(id: &str) -> Result<Option<Snapshot>> {
+    match tokio::fs::read(snapshot_dir(id).join("snapshot.json")).await {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The snapshots taken, the oldest first.
+pub async fn list() -> Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    let mut entries = match tokio::fs::read_dir(SNAPSHOT_DIR).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(snapshots),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(id) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if !is_valid_id(&id) {
+            continue;
+        }
+        if let Some(snapshot) = read(&id).await? {
+            snapshots.push(snapshot);
+        }
+    }
+    // Ids of the same second are numbered, `-10` comes after `-9`
+    snapshots
+        .sort_by(|a, b| (a.created_at, a.id.len(), &a.id).cmp(&(b.created_at, b.id.len(), &b.id)));
+    Ok(snapshots)
+}
+
+#[derive(Serialize, Default)]
+pub struct RollbackReport {
+    id: String,
+    /// Blobs the store had lost since, put back from the snapshot.
+    blobs_restored: u64,
+    tags_removed: u64,
+    tags_set: u64,
+    /// Blobs of the snapshot whose data no longer matches their hash.
+    corrupt: Vec<Hash>,
+    /// Tags of the snapshot left out because their blob is in neither the snapshot nor
+    /// the store.
+    missing: Vec<String>,
+}
+
+/// Puts the store back the way the snapshot has it: blobs deleted since come back, the
+/// tags are those of the snapshot again, and so are pins and S3 objects. Blobs added
+/// since lose their tags and go with the next garbage collection. Legal holds placed
+/// since stay, no rollback releases them.
+pub async fn rollback(app_state: &AppState, id: &str) -> Result<RollbackReport> {
+    let dir = std::path::absolute(snapshot_dir(id))?;
+    let mut report = RollbackReport {
+        id: id.to_string(),
+        ..Default::default()
+    };
+
+    let mut tagger = Tagger::new(&app_state.blobs).await?;
+    let mut entries = tokio::fs::read_dir(dir.join("blobs")).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(hash) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Hash::from_str(name).ok())
+        else {
+            continue;
+        };
+        if app_state.blobs.store().entry_status(&hash).await? == EntryStatus::Complete {
+            continue;
+        }
+        let (temp_tag, _) = tagger.batch().add_file(entry.path()).await?;
+        if *temp_tag.hash() == hash {
+            tagger.keep(temp_tag);
+            report.blobs_restored += 1;
+        } else {
+            println!("Left {} out of the rollback, its data doesn't match", hash);
+            report.corrupt.push(hash);
+        }
+    }
+
+    let stored: HashMap<Hash, u64> = app_state
+        .blobs
+        .client()
+        .list()
+        .await?
+        .map_ok(|blob| (blob.hash, blob.size))
+        .try_collect()
+        .await?;
+    let tags: Vec<ArchivedTag> = serde_json::from_slice(
+        &tokio::fs::read(dir.join("tags.json"))
+            .await
+            .context("snapshot without tags")?,
+    )?;
+    let snapshot_tags: HashMap<&str, HashAndFormat> = tags
+        .iter()
+        .map(|tag| {
+            let content = HashAndFormat {
+                hash: tag.hash,
+                format: tag.format,
+            };
+            (tag.name.as_str(), content)
+        })
+        .collect();
+    let current = string_tags(&app_state.blobs).await?;
+    let current_tags: HashMap<&str, HashAndFormat> = current
+        .iter()
+        .map(|tag| {
+            let content = HashAndFormat {
+                hash: tag.hash,
+                format: tag.format,
+            };
+            (tag.name.as_str(), content)
+        })
+        .collect();
+    for name in current_tags.keys() {
+        if !snapshot_tags.contains_key(name) && !name.starts_with(holds::TAG_PREFIX) {
+            app_state
+                .blobs
+                .client()
+                .tags()
+                .delete(Tag::from(*name))
+                .await?;
+            report.tags_removed += 1;
+        }
+    }
+    for (name, content) in &snapshot_tags {
+        if current_tags.get(name) == Some(content) {
+            continue;
+        }
+        if stored.contains_key(&content.hash) {
+            tagger.set(name, *content).await?;
+            report.tags_set += 1;
+        } else {
+            report.missing.push(name.to_string());
+        }
+    }
+    drop(tagger);
+
+    let pins: Vec<Pin> = read_records(&dir, "pins.json").await?.unwrap_or_default();
+    app_state.pins.restore(pins);
+    match tokio::fs::read(dir.join("s3.json")).await {
+        Ok(index) => app_state.buckets.restore(&index)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => app_state.buckets.restore(b"{}")?,
+        Err(err) => return Err(err.into()),
+    }
+    let holds: Vec<Hold> = read_records(&dir, "holds.json").await?.unwrap_or_default();
+    for hold in holds {
+        app_state.holds.adopt(hold);
+    }
+    app_state.tenancy.rescan().await?;
+    app_state.aliases.rescan().await?;
+
+    println!(
+        "Rolled back to snapshot {}: {} blobs restored, {} tags removed and {} set",
+        id, report.blobs_restored, report.tags_removed, report.tags_set
+    );
+    Ok(report)
+}
+
+async fn read_records<T: serde::de::DeserializeOwned>(
+    dir: &std::path::Path,
+    file: &str,
+) -> Result<Option<T>> {
+    match tokio::fs::read(dir.join(file)).await {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SnapshotRequest {
+    label: Option<String>,
+}
+
+/// `POST /admin/snapshots`: takes a snapshot to roll back to, with an optional JSON
+/// body giving it a `label`.
+pub async fn create_snapshot(
+    State(app_state): State<AppState>,
+    body: Bytes,
+) -> Result<impl IntoResponse, StatusCode> {
+    let request: SnapshotRequest = if body.is_empty() {
+        SnapshotRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?
+    };
+    let snapshot = take(&app_state, request.label).await.map_err(|err| {
+        println!("Failed to take a snapshot: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    println!(
+        "Took snapshot {} of {} blobs, {} of them linked",
+        snapshot.id, snapshot.blobs, snapshot.linked
+    );
+    Ok((StatusCode::CREATED, Json(snapshot)))
+}
+
+/// `GET /admin/snapshots`
+pub async fn list_snapshots() -> Result<impl IntoResponse, StatusCode> {
+    let snapshots = list().await.map_err(|err| {
+        println!("Failed to list snapshots: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(snapshots))
+}
+
+/// `DELETE /admin/snapshots/{id}`
+pub async fn delete_snapshot(Path(id): Path<String>) -> Result<StatusCode, StatusCode> {
+    if !is_valid_id(&id) || !snapshot_dir(&id).exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    tokio::fs::remove_dir_all(snapshot_dir(&id))
+        .await
+        .map_err(|err| {
+            println!("Failed to delete snapshot {}: {}", id, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /admin/snapshots/{id}/rollback`: starts a job rolling the store back to a
+/// snapshot, whose result reports what changed.
+pub async fn rollback_snapshot(
+    State(app_state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let exists = is_valid_id(&id)
+        && read(&id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .is_some();
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let state = app_state.clone();
+    let job_id = app_state.jobs.spawn("rollback", async move {
+        let report = rollback(&state, &id).await?;
+        Ok(serde_json::to_value(report)?)
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "job_id": job_id })),
+    ))
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::network;
+use crate::persist::StateFile;
+use crate::policy::content_type;
+use crate::AppState;
+
+/// Days of uploads kept and reported.
+const DAYS_KEPT: usize = 30;
+const TOP_CONTENT_TYPES: usize = 10;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct DayCounts {
+    uploads: u64,
+    bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+    /// By `YYYY-MM-DD`, in UTC.
+    days: BTreeMap<String, DayCounts>,
+    content_types: BTreeMap<String, u64>,
+    uploaded_bytes: u64,
+    /// Bytes of uploads whose blob was already stored.
+    deduplicated_bytes: u64,
+}
+
+/// Counts of what was uploaded, for `GET /stats`. Written to disk after uploads, so they
+/// survive restarts.
+#[derive(Clone)]
+pub struct Stats {
+    saved: Arc<Mutex<Saved>>,
+    file: StateFile,
+}
+
+impl Stats {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let saved = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            Saved::default()
+        };
+        let saved = Arc::new(Mutex::new(saved));
+        let file = StateFile::spawn(path, {
+            let saved = saved.clone();
+            move || Ok(serde_json::to_vec_pretty(&*saved.lock().unwrap())?)
+        });
+        Ok(Self { saved, file })
+    }
+
+    /// Counts an upload of `data`, `duplicate` when its blob was stored already.
+    pub fn record_upload(&self, data: &[u8], duplicate: bool) {
+        let size = data.len() as u64;
+        let mut saved = self.saved.lock().unwrap();
+        let day = saved
+            .days
+            .entry(Utc::now().format("%Y-%m-%d").to_string())
+            .or_default();
+        day.uploads += 1;
+        day.bytes += size;
+        while saved.days.len() > DAYS_KEPT {
+            saved.days.pop_first();
+        }
+        *saved
+            .content_types
+            .entry(content_type(data).to_string())
+            .or_default() += 1;
+        saved.uploaded_bytes += size;
+        if duplicate {
+            saved.deduplicated_bytes += size;
+        }
+        self.file.changed();
+    }
+}
+
+#[derive(Serialize)]
+pub struct DayUploads {
+    date: String,
+    uploads: u64,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct ContentTypeCount {
+    content_type: String,
+    uploads: u64,
+}
+
+#[derive(Serialize)]
+pub struct Egress {
+    http_bytes: u64,
+    p2p_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct Dedup {
+    uploaded_bytes: u64,
+    deduplicated_bytes: u64,
+    /// Bytes uploaded per byte stored for them, 1 when nothing was deduplicated.
+    ratio: f64,
+}
+
+#[derive(Serialize)]
+pub struct StoreCounts {
+    blobs: u64,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    uploads_per_day: Vec<DayUploads>,
+    top_content_types: Vec<ContentTypeCount>,
+    /// Since the gateway started.
+    egress: Egress,
+    dedup: Dedup,
+    store: StoreCounts,
+    active_peers: usize,
+}
+
+/// `GET /stats`: the numbers a dashboard shows, in one call.
+pub async fn get_stats(
+    State(app_state): State<AppState>,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    let (blobs, bytes) = app_state
+        .blobs
+        .client()
+        .list()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .try_fold((0, 0), |(blobs, bytes), blob| async move {
+            Ok((blobs + 1, bytes + blob.size))
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let saved = app_state.stats.saved.lock().unwrap();
+    let uploads_per_day = saved
+        .days
+        .iter()
+        .map(|(date, counts)| DayUploads {
+            date: date.clone(),
+            uploads: counts.uploads,
+            bytes: counts.bytes,
+        })
+        .collect();
+    let mut top_content_types: Vec<ContentTypeCount> = saved
+        .content_types
+        .iter()
+        .map(|(content_type, uploads)| ContentTypeCount {
+            content_type: content_type.clone(),
+            uploads: *uploads,
+        })
+        .collect();
+    top_content_types.sort_by_key(|count| Reverse(count.uploads));
+    top_content_types.truncate(TOP_CONTENT_TYPES);
+    let stored_bytes = saved.uploaded_bytes - saved.deduplicated_bytes;
+    let dedup = Dedup {
+        uploaded_bytes: saved.uploaded_bytes,
+        deduplicated_bytes: saved.deduplicated_bytes,
+        ratio: if stored_bytes == 0 {
+            1.0
+        } else {
+            saved.uploaded_bytes as f64 / stored_bytes as f64
+        },
+    };
+    drop(saved);
+
+    let totals = app_state.metering.totals();
+    Ok(Json(StatsResponse {
+        uploads_per_day,
+        top_content_types,
+        egress: Egress {
+            http_bytes: totals.http_egress_bytes,
+            p2p_bytes: totals.p2p_egress_bytes,
+        },
+        dedup,
+        store: StoreCounts { blobs, bytes },
+        active_peers: network::connections(&app_state.endpoint).len(),
+    }))
+}
@@ -0,0 +1,51 @@
+use anyhow::{bail, Result};
+use sd_notify::NotifyState;
+use socket2::{Socket, Type};
+use std::net::{TcpListener, UdpSocket};
+use std::os::fd::FromRawFd;
+use std::time::Duration;
+
+/// Sockets handed over by systemd socket activation, split into TCP listeners and UDP
+/// sockets, or `None` when the process wasn't socket activated.
+pub fn listeners() -> Result<Option<(Vec<TcpListener>, Vec<UdpSocket>)>> {
+    let fds: Vec<_> = sd_notify::listen_fds()?.collect();
+    if fds.is_empty() {
+        return Ok(None);
+    }
+
+    let mut tcp = Vec::new();
+    let mut udp = Vec::new();
+    for fd in fds {
+        // Safety: systemd passes these descriptors on for this process to own
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_nonblocking(true)?;
+        match socket.r#type()? {
+            Type::STREAM => tcp.push(socket.into()),
+            Type::DGRAM => udp.push(socket.into()),
+            _ => bail!("systemd passed a socket that is neither TCP nor UDP"),
+        }
+    }
+    Ok(Some((tcp, udp)))
+}
+
+/// Tells systemd the service is up, and keeps its watchdog fed when `WatchdogSec` is
+/// set. Does nothing when not running under systemd.
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        println!("Failed to notify systemd: {}", err);
+    }
+
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        // Ping at half the timeout, as systemd recommends. A stalled runtime stops the
+        // pings and gets the service restarted.
+        let period = Duration::from_micros(usec / 2);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+            }
+        });
+    }
+}
@@ -0,0 +1,1457 @@
+use anyhow::{bail, Result};
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Path, RawPathParams, Request, State},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json, RequestExt,
+};
+use futures::TryStreamExt;
+use iroh_blobs::format::collection::Collection;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::ticket::BlobTicket;
+use iroh_blobs::util::{SetTagOption, Tag};
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::blob::parse_hash;
+use crate::config::{RolesConfig, RouteGroup, TenancyConfig, TenantConfig};
+use crate::holds::Holds;
+use crate::jobs::now_secs;
+use crate::proxy::ClientInfo;
+use crate::reload::Live;
+use crate::roles::{RoleTable, Roles, ADMIN, UPLOADER};
+use crate::users::SessionUser;
+use crate::AppState;
+
+const TAG_PREFIX: &str = "tenant/";
+/// Tags of the collections tenants are exported as, and imported through.
+const EXPORT_TAG_PREFIX: &str = "tenant-export/";
+const IMPORT_TAG_PREFIX: &str = "tenant-import/";
+/// Tags keeping deleted uploads restorable until their grace period is over.
+const TRASH_TAG_PREFIX: &str = "trash/";
+/// Automatic tags, which keep the operator's uploads.
+const AUTO_TAG_PREFIX: &str = "auto-";
+/// Whose trash the operator's deletions go to. Tenant names have no `~`, and users are
+/// `~<username>` with a name, so nobody else can have it.
+pub const OPERATOR: &str = "~";
+/// How often the trash is checked for uploads past their grace period.
+const TRASH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Path prefix picking a tenant by name, as in `/t/<name>/upload`.
+pub const PATH_PREFIX: &str = "/t/";
+/// Routes tenants may use. The rest of the API works on the whole store and is left to
+/// the operator.
+pub const TENANT_ROUTES: &[&str] = &[
+    "/upload",
+    "/hash",
+    "/verify/{hash}",
+    "/node-id",
+    "/capabilities",
+    "/blobs",
+    "/blob/{hash}",
+    "/blob/{hash}/info",
+    "/blob/{hash}/bao",
+    "/blob/{hash}/slice",
+    "/blob/{hash}/thumb",
+    "/blob/{hash}/preview",
+    "/blob/{hash}/pin",
+    "/blob/{hash}/restore",
+    "/pins",
+    "/trash",
+    "/raw/{hash}",
+];
+
+fn owner_tag(tenant: &str, hash: &Hash) -> Tag {
+    Tag::from(format!("{}{}/{}", TAG_PREFIX, tenant, hash))
+}
+
+/// The deletion time is part of the name, so the grace period survives restarts.
+fn trash_tag(tenant: &str, hash: &Hash, deleted_at: u64) -> Tag {
+    Tag::from(format!(
+        "{}{}/{}/{}",
+        TRASH_TAG_PREFIX, tenant, hash, deleted_at
+    ))
+}
+
+/// The tenant of an upload or trash tag, with the deletion time for the trash.
+fn parse_tag(name: &str) -> Option<(&str, Option<u64>)> {
+    if let Some((tenant, _)) = name
+        .strip_prefix(TAG_PREFIX)
+        .and_then(|name| name.split_once('/'))
+    {
+        return Some((tenant, None));
+    }
+    let (tenant, rest) = name.strip_prefix(TRASH_TAG_PREFIX)?.split_once('/')?;
+    Some((tenant, Some(rest.rsplit_once('/')?.1.parse().ok()?)))
+}
+
+/// An upload a tenant deleted, restorable until `deleted_at` plus the grace period.
+#[derive(Clone, Copy)]
+struct Tombstone {
+    size: u64,
+    deleted_at: u64,
+}
+
+/// The tenant a request is made for. `None` for the operator, and for every request
+/// when no tenants are configured.
+#[derive(Clone, Debug, Default)]
+pub struct Tenant(pub Option<String>);
+
+impl Tenant {
+    /// `key` in the tenant's own namespace, for client-chosen names that must not clash
+    /// between tenants.
+    pub fn scope(&self, key: String) -> String {
+        match &self.0 {
+            Some(tenant) => format!("{}/{}", tenant, key),
+            None => key,
+        }
+    }
+
+    /// The tag an upload of `hash` by the tenant is stored under, `None` for the operator
+    /// whose uploads get automatic tags.
+    pub fn tag(&self, hash: &Hash) -> Option<Tag> {
+        self.0.as_ref().map(|tenant| owner_tag(tenant, hash))
+    }
+}
+
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _app_state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Tenant>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Independent applications sharing the gateway.
+///
+/// Requests pick their tenant with an API key, or by name with a `/t/<name>` path
+/// prefix. Tenants only see what they uploaded themselves: which blobs a tenant owns is
+/// kept in tags, which also keep those blobs from being garbage collected. Deleted
+/// uploads stay in the trash for a grace period before they are let go. Users signed in
+/// to the web UI are tenants of their own in the same way, see [`crate::users`].
+#[derive(Clone)]
+pub struct Tenancy {
+    access: Live<Access>,
+    roles: Roles,
+    base_path: String,
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
+    owned: Arc<RwLock<HashMap<String, BTreeMap<Hash, u64>>>>,
+    grace_secs: u64,
+    trash: Arc<RwLock<HashMap<String, BTreeMap<Hash, Tombstone>>>>,
+}
+
+/// The tenants and keys of one version of the config, which reloading it replaces.
+pub struct Access {
+    tenants: HashMap<String, TenantConfig>,
+    /// The tenant of each API key.
+    keys: HashMap<String, String>,
+    admin_keys: HashSet<String>,
+}
+
+impl Access {
+    /// Checks the tenants of `config`, and that every key given a role in `roles` is
+    /// one of their keys or an admin key.
+    pub fn new(config: &TenancyConfig, roles: &RoleTable) -> Result<Self> {
+        let mut tenants = HashMap::new();
+        let mut keys = HashMap::new();
+        for tenant in &config.tenants {
+            let name = &tenant.name;
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!("Invalid tenant name {:?}", name);
+            }
+            for key in &tenant.api_keys {
+                if keys.insert(key.clone(), name.clone()).is_some()
+                    || config.admin_keys.contains(key)
+                {
+                    bail!("API key of tenant {} is used more than once", name);
+                }
+            }
+            if tenants.insert(name.clone(), tenant.clone()).is_some() {
+                bail!("Tenant {} is configured more than once", name);
+            }
+        }
+
+        for key in roles.keys() {
+            if !keys.contains_key(key) && !config.admin_keys.iter().any(|admin| admin == key) {
+                bail!("A key given a role under [roles.keys] is neither an admin nor a tenant key");
+            }
+        }
+
+        Ok(Self {
+            tenants,
+            keys,
+            admin_keys: config.admin_keys.iter().cloned().collect(),
+        })
+    }
+}
+
+impl Tenancy {
+    pub async fn load(
+        config: &TenancyConfig,
+        roles: Roles,
+        roles_config: &RolesConfig,
+        base_path: &str,
+        blobs: &Blobs<iroh_blobs::store::fs::Store>,
+        grace_secs: u64,
+    ) -> Result<Self> {
+        let access = Access::new(config, &RoleTable::new(roles_config)?)?;
+        let tenancy = Self {
+            access: Live::new(access),
+            roles,
+            base_path: base_path.to_string(),
+            blobs: blobs.clone(),
+            owned: Arc::new(RwLock::new(HashMap::new())),
+            grace_secs,
+            trash: Arc::new(RwLock::new(HashMap::new())),
+        };
+        tenancy.rescan().await?;
+        tokio::spawn(tenancy.clone().empty_trash_periodically());
+        Ok(tenancy)
+    }
+
+    /// Replaces the tenants and keys, as reloading the config does. What tenants own
+    /// stays, also for tenants taken out of the config. Path prefixes of tenants added
+    /// since the start wait for a restart.
+    pub fn set_access(&self, access: Access) {
+        self.access.set(access);
+    }
+
+    /// Whether any keys or tenants are configured.
+    pub fn is_enabled(&self) -> bool {
+        let access = self.access.get();
+        !access.tenants.is_empty() || !access.admin_keys.is_empty()
+    }
+
+    /// Whether a request with neither key nor session may use `route` on a gateway
+    /// without keys. Nobody can prove to be the operator there, so admin routes are
+    /// left to `local` requests, made from the host itself.
+    fn allows_keyless(&self, route: &str, method: &Method, local: bool) -> bool {
+        let route = route.strip_prefix(&self.base_path).unwrap_or(route);
+        local || self.roles.group(route, method) != RouteGroup::Admin
+    }
+
+    /// How clients authenticate: `none` when there are no keys or tenants, and `api_key`
+    /// when every request needs a key, other than those to tenants without one.
+    pub fn auth_mode(&self) -> &'static str {
+        if self.is_enabled() {
+            "api_key"
+        } else {
+            "none"
+        }
+    }
+
+    /// Names of the configured tenants, for serving the API under their path prefixes.
+    pub fn names(&self) -> Vec<String> {
+        self.access.get().tenants.keys().cloned().collect()
+    }
+
+    pub fn owns(&self, tenant: &str, hash: &Hash) -> bool {
+        self.owned
+            .read()
+            .unwrap()
+            .get(tenant)
+            .is_some_and(|owned| owned.contains_key(hash))
+    }
+
+    /// The tenants owning `hash`.
+    pub fn owners(&self, hash: &Hash) -> Vec<String> {
+        self.owned
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, owned)| owned.contains_key(hash))
+            .map(|(tenant, _)| tenant.clone())
+            .collect()
+    }
+
+    /// The blobs a tenant owns with their sizes, in hash order.
+    pub fn blobs(&self, tenant: &str) -> Vec<(Hash, u64)> {
+        self.owned
+            .read()
+            .unwrap()
+            .get(tenant)
+            .map(|owned| owned.iter().map(|(hash, size)| (*hash, *size)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Lets an upload of `size` bytes through unless it would take the tenant past its
+    /// quota, answering with 507 then. Content the tenant already owns takes no extra
+    /// space.
+    pub fn check_quota(&self, tenant: &Tenant, hash: &Hash, size: u64) -> Result<(), StatusCode> {
+        let Some(name) = &tenant.0 else {
+            return Ok(());
+        };
+        let quota = self
+            .access
+            .get()
+            .tenants
+            .get(name)
+            .map_or(0, |tenant| tenant.quota_bytes);
+        if quota == 0 {
+            return Ok(());
+        }
+        let used: u64 = self
+            .owned
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|owned| owned.values().sum())
+            .unwrap_or_default();
+        if used + size > quota && !self.owns(name, hash) {
+            println!("Tenant {} is over its quota of {} bytes", name, quota);
+            return Err(StatusCode::INSUFFICIENT_STORAGE);
+        }
+        Ok(())
+    }
+
+    /// Records that `tenant` uploaded `hash`.
+    pub async fn record(&self, tenant: &Tenant, hash: Hash, size: u64) -> Result<()> {
+        match &tenant.0 {
+            Some(name) => self.claim(name, hash, size).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn claim(&self, name: &str, hash: Hash, size: u64) -> Result<()> {
+        let batch = self.blobs.client().batch().await?;
+        let temp_tag = batch.temp_tag(HashAndFormat::raw(hash)).await?;
+        batch.persist_to(temp_tag, owner_tag(name, &hash)).await?;
+        self.owned
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(hash, size);
+        Ok(())
+    }
+
+    /// Drops the tenant's claim on `hash`. The blob goes with the next garbage
+    /// collection, unless other tenants or anything else still keep it.
+    pub async fn release(&self, name: &str, hash: Hash) -> Result<bool> {
+        if !self.owns(name, &hash) {
+            return Ok(false);
+        }
+        self.blobs
+            .client()
+            .tags()
+            .delete(owner_tag(name, &hash))
+            .await?;
+        if let Some(owned) = self.owned.write().unwrap().get_mut(name) {
+            owned.remove(&hash);
+        }
+        Ok(true)
+    }
+
+    /// Deletes the tenant's upload of `hash`, keeping it in the trash until the grace
+    /// period is over. Without a grace period the claim is dropped right away.
+    pub async fn discard(&self, name: &str, hash: Hash) -> Result<bool> {
+        if self.grace_secs == 0 {
+            return self.release(name, hash).await;
+        }
+        let Some(size) = self
+            .owned
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|owned| owned.get(&hash).copied())
+        else {
+            return Ok(false);
+        };
+        self.trash_blob(name, hash, size).await?;
+        self.release(name, hash).await?;
+        Ok(true)
+    }
+
+    /// Deletes the operator's upload of `hash`, which automatic tags keep, the way
+    /// [`discard`](Self::discard) deletes a tenant's: into the trash of [`OPERATOR`]
+    /// during the grace period.
+    pub async fn discard_own(&self, hash: Hash) -> Result<bool> {
+        let tags: Vec<Tag> = self
+            .blobs
+            .client()
+            .tags()
+            .list()
+            .await?
+            .try_filter(|tag| {
+                futures::future::ready(
+                    tag.hash == hash
+                        && tag.format == BlobFormat::Raw
+                        && tag.name.0.starts_with(AUTO_TAG_PREFIX.as_bytes()),
+                )
+            })
+            .map_ok(|tag| tag.name)
+            .try_collect()
+            .await?;
+        if tags.is_empty() {
+            return Ok(false);
+        }
+        if self.grace_secs > 0 {
+            let size = match self.blobs.client().status(hash).await? {
+                BlobStatus::Complete { size } => size,
+                BlobStatus::Partial { size } => size.value(),
+                BlobStatus::NotFound => 0,
+            };
+            self.trash_blob(OPERATOR, hash, size).await?;
+        }
+        for tag in tags {
+            self.blobs.client().tags().delete(tag).await?;
+        }
+        Ok(true)
+    }
+
+    /// Keeps `hash` in the trash of `name` from now on, before what kept it goes.
+    async fn trash_blob(&self, name: &str, hash: Hash, size: u64) -> Result<()> {
+        // Deleted before, restored or uploaded again since: the grace period starts over
+        if let Some(previous) = self.tombstone(name, &hash) {
+            self.forget(name, hash, previous).await?;
+        }
+        let deleted_at = now_secs();
+        let batch = self.blobs.client().batch().await?;
+        let temp_tag = batch.temp_tag(HashAndFormat::raw(hash)).await?;
+        batch
+            .persist_to(temp_tag, trash_tag(name, &hash, deleted_at))
+            .await?;
+        self.trash
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(hash, Tombstone { size, deleted_at });
+        Ok(())
+    }
+
+    /// What the trash keeps of the tenant's deleted upload of `hash`.
+    fn tombstone(&self, name: &str, hash: &Hash) -> Option<Tombstone> {
+        self.trash
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|trash| trash.get(hash).copied())
+    }
+
+    /// Gives a deleted upload back to the tenant, as long as it is still in the trash.
+    pub async fn restore(&self, name: &str, hash: Hash) -> Result<bool> {
+        let Some(tombstone) = self.tombstone(name, &hash) else {
+            return Ok(false);
+        };
+        if name == OPERATOR {
+            // The operator's uploads go back to being kept by an automatic tag
+            let batch = self.blobs.client().batch().await?;
+            let temp_tag = batch.temp_tag(HashAndFormat::raw(hash)).await?;
+            batch.persist(temp_tag).await?;
+        } else {
+            self.claim(name, hash, tombstone.size).await?;
+        }
+        self.forget(name, hash, tombstone).await?;
+        Ok(true)
+    }
+
+    /// What the tenant deleted and can still restore, with sizes and deletion times.
+    fn trash(&self, name: &str) -> Vec<(Hash, Tombstone)> {
+        self.trash
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|trash| {
+                trash
+                    .iter()
+                    .map(|(hash, tombstone)| (*hash, *tombstone))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether anything `name` owns or has in the trash is under legal hold, which
+    /// keeps it from being dropped as a whole.
+    pub fn holds_any(&self, name: &str, holds: &Holds) -> bool {
+        self.blobs(name)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .chain(self.trashed(name))
+            .any(|hash| holds.is_held(&hash))
+    }
+
+    /// The blobs in a tenant's trash, without what the trash keeps of them.
+    pub fn trashed(&self, name: &str) -> Vec<Hash> {
+        self.trash(name).into_iter().map(|(hash, _)| hash).collect()
+    }
+
+    /// Rebuilds what each tenant owns and has in the trash from the tags, which are
+    /// the record of it, after they changed underneath, like with a rollback.
+    pub async fn rescan(&self) -> Result<()> {
+        let sizes: HashMap<Hash, u64> = self
+            .blobs
+            .client()
+            .list()
+            .await?
+            .map_ok(|blob| (blob.hash, blob.size))
+            .try_collect()
+            .await?;
+        let mut owned: HashMap<String, BTreeMap<Hash, u64>> = HashMap::new();
+        let mut trash: HashMap<String, BTreeMap<Hash, Tombstone>> = HashMap::new();
+        let tags: Vec<_> = self.blobs.client().tags().list().await?.try_collect().await?;
+        for tag in tags {
+            let Ok(name) = std::str::from_utf8(&tag.name.0) else {
+                continue;
+            };
+            let size = sizes.get(&tag.hash).copied().unwrap_or_default();
+            match parse_tag(name) {
+                Some((tenant, None)) => {
+                    owned
+                        .entry(tenant.to_string())
+                        .or_default()
+                        .insert(tag.hash, size);
+                }
+                Some((tenant, Some(deleted_at))) => {
+                    trash
+                        .entry(tenant.to_string())
+                        .or_default()
+                        .insert(tag.hash, Tombstone { size, deleted_at });
+                }
+                None => {}
+            }
+        }
+        *self.owned.write().unwrap() = owned;
+        *self.trash.write().unwrap() = trash;
+        Ok(())
+    }
+
+    /// Takes note of an upload or trash tag set on the store from elsewhere, like an
+    /// imported archive. Other tags are ignored.
+    pub fn adopt(&self, tag: &str, hash: Hash, size: u64) {
+        match parse_tag(tag) {
+            Some((tenant, None)) => {
+                self.owned
+                    .write()
+                    .unwrap()
+                    .entry(tenant.to_string())
+                    .or_default()
+                    .insert(hash, size);
+            }
+            Some((tenant, Some(deleted_at))) => {
+                self.trash
+                    .write()
+                    .unwrap()
+                    .entry(tenant.to_string())
+                    .or_default()
+                    .insert(hash, Tombstone { size, deleted_at });
+            }
+            None => {}
+        }
+    }
+
+    /// Drops `hash` from the tenant's uploads and trash alike.
+    pub async fn drop_blob(&self, name: &str, hash: Hash) -> Result<()> {
+        self.release(name, hash).await?;
+        let tombstone = self
+            .trash
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|trash| trash.get(&hash).copied());
+        if let Some(tombstone) = tombstone {
+            self.forget(name, hash, tombstone).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes `hash` from the tenant's trash for good, for garbage collection to take.
+    async fn forget(&self, name: &str, hash: Hash, tombstone: Tombstone) -> Result<()> {
+        self.blobs
+            .client()
+            .tags()
+            .delete(trash_tag(name, &hash, tombstone.deleted_at))
+            .await?;
+        if let Some(trash) = self.trash.write().unwrap().get_mut(name) {
+            trash.remove(&hash);
+        }
+        Ok(())
+    }
+
+    /// Drops everything `name` owns and has in the trash, returning how many uploads
+    /// it owned.
+    pub async fn purge(&self, name: &str) -> Result<usize> {
+        let owned = self.blobs(name);
+        for (hash, _) in &owned {
+            self.release(name, *hash).await?;
+        }
+        for (hash, tombstone) in self.trash(name) {
+            self.forget(name, hash, tombstone).await?;
+        }
+        Ok(owned.len())
+    }
+
+    async fn empty_trash_periodically(self) {
+        let mut ticker = tokio::time::interval(TRASH_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let cutoff = now_secs().saturating_sub(self.grace_secs);
+            let expired: Vec<(String, Hash, Tombstone)> = self
+                .trash
+                .read()
+                .unwrap()
+                .iter()
+                .flat_map(|(name, trash)| {
+                    trash
+                        .iter()
+                        .filter(|(_, tombstone)| tombstone.deleted_at <= cutoff)
+                        .map(|(hash, tombstone)| (name.clone(), *hash, *tombstone))
+                })
+                .collect();
+            for (name, hash, tombstone) in expired {
+                if let Err(err) = self.forget(&name, hash, tombstone).await {
+                    println!(
+                        "Failed to empty {} from the trash of {}: {}",
+                        hash, name, err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Puts everything the tenant owns into a collection, kept under
+    /// `tenant-export/<name>` until the next export, so another gateway can fetch it.
+    async fn export(&self, name: &str) -> Result<(Hash, usize)> {
+        let collection: Collection = self
+            .blobs(name)
+            .into_iter()
+            .map(|(hash, _)| (hash.to_string(), hash))
+            .collect();
+        let len = collection.len();
+        let tag = Tag::from(format!("{}{}", EXPORT_TAG_PREFIX, name));
+        let (hash, _) = self
+            .blobs
+            .client()
+            .create_collection(collection, SetTagOption::Named(tag), Vec::new())
+            .await?;
+        Ok((hash, len))
+    }
+
+    /// Fetches a collection made by [`Tenancy::export`] and claims its blobs for the
+    /// tenant. Quotas don't apply, the operator moves tenants around.
+    async fn import(&self, app_state: &AppState, name: &str, ticket: &BlobTicket) -> Result<usize> {
+        let tag = Tag::from(format!("{}{}", IMPORT_TAG_PREFIX, name));
+        app_state
+            .fetcher
+            .fetch_tagged(
+                ticket.hash(),
+                BlobFormat::HashSeq,
+                vec![ticket.node_addr().clone()],
+                SetTagOption::Named(tag.clone()),
+            )
+            .await?;
+        let blobs_client = self.blobs.client();
+        let collection = blobs_client.get_collection(ticket.hash()).await?;
+        for (_, hash) in collection.iter() {
+            let size = match blobs_client.status(*hash).await? {
+                BlobStatus::Complete { size } => size,
+                _ => bail!("{} was not fetched completely", hash),
+            };
+            self.claim(name, *hash, size).await?;
+        }
+        // The blobs are kept by the tenant's tags now
+        blobs_client.tags().delete(tag).await?;
+        Ok(collection.len())
+    }
+
+    /// The tenant and role a request to `route` is made with, and the route without the
+    /// prefixes, or the status refusing it. API keys go before the session a request may
+    /// also be signed in with.
+    fn authorize(
+        &self,
+        route: &str,
+        headers: &HeaderMap,
+        session: Option<&SessionUser>,
+    ) -> Result<(Tenant, String, String), StatusCode> {
+        let route = route.strip_prefix(&self.base_path).unwrap_or(route);
+        let (by_path, route) = match route.strip_prefix(PATH_PREFIX) {
+            Some(rest) => {
+                let (name, rest) = rest.split_once('/').unwrap_or((rest, ""));
+                (Some(name.to_string()), format!("/{}", rest))
+            }
+            None => (None, route.to_string()),
+        };
+
+        // Keys mean nothing on gateways without them, where only sessions get here
+        let key = api_key(headers).filter(|_| self.is_enabled());
+        let access = self.access.get();
+        let role = |key: &str, default: &str| {
+            self.roles
+                .of_key(key)
+                .unwrap_or_else(|| default.to_string())
+        };
+        let (tenant, role) = match (key, session) {
+            (Some(key), _) if access.admin_keys.contains(key) => (by_path, role(key, ADMIN)),
+            (Some(key), _) => {
+                let tenant = access.keys.get(key).ok_or(StatusCode::UNAUTHORIZED)?;
+                if by_path.as_ref().is_some_and(|name| name != tenant) {
+                    return Err(StatusCode::FORBIDDEN);
+                }
+                (Some(tenant.clone()), role(key, UPLOADER))
+            }
+            // Users of the web UI act as their tenant, own their uploads themselves, or
+            // act as the operator when their role reaches admin routes
+            (None, Some(user)) => match user.owner() {
+                Some(owner) if by_path.as_ref().is_some_and(|name| *name != owner) => {
+                    return Err(StatusCode::FORBIDDEN)
+                }
+                Some(owner) => (Some(owner), user.role.clone()),
+                None => (by_path, user.role.clone()),
+            },
+            // Tenants without keys can be used by anyone knowing the path
+            (None, None) => match by_path {
+                Some(name)
+                    if access
+                        .tenants
+                        .get(&name)
+                        .is_some_and(|tenant| tenant.api_keys.is_empty()) =>
+                {
+                    (Some(name), UPLOADER.to_string())
+                }
+                // Without a key nobody is the operator, also when no admin key is set
+                _ => return Err(StatusCode::UNAUTHORIZED),
+            },
+        };
+        Ok((Tenant(tenant), route, role))
+    }
+}
+
+/// The key a request authenticates with, from `Authorization: Bearer` or `X-Api-Key`.
+/// S3 clients send it as the access key id of their signature, which isn't checked.
+pub fn api_key(headers: &HeaderMap) -> Option<&str> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+    let access_key_id = || {
+        authorization?
+            .strip_prefix("AWS4-HMAC-SHA256 ")?
+            .split(',')
+            .find_map(|part| part.trim().strip_prefix("Credential="))?
+            .split('/')
+            .next()
+    };
+    bearer
+        .or_else(|| headers.get("x-api-key")?.to_str().ok())
+        .or_else(access_key_id)
+        .map(str::trim)
+}
+
+/// Whether a request comes from the host itself. Requests passed on by a proxy that
+/// isn't trusted look local too, so they don't count when they say they were.
+fn is_local(ip: Option<IpAddr>, headers: &HeaderMap) -> bool {
+    ip.is_some_and(|ip| ip.is_loopback())
+        && !headers.contains_key("x-forwarded-for")
+        && !headers.contains_key(header::FORWARDED)
+}
+
+/// Middleware working out the [`Tenant`] of a request and keeping tenants to their
+/// routes and blobs, and requests to the routes their role allows. Blobs of other
+/// tenants are answered with 404, as if they didn't exist.
+pub async fn apply(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let tenancy = &app_state.tenancy;
+    let matched = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    // Signed in users own their uploads even on gateways without keys
+    if !tenancy.is_enabled() && request.extensions().get::<SessionUser>().is_none() {
+        let local = is_local(client.ip, request.headers());
+        if !tenancy.allows_keyless(&matched, request.method(), local) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        return next.run(request).await;
+    }
+    let session = request.extensions().get::<SessionUser>();
+    let (tenant, route, role) = match tenancy.authorize(&matched, request.headers(), session) {
+        Ok(authorized) => authorized,
+        Err(StatusCode::UNAUTHORIZED) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response()
+        }
+        Err(status) => return status.into_response(),
+    };
+    let group = tenancy.roles.group(&route, request.method());
+    if !tenancy.roles.allows(&role, group) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if let Some(name) = &tenant.0 {
+        if !TENANT_ROUTES.contains(&route.as_str()) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        // Hashes that don't parse are left for the handler to refuse, tickets go by the
+        // hash they name. Deleted uploads aren't owned anymore, restoring them checks the
+        // tenant's trash instead.
+        if (route.starts_with("/blob/") || route.starts_with("/raw/") || route == "/verify/{hash}")
+            && route != "/blob/{hash}/restore"
+        {
+            let params = request.extract_parts::<RawPathParams>().await.ok();
+            let hash = params
+                .as_ref()
+                .and_then(|params| params.iter().find(|(name, _)| *name == "hash"))
+                .and_then(|(_, hash)| {
+                    parse_hash(hash)
+                        .ok()
+                        .or_else(|| Some(BlobTicket::from_str(hash).ok()?.hash()))
+                });
+            if hash.is_some_and(|hash| !tenancy.owns(name, &hash)) {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+        }
+    }
+    request.extensions_mut().insert(tenant);
+    next.run(request).await
+}
+
+#[derive(Serialize)]
+pub struct TenantUsage {
+    name: String,
+    blobs: usize,
+    bytes: u64,
+    quota_bytes: u64,
+}
+
+/// `GET /tenants`: what each tenant stores.
+pub async fn list_tenants(State(app_state): State<AppState>) -> Json<Vec<TenantUsage>> {
+    let tenancy = &app_state.tenancy;
+    let mut usage: Vec<TenantUsage> = tenancy
+        .access
+        .get()
+        .tenants
+        .values()
+        .map(|tenant| {
+            let blobs = tenancy.blobs(&tenant.name);
+            TenantUsage {
+                name: tenant.name.clone(),
+                blobs: blobs.len(),
+                bytes: blobs.iter().map(|(_, size)| size).sum(),
+                quota_bytes: tenant.quota_bytes,
+            }
+        })
+        .collect();
+    usage.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(usage)
+}
+
+fn known_tenant(app_state: &AppState, name: &str) -> Result<(), StatusCode> {
+    if app_state.tenancy.access.get().tenants.contains_key(name) {
+        Ok(())
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// `DELETE /blob/{hash}`: a tenant dropping one of its uploads, or the operator one of
+/// the blobs automatic tags keep. It goes to the trash for the grace period first.
+pub async fn delete_blob(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Path(hash): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if app_state.holds.is_held(&hash) {
+        return Err(StatusCode::LOCKED);
+    }
+    // A pin would keep the deleted blob around, outside the tenant's quota
+    if app_state
+        .pins
+        .get(&hash)
+        .is_some_and(|pin| pin.owner == tenant.0)
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+    // The operator's uploads are the blobs kept by automatic tags
+    let (name, discarded) = match &tenant.0 {
+        Some(name) => (name.as_str(), app_state.tenancy.discard(name, hash).await),
+        None => (OPERATOR, app_state.tenancy.discard_own(hash).await),
+    };
+    match discarded {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            println!("Failed to release {} of {}: {}", hash, name, err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// `POST /blob/{hash}/restore`: undoes a delete within the grace period.
+pub async fn restore_blob(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+    Path(hash): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let name = tenant.0.as_deref().unwrap_or(OPERATOR);
+    // The space the delete freed may have been taken by uploads since
+    if let Some(tombstone) = app_state.tenancy.tombstone(name, &hash) {
+        app_state.tenancy.check_quota(&tenant, &hash, tombstone.size)?;
+    }
+    match app_state.tenancy.restore(name, hash).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            println!("Failed to restore {} of {}: {}", hash, name, err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TrashedBlob {
+    hash: String,
+    tenant: String,
+    size: u64,
+    deleted_at: u64,
+    expires_at: u64,
+}
+
+/// `GET /trash`: deleted uploads that can still be restored, the tenant's own or those
+/// of every tenant and the operator's, under `~`.
+pub async fn list_trash(
+    State(app_state): State<AppState>,
+    tenant: Tenant,
+) -> Json<Vec<TrashedBlob>> {
+    let tenancy = &app_state.tenancy;
+    let names = match tenant.0 {
+        Some(name) => vec![name],
+        None => {
+            let mut names = tenancy.names();
+            names.push(OPERATOR.to_string());
+            names
+        }
+    };
+    let mut trash: Vec<TrashedBlob> = names
+        .into_iter()
+        .flat_map(|name| {
+            tenancy
+                .trash(&name)
+                .into_iter()
+                .map(move |(hash, tombstone)| TrashedBlob {
+                    hash: hash.to_string(),
+                    tenant: name.clone(),
+                    size: tombstone.size,
+                    deleted_at: tombstone.deleted_at,
+                    expires_at: tombstone.deleted_at + tenancy.grace_secs,
+                })
+        })
+        .collect();
+    trash.sort_by_key(|blob| blob.deleted_at);
+    Json(trash)
+}
+
+/// `DELETE /tenants/{name}/blobs`: drops everything a tenant owns, e.g. once it has
+/// been moved to another gateway.
+pub async fn purge_tenant(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    known_tenant(&app_state, &name)?;
+    if app_state.tenancy.holds_any(&name, &app_state.holds) {
+        return Err(StatusCode::LOCKED);
+    }
+    let released = app_state.tenancy.purge(&name).await.map_err(|err| {
+        println!("Failed to release the blobs of {}: {}", name, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    app_state.pins.unpin_owned_by(&name).await.map_err(|err| {
+        println!("Failed to drop the pins of {}: {}", name, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    // An export would keep the blobs around too
+    let export_tag = Tag::from(format!("{}{}", EXPORT_TAG_PREFIX, name));
+    app_state
+        .blobs
+        .client()
+        .tags()
+        .delete(export_tag)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(serde_json::json!({ "released": released })))
+}
+
+/// `POST /tenants/{name}/export`: a ticket for a collection of everything the tenant
+/// owns, for `POST /tenants/{name}/import` on another gateway.
+pub async fn export_tenant(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    known_tenant(&app_state, &name)?;
+    let (hash, blobs) = app_state.tenancy.export(&name).await.map_err(|err| {
+        println!("Failed to export tenant {}: {}", name, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let ticket = BlobTicket::new(app_state.node_id.into(), hash, BlobFormat::HashSeq)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(serde_json::json!({
+        "hash": hash.to_string(),
+        "ticket": ticket.to_string(),
+        "blobs": blobs,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    ticket: String,
+}
+
+/// `POST /tenants/{name}/import`: fetches an export of a tenant and gives its blobs to
+/// the tenant here.
+pub async fn import_tenant(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<ImportRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    known_tenant(&app_state, &name)?;
+    let ticket = BlobTicket::from_str(&request.ticket).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if ticket.format() != BlobFormat::HashSeq {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let blobs = app_state
+        .tenancy
+        .import(&app_state, &name, &ticket)
+        .await
+        .map_err(|err| {
+            println!("Failed to import tenant {}: {}", name, err);
+            StatusCode::BAD_GATEWAY
+        })?;
+    Ok(Json(serde_json::json!({ "blobs": blobs })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::Endpoint;
+    use iroh_blobs::util::local_pool::LocalPool;
+    use std::path::PathBuf;
+
+    const TENANCY: &str = r#"
+        admin_keys = ["admin-key"]
+
+        [[tenants]]
+        name = "photos"
+        api_keys = ["photos-key"]
+        quota_bytes = 10
+
+        [[tenants]]
+        name = "docs"
+        api_keys = ["docs-key"]
+
+        [[tenants]]
+        name = "public"
+    "#;
+
+    const ROLES: &str = r#"
+        [keys]
+        "docs-key" = "reader"
+    "#;
+
+    /// A store of its own in the temp directory, removed when dropped.
+    struct Store {
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        dir: PathBuf,
+        _local_pool: LocalPool,
+    }
+
+    impl Drop for Store {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    async fn tenancy(base_path: &str, grace_secs: u64) -> (Tenancy, Store) {
+        configured(TENANCY, ROLES, base_path, grace_secs).await
+    }
+
+    async fn configured(
+        tenancy: &str,
+        roles: &str,
+        base_path: &str,
+        grace_secs: u64,
+    ) -> (Tenancy, Store) {
+        let dir = std::env::temp_dir().join(format!("iroh-api-test-{}", rand::random::<u64>()));
+        let endpoint = Endpoint::builder()
+            .relay_mode(iroh::RelayMode::Disabled)
+            .clear_discovery()
+            .bind()
+            .await
+            .unwrap();
+        let local_pool = LocalPool::default();
+        let blobs = Blobs::persistent(&dir)
+            .await
+            .unwrap()
+            .build(&local_pool, &endpoint);
+        let config: TenancyConfig = toml::from_str(tenancy).unwrap();
+        let roles_config: RolesConfig = toml::from_str(roles).unwrap();
+        let roles = Roles::new(&roles_config).unwrap();
+        let tenancy = Tenancy::load(&config, roles, &roles_config, base_path, &blobs, grace_secs)
+            .await
+            .unwrap();
+        let store = Store {
+            blobs,
+            dir,
+            _local_pool: local_pool,
+        };
+        (tenancy, store)
+    }
+
+    fn key(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", key).parse().unwrap());
+        headers
+    }
+
+    fn user(tenant: Option<&str>, role: &str, admin: bool) -> SessionUser {
+        SessionUser {
+            username: "alice".to_string(),
+            tenant: tenant.map(str::to_string),
+            role: role.to_string(),
+            admin,
+        }
+    }
+
+    async fn add(store: &Store, data: &'static [u8]) -> (Hash, u64) {
+        let added = store.blobs.client().add_bytes(data).await.unwrap();
+        (added.hash, added.size)
+    }
+
+    #[tokio::test]
+    async fn keys_pick_their_tenant_and_role() {
+        let (tenancy, _store) = tenancy("/api", 0).await;
+
+        let (tenant, route, role) = tenancy
+            .authorize("/api/blobs", &key("photos-key"), None)
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("photos"));
+        assert_eq!(route, "/blobs");
+        assert_eq!(role, UPLOADER);
+
+        let (tenant, _, role) = tenancy
+            .authorize("/api/blobs", &key("admin-key"), None)
+            .unwrap();
+        assert_eq!(tenant.0, None);
+        assert_eq!(role, ADMIN);
+
+        // Admin keys may act as any tenant by path
+        let (tenant, route, _) = tenancy
+            .authorize("/api/t/photos/upload", &key("admin-key"), None)
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("photos"));
+        assert_eq!(route, "/upload");
+
+        // Roles of keys go before the default of tenant keys
+        let (_, _, role) = tenancy
+            .authorize("/api/blobs", &key("docs-key"), None)
+            .unwrap();
+        assert_eq!(role, "reader");
+
+        // The S3 access key id counts as the key
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            "AWS4-HMAC-SHA256 Credential=admin-key/20250101/us-east-1/s3/aws4_request, SignedHeaders=host, Signature=00"
+                .parse()
+                .unwrap(),
+        );
+        let (tenant, _, role) = tenancy.authorize("/api/s3", &headers, None).unwrap();
+        assert_eq!(tenant.0, None);
+        assert_eq!(role, ADMIN);
+    }
+
+    #[tokio::test]
+    async fn wrong_or_missing_keys_are_refused() {
+        let (tenancy, _store) = tenancy("", 0).await;
+
+        let refused = |route: &str, headers: &HeaderMap| {
+            tenancy.authorize(route, headers, None).map(|_| ()).unwrap_err()
+        };
+        assert_eq!(refused("/blobs", &key("nobody")), StatusCode::UNAUTHORIZED);
+        assert_eq!(refused("/t/docs/blobs", &key("photos-key")), StatusCode::FORBIDDEN);
+        assert_eq!(refused("/blobs", &HeaderMap::new()), StatusCode::UNAUTHORIZED);
+        assert_eq!(refused("/t/photos/blobs", &HeaderMap::new()), StatusCode::UNAUTHORIZED);
+
+        // Tenants without keys are open to anyone knowing the path
+        let (tenant, route, role) = tenancy
+            .authorize("/t/public/blobs", &HeaderMap::new(), None)
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("public"));
+        assert_eq!(route, "/blobs");
+        assert_eq!(role, UPLOADER);
+    }
+
+    #[tokio::test]
+    async fn sessions_act_as_their_owner() {
+        let (tenancy, _store) = tenancy("", 0).await;
+
+        let alice = user(None, UPLOADER, false);
+        let (tenant, _, role) = tenancy
+            .authorize("/blobs", &HeaderMap::new(), Some(&alice))
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("~alice"));
+        assert_eq!(role, UPLOADER);
+        let refused = tenancy
+            .authorize("/t/photos/blobs", &HeaderMap::new(), Some(&alice))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(refused, StatusCode::FORBIDDEN);
+
+        let member = user(Some("photos"), "reader", false);
+        let (tenant, _, role) = tenancy
+            .authorize("/t/photos/blobs", &HeaderMap::new(), Some(&member))
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("photos"));
+        assert_eq!(role, "reader");
+
+        let admin = user(None, ADMIN, true);
+        let (tenant, _, _) = tenancy
+            .authorize("/blobs", &HeaderMap::new(), Some(&admin))
+            .unwrap();
+        assert_eq!(tenant.0, None);
+
+        // Keys go before the session
+        let (tenant, _, _) = tenancy
+            .authorize("/blobs", &key("docs-key"), Some(&alice))
+            .unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("docs"));
+    }
+
+    #[tokio::test]
+    async fn gateways_without_keys_keep_admin_routes_local() {
+        let (tenancy, _store) = configured("", "", "/api", 0).await;
+        assert!(!tenancy.is_enabled());
+
+        for (route, method) in [
+            ("/api/upload", Method::POST),
+            ("/api/blob/{hash}", Method::GET),
+            ("/api/blob/{hash}", Method::DELETE),
+        ] {
+            assert!(tenancy.allows_keyless(route, &method, false), "{}", route);
+        }
+        for (route, method) in [
+            ("/api/admin/reload", Method::POST),
+            ("/api/admin/runtime", Method::GET),
+            ("/api/admin/export-all", Method::POST),
+            ("/api/store/compact", Method::POST),
+            ("/api/tenants", Method::GET),
+            ("/api/users/{name}", Method::PUT),
+        ] {
+            assert!(!tenancy.allows_keyless(route, &method, false), "{}", route);
+            assert!(tenancy.allows_keyless(route, &method, true), "{}", route);
+        }
+
+        let (tenancy, _store) = configured("", r#"routes = { "/tenants" = "read" }"#, "", 0).await;
+        assert!(tenancy.allows_keyless("/tenants", &Method::GET, false));
+    }
+
+    #[test]
+    fn only_loopback_requests_are_local() {
+        let loopback = Some(IpAddr::from([127, 0, 0, 1]));
+        assert!(is_local(loopback, &HeaderMap::new()));
+        assert!(is_local(Some("::1".parse().unwrap()), &HeaderMap::new()));
+        assert!(!is_local(Some(IpAddr::from([192, 168, 1, 2])), &HeaderMap::new()));
+        assert!(!is_local(None, &HeaderMap::new()));
+
+        // A proxy on the same host passes on requests from anywhere
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert!(!is_local(loopback, &headers));
+        let mut headers = HeaderMap::new();
+        headers.insert(header::FORWARDED, "for=203.0.113.7".parse().unwrap());
+        assert!(!is_local(loopback, &headers));
+    }
+
+    #[tokio::test]
+    async fn reloading_replaces_keys() {
+        let (tenancy, _store) = tenancy("", 0).await;
+        let config: TenancyConfig = toml::from_str(
+            r#"
+            [[tenants]]
+            name = "photos"
+            api_keys = ["new-key"]
+            "#,
+        )
+        .unwrap();
+        let roles = RoleTable::new(&RolesConfig::default()).unwrap();
+        tenancy.set_access(Access::new(&config, &roles).unwrap());
+
+        let refused = tenancy
+            .authorize("/blobs", &key("photos-key"), None)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(refused, StatusCode::UNAUTHORIZED);
+        let (tenant, _, _) = tenancy.authorize("/blobs", &key("new-key"), None).unwrap();
+        assert_eq!(tenant.0.as_deref(), Some("photos"));
+        let refused = tenancy
+            .authorize("/blobs", &key("admin-key"), None)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(refused, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn roles_of_unknown_keys_are_refused() {
+        let config: TenancyConfig = toml::from_str(TENANCY).unwrap();
+        let roles = RoleTable::new(
+            &toml::from_str(
+                r#"
+                [keys]
+                "stray-key" = "reader"
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(Access::new(&config, &roles).is_err());
+    }
+
+    #[tokio::test]
+    async fn quotas_count_owned_content_once() {
+        let (tenancy, store) = tenancy("", 0).await;
+        let photos = Tenant(Some("photos".to_string()));
+        let (first, size) = add(&store, b"0123456").await;
+        let (second, _) = add(&store, b"abcdefg").await;
+
+        assert_eq!(tenancy.check_quota(&photos, &first, size), Ok(()));
+        tenancy.record(&photos, first, size).await.unwrap();
+        assert!(tenancy.owns("photos", &first));
+        assert_eq!(tenancy.blobs("photos"), vec![(first, size)]);
+
+        // 7 more bytes would take it to 14 of 10, but storing the same again is free
+        assert_eq!(
+            tenancy.check_quota(&photos, &second, size),
+            Err(StatusCode::INSUFFICIENT_STORAGE)
+        );
+        assert_eq!(tenancy.check_quota(&photos, &first, size), Ok(()));
+
+        // Other tenants and the operator have quotas of their own, or none
+        let docs = Tenant(Some("docs".to_string()));
+        assert_eq!(tenancy.check_quota(&docs, &second, 1 << 30), Ok(()));
+        assert_eq!(tenancy.check_quota(&Tenant(None), &second, 1 << 30), Ok(()));
+
+        // Releasing frees the space again
+        assert!(tenancy.release("photos", first).await.unwrap());
+        assert!(!tenancy.owns("photos", &first));
+        assert_eq!(tenancy.check_quota(&photos, &second, size), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn trash_keeps_deletions_until_restored() {
+        let (tenancy, store) = tenancy("", 3600).await;
+        let photos = Tenant(Some("photos".to_string()));
+        let (hash, size) = add(&store, b"0123456").await;
+        tenancy.record(&photos, hash, size).await.unwrap();
+
+        assert!(tenancy.discard("photos", hash).await.unwrap());
+        assert!(!tenancy.owns("photos", &hash));
+        assert_eq!(tenancy.trashed("photos"), vec![hash]);
+        assert_eq!(tenancy.tombstone("photos", &hash).map(|t| t.size), Some(size));
+        // Deleting what the tenant doesn't own is a no-op
+        assert!(!tenancy.discard("photos", hash).await.unwrap());
+        assert!(!tenancy.discard("docs", hash).await.unwrap());
+
+        // Tags are the record, so a rescan finds the same
+        tenancy.rescan().await.unwrap();
+        assert_eq!(tenancy.trashed("photos"), vec![hash]);
+
+        assert!(tenancy.restore("photos", hash).await.unwrap());
+        assert!(tenancy.owns("photos", &hash));
+        assert!(tenancy.trashed("photos").is_empty());
+        assert!(!tenancy.restore("photos", hash).await.unwrap());
+
+        // Purging drops owned blobs and the trash alike
+        assert!(tenancy.discard("photos", hash).await.unwrap());
+        tenancy.purge("photos").await.unwrap();
+        assert!(tenancy.trashed("photos").is_empty());
+        assert!(!tenancy.restore("photos", hash).await.unwrap());
+    }
+
+    async fn auto_tags(store: &Store, hash: Hash) -> usize {
+        let tags: Vec<_> = store
+            .blobs
+            .client()
+            .tags()
+            .list()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        tags.iter()
+            .filter(|tag| tag.hash == hash && tag.name.0.starts_with(AUTO_TAG_PREFIX.as_bytes()))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn the_operator_deletes_its_own_uploads() {
+        let (tenancy, store) = configured("", "", "", 3600).await;
+        let (hash, size) = add(&store, b"0123456").await;
+        assert_eq!(auto_tags(&store, hash).await, 1);
+
+        assert!(tenancy.discard_own(hash).await.unwrap());
+        assert_eq!(auto_tags(&store, hash).await, 0);
+        assert_eq!(tenancy.trashed(OPERATOR), vec![hash]);
+        assert_eq!(tenancy.tombstone(OPERATOR, &hash).map(|t| t.size), Some(size));
+        assert!(!tenancy.discard_own(hash).await.unwrap());
+
+        assert!(tenancy.restore(OPERATOR, hash).await.unwrap());
+        assert_eq!(auto_tags(&store, hash).await, 1);
+        assert!(tenancy.trashed(OPERATOR).is_empty());
+        // Restoring doesn't make the operator a tenant owning it
+        assert!(tenancy.owners(&hash).is_empty());
+
+        // Tenant uploads aren't the operator's
+        let photos = Tenant(Some("photos".to_string()));
+        let (other, size) = add(&store, b"abcdefg").await;
+        assert!(tenancy.discard_own(other).await.unwrap());
+        tenancy.record(&photos, other, size).await.unwrap();
+        assert!(!tenancy.discard_own(other).await.unwrap());
+        assert!(tenancy.owns("photos", &other));
+
+        let (tenancy, store) = configured("", "", "", 0).await;
+        let (hash, _) = add(&store, b"0123456").await;
+        assert!(tenancy.discard_own(hash).await.unwrap());
+        assert_eq!(auto_tags(&store, hash).await, 0);
+        assert!(tenancy.trashed(OPERATOR).is_empty());
+    }
+
+    #[tokio::test]
+    async fn without_grace_period_deletions_skip_the_trash() {
+        let (tenancy, store) = tenancy("", 0).await;
+        let photos = Tenant(Some("photos".to_string()));
+        let (hash, size) = add(&store, b"0123456").await;
+        tenancy.record(&photos, hash, size).await.unwrap();
+
+        assert!(tenancy.discard("photos", hash).await.unwrap());
+        assert!(!tenancy.owns("photos", &hash));
+        assert!(tenancy.trashed("photos").is_empty());
+        assert!(!tenancy.restore("photos", hash).await.unwrap());
+    }
+}
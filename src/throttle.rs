@@ -0,0 +1,270 @@
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use quinn_proto::congestion::{Controller, ControllerFactory, CubicConfig};
+use quinn_proto::RttEstimator;
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::BandwidthConfig;
+use crate::reload::Live;
+
+/// Smallest congestion window a capped connection is allowed, so it never stalls.
+const MIN_WINDOW: u64 = 2 * 1200;
+
+/// Token bucket holding up to one second worth of bytes.
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// A limiter for `bytes_per_sec`, or `None` when the rate is unlimited.
+    pub fn new(bytes_per_sec: u64) -> Option<Arc<Self>> {
+        if bytes_per_sec == 0 {
+            return None;
+        }
+        let bytes_per_sec = bytes_per_sec as f64;
+        Some(Arc::new(Self {
+            bytes_per_sec,
+            bucket: Mutex::new((bytes_per_sec, Instant::now())),
+        }))
+    }
+
+    /// Waits until `bytes` fit in the budget. The bucket may go into debt, so a large
+    /// chunk goes through right away and the ones after it wait longer.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (tokens, last) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.bytes_per_sec)
+                .min(self.bytes_per_sec);
+            *last = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// The limiters one transfer draws from: the global budget for its direction and a
+/// budget of its own.
+#[derive(Clone, Default)]
+pub struct Limiters(Vec<Arc<RateLimiter>>);
+
+impl Limiters {
+    pub async fn consume(&self, bytes: usize) {
+        for limiter in self.0.iter() {
+            limiter.consume(bytes).await;
+        }
+    }
+
+    /// Paces a body stream through the limiters.
+    pub fn stream<S, E>(self, stream: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        stream.then(move |chunk| {
+            let limiters = self.clone();
+            async move {
+                if let Ok(bytes) = &chunk {
+                    limiters.consume(bytes.len()).await;
+                }
+                chunk
+            }
+        })
+    }
+}
+
+/// Bandwidth caps for HTTP transfers.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    budgets: Live<Budgets>,
+}
+
+#[derive(Default)]
+struct Budgets {
+    upload: Option<Arc<RateLimiter>>,
+    download: Option<Arc<RateLimiter>>,
+    per_connection: u64,
+}
+
+impl Budgets {
+    fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            upload: RateLimiter::new(config.upload),
+            download: RateLimiter::new(config.download),
+            per_connection: config.per_connection,
+        }
+    }
+
+    fn limiters(&self, global: &Option<Arc<RateLimiter>>) -> Limiters {
+        Limiters(
+            global
+                .iter()
+                .cloned()
+                .chain(RateLimiter::new(self.per_connection))
+                .collect(),
+        )
+    }
+}
+
+impl Throttle {
+    pub fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            budgets: Live::new(Budgets::new(config)),
+        }
+    }
+
+    /// Switches to new caps. Transfers already running keep the ones they started with.
+    pub fn reload(&self, config: &BandwidthConfig) {
+        self.budgets.set(Budgets::new(config));
+    }
+
+    /// Limiters for data sent to a client.
+    pub fn upload(&self) -> Limiters {
+        let budgets = self.budgets.get();
+        budgets.limiters(&budgets.upload)
+    }
+
+    /// Limiters for data received from a client.
+    pub fn download(&self) -> Limiters {
+        let budgets = self.budgets.get();
+        budgets.limiters(&budgets.download)
+    }
+}
+
+/// Caps how fast QUIC connections send, by keeping the congestion window below the
+/// bandwidth-delay product of the allowed rate.
+///
+/// The global cap is split evenly between the open connections.
+#[derive(Debug)]
+pub struct SendCap {
+    global: u64,
+    per_connection: u64,
+    connections: AtomicUsize,
+    inner: Arc<CubicConfig>,
+}
+
+impl SendCap {
+    /// A congestion controller factory for the config, or `None` when sending is unlimited.
+    pub fn new(config: &BandwidthConfig) -> Option<Arc<Self>> {
+        if config.upload == 0 && config.per_connection == 0 {
+            return None;
+        }
+        Some(Arc::new(Self {
+            global: config.upload,
+            per_connection: config.per_connection,
+            connections: AtomicUsize::new(0),
+            inner: Arc::new(CubicConfig::default()),
+        }))
+    }
+
+    fn bytes_per_sec(&self) -> u64 {
+        let share = match self.global {
+            0 => u64::MAX,
+            global => global / self.connections.load(Ordering::Relaxed).max(1) as u64,
+        };
+        match self.per_connection {
+            0 => share,
+            per_connection => share.min(per_connection),
+        }
+    }
+}
+
+impl ControllerFactory for SendCap {
+    fn build(self: Arc<Self>, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        Box::new(CappedController {
+            inner: self.inner.clone().build(now, current_mtu),
+            rtt: Duration::from_millis(100),
+            cap: self,
+        })
+    }
+}
+
+struct CappedController {
+    inner: Box<dyn Controller>,
+    rtt: Duration,
+    cap: Arc<SendCap>,
+}
+
+impl Drop for CappedController {
+    fn drop(&mut self) {
+        self.cap.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Controller for CappedController {
+    fn on_sent(&mut self, now: Instant, bytes: u64, last_packet_number: u64) {
+        self.inner.on_sent(now, bytes, last_packet_number);
+    }
+
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.rtt = rtt.get();
+        self.inner.on_ack(now, sent, bytes, app_limited, rtt);
+    }
+
+    fn on_end_acks(
+        &mut self,
+        now: Instant,
+        in_flight: u64,
+        app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        self.inner
+            .on_end_acks(now, in_flight, app_limited, largest_packet_num_acked);
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        lost_bytes: u64,
+    ) {
+        self.inner
+            .on_congestion_event(now, sent, is_persistent_congestion, lost_bytes);
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.inner.on_mtu_update(new_mtu);
+    }
+
+    fn window(&self) -> u64 {
+        let cap = (self.cap.bytes_per_sec() as f64 * self.rtt.as_secs_f64()) as u64;
+        self.inner.window().min(cap.max(MIN_WINDOW))
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        self.cap.connections.fetch_add(1, Ordering::Relaxed);
+        Box::new(CappedController {
+            inner: self.inner.clone_box(),
+            rtt: self.rtt,
+            cap: self.cap.clone(),
+        })
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.inner.initial_window()
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
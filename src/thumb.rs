@@ -0,0 +1,169 @@
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::TryStreamExt;
+use image::{ImageFormat, ImageReader};
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::rpc::client::blobs::ReadAtLen;
+use iroh_blobs::util::Tag;
+use iroh_blobs::Hash;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use crate::blob::{etag, etag_matches, make_local, not_modified, parse_hash};
+use crate::AppState;
+
+/// Thumbnail sizes, in pixels along the longer side. Requested sizes are rounded up to
+/// one of these, so a blob has a handful of thumbnails at most.
+const SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+const DEFAULT_SIZE: u32 = 256;
+/// Larger images are not decoded at all.
+const MAX_SOURCE_BYTES: u64 = 64 * 1024 * 1024;
+/// Nor are images whose pixels would take more memory than this once decoded.
+const MAX_DECODED_BYTES: u64 = 512 * 1024 * 1024;
+/// Bytes at the start of a blob looked at to tell whether it is an image.
+const MAGIC_LEN: u64 = 32;
+const TAG_PREFIX: &str = "thumb/";
+
+fn thumb_tag(hash: &Hash, size: u32) -> Tag {
+    Tag::from(format!("{}{}/{}", TAG_PREFIX, hash, size))
+}
+
+/// Resized versions of image blobs, stored as blobs of their own.
+///
+/// Each thumbnail is kept under a tag naming its original and size, so they survive
+/// restarts and garbage collection. The index is rebuilt from those tags on start.
+#[derive(Clone)]
+pub struct Thumbnails {
+    index: Arc<Mutex<HashMap<(Hash, u32), Hash>>>,
+}
+
+impl Thumbnails {
+    pub async fn load(blobs: &Blobs<iroh_blobs::store::fs::Store>) -> Result<Self> {
+        let tags: Vec<_> = blobs.client().tags().list().await?.try_collect().await?;
+        let index = tags
+            .into_iter()
+            .filter_map(|tag| {
+                let name = std::str::from_utf8(&tag.name.0).ok()?;
+                let (original, size) = name.strip_prefix(TAG_PREFIX)?.split_once('/')?;
+                let original = parse_hash(original).ok()?;
+                Some(((original, size.parse().ok()?), tag.hash))
+            })
+            .collect();
+        Ok(Self {
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// The thumbnail of `hash` at `size`, made and stored on first use. `None` when the
+    /// blob is no image this can decode.
+    async fn get(&self, app_state: &AppState, hash: Hash, size: u32) -> Result<Option<Hash>> {
+        if let Some(thumb) = self.index.lock().unwrap().get(&(hash, size)) {
+            return Ok(Some(*thumb));
+        }
+
+        let blobs_client = app_state.blobs.client();
+        let magic = blobs_client
+            .read_at_to_bytes(hash, 0, ReadAtLen::AtMost(MAGIC_LEN))
+            .await?;
+        if image::guess_format(&magic).is_err() {
+            return Ok(None);
+        }
+        let mut reader = blobs_client.read(hash).await?;
+        if reader.size() > MAX_SOURCE_BYTES {
+            return Ok(None);
+        }
+        let data = reader.read_to_bytes().await?;
+        let Some(thumb) = tokio::task::spawn_blocking(move || render(&data, size)).await? else {
+            return Ok(None);
+        };
+        let thumb = blobs_client
+            .add_bytes_named(thumb, thumb_tag(&hash, size))
+            .await?
+            .hash;
+        self.index.lock().unwrap().insert((hash, size), thumb);
+        Ok(Some(thumb))
+    }
+}
+
+/// Scales an image down to fit `size`, as PNG when it has transparency and JPEG
+/// otherwise. `None` when `data` is no supported image.
+fn render(data: &[u8], size: u32) -> Option<Bytes> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?;
+    reader.format()?;
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(MAX_DECODED_BYTES);
+    reader.limits(limits);
+    let image = reader.decode().ok()?.thumbnail(size, size);
+
+    let mut out = Cursor::new(Vec::new());
+    if image.color().has_alpha() {
+        image.write_to(&mut out, ImageFormat::Png).ok()?;
+    } else {
+        image.to_rgb8().write_to(&mut out, ImageFormat::Jpeg).ok()?;
+    }
+    Some(Bytes::from(out.into_inner()))
+}
+
+#[derive(Deserialize)]
+pub struct ThumbParams {
+    size: Option<u32>,
+}
+
+/// `GET /blob/{hash}/thumb?size=`: a thumbnail of an image blob, at most `size` pixels
+/// wide and high.
+pub async fn get_thumbnail(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    Query(params): Query<ThumbParams>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = parse_hash(&hash)?;
+    let requested = params.size.unwrap_or(DEFAULT_SIZE);
+    let size = SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1]);
+    if let Some(redirect) = make_local(&app_state, hash, &format!("/thumb?size={}", size)).await? {
+        return Ok(redirect.into_response());
+    }
+
+    let thumb = app_state
+        .thumbnails
+        .get(&app_state, hash, size)
+        .await
+        .map_err(|err| {
+            println!("Failed to make thumbnail of {}: {}", hash, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&thumb)) == Some(true) {
+        return Ok(not_modified(&thumb));
+    }
+    let data = app_state
+        .blobs
+        .client()
+        .read_to_bytes(thumb)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let content_type = match image::guess_format(&data) {
+        Ok(ImageFormat::Png) => "image/png",
+        _ => "image/jpeg",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::ETAG, etag(&thumb)),
+        ],
+        data,
+    )
+        .into_response())
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::P2pTimeoutsConfig;
+
+/// The part of an outbound transfer that took too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Connect,
+    FirstByte,
+    Transfer,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Connect => "connecting",
+            Phase::FirstByte => "waiting for the first byte",
+            Phase::Transfer => "transferring",
+        })
+    }
+}
+
+/// Returned when an outbound fetch or push ran out of time.
+#[derive(Debug, Clone, Copy)]
+pub struct TimedOut {
+    pub phase: Phase,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timed out {} after {}s",
+            self.phase,
+            self.after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Time limits on blob fetches from and pushes to remote nodes, `None` for no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub transfer: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new(config: &P2pTimeoutsConfig) -> Self {
+        let limit = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        Self {
+            connect: limit(config.connect_secs),
+            first_byte: limit(config.first_byte_secs),
+            transfer: limit(config.transfer_secs),
+        }
+    }
+
+    /// Starts the clock on a transfer.
+    pub fn start(&self) -> Clock {
+        Clock {
+            timeouts: *self,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// The time left for one transfer, counted from [`Timeouts::start`].
+pub struct Clock {
+    timeouts: Timeouts,
+    started: Instant,
+}
+
+impl Clock {
+    /// Waits for `future`, a step of `phase`, failing with [`TimedOut`] once the phase
+    /// took too long or the transfer as a whole did, whichever comes first.
+    pub async fn within<T>(
+        &self,
+        phase: Phase,
+        future: impl Future<Output = T>,
+    ) -> Result<T, TimedOut> {
+        let phase_limit = match phase {
+            Phase::Connect => self.timeouts.connect,
+            Phase::FirstByte => self.timeouts.first_byte,
+            Phase::Transfer => None,
+        };
+        let phase_deadline = phase_limit.map(|limit| (Instant::now() + limit, phase, limit));
+        let transfer_deadline = self
+            .timeouts
+            .transfer
+            .map(|limit| (self.started + limit, Phase::Transfer, limit));
+        let deadline = match (phase_deadline, transfer_deadline) {
+            (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+            (a, b) => a.or(b),
+        };
+        match deadline {
+            Some((at, phase, after)) => tokio::time::timeout_at(at, future)
+                .await
+                .map_err(|_| TimedOut { phase, after }),
+            None => Ok(future.await),
+        }
+    }
+}
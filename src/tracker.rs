@@ -0,0 +1,232 @@
+use anyhow::Result;
+use futures::StreamExt;
+use iroh::{Endpoint, NodeId};
+use iroh_base::Signature;
+use iroh_blobs::net_protocol::Blobs;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::TrackerConfig;
+use crate::timeouts::{Phase, Timeouts};
+
+/// ALPN of the iroh content tracker protocol.
+///
+/// Requests and responses are postcard encoded, one per stream. Announcements are signed
+/// by the announcing node, which must be the node on the connection.
+pub const ALPN: &[u8] = b"n0/tracker/1";
+
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+enum AnnounceKind {
+    // Only complete blobs are announced, this keeps the wire format
+    #[allow(dead_code)]
+    Partial,
+    Complete,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Announce {
+    host: NodeId,
+    content: HashAndFormat,
+    kind: AnnounceKind,
+    /// Microseconds since the Unix epoch.
+    timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SignedAnnounce {
+    announce: Announce,
+    signature: Signature,
+}
+
+impl SignedAnnounce {
+    fn verify(&self) -> Result<()> {
+        let bytes = postcard::to_allocvec(&self.announce)?;
+        self.announce.host.verify(&bytes, &self.signature)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct QueryFlags {
+    complete: bool,
+    verified: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Query {
+    content: HashAndFormat,
+    flags: QueryFlags,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct QueryResponse {
+    hosts: Vec<SignedAnnounce>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Request {
+    Announce(SignedAnnounce),
+    Query(Query),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Response {
+    QueryResponse(QueryResponse),
+}
+
+/// Talks to the configured content trackers: tells them which blobs this node holds,
+/// and asks them who holds a blob nobody gave a ticket for.
+#[derive(Clone)]
+pub struct Tracker {
+    endpoint: Endpoint,
+    timeouts: Timeouts,
+    trackers: Arc<Vec<NodeId>>,
+}
+
+impl Tracker {
+    /// Starts announcing the tagged blobs of the store to the trackers every
+    /// `announce_interval_secs`. Does nothing without trackers.
+    pub fn spawn(
+        endpoint: Endpoint,
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        timeouts: Timeouts,
+        config: &TrackerConfig,
+    ) -> Self {
+        let tracker = Self {
+            endpoint,
+            timeouts,
+            trackers: Arc::new(config.trackers.clone()),
+        };
+        if !tracker.trackers.is_empty() {
+            let interval = Duration::from_secs(config.announce_interval_secs.max(1));
+            tokio::spawn(tracker.clone().announce_periodically(blobs, interval));
+            println!("Announcing blobs to {} trackers", tracker.trackers.len());
+        }
+        tracker
+    }
+
+    async fn announce_periodically(
+        self,
+        blobs: Blobs<iroh_blobs::store::fs::Store>,
+        interval: Duration,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let content = match tagged_content(&blobs).await {
+                Ok(content) => content,
+                Err(err) => {
+                    println!("Failed to list blobs to announce: {}", err);
+                    continue;
+                }
+            };
+            for tracker in self.trackers.iter() {
+                let mut failed = 0;
+                for content in &content {
+                    if let Err(err) = self.announce(*tracker, *content).await {
+                        failed += 1;
+                        if failed == 1 {
+                            println!("Failed to announce to tracker {}: {:#}", tracker, err);
+                        }
+                    }
+                }
+                if failed > 1 {
+                    println!("{} announcements to tracker {} failed", failed, tracker);
+                }
+            }
+        }
+    }
+
+    async fn announce(&self, tracker: NodeId, content: HashAndFormat) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let announce = Announce {
+            host: self.endpoint.node_id(),
+            content,
+            kind: AnnounceKind::Complete,
+            timestamp,
+        };
+        let signature = self
+            .endpoint
+            .secret_key()
+            .sign(&postcard::to_allocvec(&announce)?);
+        let request = Request::Announce(SignedAnnounce {
+            announce,
+            signature,
+        });
+        self.request(tracker, &request).await?;
+        Ok(())
+    }
+
+    /// Asks every tracker for nodes holding `hash` completely. Answers are checked
+    /// against their signatures, trackers that fail are skipped.
+    pub async fn query(&self, hash: Hash, format: BlobFormat) -> Vec<NodeId> {
+        let request = Request::Query(Query {
+            content: HashAndFormat { hash, format },
+            flags: QueryFlags {
+                complete: true,
+                verified: false,
+            },
+        });
+        let mut hosts = BTreeSet::new();
+        for tracker in self.trackers.iter() {
+            let response = match self.request(*tracker, &request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    println!(
+                        "Failed to query tracker {} for {}: {:#}",
+                        tracker, hash, err
+                    );
+                    continue;
+                }
+            };
+            let Ok(Response::QueryResponse(response)) = postcard::from_bytes(&response) else {
+                println!("Tracker {} sent an invalid response", tracker);
+                continue;
+            };
+            for host in response.hosts {
+                if host.verify().is_ok() && host.announce.content.hash == hash {
+                    hosts.insert(host.announce.host);
+                }
+            }
+        }
+        hosts.remove(&self.endpoint.node_id());
+        hosts.into_iter().collect()
+    }
+
+    async fn request(&self, tracker: NodeId, request: &Request) -> Result<Vec<u8>> {
+        let clock = self.timeouts.start();
+        let conn = clock
+            .within(Phase::Connect, self.endpoint.connect(tracker, ALPN))
+            .await??;
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&postcard::to_allocvec(request)?).await?;
+        send.finish()?;
+        let response = clock
+            .within(Phase::FirstByte, recv.read_to_end(MAX_MESSAGE_SIZE))
+            .await??;
+        conn.close(0u32.into(), b"done");
+        Ok(response)
+    }
+}
+
+/// The distinct content tags in the store point at, what this node keeps on purpose.
+pub async fn tagged_content(blobs: &Blobs<iroh_blobs::store::fs::Store>) -> Result<Vec<HashAndFormat>> {
+    let mut tags = blobs.client().tags().list().await?;
+    let mut content = BTreeSet::new();
+    while let Some(tag) = tags.next().await {
+        let tag = tag?;
+        content.insert(HashAndFormat {
+            hash: tag.hash,
+            format: tag.format,
+        });
+    }
+    Ok(content.into_iter().collect())
+}
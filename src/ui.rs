@@ -0,0 +1,56 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse},
+};
+use iroh_blobs::ticket::BlobTicket;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rust_embed::RustEmbed;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::AppState;
+
+/// The web UI, built into the binary from `ui/`.
+#[derive(RustEmbed)]
+#[folder = "ui/"]
+struct Assets;
+
+/// `GET /`: the web UI. Its links are relative to `<base>`, which is pointed at
+/// wherever the API is served.
+pub async fn index(State(app_state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    let page = Assets::get("index.html").ok_or(StatusCode::NOT_FOUND)?;
+    let page = String::from_utf8_lossy(&page.data).replace(
+        "<base href=\"/\">",
+        &format!("<base href=\"{}/\">", app_state.proxy.base_path()),
+    );
+    Ok(Html(page))
+}
+
+/// `GET /ui/{*path}`: scripts and styles of the web UI.
+pub async fn asset(Path(path): Path<String>) -> Result<impl IntoResponse, StatusCode> {
+    let asset = Assets::get(&path).ok_or(StatusCode::NOT_FOUND)?;
+    let content_type = match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], asset.data))
+}
+
+#[derive(Deserialize)]
+pub struct QrParams {
+    ticket: String,
+}
+
+/// `GET /qr?ticket=`: a blob ticket as a QR code, for fetching from a phone.
+pub async fn ticket_qr(Query(params): Query<QrParams>) -> Result<impl IntoResponse, StatusCode> {
+    // Only tickets are rendered, the endpoint is no general purpose QR code service
+    BlobTicket::from_str(&params.ticket).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let code = QrCode::new(&params.ticket).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], image))
+}
@@ -0,0 +1,514 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{multipart::Field, Multipart, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use bao_tree::blake3;
+use futures::{Stream, StreamExt};
+use iroh_blobs::rpc::client::blobs::BlobStatus;
+use iroh_blobs::util::Tag;
+use iroh_blobs::{ticket::BlobTicket, BlobFormat, Hash};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::announce::Announcement;
+use crate::blob::parse_hash;
+use crate::gossip::parse_node_id;
+use crate::idempotency::{self, Claim};
+use crate::policy;
+use crate::proxy::ClientInfo;
+use crate::receipt::Receipt;
+use crate::tenancy::Tenant;
+use crate::throttle::Limiters;
+use crate::AppState;
+
+/// Seconds clients are asked to wait when every ingest slot is taken.
+const RETRY_AFTER_SECS: u64 = 2;
+/// Room left for the multipart framing around a file when telling from the
+/// `Content-Length` of an upload that the file is too large.
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+/// Longest file read into memory when uploads have no `max_file_size`.
+pub const MAX_BUFFERED_SIZE: u64 = 1 << 30;
+
+/// The most bytes of a file read into memory: `max_file_size`, or
+/// [`MAX_BUFFERED_SIZE`] without one.
+pub fn max_buffered_len(max_file_size: u64) -> u64 {
+    match max_file_size {
+        0 => MAX_BUFFERED_SIZE,
+        max => max,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UploadResponse {
+    pub ticket: String,
+    pub node_id: String,
+    pub blob_hash: String,
+    pub blob_format: String,
+    /// Where the blob can be downloaded over HTTP, when this gateway serves it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    /// Signed by the node that stored the blob, see `POST /receipts/verify`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Receipt>,
+}
+
+pub async fn upload_file(
+    State(app_state): State<AppState>, // Extract shared state
+    client: ClientInfo,               // Who is uploading, seen through trusted proxies
+    tenant: Tenant,                   // Whose upload it is, when tenants share the gateway
+    headers: HeaderMap,
+    multipart: Multipart,             // Extract multipart form data
+) -> Result<Response, Response> {
+    // Files that can't fit under the size limit are refused before reading anything
+    let max_file_size = max_buffered_len(app_state.upload.get().max_file_size);
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_file_size.saturating_add(MULTIPART_OVERHEAD)) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+
+    // Retries of an upload made with an Idempotency-Key get the original response
+    let ttl_secs = app_state.upload.get().idempotency_ttl_secs;
+    let key = idempotency::key(&headers).map_err(IntoResponse::into_response)?;
+    let pending = match key.filter(|_| ttl_secs > 0).map(|key| tenant.scope(key)) {
+        Some(key) => match app_state
+            .idempotency
+            .claim(key, ttl_secs)
+            .map_err(IntoResponse::into_response)?
+        {
+            Claim::Replay(response) => {
+                return Ok(([(idempotency::REPLAYED_HEADER, "true")], Json(response)).into_response())
+            }
+            Claim::New(pending) => Some(pending),
+        },
+        None => None,
+    };
+
+    // The slot is held while the body is read too, bounding the memory uploads take up
+    let _slot = app_state.ingest_slots.clone().try_acquire_owned().map_err(|_| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+        )
+            .into_response()
+    })?;
+    let mut response = receive(&app_state, &client, &tenant, multipart)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // Blobs stored here or on a cluster member can be downloaded through this gateway
+    let stored_by = parse_node_id(&response.node_id);
+    if stored_by == Some(app_state.node_id)
+        || stored_by.is_some_and(|node_id| app_state.cluster.is_member(&node_id))
+    {
+        response.download_url = client.url(&format!("/blob/{}", response.blob_hash));
+    }
+    if let Some(pending) = pending {
+        pending.complete(&response);
+    }
+    Ok(Json(response).into_response())
+}
+
+async fn receive(
+    app_state: &AppState,
+    client: &ClientInfo,
+    tenant: &Tenant,
+    mut multipart: Multipart,
+) -> Result<UploadResponse, StatusCode> {
+    let read_timeout = Duration::from_secs(app_state.upload.get().read_timeout_secs.max(1));
+    let next_field = tokio::time::timeout(read_timeout, multipart.next_field())
+        .await
+        .map_err(|_| StatusCode::REQUEST_TIMEOUT)?;
+    if let Some(mut field) = next_field.map_err(|err| err.status())? {
+        let file_name = field.file_name().map(str::to_string);
+        let data = read_field(app_state, &mut field, read_timeout).await?;
+        let (hash, size) = (Hash::new(&data), data.len() as u64);
+        app_state.tenancy.check_quota(tenant, &hash, size)?;
+        let uploader = tenant
+            .0
+            .clone()
+            .or_else(|| client.ip.map(|ip| ip.to_string()));
+        let response = store(
+            app_state,
+            client.ip,
+            uploader,
+            file_name,
+            data,
+            tenant.tag(&hash),
+        )
+        .await?;
+        if let Err(err) = app_state.tenancy.record(tenant, hash, size).await {
+            println!("Failed to record upload {} for {:?}: {}", hash, tenant.0, err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        return Ok(response);
+    }
+
+    // Return a bad request error if no file is uploaded
+    Err(StatusCode::BAD_REQUEST)
+}
+
+/// Stores an upload from `ip` where it belongs: with the cluster member owning it, with
+/// an upstream gateway, or here under the tag `name` when given. `uploader` goes into
+/// the receipt.
+pub async fn store(
+    app_state: &AppState,
+    ip: Option<IpAddr>,
+    uploader: Option<String>,
+    file_name: Option<String>,
+    data: Bytes,
+    name: Option<Tag>,
+) -> Result<UploadResponse, StatusCode> {
+    // In cluster mode the blob is stored by the member owning its hash
+    if app_state.cluster.is_enabled() {
+        if let Some(owner) = app_state.cluster.remote_owner(&Hash::new(&data)) {
+            match app_state
+                .forwarder
+                .forward_to_node(owner, uploader.clone(), file_name.clone(), data.clone())
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(err) => println!("Failed to hand upload to owner {}: {}", owner, err),
+            }
+        }
+    }
+
+    // Edge nodes and nodes short on disk hand the upload to an upstream gateway
+    if app_state.forwarder.should_forward() {
+        return app_state
+            .forwarder
+            .forward(uploader, file_name, data)
+            .await
+            .map_err(|_| StatusCode::BAD_GATEWAY);
+    }
+    if let Some(ip) = ip {
+        println!("Upload from {}", ip);
+    }
+    ingest_named(app_state, uploader, file_name, data, name).await
+}
+
+/// Reads an upload body chunk by chunk so it can be held to the download bandwidth cap,
+/// giving up with 408 on clients that stall or trickle and with 413 once the file is
+/// larger than the upload policy allows, or [`MAX_BUFFERED_SIZE`] without a policy.
+async fn read_field(
+    app_state: &AppState,
+    field: &mut Field<'_>,
+    read_timeout: Duration,
+) -> Result<Bytes, StatusCode> {
+    let max_len = max_buffered_len(app_state.upload.get().max_file_size);
+    let mut data = Vec::new();
+    read_body(app_state, field, read_timeout, max_len, |chunk| {
+        data.extend_from_slice(chunk)
+    })
+    .await?;
+    Ok(Bytes::from(data))
+}
+
+/// Hands the chunks of a request body to `on_chunk` as they arrive, with the limits of
+/// [`read_field`]. Bodies growing past `max_len` bytes (0 for no limit) are given up on
+/// with 413 before the chunk going over is handed on. Returns the body length.
+pub async fn read_body<S, E>(
+    app_state: &AppState,
+    body: S,
+    read_timeout: Duration,
+    max_len: u64,
+    on_chunk: impl FnMut(&Bytes),
+) -> Result<u64, StatusCode>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    read_limited(
+        app_state.throttle.download(),
+        app_state.upload.get().min_bytes_per_sec,
+        body,
+        read_timeout,
+        max_len,
+        on_chunk,
+    )
+    .await
+}
+
+async fn read_limited<S, E>(
+    limiters: Limiters,
+    min_bytes_per_sec: u64,
+    mut body: S,
+    read_timeout: Duration,
+    max_len: u64,
+    mut on_chunk: impl FnMut(&Bytes),
+) -> Result<u64, StatusCode>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let mut len = 0;
+    // Only time spent waiting on the client counts, not time held back by the cap
+    let mut reading = Duration::ZERO;
+    loop {
+        let started = Instant::now();
+        let chunk = tokio::time::timeout(read_timeout, body.next())
+            .await
+            .map_err(|_| StatusCode::REQUEST_TIMEOUT)?
+            .transpose()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        reading += started.elapsed();
+        let Some(chunk) = chunk else {
+            break;
+        };
+        len += chunk.len() as u64;
+        if max_len > 0 && len > max_len {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        on_chunk(&chunk);
+        if min_bytes_per_sec > 0
+            && reading >= read_timeout
+            && (len as f64) < min_bytes_per_sec as f64 * reading.as_secs_f64()
+        {
+            return Err(StatusCode::REQUEST_TIMEOUT);
+        }
+        limiters.consume(chunk.len()).await;
+    }
+    Ok(len)
+}
+
+#[derive(Serialize)]
+pub struct HashResponse {
+    blob_hash: String,
+    blob_format: String,
+    size: u64,
+    /// The ticket an upload of the body would get, naming the cluster member that
+    /// would store it.
+    ticket: String,
+    /// Whether this gateway already holds the blob, so the upload can be skipped.
+    stored: bool,
+}
+
+/// Hashes a raw request body the way an upload would be hashed. Returns the hash and
+/// the body length.
+async fn hash_request_body(app_state: &AppState, body: Body) -> Result<(Hash, u64), StatusCode> {
+    let read_timeout = Duration::from_secs(app_state.upload.get().read_timeout_secs.max(1));
+    let mut hasher = blake3::Hasher::new();
+    let size = read_body(app_state, body.into_data_stream(), read_timeout, 0, |chunk| {
+        hasher.update(chunk);
+    })
+    .await?;
+    Ok((Hash::from(hasher.finalize()), size))
+}
+
+/// `POST /hash`: hashes the raw request body like an upload would, without storing
+/// anything, so clients can check for duplicates first.
+pub async fn hash_body(
+    State(app_state): State<AppState>,
+    body: Body,
+) -> Result<Json<HashResponse>, StatusCode> {
+    let (hash, size) = hash_request_body(&app_state, body).await?;
+
+    let node_id = app_state
+        .cluster
+        .remote_owner(&hash)
+        .unwrap_or(app_state.node_id);
+    let ticket = BlobTicket::new(node_id.into(), hash, BlobFormat::Raw)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let stored = app_state
+        .blobs
+        .client()
+        .has(hash)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(HashResponse {
+        blob_hash: hash.to_string(),
+        blob_format: BlobFormat::Raw.to_string(),
+        size,
+        ticket: ticket.to_string(),
+        stored,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    blob_hash: String,
+    actual_hash: String,
+    size: u64,
+    matches: bool,
+}
+
+/// `POST /verify/{hash}`: whether the raw request body is the content of `hash`, for
+/// checking data obtained out of band. A blob ticket can be given instead of the hash.
+/// Nothing is stored.
+pub async fn verify_body(
+    State(app_state): State<AppState>,
+    Path(hash): Path<String>,
+    body: Body,
+) -> Result<Json<VerifyResponse>, StatusCode> {
+    let expected = match BlobTicket::from_str(&hash) {
+        Ok(ticket) => ticket.hash(),
+        Err(_) => parse_hash(&hash)?,
+    };
+    let (actual, size) = hash_request_body(&app_state, body).await?;
+    Ok(Json(VerifyResponse {
+        blob_hash: expected.to_string(),
+        actual_hash: actual.to_string(),
+        size,
+        matches: actual == expected,
+    }))
+}
+
+/// Adds uploaded bytes to the local store, announces and replicates them, and
+/// returns the ticket for the new blob with a receipt naming `uploader`.
+pub async fn ingest(
+    app_state: &AppState,
+    uploader: Option<String>,
+    file_name: Option<String>,
+    data: Bytes,
+) -> Result<UploadResponse, StatusCode> {
+    ingest_named(app_state, uploader, file_name, data, None).await
+}
+
+/// Like [`ingest`], but keeps the blob under the tag `name` instead of an automatic one
+/// when given.
+pub async fn ingest_named(
+    app_state: &AppState,
+    uploader: Option<String>,
+    file_name: Option<String>,
+    data: Bytes,
+    name: Option<Tag>,
+) -> Result<UploadResponse, StatusCode> {
+    // Uploads outside the policy, infected or refused ones never make it into the store
+    policy::check(&app_state.upload.get(), file_name.as_deref(), &data)?;
+    app_state.antivirus.check(&data).await?;
+    app_state
+        .screening
+        .check_ingest(file_name.as_deref(), &data)
+        .await?;
+    app_state
+        .plugins
+        .check_ingest(file_name.as_deref(), &data)
+        .await?;
+
+    let blobs_client = app_state.blobs.client();
+    let size = data.len();
+    let duplicate = matches!(
+        blobs_client.status(Hash::new(&data)).await,
+        Ok(BlobStatus::Complete { .. })
+    );
+    let counted = data.clone();
+
+    // Attempt to add the bytes to the blob store
+    let blob = match name {
+        Some(name) => blobs_client.add_bytes_named(data, name).await,
+        None => blobs_client.add_bytes(data).await,
+    }
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    app_state.stats.record_upload(&counted, duplicate);
+
+    let node_id: iroh::PublicKey = app_state.node_id;
+
+    // Attempt to generate the ticket
+    let ticket = BlobTicket::new(node_id.into(), blob.hash, blob.format)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let receipt = Receipt::sign(
+        app_state.endpoint.secret_key(),
+        blob.hash,
+        blob.size,
+        uploader,
+    )
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    println!(
+        "Received file: {} ({} bytes)",
+        file_name.as_deref().unwrap_or("unknown"),
+        size
+    );
+
+    publish(app_state, &ticket, blob.size, file_name, "upload").await;
+    app_state
+        .replicator
+        .replicate(&app_state.jobs, blob.hash, blob.format);
+
+    // Return the response with ticket, node_id, blob.hash, and blob.format
+    Ok(UploadResponse {
+        ticket: ticket.to_string(),
+        node_id: node_id.to_string(),
+        blob_hash: blob.hash.to_string(),
+        blob_format: blob.format.to_string(),
+        download_url: None,
+        receipt: Some(receipt),
+    })
+}
+
+/// Makes a blob new to the store known the way uploads are: announced on the announce
+/// topic and sent to `/events` subscribers as `blob_added`, with the `source` it came
+/// from.
+pub async fn publish(
+    app_state: &AppState,
+    ticket: &BlobTicket,
+    size: u64,
+    file_name: Option<String>,
+    source: &'static str,
+) {
+    let announcement = Announcement {
+        ticket: ticket.to_string(),
+        node_id: app_state.node_id.to_string(),
+        blob_hash: ticket.hash().to_string(),
+        blob_format: ticket.format().to_string(),
+        size,
+        file_name,
+    };
+    if let Err(err) = app_state.announcer.announce(&announcement).await {
+        println!("Failed to announce {}: {}", ticket.hash(), err);
+    }
+    app_state.events.send(
+        "blob_added",
+        serde_json::json!({
+            "hash": announcement.blob_hash,
+            "format": announcement.blob_format,
+            "size": size,
+            "file_name": announcement.file_name,
+            "source": source,
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BandwidthConfig;
+    use crate::throttle::Throttle;
+
+    async fn read(chunks: &[usize], max_len: u64) -> Result<u64, StatusCode> {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = chunks
+            .iter()
+            .map(|&len| Ok(Bytes::from(vec![0; len])))
+            .collect();
+        let mut handed_on = 0;
+        let len = read_limited(
+            Throttle::new(&BandwidthConfig::default()).download(),
+            0,
+            futures::stream::iter(chunks),
+            Duration::from_secs(5),
+            max_len,
+            |chunk| handed_on += chunk.len() as u64,
+        )
+        .await?;
+        assert_eq!(handed_on, len);
+        Ok(len)
+    }
+
+    #[tokio::test]
+    async fn bodies_past_the_cap_are_refused() {
+        assert_eq!(read(&[400, 600], 1000).await, Ok(1000));
+        assert_eq!(read(&[400, 601], 1000).await, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(read(&[4000], 0).await, Ok(4000));
+    }
+
+    #[test]
+    fn uploads_without_a_limit_are_capped_in_memory() {
+        assert_eq!(max_buffered_len(0), MAX_BUFFERED_SIZE);
+        assert_eq!(max_buffered_len(1 << 20), 1 << 20);
+        assert_eq!(max_buffered_len(4 << 30), 4 << 30);
+    }
+}
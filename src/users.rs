@@ -0,0 +1,527 @@
+use anyhow::{Context, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::config::{RouteGroup, SessionsConfig};
+use crate::csrf::{self, SESSION_COOKIE};
+use crate::jobs::now_secs;
+use crate::persist::StateFile;
+use crate::proxy::ClientInfo;
+use crate::roles::Roles;
+use crate::AppState;
+
+const MAX_USERNAME_LEN: usize = 64;
+const MIN_PASSWORD_LEN: usize = 8;
+
+fn is_valid_username(name: &str) -> bool {
+    (1..=MAX_USERNAME_LEN).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '@')
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("{}", err))
+        .context("Failed to hash the password")?;
+    Ok(hash.to_string())
+}
+
+/// Unknown users are checked against this, so they take as long to turn away as wrong
+/// passwords and don't give away which usernames exist.
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("not the password of anyone").unwrap_or_default())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredUser {
+    username: String,
+    /// In the PHC string format, with the parameters and salt it was hashed with.
+    password_hash: String,
+    tenant: Option<String>,
+    /// Empty for users created before roles, who get the default role.
+    #[serde(default)]
+    role: String,
+    created_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct UserInfo {
+    username: String,
+    tenant: Option<String>,
+    role: String,
+    created_at: u64,
+}
+
+impl From<&StoredUser> for UserInfo {
+    fn from(user: &StoredUser) -> Self {
+        Self {
+            username: user.username.clone(),
+            tenant: user.tenant.clone(),
+            role: user.role.clone(),
+            created_at: user.created_at,
+        }
+    }
+}
+
+struct Session {
+    username: String,
+    expires_at: u64,
+}
+
+/// The tenant uploads of a user are owned by when it has none: a namespace of its own,
+/// which can't clash with configured tenants since their names have no `~`.
+pub fn owner(username: &str) -> String {
+    format!("~{}", username)
+}
+
+/// The user a request is signed in as, put in its extensions by [`apply`].
+#[derive(Clone, Debug)]
+pub struct SessionUser {
+    pub username: String,
+    pub tenant: Option<String>,
+    pub role: String,
+    /// Whether the role reaches admin routes.
+    pub admin: bool,
+}
+
+impl SessionUser {
+    /// Who owns what the user uploads, which tenancy treats as the request's tenant.
+    /// `None` for admins without a tenant, who act as the operator.
+    pub fn owner(&self) -> Option<String> {
+        match &self.tenant {
+            Some(tenant) => Some(tenant.clone()),
+            None if self.admin => None,
+            None => Some(owner(&self.username)),
+        }
+    }
+}
+
+/// Accounts for the web UI, which signs in with a username and password and is then
+/// known by a session cookie. Programmatic clients keep using API keys.
+///
+/// Every upload of a user is owned by it, or by its tenant when it has one. Users only
+/// see and delete what they own, the same way tenants do.
+///
+/// Users are kept in `data/users.json` with their passwords hashed with argon2.
+/// Sessions only live in memory, so a restart signs everyone out. They are found by
+/// the hash of their cookie, the cookie itself is never kept.
+#[derive(Clone)]
+pub struct Users {
+    file: StateFile,
+    ttl_secs: u64,
+    registration: bool,
+    roles: Roles,
+    users: Arc<RwLock<BTreeMap<String, StoredUser>>>,
+    sessions: Arc<RwLock<HashMap<Hash, Session>>>,
+}
+
+impl Users {
+    pub fn load(config: &SessionsConfig, roles: Roles, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut users = BTreeMap::new();
+        if path.exists() {
+            let saved: Vec<StoredUser> = serde_json::from_slice(&std::fs::read(&path)?)?;
+            for mut user in saved {
+                if user.role.is_empty() {
+                    user.role = roles.default_user_role();
+                }
+                users.insert(user.username.clone(), user);
+            }
+        }
+        let users = Arc::new(RwLock::new(users));
+        let file = StateFile::spawn(path, {
+            let users = users.clone();
+            move || to_json(&users.read().unwrap())
+        });
+        Ok(Self {
+            file,
+            ttl_secs: config.ttl_secs,
+            registration: config.registration,
+            roles,
+            users,
+            sessions: Arc::default(),
+        })
+    }
+
+    /// Creates a user, or replaces its password, tenant and role, if given. Its sessions
+    /// end either way. Returns the user, and whether it is new.
+    async fn set(
+        &self,
+        username: &str,
+        password: String,
+        tenant: Option<String>,
+        role: Option<String>,
+    ) -> Result<(UserInfo, bool)> {
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+        let mut users = self.users.write().unwrap();
+        let previous = users.get(username);
+        let created_at = previous.map_or_else(now_secs, |user| user.created_at);
+        let role = role
+            .or_else(|| previous.map(|user| user.role.clone()))
+            .unwrap_or_else(|| self.roles.default_user_role());
+        let user = StoredUser {
+            username: username.to_string(),
+            password_hash,
+            tenant,
+            role,
+            created_at,
+        };
+        let info = UserInfo::from(&user);
+        let previous = users.insert(username.to_string(), user);
+        self.file.changed();
+        self.end_sessions(username);
+        Ok((info, previous.is_none()))
+    }
+
+    /// Creates a user of its own, unless the name is taken already.
+    async fn create(&self, username: &str, password: String) -> Result<Option<UserInfo>> {
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(username) {
+            return Ok(None);
+        }
+        let user = StoredUser {
+            username: username.to_string(),
+            password_hash,
+            tenant: None,
+            role: self.roles.default_user_role(),
+            created_at: now_secs(),
+        };
+        let info = UserInfo::from(&user);
+        users.insert(username.to_string(), user);
+        self.file.changed();
+        Ok(Some(info))
+    }
+
+    fn remove(&self, username: &str) -> bool {
+        let mut users = self.users.write().unwrap();
+        if users.remove(username).is_none() {
+            return false;
+        }
+        self.file.changed();
+        self.end_sessions(username);
+        true
+    }
+
+    fn list(&self) -> Vec<UserInfo> {
+        self.users
+            .read()
+            .unwrap()
+            .values()
+            .map(UserInfo::from)
+            .collect()
+    }
+
+    /// Checks a password, on a blocking thread since argon2 is slow on purpose.
+    async fn check(&self, username: &str, password: String) -> Option<UserInfo> {
+        let user = self.users.read().unwrap().get(username).cloned();
+        let hash = user.as_ref().map_or_else(
+            || dummy_hash().to_string(),
+            |user| user.password_hash.clone(),
+        );
+        let verified = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        user.filter(|_| verified).as_ref().map(UserInfo::from)
+    }
+
+    /// Starts a session for a user, returning the token for its cookie.
+    fn start_session(&self, username: &str) -> (String, u64) {
+        let token = csrf::new_token();
+        let now = now_secs();
+        let expires_at = now + self.ttl_secs;
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            Hash::new(&token),
+            Session {
+                username: username.to_string(),
+                expires_at,
+            },
+        );
+        (token, expires_at)
+    }
+
+    fn end_session(&self, token: &str) {
+        self.sessions.write().unwrap().remove(&Hash::new(token));
+    }
+
+    fn end_sessions(&self, username: &str) {
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|_, session| session.username != username);
+    }
+
+    /// The user signed in with the session cookie of a request, and when the session
+    /// ends, if it is still valid.
+    fn session(&self, headers: &HeaderMap) -> Option<(StoredUser, u64)> {
+        let token = csrf::cookie(headers, SESSION_COOKIE)?;
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions
+            .get(&Hash::new(token))
+            .filter(|session| session.expires_at > now_secs())?;
+        let user = self.users.read().unwrap().get(&session.username)?.clone();
+        Some((user, session.expires_at))
+    }
+}
+
+fn to_json(users: &BTreeMap<String, StoredUser>) -> Result<Vec<u8>> {
+    let users: Vec<&StoredUser> = users.values().collect();
+    Ok(serde_json::to_vec_pretty(&users)?)
+}
+
+/// Middleware putting the [`SessionUser`] of a request's session cookie in its
+/// extensions, for tenancy to authorize it with.
+pub async fn apply(State(users): State<Users>, mut request: Request, next: Next) -> Response {
+    if let Some((user, _)) = users.session(request.headers()) {
+        let admin = users.roles.allows(&user.role, RouteGroup::Admin);
+        request.extensions_mut().insert(SessionUser {
+            username: user.username,
+            tenant: user.tenant,
+            role: user.role,
+            admin,
+        });
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    user: UserInfo,
+    expires_at: u64,
+}
+
+/// `POST /login` with `{"username": ..., "password": ...}`: starts a session, kept in
+/// an `HttpOnly` cookie that is only sent back to the gateway itself.
+pub async fn login(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Json(request): Json<LoginRequest>,
+) -> Result<Response, StatusCode> {
+    let user = app_state
+        .users
+        .check(&request.username, request.password)
+        .await
+        .ok_or_else(|| {
+            let ip = client.ip.map(|ip| ip.to_string()).unwrap_or_default();
+            println!("Failed login as {:?} from {}", request.username, ip);
+            StatusCode::UNAUTHORIZED
+        })?;
+    let (token, expires_at) = app_state.users.start_session(&user.username);
+    let set_cookie = format!(
+        "{}={}; Max-Age={}; {}",
+        SESSION_COOKIE,
+        token,
+        app_state.users.ttl_secs,
+        csrf::cookie_attributes(&app_state, &client)
+    );
+    Ok((
+        [
+            (header::SET_COOKIE, set_cookie),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Json(SessionInfo { user, expires_at }),
+    )
+        .into_response())
+}
+
+/// `POST /logout`: ends the session of the request and clears its cookie.
+pub async fn logout(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(token) = csrf::cookie(&headers, SESSION_COOKIE) {
+        app_state.users.end_session(token);
+    }
+    let set_cookie = format!(
+        "{}=; Max-Age=0; {}",
+        SESSION_COOKIE,
+        csrf::cookie_attributes(&app_state, &client)
+    );
+    (StatusCode::NO_CONTENT, [(header::SET_COOKIE, set_cookie)]).into_response()
+}
+
+/// `GET /session`: who the request is signed in as, 401 without a valid session.
+pub async fn get_session(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let (user, expires_at) = app_state
+        .users
+        .session(&headers)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let user = UserInfo::from(&user);
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(SessionInfo { user, expires_at }),
+    )
+        .into_response())
+}
+
+/// `POST /register` with `{"username": ..., "password": ...}`: lets anyone create an
+/// account of their own, with `sessions.registration` on. Taken names get a 409.
+pub async fn register(
+    State(app_state): State<AppState>,
+    Json(request): Json<LoginRequest>,
+) -> Result<Response, StatusCode> {
+    let users = &app_state.users;
+    if !users.registration {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let username = request.username;
+    if !is_valid_username(&username) || request.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = users
+        .create(&username, request.password)
+        .await
+        .map_err(|err| {
+            println!("Failed to save user {}: {}", username, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::CONFLICT)?;
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UserRequest {
+    password: String,
+    tenant: Option<String>,
+    role: Option<String>,
+}
+
+/// `PUT /users/{name}` with `{"password": ..., "tenant": ..., "role": ...}`: creates a
+/// user of the web UI, or sets a new password. Users with a tenant act as it and share
+/// its uploads, admins act as the operator, the rest own their uploads themselves.
+/// Without a role, users keep theirs, or get the default role.
+pub async fn put_user(
+    State(app_state): State<AppState>,
+    Path(username): Path<String>,
+    Json(request): Json<UserRequest>,
+) -> Result<Response, StatusCode> {
+    if !is_valid_username(&username) || request.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(tenant) = &request.tenant {
+        if !app_state.tenancy.names().contains(tenant) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    if let Some(role) = &request.role {
+        if !app_state.users.roles.exists(role) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let (user, created) = app_state
+        .users
+        .set(&username, request.password, request.tenant, request.role)
+        .await
+        .map_err(|err| {
+            println!("Failed to save user {}: {}", username, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(user)).into_response())
+}
+
+/// `DELETE /users/{name}`: removes a user and signs it out. What it owns is let go,
+/// for garbage collection unless something else keeps it.
+pub async fn delete_user(
+    State(app_state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if app_state
+        .tenancy
+        .holds_any(&owner(&username), &app_state.holds)
+    {
+        return Err(StatusCode::LOCKED);
+    }
+    if !app_state.users.remove(&username) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    app_state
+        .tenancy
+        .purge(&owner(&username))
+        .await
+        .map_err(|err| {
+            println!("Failed to release the uploads of {}: {}", username, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    app_state
+        .pins
+        .unpin_owned_by(&owner(&username))
+        .await
+        .map_err(|err| {
+            println!("Failed to drop the pins of {}: {}", username, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+pub struct UserUsage {
+    #[serde(flatten)]
+    user: UserInfo,
+    /// What the user owns, unless it shares a tenant's uploads.
+    blobs: usize,
+    bytes: u64,
+}
+
+/// `GET /users`: the users of the web UI with what they store, without their password
+/// hashes.
+pub async fn list_users(State(app_state): State<AppState>) -> Json<Vec<UserUsage>> {
+    let users = app_state
+        .users
+        .list()
+        .into_iter()
+        .map(|user| {
+            let blobs = match &user.tenant {
+                Some(_) => Vec::new(),
+                None => app_state.tenancy.blobs(&owner(&user.username)),
+            };
+            UserUsage {
+                blobs: blobs.len(),
+                bytes: blobs.iter().map(|(_, size)| size).sum(),
+                user,
+            }
+        })
+        .collect();
+    Json(users)
+}
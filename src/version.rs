@@ -0,0 +1,62 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::config::{Config, ForwardMode};
+use crate::AppState;
+
+/// What this build is and which optional parts of the gateway are switched on, for
+/// support to tell what someone is running.
+#[derive(Serialize)]
+pub struct VersionInfo {
+    version: &'static str,
+    /// `unknown` when built outside a git checkout without `GIT_COMMIT` set.
+    git_commit: &'static str,
+    iroh: &'static str,
+    iroh_blobs: &'static str,
+    features: Vec<&'static str>,
+}
+
+/// The optional parts `config` switches on, as they were at startup.
+pub fn features(config: &Config) -> Vec<&'static str> {
+    [
+        ("tls", config.http.tls_cert.is_some()),
+        ("http3", config.http.http3),
+        ("announce", config.announce.topic.is_some()),
+        ("dir_sync", !config.sync.is_empty()),
+        ("push", !config.push.accept_from.is_empty()),
+        ("tracker", !config.tracker.trackers.is_empty()),
+        ("replication", !config.replication.peers.is_empty()),
+        ("mirror", config.mirror.topic.is_some()),
+        ("forward", config.forward.mode != ForwardMode::Off),
+        ("cluster", config.cluster.name.is_some()),
+        ("antivirus", config.antivirus.clamd.is_some()),
+        ("screening", config.screening.webhook.is_some()),
+        ("tenancy", !config.tenancy.tenants.is_empty()),
+        ("gc", config.gc.interval_secs > 0),
+        ("plugins", !config.plugins.enabled.is_empty()),
+        ("schedule", config.schedule.iter().any(|entry| entry.enabled)),
+        (
+            "metering",
+            config.metering.file.is_some() || config.metering.webhook.is_some(),
+        ),
+        (
+            "compression",
+            config.compression.downloads || config.compression.json,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// `GET /version`: the crate version, the commit it was built from, the iroh versions
+/// it links and the enabled features.
+pub async fn get_version(State(app_state): State<AppState>) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("IROH_API_GIT_COMMIT"),
+        iroh: env!("IROH_API_IROH_VERSION"),
+        iroh_blobs: env!("IROH_API_IROH_BLOBS_VERSION"),
+        features: app_state.features.to_vec(),
+    })
+}
@@ -0,0 +1,620 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, Request, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Buf;
+use dav_server::davpath::DavPath;
+use dav_server::fakels::FakeLs;
+use dav_server::fs::{
+    DavDirEntry, DavFile, DavFileSystem, DavMetaData, FsError, FsFuture, FsResult, FsStream,
+    OpenOptions, ReadDirMeta,
+};
+use dav_server::DavHandler;
+use futures::{stream, FutureExt};
+use iroh_blobs::rpc::client::blobs::ReadAtLen;
+use iroh_blobs::Hash;
+use std::fmt;
+use std::io::SeekFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::s3::{self, Object, S3Error};
+use crate::upload::MAX_BUFFERED_SIZE;
+use crate::AppState;
+
+/// Seconds clients are asked to wait when every ingest slot is taken.
+const RETRY_AFTER_SECS: u64 = 2;
+/// Bytes read from the store at once when serving a file.
+const READ_BUF_SIZE: usize = 64 * 1024;
+/// The content type of the objects marking empty folders.
+pub const FOLDER_CONTENT_TYPE: &str = "application/x-directory";
+
+impl From<S3Error> for FsError {
+    fn from(err: S3Error) -> Self {
+        match err.status() {
+            StatusCode::NOT_FOUND => FsError::NotFound,
+            StatusCode::CONFLICT => FsError::Exists,
+            StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN => FsError::Forbidden,
+            _ => FsError::GeneralFailure,
+        }
+    }
+}
+
+fn system_time(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Where a WebDAV path points: the top level lists buckets, and inside a bucket the
+/// keys are split at `/` into folders.
+enum Location {
+    Root,
+    Bucket(String),
+    Entry { bucket: String, key: String },
+}
+
+impl Location {
+    fn of(path: &DavPath) -> FsResult<Self> {
+        let path = std::str::from_utf8(path.as_bytes()).map_err(|_| FsError::NotFound)?;
+        let path = path.trim_matches('/');
+        Ok(match path.split_once('/') {
+            _ if path.is_empty() => Location::Root,
+            None => Location::Bucket(path.to_string()),
+            Some((bucket, key)) => Location::Entry {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Meta {
+    len: u64,
+    modified: SystemTime,
+    /// `None` for folders.
+    hash: Option<Hash>,
+}
+
+impl Meta {
+    fn folder(modified: SystemTime) -> Self {
+        Self {
+            len: 0,
+            modified,
+            hash: None,
+        }
+    }
+
+    fn file(object: &Object) -> Self {
+        Self {
+            len: object.size,
+            modified: system_time(object.last_modified),
+            hash: Some(object.hash),
+        }
+    }
+}
+
+impl DavMetaData for Meta {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.hash.is_none()
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.hash.map(|hash| hash.to_string())
+    }
+}
+
+struct Entry {
+    name: String,
+    meta: Meta,
+}
+
+impl DavDirEntry for Entry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone().into_bytes()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = Box::new(self.meta.clone()) as Box<dyn DavMetaData>;
+        async move { Ok(meta) }.boxed()
+    }
+}
+
+/// The S3 buckets as a file system. Folders exist as long as keys below them do, or a
+/// marker object named after the folder with a trailing `/`, as S3 tools create them.
+#[derive(Clone)]
+struct StoreFs {
+    app_state: AppState,
+    /// What files written in this request are stored as.
+    content_type: String,
+}
+
+impl StoreFs {
+    fn bucket_modified(&self, bucket: &str) -> FsResult<SystemTime> {
+        self.app_state
+            .buckets
+            .names()
+            .into_iter()
+            .find(|(name, _)| name == bucket)
+            .map(|(_, created)| system_time(created))
+            .ok_or(FsError::NotFound)
+    }
+
+    fn is_folder(&self, bucket: &str, key: &str) -> FsResult<bool> {
+        Ok(!self
+            .app_state
+            .buckets
+            .keys(bucket, &format!("{}/", key))?
+            .is_empty())
+    }
+
+    fn meta(&self, location: &Location) -> FsResult<Meta> {
+        match location {
+            Location::Root => Ok(Meta::folder(UNIX_EPOCH)),
+            Location::Bucket(bucket) => Ok(Meta::folder(self.bucket_modified(bucket)?)),
+            Location::Entry { bucket, key } => match self.app_state.buckets.object(bucket, key) {
+                Ok(object) => Ok(Meta::file(&object)),
+                Err(_) if self.is_folder(bucket, key)? => {
+                    Ok(Meta::folder(self.bucket_modified(bucket)?))
+                }
+                Err(err) => Err(err.into()),
+            },
+        }
+    }
+}
+
+impl DavFileSystem for StoreFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let Location::Entry { bucket, key } = Location::of(path)? else {
+                return Err(FsError::Forbidden);
+            };
+            let object = match self.app_state.buckets.object(&bucket, &key) {
+                Ok(object) => Some(object),
+                Err(err) if err.status() == StatusCode::NOT_FOUND => {
+                    if !self.app_state.buckets.exists(&bucket) {
+                        return Err(FsError::NotFound);
+                    }
+                    None
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if object.is_none() && self.is_folder(&bucket, &key)? {
+                return Err(FsError::Forbidden);
+            }
+
+            let mut buffer = None;
+            let mut pos = 0;
+            if options.write {
+                match &object {
+                    Some(_) if options.create_new => return Err(FsError::Exists),
+                    None if !options.create && !options.create_new => {
+                        return Err(FsError::NotFound)
+                    }
+                    _ => {}
+                }
+                // Objects can't change in place, writes collect the new content first
+                let mut data = Vec::new();
+                if let Some(object) = object.as_ref().filter(|_| !options.truncate) {
+                    let existing = self
+                        .app_state
+                        .blobs
+                        .client()
+                        .read_to_bytes(object.hash)
+                        .await
+                        .map_err(|_| FsError::GeneralFailure)?;
+                    data.extend_from_slice(&existing);
+                }
+                if options.append {
+                    pos = data.len() as u64;
+                }
+                buffer = Some(data);
+            } else if let Some(object) = &object {
+                self.app_state
+                    .screening
+                    .check_serve(&self.app_state, object.hash)
+                    .await
+                    .map_err(|_| FsError::Forbidden)?;
+                self.app_state
+                    .plugins
+                    .check_download(object.hash)
+                    .await
+                    .map_err(|_| FsError::Forbidden)?;
+            } else {
+                return Err(FsError::NotFound);
+            }
+            let max_len = match self.app_state.upload.get().max_file_size {
+                0 => MAX_BUFFERED_SIZE,
+                max => max.min(MAX_BUFFERED_SIZE),
+            };
+            Ok(Box::new(StoreFile {
+                fs: self.clone(),
+                bucket,
+                key,
+                object,
+                buffer,
+                max_len,
+                dirty: options.write,
+                pos,
+            }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        async move {
+            let entries = match Location::of(path)? {
+                Location::Root => self
+                    .app_state
+                    .buckets
+                    .names()
+                    .into_iter()
+                    .map(|(name, created)| Entry {
+                        name,
+                        meta: Meta::folder(system_time(created)),
+                    })
+                    .collect(),
+                location @ (Location::Bucket(_) | Location::Entry { .. }) => {
+                    let (bucket, prefix) = match &location {
+                        Location::Entry { bucket, key } => {
+                            if !self.is_folder(bucket, key)? {
+                                return Err(FsError::NotFound);
+                            }
+                            (bucket.as_str(), format!("{}/", key))
+                        }
+                        Location::Bucket(bucket) => (bucket.as_str(), String::new()),
+                        Location::Root => unreachable!(),
+                    };
+                    let modified = self.bucket_modified(bucket)?;
+                    self.app_state
+                        .buckets
+                        .folder(bucket, &prefix)?
+                        .into_iter()
+                        .map(|(name, object)| Entry {
+                            name,
+                            meta: object
+                                .as_ref()
+                                .map_or_else(|| Meta::folder(modified), Meta::file),
+                        })
+                        .collect::<Vec<_>>()
+                }
+            };
+            let entries = entries
+                .into_iter()
+                .map(|entry| Ok(Box::new(entry) as Box<dyn DavDirEntry>));
+            Ok(Box::pin(stream::iter(entries)) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let meta = self.meta(&Location::of(path)?)?;
+            Ok(Box::new(meta) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    /// Folders at the top level are buckets, below that they are marker objects.
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let location = Location::of(path)?;
+            if self.meta(&location).is_ok() {
+                return Err(FsError::Exists);
+            }
+            match location {
+                Location::Root => Err(FsError::Exists),
+                Location::Bucket(bucket) => Ok(self.app_state.buckets.create(&bucket)?),
+                Location::Entry { bucket, key } => {
+                    s3::store_object(
+                        &self.app_state,
+                        &bucket,
+                        &format!("{}/", key),
+                        Bytes::new(),
+                        FOLDER_CONTENT_TYPE.to_string(),
+                    )
+                    .await?;
+                    Ok(())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match Location::of(path)? {
+                Location::Root => Err(FsError::Forbidden),
+                Location::Bucket(bucket) => Ok(self.app_state.buckets.remove(&bucket)?),
+                Location::Entry { bucket, key } => {
+                    let marker = format!("{}/", key);
+                    let keys = self.app_state.buckets.keys(&bucket, &marker)?;
+                    if keys.is_empty() {
+                        return Err(FsError::NotFound);
+                    }
+                    if keys.iter().any(|other| *other != marker) {
+                        return Err(FsError::Exists);
+                    }
+                    s3::remove_object(&self.app_state, &bucket, &marker).await?;
+                    Ok(())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let Location::Entry { bucket, key } = Location::of(path)? else {
+                return Err(FsError::Forbidden);
+            };
+            if !s3::remove_object(&self.app_state, &bucket, &key).await? {
+                return Err(FsError::NotFound);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Moves re-tag the blobs under their new keys, no data is copied.
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let (
+                Location::Entry {
+                    bucket: from_bucket,
+                    key: from_key,
+                },
+                Location::Entry {
+                    bucket: to_bucket,
+                    key: to_key,
+                },
+            ) = (Location::of(from)?, Location::of(to)?)
+            else {
+                return Err(FsError::Forbidden);
+            };
+            let moved = s3::move_objects(
+                &self.app_state,
+                (&from_bucket, &from_key),
+                (&to_bucket, &to_key),
+            )
+            .await?;
+            if moved == 0 {
+                return Err(FsError::NotFound);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let (
+                Location::Entry {
+                    bucket: from_bucket,
+                    key: from_key,
+                },
+                Location::Entry {
+                    bucket: to_bucket,
+                    key: to_key,
+                },
+            ) = (Location::of(from)?, Location::of(to)?)
+            else {
+                return Err(FsError::Forbidden);
+            };
+            s3::copy_object(
+                &self.app_state,
+                (&from_bucket, &from_key),
+                (&to_bucket, &to_key),
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// An open file. Reads go to the blob, writes collect the whole new content, which is
+/// stored as a new object on flush.
+struct StoreFile {
+    fs: StoreFs,
+    bucket: String,
+    key: String,
+    object: Option<Object>,
+    buffer: Option<Vec<u8>>,
+    /// Past this, writes and seeks are refused before the buffer grows.
+    max_len: u64,
+    dirty: bool,
+    pos: u64,
+}
+
+impl fmt::Debug for StoreFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreFile")
+            .field("bucket", &self.bucket)
+            .field("key", &self.key)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StoreFile {
+    fn len(&self) -> u64 {
+        match (&self.buffer, &self.object) {
+            (Some(buffer), _) => buffer.len() as u64,
+            (None, Some(object)) => object.size,
+            (None, None) => 0,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> FsResult<()> {
+        let buffer = self.buffer.as_mut().ok_or(FsError::Forbidden)?;
+        let end = self.pos.saturating_add(data.len() as u64);
+        if end > self.max_len {
+            return Err(FsError::TooLarge);
+        }
+        let (start, end) = (self.pos as usize, end as usize);
+        if buffer.len() < end {
+            buffer.resize(end, 0);
+        }
+        buffer[start..end].copy_from_slice(data);
+        self.pos = end as u64;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl DavFile for StoreFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = match (&self.object, self.dirty) {
+            (Some(object), false) => Meta::file(object),
+            _ => Meta {
+                len: self.len(),
+                modified: SystemTime::now(),
+                hash: Some(Hash::EMPTY),
+            },
+        };
+        async move { Ok(Box::new(meta) as Box<dyn DavMetaData>) }.boxed()
+    }
+
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        let data = buf.copy_to_bytes(buf.remaining());
+        let result = self.write(&data);
+        async move { result }.boxed()
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        let result = self.write(&buf);
+        async move { result }.boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        async move {
+            let start = self.pos.min(self.len());
+            let data = match (&self.buffer, &self.object) {
+                (Some(buffer), _) => {
+                    let end = buffer.len().min(start as usize + count);
+                    Bytes::copy_from_slice(&buffer[start as usize..end])
+                }
+                (None, Some(object)) if start < object.size => self
+                    .fs
+                    .app_state
+                    .blobs
+                    .client()
+                    .read_at_to_bytes(object.hash, start, ReadAtLen::AtMost(count as u64))
+                    .await
+                    .map_err(|_| FsError::GeneralFailure)?,
+                _ => Bytes::new(),
+            };
+            self.pos = start + data.len() as u64;
+            Ok(data)
+        }
+        .boxed()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        let result = match pos {
+            Some(pos) if self.buffer.is_some() && pos > self.max_len => Err(FsError::TooLarge),
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(FsError::GeneralFailure),
+        };
+        async move { result }.boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        async move {
+            let Some(buffer) = self.buffer.as_mut().filter(|_| self.dirty) else {
+                return Ok(());
+            };
+            let data = Bytes::from(std::mem::take(buffer));
+            let app_state = &self.fs.app_state;
+            s3::store_object(
+                app_state,
+                &self.bucket,
+                &self.key,
+                data.clone(),
+                self.fs.content_type.clone(),
+            )
+            .await?;
+            self.object = Some(app_state.buckets.object(&self.bucket, &self.key)?);
+            self.buffer = Some(Vec::from(data));
+            self.dirty = false;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// `/dav`: the S3 buckets as a WebDAV share, to mount the gateway as a network drive.
+///
+/// Locks are faked, which Finder and Explorer need to mount it writable.
+pub async fn handle(
+    State(app_state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+) -> Response {
+    // Uploads hold an ingest slot while the body is read, like POST /upload
+    let _slot = if request.method() == axum::http::Method::PUT {
+        match app_state.ingest_slots.clone().try_acquire_owned() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+                )
+                    .into_response()
+            }
+        }
+    } else {
+        None
+    };
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(s3::DEFAULT_CONTENT_TYPE)
+        .to_string();
+
+    // Paths in responses need the prefix the API is served under too
+    let (mut parts, body) = request.into_parts();
+    parts.uri = uri;
+    let body = Body::from_stream(app_state.throttle.download().stream(body.into_data_stream()));
+    let handler = DavHandler::builder()
+        .filesystem(Box::new(StoreFs {
+            app_state: app_state.clone(),
+            content_type,
+        }))
+        .locksystem(FakeLs::new())
+        .strip_prefix(format!("{}/dav", app_state.proxy.base_path()))
+        .read_buf_size(READ_BUF_SIZE)
+        .build_handler();
+    let response = handler.handle(Request::from_parts(parts, body)).await;
+
+    let limiters = app_state.throttle.upload();
+    response
+        .map(|body| Body::from_stream(limiters.stream(Body::new(body).into_data_stream())))
+        .into_response()
+}
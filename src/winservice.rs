@@ -0,0 +1,99 @@
+use anyhow::Result;
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// Name the service is registered under with `sc.exe create`.
+const SERVICE_NAME: &str = "iroh-api";
+
+/// How long the service control manager is told stopping may take.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(20);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hands the process over to the service control manager, which calls back into
+/// `service_main` on a thread of its own. Returns once the service has stopped.
+pub fn run() -> Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        println!("Service failed: {:?}", err);
+    }
+}
+
+fn set_status(
+    handle: &ServiceStatusHandle,
+    state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+    exit_code: u32,
+    wait_hint: Duration,
+) -> windows_service::Result<()> {
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint,
+        process_id: None,
+    })
+}
+
+fn run_service() -> Result<()> {
+    let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel();
+    let stop_sender = Mutex::new(Some(stop_sender));
+    let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(sender) = stop_sender.lock().unwrap().take() {
+                let _ = sender.send(());
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    // Services start in the system directory, the config and data live next to the
+    // executable instead
+    if let Some(dir) = std::env::current_exe()?.parent() {
+        std::env::set_current_dir(dir)?;
+    }
+    let config = crate::config::Config::load()?;
+
+    set_status(
+        &handle,
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+        Duration::ZERO,
+    )?;
+    let stopping = handle;
+    let result = tokio::runtime::Runtime::new()?.block_on(crate::run(config, Default::default(), async move {
+        let _ = stop_receiver.await;
+        let _ = set_status(
+            &stopping,
+            ServiceState::StopPending,
+            ServiceControlAccept::empty(),
+            0,
+            STOP_WAIT_HINT,
+        );
+    }));
+    set_status(
+        &handle,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        if result.is_ok() { 0 } else { 1 },
+        Duration::ZERO,
+    )?;
+    result
+}
@@ -0,0 +1,192 @@
+"use strict";
+
+// All URLs are relative to <base>, which the server points at the API.
+const PAGE_SIZE = 50;
+let offset = 0;
+
+const $ = (id) => document.getElementById(id);
+
+// Changes made with a session cookie have to carry this token
+const csrfToken = fetch("csrf-token")
+  .then((response) => response.json())
+  .then((body) => body.token)
+  .catch(() => "");
+
+function formatSize(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let unit = 0;
+  while (bytes >= 1024 && unit < units.length - 1) {
+    bytes /= 1024;
+    unit += 1;
+  }
+  return `${unit === 0 ? bytes : bytes.toFixed(1)} ${units[unit]}`;
+}
+
+function showTicket(ticket, hash) {
+  $("ticket").hidden = false;
+  $("ticket-text").textContent = ticket;
+  $("ticket-qr").src = `qr?ticket=${encodeURIComponent(ticket)}`;
+  $("ticket-download").href = `blob/${hash}`;
+  $("ticket").scrollIntoView({ behavior: "smooth" });
+}
+
+async function upload(file) {
+  const item = document.createElement("li");
+  item.textContent = `${file.name}: uploading…`;
+  $("uploads").prepend(item);
+
+  const form = new FormData();
+  form.append("file", file, file.name);
+  try {
+    const response = await fetch("upload", {
+      method: "POST",
+      body: form,
+      headers: { "X-CSRF-Token": await csrfToken },
+    });
+    if (!response.ok) {
+      throw new Error(`${response.status} ${response.statusText}`);
+    }
+    const result = await response.json();
+    item.textContent = `${file.name}: ${result.blob_hash}`;
+    item.onclick = () => showTicket(result.ticket, result.blob_hash);
+    showTicket(result.ticket, result.blob_hash);
+    loadBlobs();
+  } catch (err) {
+    item.classList.add("failed");
+    item.textContent = `${file.name}: ${err.message}`;
+  }
+}
+
+async function loadBlobs() {
+  const response = await fetch(`blobs?offset=${offset}&limit=${PAGE_SIZE}`);
+  // Gateways with API keys need a sign-in first
+  $("login").hidden = response.status !== 401;
+  if (!response.ok) {
+    return;
+  }
+  const blobs = await response.json();
+  const rows = blobs.map((blob) => {
+    const row = document.createElement("tr");
+
+    const hash = document.createElement("td");
+    const link = document.createElement("a");
+    link.href = `blob/${blob.hash}`;
+    link.textContent = blob.hash;
+    hash.append(link);
+
+    const size = document.createElement("td");
+    size.className = "size";
+    size.textContent = formatSize(blob.size);
+
+    const actions = document.createElement("td");
+    const ticket = document.createElement("button");
+    ticket.type = "button";
+    ticket.textContent = "Ticket";
+    ticket.onclick = () => showTicket(blob.ticket, blob.hash);
+    actions.append(ticket);
+
+    row.append(hash, size, actions);
+    return row;
+  });
+  $("blobs").replaceChildren(...rows);
+  $("previous").disabled = offset === 0;
+  $("next").disabled = blobs.length < PAGE_SIZE;
+}
+
+async function loadSession() {
+  const response = await fetch("session");
+  $("account").hidden = !response.ok;
+  if (response.ok) {
+    const session = await response.json();
+    $("username").textContent = session.username;
+  }
+}
+
+function postCredentials(path) {
+  return fetch(path, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({
+      username: $("login-username").value,
+      password: $("login-password").value,
+    }),
+  });
+}
+
+async function login() {
+  const response = await postCredentials("login");
+  if (!response.ok) {
+    $("login-error").textContent =
+      response.status === 401 ? "Wrong username or password" : response.statusText;
+    return;
+  }
+  $("login-password").value = "";
+  $("login-error").textContent = "";
+  loadSession();
+  loadBlobs();
+}
+
+$("login").addEventListener("submit", (event) => {
+  event.preventDefault();
+  login();
+});
+
+const REGISTER_ERRORS = {
+  400: "Usernames are letters, digits and -_.@, passwords at least 8 characters",
+  403: "This gateway doesn't take new accounts",
+  409: "That username is taken",
+};
+
+$("register").onclick = async () => {
+  if (!$("login").reportValidity()) {
+    return;
+  }
+  const response = await postCredentials("register");
+  if (!response.ok) {
+    $("login-error").textContent =
+      REGISTER_ERRORS[response.status] || response.statusText;
+    return;
+  }
+  login();
+};
+
+$("logout").onclick = async () => {
+  await fetch("logout", {
+    method: "POST",
+    headers: { "X-CSRF-Token": await csrfToken },
+  });
+  location.reload();
+};
+
+const drop = $("drop");
+drop.addEventListener("dragover", (event) => {
+  event.preventDefault();
+  drop.classList.add("over");
+});
+drop.addEventListener("dragleave", () => drop.classList.remove("over"));
+drop.addEventListener("drop", (event) => {
+  event.preventDefault();
+  drop.classList.remove("over");
+  [...event.dataTransfer.files].forEach(upload);
+});
+$("file").addEventListener("change", (event) => {
+  [...event.target.files].forEach(upload);
+  event.target.value = "";
+});
+
+$("ticket-copy").onclick = () =>
+  navigator.clipboard.writeText($("ticket-text").textContent);
+$("previous").onclick = () => {
+  offset = Math.max(0, offset - PAGE_SIZE);
+  loadBlobs();
+};
+$("next").onclick = () => {
+  offset += PAGE_SIZE;
+  loadBlobs();
+};
+
+fetch("node-id")
+  .then((response) => response.json())
+  .then((body) => ($("node-id").textContent = body.node_id));
+loadSession();
+loadBlobs();